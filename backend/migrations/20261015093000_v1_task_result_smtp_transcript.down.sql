@@ -0,0 +1 @@
+ALTER TABLE v1_task_result DROP COLUMN IF EXISTS smtp_transcript;
@@ -0,0 +1,2 @@
+-- The SMTP transcript of the task, if enabled on its input.
+ALTER TABLE v1_task_result ADD COLUMN smtp_transcript JSONB;
@@ -15,27 +15,44 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::create_db;
+use crate::http::client_ip::TrustedProxies;
+use crate::http::concurrency_limit::ConcurrencyLimiter;
+use crate::http::idempotency::{
+	IdempotencyStore, DEFAULT_IDEMPOTENCY_KEY_TTL, DEFAULT_IDEMPOTENCY_MAX_KEYS,
+};
+use crate::http::result_cache::{ResultCache, DEFAULT_RESULT_CACHE_MAX_ENTRIES};
 #[cfg(feature = "worker")]
-use crate::worker::check_email::TaskWebhook;
+use crate::worker::check_email::{
+	ProxyBudget, TaskWebhook, Webhook, WebhookClient, DEFAULT_WEBHOOK_CONCURRENCY,
+};
 #[cfg(feature = "worker")]
 use crate::worker::setup_rabbit_mq;
 use anyhow::bail;
+use arc_swap::{ArcSwap, Guard};
 use check_if_email_exists::config::ReacherConfig;
+use check_if_email_exists::misc::{BreachProviders, DisposableList};
+use check_if_email_exists::mx::create_resolver;
+use check_if_email_exists::smtp::{
+	CatchAllCache, EgressMonitor, HeadlessConfig, HostLatencies, SmtpPool, WebDriverPool,
+	DEFAULT_DEGRADED_RETRY_AFTER, DEFAULT_SMTP_POOL_IDLE_TIMEOUT, DEFAULT_SMTP_POOL_KEEP_ALIVE,
+	DEFAULT_WEBDRIVER_IDLE_TIMEOUT, DEFAULT_WEBDRIVER_MAX_SESSIONS,
+};
 use check_if_email_exists::{
 	CheckEmailInputProxy, GmailVerifMethod, HotmailB2BVerifMethod, HotmailB2CVerifMethod,
 	SentryConfig, YahooVerifMethod,
 };
 use config::Config;
+use hickory_resolver::TokioAsyncResolver;
 #[cfg(feature = "worker")]
 use lapin::Channel;
 use serde::de::{self, Deserializer, Visitor};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
 use sqlx::PgPool;
-#[cfg(feature = "worker")]
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use std::{env, fmt};
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct BackendConfig {
 	/// Name of the backend.
 	pub backend_name: String,
@@ -44,6 +61,38 @@ pub struct BackendConfig {
 	pub from_email: String,
 	pub hello_name: String,
 	pub webdriver_addr: String,
+	/// Maximum number of WebDriver sessions in use at once by the headless
+	/// verifications. Defaults to 4.
+	pub webdriver_max_sessions: Option<usize>,
+	/// How long, in seconds, an idle WebDriver session is kept for reuse.
+	/// Defaults to 5 minutes.
+	pub webdriver_idle_timeout: Option<u64>,
+	/// Selectors of the page elements the headless verifications interact
+	/// with, with fallbacks. Defaults to `HeadlessConfig::default()`.
+	#[serde(default)]
+	pub headless: HeadlessConfig,
+	/// Number of consecutive SMTP connection failures, refused or timed out
+	/// on at least two different hosts, after which outbound SMTP is
+	/// considered blocked: the verifications then skip SMTP and only return
+	/// the syntax and MX results. Defaults to no detection.
+	pub egress_failure_threshold: Option<usize>,
+	/// Force the degraded mode on or off, overriding the detection.
+	pub degraded_mode: Option<bool>,
+	/// How long, in seconds, the detected degraded mode lasts before SMTP is
+	/// tried again. Defaults to 300 seconds.
+	pub degraded_retry_after: Option<u64>,
+	/// How long, in seconds, the catch-all determination of a domain is
+	/// reused by the next verifications at the same domain. Defaults to no
+	/// caching.
+	pub catch_all_cache_ttl: Option<u64>,
+	/// Maximum number of idle SMTP connections kept open per MX host, to be
+	/// reused by the next verifications at this host. Defaults to 0, i.e. no
+	/// pooling.
+	pub smtp_pool_max_idle_per_host: Option<usize>,
+	/// How long, in seconds, a pooled SMTP connection is kept idle. Defaults
+	/// to 60 seconds.
+	pub smtp_pool_idle_timeout: Option<u64>,
+	#[serde(serialize_with = "redact")]
 	pub proxy: Option<CheckEmailInputProxy>,
 
 	/// Verification method configuration.
@@ -55,14 +104,63 @@ pub struct BackendConfig {
 	/// Backend port
 	pub http_port: u16,
 	/// Shared secret between a trusted client and the backend.
+	#[serde(serialize_with = "redact")]
 	pub header_secret: Option<String>,
+	/// Token required in the `x-reacher-admin-token` header of the
+	/// `PATCH /v1/config` requests. The endpoint is disabled if unset.
+	#[serde(serialize_with = "redact")]
+	pub admin_token: Option<String>,
 
 	/// Worker configuration, only present if the backend is a worker.
 	pub worker: WorkerConfig,
 
 	/// Sentry configuration to report errors.
+	#[serde(serialize_with = "redact")]
 	pub sentry: Option<SentryConfig>,
 
+	/// URL of a list of disposable domains, one per line, which replaces the
+	/// bundled list. It's fetched at startup, and then every
+	/// `disposable_list_refresh_interval` seconds if set.
+	pub disposable_list_url: Option<String>,
+	/// Interval, in seconds, between two fetches of `disposable_list_url`.
+	pub disposable_list_refresh_interval: Option<u64>,
+	/// How long, in seconds, the response of a request with an
+	/// `Idempotency-Key` header is returned to the requests with the same
+	/// key. Defaults to 24 hours.
+	pub idempotency_key_ttl: Option<u64>,
+	/// Maximum number of idempotency keys kept, above which the least
+	/// recently used ones are forgotten. Defaults to 10,000.
+	pub idempotency_max_keys: Option<usize>,
+	/// How long, in seconds, the output of a `POST /v0/check_email` request
+	/// is returned to the next requests for the same email (regardless of
+	/// case) with the same options, instead of verifying the email again.
+	/// Defaults to no caching.
+	pub result_cache_ttl: Option<u64>,
+	/// Maximum number of outputs cached, above which the least recently
+	/// used ones are forgotten. Defaults to 10,000.
+	pub result_cache_max_entries: Option<usize>,
+	/// Maximum number of in-flight `POST /v0/check_email` requests per
+	/// client IP, beyond which requests get a 429. Defaults to no limit.
+	pub max_concurrent_requests_per_ip: Option<usize>,
+	/// IPs or CIDR ranges of the reverse proxies in front of the backend.
+	/// For requests coming from them, the client IP used by
+	/// `max_concurrent_requests_per_ip` and the access logs is taken from
+	/// the `Forwarded` or `X-Forwarded-For` headers, skipping the hops of
+	/// these proxies. Defaults to none.
+	#[serde(default)]
+	pub trusted_proxies: Vec<String>,
+	/// Trust every proxy, i.e. use the first IP of the forwarding headers as
+	/// the client IP. Clients can forge it, prefer `trusted_proxies`. Ignored
+	/// if `trusted_proxies` is set.
+	#[serde(default)]
+	pub trust_x_forwarded_for: bool,
+	/// HTTP client used for the Gravatar and breach providers' requests, for
+	/// the webhooks, and to fetch `disposable_list_url`, e.g. to add tracing
+	/// or proxying middleware. It's not
+	/// part of the configuration file, set it before starting the backend.
+	#[serde(skip)]
+	pub http_client: Option<reqwest::Client>,
+
 	// Internal fields, not part of the configuration.
 	#[serde(skip)]
 	pg_pool: Option<PgPool>,
@@ -72,6 +170,84 @@ pub struct BackendConfig {
 	#[cfg(feature = "worker")]
 	#[serde(skip)]
 	preprocess_channel: Option<Arc<Channel>>,
+	/// DNS resolver shared by all verifications, created on first use.
+	#[serde(skip)]
+	resolver: OnceLock<Arc<TokioAsyncResolver>>,
+	/// Response latency of the MX hosts, shared by all verifications.
+	#[serde(skip)]
+	smtp_latencies: Arc<HostLatencies>,
+	/// List of disposable domains, shared by all verifications.
+	#[serde(skip)]
+	disposable_list: Arc<DisposableList>,
+	/// Breach providers, shared by all verifications for their rate limits.
+	#[serde(skip)]
+	breach_providers: Arc<BreachProviders>,
+	/// WebDriver sessions shared by all verifications, created on first
+	/// use.
+	#[serde(skip)]
+	webdriver_pool: OnceLock<Arc<WebDriverPool>>,
+	/// Detection of blocked outbound SMTP, shared by all verifications,
+	/// created on first use.
+	#[serde(skip)]
+	egress_monitor: OnceLock<Arc<EgressMonitor>>,
+	/// Catch-all determination of each domain, shared by all verifications,
+	/// created on first use.
+	#[serde(skip)]
+	catch_all_cache: OnceLock<Arc<CatchAllCache>>,
+	/// Idle SMTP connections shared by all verifications, created on first
+	/// use.
+	#[serde(skip)]
+	smtp_pool: OnceLock<Arc<SmtpPool>>,
+	/// Responses of the requests with an idempotency key, created on first
+	/// use.
+	#[serde(skip)]
+	idempotency_store: OnceLock<Arc<IdempotencyStore>>,
+	/// Outputs of the previous verifications, created on first use.
+	#[serde(skip)]
+	result_cache: OnceLock<Arc<ResultCache>>,
+	/// In-flight requests per client IP, created on first use if
+	/// `max_concurrent_requests_per_ip` is set.
+	#[serde(skip)]
+	concurrency_limiter: OnceLock<Option<Arc<ConcurrencyLimiter>>>,
+	/// The parsed `trusted_proxies`, created on first use.
+	#[serde(skip)]
+	trusted_proxies_networks: OnceLock<Arc<TrustedProxies>>,
+	/// HTTP client sending the webhooks, shared by all tasks, created on
+	/// first use.
+	#[cfg(feature = "worker")]
+	#[serde(skip)]
+	webhook_client: OnceLock<Arc<WebhookClient>>,
+	/// Proxy requests of each bulk job, capped by
+	/// `worker.proxy_requests_per_job`, created on first use.
+	#[cfg(feature = "worker")]
+	#[serde(skip)]
+	proxy_budget: OnceLock<Arc<ProxyBudget>>,
+	/// The values changed at runtime by `PATCH /v1/config`, created on
+	/// first use from the values above.
+	#[serde(skip)]
+	runtime_config: OnceLock<ArcSwap<RuntimeConfig>>,
+}
+
+/// Serialize a secret of the config as "[redacted]", if set.
+fn redact<T, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+	value.as_ref().map(|_| "[redacted]").serialize(serializer)
+}
+
+/// Serialize a required secret of the config as "[redacted]".
+fn redact_str<S: Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str("[redacted]")
+}
+
+/// The hot-reloadable values of the config, which `PATCH /v1/config` can
+/// change without restarting the backend. Read them with
+/// `BackendConfig::get_runtime_config`, not from the fields they come from.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RuntimeConfig {
+	/// Default verification methods, from `verif_method`.
+	pub verif_method: VerifMethodConfig,
+	/// Number of concurrent messages the worker processes, from
+	/// `worker.rabbitmq.concurrency`. None if RabbitMQ isn't configured.
+	pub worker_concurrency: Option<u16>,
 }
 
 impl BackendConfig {
@@ -80,9 +256,185 @@ impl BackendConfig {
 			backend_name: self.backend_name.clone(),
 			sentry: self.sentry.clone(),
 			webdriver_addr: self.webdriver_addr.clone(),
+			webdriver_pool: self.get_webdriver_pool(),
+			headless: self.headless.clone(),
+			resolver: self.get_resolver(),
+			smtp_latencies: Arc::clone(&self.smtp_latencies),
+			egress_monitor: self.get_egress_monitor(),
+			catch_all_cache: self.get_catch_all_cache(),
+			limiter: Default::default(),
+			smtp_pool: self.get_smtp_pool(),
+			disposable_list: self.get_disposable_list(),
+			breach_providers: Arc::clone(&self.breach_providers),
+			post_classify: None,
+			http_client: self.http_client.clone(),
+			canned_results: None,
+			on_proxy_connection: None,
 		}
 	}
 
+	fn runtime_config_swap(&self) -> &ArcSwap<RuntimeConfig> {
+		self.runtime_config.get_or_init(|| {
+			ArcSwap::from_pointee(RuntimeConfig {
+				verif_method: self.verif_method.clone(),
+				worker_concurrency: self.worker.rabbitmq.as_ref().map(|r| r.concurrency),
+			})
+		})
+	}
+
+	/// Get the current hot-reloadable values of the config.
+	pub fn get_runtime_config(&self) -> Arc<RuntimeConfig> {
+		self.runtime_config_swap().load_full()
+	}
+
+	/// Atomically update the hot-reloadable values of the config with `f`,
+	/// given the current ones, and return the new ones. If they changed
+	/// concurrently meanwhile, `f` is called again with them, so that no
+	/// update is lost. The next requests and tasks use the new values.
+	pub fn update_runtime_config<E>(
+		&self,
+		f: impl Fn(&RuntimeConfig) -> Result<RuntimeConfig, E>,
+	) -> Result<Arc<RuntimeConfig>, E> {
+		let swap = self.runtime_config_swap();
+		let mut current = swap.load_full();
+		loop {
+			let new = Arc::new(f(&current)?);
+			let prev = swap.compare_and_swap(&current, Arc::clone(&new));
+			if Arc::ptr_eq(&prev, &current) {
+				return Ok(new);
+			}
+			current = Guard::into_inner(prev);
+		}
+	}
+
+	/// Get the list of disposable domains shared by all verifications.
+	pub fn get_disposable_list(&self) -> Arc<DisposableList> {
+		Arc::clone(&self.disposable_list)
+	}
+
+	/// Get the store of the responses of the requests with an idempotency
+	/// key.
+	pub fn get_idempotency_store(&self) -> Arc<IdempotencyStore> {
+		Arc::clone(self.idempotency_store.get_or_init(|| {
+			let store = Arc::new(IdempotencyStore::new(
+				self.idempotency_key_ttl
+					.map(Duration::from_secs)
+					.unwrap_or(DEFAULT_IDEMPOTENCY_KEY_TTL),
+				self.idempotency_max_keys
+					.unwrap_or(DEFAULT_IDEMPOTENCY_MAX_KEYS),
+			));
+			store.spawn_pruning();
+			store
+		}))
+	}
+
+	/// Get the cache of the `POST /v0/check_email` outputs.
+	pub fn get_result_cache(&self) -> Arc<ResultCache> {
+		Arc::clone(self.result_cache.get_or_init(|| {
+			let cache = Arc::new(ResultCache::new(
+				self.result_cache_ttl.map(Duration::from_secs),
+				self.result_cache_max_entries
+					.unwrap_or(DEFAULT_RESULT_CACHE_MAX_ENTRIES),
+			));
+			if self.result_cache_ttl.is_some() {
+				cache.spawn_pruning();
+			}
+			cache
+		}))
+	}
+
+	/// Get the in-flight requests per client IP, or None if there's no
+	/// `max_concurrent_requests_per_ip`.
+	pub fn get_concurrency_limiter(&self) -> Option<Arc<ConcurrencyLimiter>> {
+		self.concurrency_limiter
+			.get_or_init(|| {
+				self.max_concurrent_requests_per_ip
+					.map(|max| Arc::new(ConcurrencyLimiter::new(max)))
+			})
+			.clone()
+	}
+
+	/// Get the reverse proxies whose forwarding headers are trusted.
+	pub fn get_trusted_proxies(&self) -> Arc<TrustedProxies> {
+		Arc::clone(self.trusted_proxies_networks.get_or_init(|| {
+			Arc::new(
+				if self.trusted_proxies.is_empty() && self.trust_x_forwarded_for {
+					TrustedProxies::all()
+				} else {
+					TrustedProxies::new(&self.trusted_proxies)
+				},
+			)
+		}))
+	}
+
+	/// Get the pool of WebDriver sessions shared by all verifications.
+	pub fn get_webdriver_pool(&self) -> Arc<WebDriverPool> {
+		Arc::clone(self.webdriver_pool.get_or_init(|| {
+			Arc::new(WebDriverPool::new(
+				self.webdriver_max_sessions
+					.unwrap_or(DEFAULT_WEBDRIVER_MAX_SESSIONS),
+				self.webdriver_idle_timeout
+					.map(Duration::from_secs)
+					.unwrap_or(DEFAULT_WEBDRIVER_IDLE_TIMEOUT),
+			))
+		}))
+	}
+
+	/// Get the detection of blocked outbound SMTP shared by all
+	/// verifications.
+	pub fn get_egress_monitor(&self) -> Arc<EgressMonitor> {
+		Arc::clone(self.egress_monitor.get_or_init(|| {
+			let monitor = EgressMonitor::new(self.egress_failure_threshold).with_retry_after(
+				self.degraded_retry_after
+					.map(Duration::from_secs)
+					.unwrap_or(DEFAULT_DEGRADED_RETRY_AFTER),
+			);
+			monitor.force(self.degraded_mode);
+			Arc::new(monitor)
+		}))
+	}
+
+	/// Get the catch-all determination of each domain shared by all
+	/// verifications.
+	pub fn get_catch_all_cache(&self) -> Arc<CatchAllCache> {
+		Arc::clone(self.catch_all_cache.get_or_init(|| {
+			Arc::new(CatchAllCache::new(
+				self.catch_all_cache_ttl.map(Duration::from_secs),
+			))
+		}))
+	}
+
+	/// Get the pool of idle SMTP connections shared by all verifications.
+	/// If enabled, a task keeping the connections alive is spawned on first
+	/// use.
+	pub fn get_smtp_pool(&self) -> Arc<SmtpPool> {
+		Arc::clone(self.smtp_pool.get_or_init(|| {
+			let pool = Arc::new(SmtpPool::new(
+				self.smtp_pool_max_idle_per_host.unwrap_or_default(),
+				self.smtp_pool_idle_timeout
+					.map(Duration::from_secs)
+					.unwrap_or(DEFAULT_SMTP_POOL_IDLE_TIMEOUT),
+				DEFAULT_SMTP_POOL_KEEP_ALIVE,
+			));
+			if self.smtp_pool_max_idle_per_host.unwrap_or_default() > 0 {
+				pool.spawn_keep_alive();
+			}
+			pool
+		}))
+	}
+
+	/// Get the DNS resolver shared by all verifications.
+	pub fn get_resolver(&self) -> Arc<TokioAsyncResolver> {
+		Arc::clone(self.resolver.get_or_init(|| Arc::new(create_resolver())))
+	}
+
+	/// Use this DNS resolver for all verifications, instead of one created
+	/// from the system's configuration. Has no effect once the resolver was
+	/// used.
+	pub fn set_resolver(&self, resolver: TokioAsyncResolver) {
+		let _ = self.resolver.set(Arc::new(resolver));
+	}
+
 	/// Get the worker configuration.
 	///
 	/// # Panics
@@ -140,9 +492,31 @@ impl BackendConfig {
 	pub fn get_preprocess_channel(&self) -> Option<Arc<Channel>> {
 		self.preprocess_channel.clone()
 	}
+
+	/// Get the webhook client shared by all tasks.
+	#[cfg(feature = "worker")]
+	pub fn get_webhook_client(&self) -> Arc<WebhookClient> {
+		Arc::clone(self.webhook_client.get_or_init(|| {
+			Arc::new(WebhookClient::with_client(
+				self.http_client.clone().unwrap_or_default(),
+				self.worker
+					.webhook_concurrency
+					.unwrap_or(DEFAULT_WEBHOOK_CONCURRENCY),
+			))
+		}))
+	}
+
+	/// Get the proxy requests of each bulk job, shared by all tasks.
+	#[cfg(feature = "worker")]
+	pub fn get_proxy_budget(&self) -> Arc<ProxyBudget> {
+		Arc::clone(
+			self.proxy_budget
+				.get_or_init(|| Arc::new(ProxyBudget::new(self.worker.proxy_requests_per_job))),
+		)
+	}
 }
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct VerifMethodConfig {
 	/// Verification method for Gmail emails.
 	pub gmail: GmailVerifMethod,
@@ -154,7 +528,7 @@ pub struct VerifMethodConfig {
 	pub yahoo: YahooVerifMethod,
 }
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct WorkerConfig {
 	pub enable: bool,
 
@@ -163,7 +537,21 @@ pub struct WorkerConfig {
 	pub rabbitmq: Option<RabbitMQConfig>,
 	/// Optional webhook configuration to send email verification results.
 	#[cfg(feature = "worker")]
+	#[serde(serialize_with = "redact")]
 	pub webhook: Option<TaskWebhook>,
+	/// Maximum number of webhook requests in flight at the same time, across
+	/// all tasks. Defaults to DEFAULT_WEBHOOK_CONCURRENCY.
+	pub webhook_concurrency: Option<usize>,
+	/// Optional webhook notified when the worker enters or exits the
+	/// throttled state of `throttle`, with its current capacity usage.
+	#[cfg(feature = "worker")]
+	#[serde(serialize_with = "redact")]
+	pub throttle_webhook: Option<Webhook>,
+	/// Maximum number of connections each bulk job opens through the proxy,
+	/// SMTP or HTTP, tracked by each worker process. Once reached, the job's
+	/// remaining tasks fail instead of being verified, and the tasks in
+	/// progress may still go over it. None means unlimited.
+	pub proxy_requests_per_job: Option<u64>,
 	/// Postgres database configuration to store email verification
 	/// results.
 	pub postgres: Option<PostgresConfig>,
@@ -236,6 +624,18 @@ impl<'de> Deserialize<'de> for RabbitMQQueues {
 	}
 }
 
+impl Serialize for RabbitMQQueues {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		match self {
+			RabbitMQQueues::All => serializer.serialize_str("all"),
+			RabbitMQQueues::Only(queues) => queues.serialize(serializer),
+		}
+	}
+}
+
 impl RabbitMQQueues {
 	pub fn to_queues(&self) -> Vec<Queue> {
 		match self {
@@ -251,8 +651,9 @@ impl RabbitMQQueues {
 	}
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RabbitMQConfig {
+	#[serde(serialize_with = "redact_str")]
 	pub url: String,
 	/// Queues to consume emails from. By default the worker consumes from all
 	/// queues.
@@ -342,12 +743,22 @@ impl<'de> Deserialize<'de> for Queue {
 	}
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Serialize for Queue {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.collect_str(self)
+	}
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PostgresConfig {
+	#[serde(serialize_with = "redact_str")]
 	pub db_url: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ThrottleConfig {
 	pub max_requests_per_second: Option<u32>,
 	pub max_requests_per_minute: Option<u32>,
@@ -0,0 +1,144 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Refresh the list of disposable domains from a remote URL, see the
+//! `disposable_list_url` configuration.
+
+use anyhow::bail;
+use check_if_email_exists::misc::DisposableList;
+use check_if_email_exists::LOG_TARGET;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Fetch the list of disposable domains at this URL, with one domain per
+/// line, and replace the shared list with it. The domains are trimmed and
+/// lowercased, and empty lines and lines starting with '#' are ignored.
+/// Returns the number of domains.
+pub async fn refresh_disposable_list(
+	client: &reqwest::Client,
+	url: &str,
+	list: &DisposableList,
+) -> Result<usize, anyhow::Error> {
+	let body = client
+		.get(url)
+		.send()
+		.await?
+		.error_for_status()?
+		.text()
+		.await?;
+
+	let domains = body
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(str::to_lowercase)
+		.collect::<HashSet<_>>();
+	// An empty list is most likely an error on the server side.
+	if domains.is_empty() {
+		bail!("The list at {} is empty", url);
+	}
+
+	let count = domains.len();
+	list.replace(domains);
+	Ok(count)
+}
+
+/// Fetch the list of disposable domains with `client` now, and then at each
+/// interval if set. On failure, the last fetched list (or the bundled one)
+/// is kept.
+pub fn spawn_disposable_list_refresh(
+	client: reqwest::Client,
+	url: String,
+	interval: Option<Duration>,
+	list: Arc<DisposableList>,
+) -> JoinHandle<()> {
+	tokio::spawn(async move {
+		let mut ticker = interval.map(tokio::time::interval);
+		loop {
+			if let Some(ticker) = ticker.as_mut() {
+				// The first tick completes immediately.
+				ticker.tick().await;
+			}
+			match refresh_disposable_list(&client, &url, &list).await {
+				Ok(count) => {
+					info!(target: LOG_TARGET, url=?url, count=?count, "Refreshed disposable domains list")
+				}
+				Err(err) => {
+					warn!(target: LOG_TARGET, url=?url, err=?err, "Cannot refresh disposable domains list, keeping the previous one")
+				}
+			}
+			if ticker.is_none() {
+				break;
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+	use warp::http::StatusCode;
+	use warp::Filter;
+
+	#[tokio::test]
+	async fn should_refresh_list_on_tick() {
+		// The served list, or None to reply with an error.
+		let served = Arc::new(Mutex::new(Some(
+			"# Disposable domains\n\n  Foo.COM \r\n".to_string(),
+		)));
+		let route = {
+			let served = Arc::clone(&served);
+			warp::path("list").map(move || match served.lock().unwrap().clone() {
+				Some(body) => warp::reply::with_status(body, StatusCode::OK),
+				None => warp::reply::with_status(String::new(), StatusCode::INTERNAL_SERVER_ERROR),
+			})
+		};
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		let list = Arc::new(DisposableList::default());
+		let handle = spawn_disposable_list_refresh(
+			reqwest::Client::new(),
+			format!("http://{addr}/list"),
+			Some(Duration::from_millis(100)),
+			Arc::clone(&list),
+		);
+
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(
+			list.get().unwrap().as_ref(),
+			&HashSet::from(["foo.com".into()])
+		);
+
+		*served.lock().unwrap() = Some("bar.com\nbaz.com\n".into());
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert_eq!(
+			list.get().unwrap().as_ref(),
+			&HashSet::from(["bar.com".into(), "baz.com".into()])
+		);
+
+		// On failure, the last good list is kept.
+		*served.lock().unwrap() = None;
+		tokio::time::sleep(Duration::from_millis(150)).await;
+		assert_eq!(list.get().unwrap().len(), 2);
+
+		handle.abort();
+	}
+}
@@ -0,0 +1,304 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Extraction of the client IP of a request, for the per-IP limits and the
+//! logs. Behind reverse proxies, the connection comes from the last proxy,
+//! and the client IP is taken from the `Forwarded` or `X-Forwarded-For`
+//! headers, but only from the hops added by trusted proxies: the client can
+//! forge the rest of these headers.
+
+use check_if_email_exists::LOG_TARGET;
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use warp::http::HeaderMap;
+use warp::Filter;
+
+use crate::config::BackendConfig;
+
+/// The RFC 7239 header which holds the chain of forwarded hops.
+pub const FORWARDED_HEADER: &str = "forwarded";
+
+/// The de-facto standard header which holds the chain of forwarded hops.
+pub const X_FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// The networks of the reverse proxies whose forwarding headers are trusted.
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+	networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+	/// Parse the trusted proxies, each an IP or a CIDR range like
+	/// "10.0.0.0/8". Invalid entries are skipped with a warning.
+	pub fn new<S: AsRef<str>>(proxies: &[S]) -> Self {
+		let networks = proxies
+			.iter()
+			.filter_map(|proxy| {
+				let proxy = proxy.as_ref().trim();
+				let network = proxy
+					.parse::<IpNet>()
+					.or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from));
+				if network.is_err() {
+					tracing::warn!(target: LOG_TARGET, proxy, "Ignoring invalid trusted proxy");
+				}
+				network.ok()
+			})
+			.collect();
+
+		Self { networks }
+	}
+
+	/// Trust every proxy, i.e. take the farthest hop of the forwarding
+	/// headers as the client IP.
+	pub fn all() -> Self {
+		Self::new(&["0.0.0.0/0", "::/0"])
+	}
+
+	fn contains(&self, ip: &IpAddr) -> bool {
+		self.networks.iter().any(|network| network.contains(ip))
+	}
+}
+
+/// Parse a hop of the forwarding headers: an IP, optionally quoted, in
+/// brackets for IPv6, and with a port. None for the obfuscated identifiers
+/// and "unknown".
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+	let hop = hop.trim().trim_matches('"');
+	if let Some(rest) = hop.strip_prefix('[') {
+		return rest.split(']').next()?.parse().ok();
+	}
+	hop.parse().ok().or_else(|| {
+		// An IPv4 address with a port.
+		let (ip, _port) = hop.split_once(':')?;
+		ip.parse().ok()
+	})
+}
+
+/// The hops of the forwarding headers, from the farthest to the closest one.
+/// The `Forwarded` header takes precedence over `X-Forwarded-For`.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+	let values = |name: &str| {
+		headers
+			.get_all(name)
+			.iter()
+			.filter_map(|value| value.to_str().ok())
+			.flat_map(|value| value.split(','))
+			.map(str::to_string)
+			.collect::<Vec<_>>()
+	};
+
+	let forwarded = values(FORWARDED_HEADER);
+	if !forwarded.is_empty() {
+		return forwarded
+			.iter()
+			.map(|element| {
+				element.split(';').find_map(|pair| {
+					let (key, value) = pair.split_once('=')?;
+					key.trim()
+						.eq_ignore_ascii_case("for")
+						.then(|| parse_hop(value))?
+				})
+			})
+			.collect();
+	}
+
+	values(X_FORWARDED_FOR_HEADER)
+		.iter()
+		.map(|hop| parse_hop(hop))
+		.collect()
+}
+
+/// The client IP: the address of the connection, unless it's a trusted
+/// proxy, in which case we walk the forwarding headers back from the closest
+/// hop, until the first hop which isn't a trusted proxy. A hop we can't
+/// parse stops the walk at the last trusted proxy.
+pub fn client_ip(
+	remote: Option<SocketAddr>,
+	headers: &HeaderMap,
+	trusted_proxies: &TrustedProxies,
+) -> Option<IpAddr> {
+	let mut ip = remote?.ip();
+	for hop in forwarded_hops(headers).into_iter().rev() {
+		if !trusted_proxies.contains(&ip) {
+			break;
+		}
+		match hop {
+			Some(hop) => ip = hop,
+			None => break,
+		}
+	}
+
+	Some(ip)
+}
+
+/// Warp filter that extracts the client IP of the request, see `client_ip`,
+/// or None if it's unknown.
+pub fn with_client_ip(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+	warp::addr::remote()
+		.and(warp::header::headers_cloned())
+		.map(move |remote: Option<SocketAddr>, headers: HeaderMap| {
+			client_ip(remote, &headers, &config.get_trusted_proxies())
+		})
+}
+
+/// Warp access log, like `warp::log`, but with the client IP instead of the
+/// address of the connection.
+pub fn log_with_client_ip(
+	config: Arc<BackendConfig>,
+) -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone> {
+	warp::log::custom(move |info| {
+		let ip = client_ip(
+			info.remote_addr(),
+			info.request_headers(),
+			&config.get_trusted_proxies(),
+		);
+		tracing::info!(
+			target: LOG_TARGET,
+			"{} \"{} {} {:?}\" {} \"{}\" {:?}",
+			ip.map(|ip| ip.to_string()).unwrap_or_else(|| "-".into()),
+			info.method(),
+			info.path(),
+			info.version(),
+			info.status().as_u16(),
+			info.user_agent().unwrap_or("-"),
+			info.elapsed(),
+		);
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		for (name, value) in pairs {
+			headers.append(*name, value.parse().unwrap());
+		}
+		headers
+	}
+
+	#[test]
+	fn test_direct_connection() {
+		let remote = Some("1.2.3.4:5678".parse().unwrap());
+		let trusted = TrustedProxies::new(&["10.0.0.0/8"]);
+
+		assert_eq!(
+			client_ip(remote, &HeaderMap::new(), &trusted),
+			Some("1.2.3.4".parse().unwrap())
+		);
+		// A client which isn't a trusted proxy can't forge its IP.
+		let forged = headers(&[(X_FORWARDED_FOR_HEADER, "9.9.9.9")]);
+		assert_eq!(
+			client_ip(remote, &forged, &trusted),
+			Some("1.2.3.4".parse().unwrap())
+		);
+		assert_eq!(client_ip(None, &forged, &trusted), None);
+	}
+
+	#[test]
+	fn test_forwarded_chain() {
+		let remote = Some("10.0.0.1:5678".parse().unwrap());
+		let trusted = TrustedProxies::new(&["10.0.0.0/8", "192.168.1.1"]);
+
+		// The first hop before the trusted proxies is the client, whatever
+		// it forged before.
+		let chain = headers(&[(X_FORWARDED_FOR_HEADER, "9.9.9.9, 1.2.3.4, 192.168.1.1")]);
+		assert_eq!(
+			client_ip(remote, &chain, &trusted),
+			Some("1.2.3.4".parse().unwrap())
+		);
+		// Also across several headers.
+		let chain = headers(&[
+			(X_FORWARDED_FOR_HEADER, "9.9.9.9, 1.2.3.4"),
+			(X_FORWARDED_FOR_HEADER, "10.0.0.2"),
+		]);
+		assert_eq!(
+			client_ip(remote, &chain, &trusted),
+			Some("1.2.3.4".parse().unwrap())
+		);
+		// An unparseable hop stops at the last trusted proxy.
+		let chain = headers(&[(X_FORWARDED_FOR_HEADER, "1.2.3.4, garbage, 10.0.0.2")]);
+		assert_eq!(
+			client_ip(remote, &chain, &trusted),
+			Some("10.0.0.2".parse().unwrap())
+		);
+		// Only trusted proxies: the farthest hop is the client.
+		let chain = headers(&[(X_FORWARDED_FOR_HEADER, "10.0.0.3, 10.0.0.2")]);
+		assert_eq!(
+			client_ip(remote, &chain, &trusted),
+			Some("10.0.0.3".parse().unwrap())
+		);
+	}
+
+	#[test]
+	fn test_forwarded_header() {
+		let remote = Some("10.0.0.1:5678".parse().unwrap());
+		let trusted = TrustedProxies::new(&["10.0.0.0/8"]);
+
+		let chain = headers(&[
+			(
+				FORWARDED_HEADER,
+				r#"for=9.9.9.9, for="[2001:db8:cafe::17]:4711";proto=https, For=10.0.0.2:80"#,
+			),
+			// Ignored in favor of the Forwarded header.
+			(X_FORWARDED_FOR_HEADER, "1.2.3.4"),
+		]);
+		assert_eq!(
+			client_ip(remote, &chain, &trusted),
+			Some("2001:db8:cafe::17".parse().unwrap())
+		);
+
+		let obfuscated = headers(&[(FORWARDED_HEADER, "for=_hidden, for=10.0.0.2")]);
+		assert_eq!(
+			client_ip(remote, &obfuscated, &trusted),
+			Some("10.0.0.2".parse().unwrap())
+		);
+	}
+
+	#[test]
+	fn test_client_ip() {
+		let remote = Some("1.2.3.4:5678".parse().unwrap());
+		let forwarded_for = headers(&[(X_FORWARDED_FOR_HEADER, "9.9.9.9, 1.2.3.4")]);
+
+		assert_eq!(
+			client_ip(remote, &forwarded_for, &TrustedProxies::default()),
+			Some("1.2.3.4".parse().unwrap())
+		);
+		assert_eq!(
+			client_ip(remote, &forwarded_for, &TrustedProxies::all()),
+			Some("9.9.9.9".parse().unwrap())
+		);
+		assert_eq!(
+			client_ip(
+				remote,
+				&headers(&[(X_FORWARDED_FOR_HEADER, "garbage")]),
+				&TrustedProxies::all()
+			),
+			Some("1.2.3.4".parse().unwrap())
+		);
+	}
+
+	#[test]
+	fn test_invalid_trusted_proxies_are_skipped() {
+		let trusted = TrustedProxies::new(&["10.0.0.0/8", "not-an-ip", "::1"]);
+		assert_eq!(trusted.networks.len(), 2);
+	}
+}
@@ -0,0 +1,141 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Limit the number of in-flight requests per client IP, so that a single
+//! client can't monopolize the verifications of the HTTP endpoints. This is
+//! separate from the worker's throttle, which limits the verifications of
+//! the queue.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use warp::http::StatusCode;
+use warp::Filter;
+
+use super::client_ip::with_client_ip;
+use super::i18n::{with_lang, ErrorCode, Lang};
+use super::ReacherResponseError;
+use crate::config::BackendConfig;
+
+/// The number of in-flight requests per client IP.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+	max_per_ip: usize,
+	in_flight: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConcurrencyLimiter {
+	pub fn new(max_per_ip: usize) -> Self {
+		Self {
+			max_per_ip,
+			in_flight: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// Count a new in-flight request from this IP, until the returned guard
+	/// is dropped. Returns None if the IP already has the maximum number of
+	/// in-flight requests.
+	pub fn acquire(&self, ip: IpAddr) -> Option<ConcurrencyGuard> {
+		let mut in_flight = self
+			.in_flight
+			.lock()
+			.expect("ConcurrencyLimiter lock poisoned");
+		let count = in_flight.entry(ip).or_insert(0);
+		if *count >= self.max_per_ip {
+			return None;
+		}
+		*count += 1;
+
+		Some(ConcurrencyGuard {
+			ip,
+			in_flight: Arc::clone(&self.in_flight),
+		})
+	}
+}
+
+/// An in-flight request, counted until dropped.
+#[derive(Debug)]
+pub struct ConcurrencyGuard {
+	ip: IpAddr,
+	in_flight: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConcurrencyGuard {
+	fn drop(&mut self) {
+		let mut in_flight = self
+			.in_flight
+			.lock()
+			.expect("ConcurrencyLimiter lock poisoned");
+		if let Some(count) = in_flight.get_mut(&self.ip) {
+			*count -= 1;
+			// Don't keep an entry per IP ever seen.
+			if *count == 0 {
+				in_flight.remove(&self.ip);
+			}
+		}
+	}
+}
+
+/// Warp filter that rejects the request with a 429 if its client IP already
+/// has `max_concurrent_requests_per_ip` in-flight requests. Otherwise it
+/// extracts a guard, to hold until the response is ready. Without limit in
+/// the config, or if the client IP is unknown, it extracts None.
+pub fn with_concurrency_limit(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (Option<ConcurrencyGuard>,), Error = warp::Rejection> + Clone {
+	with_client_ip(Arc::clone(&config))
+		.and(with_lang())
+		.and_then(move |ip: Option<IpAddr>, lang: Lang| {
+			let config = Arc::clone(&config);
+			async move {
+				let Some(limiter) = config.get_concurrency_limiter() else {
+					return Ok(None);
+				};
+				let Some(ip) = ip else {
+					return Ok(None);
+				};
+
+				limiter.acquire(ip).map(Some).ok_or_else(|| {
+					warp::reject::custom(ReacherResponseError::localized(
+						StatusCode::TOO_MANY_REQUESTS,
+						ErrorCode::TooManyConcurrentRequests,
+						lang,
+					))
+				})
+			}
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_acquire_up_to_the_limit() {
+		let limiter = ConcurrencyLimiter::new(2);
+		let ip: IpAddr = "1.2.3.4".parse().unwrap();
+		let other_ip: IpAddr = "5.6.7.8".parse().unwrap();
+
+		let first = limiter.acquire(ip);
+		let second = limiter.acquire(ip);
+		assert!(first.is_some() && second.is_some());
+		assert!(limiter.acquire(ip).is_none());
+		assert!(limiter.acquire(other_ip).is_some());
+
+		drop(first);
+		assert!(limiter.acquire(ip).is_some());
+	}
+}
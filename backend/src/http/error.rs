@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use super::i18n::{message, ErrorCode, Lang};
 use check_if_email_exists::{CheckEmailInputBuilderError, LOG_TARGET};
 use serde::ser::SerializeStruct;
 use serde::Serialize;
@@ -32,6 +33,9 @@ impl<T: fmt::Display + Debug + Sync + Send> DisplayDebug for T {}
 pub struct ReacherResponseError {
 	pub code: StatusCode,
 	pub error: Box<dyn DisplayDebug>,
+	/// Machine-readable code of the error, if any. Unlike `error`, it does
+	/// not depend on the language of the request.
+	pub error_code: Option<ErrorCode>,
 }
 
 impl reject::Reject for ReacherResponseError {}
@@ -41,8 +45,13 @@ impl Serialize for ReacherResponseError {
 	where
 		S: serde::Serializer,
 	{
-		let mut state = serializer.serialize_struct("ReacherResponseError", 1)?;
+		let mut state = serializer.serialize_struct("ReacherResponseError", 2)?;
 		state.serialize_field("error", &self.error.to_string())?;
+		if let Some(error_code) = &self.error_code {
+			state.serialize_field("code", error_code)?;
+		} else {
+			state.skip_field("code")?;
+		}
 		state.end()
 	}
 }
@@ -58,16 +67,24 @@ impl ReacherResponseError {
 		Self {
 			code,
 			error: Box::new(error),
+			error_code: None,
+		}
+	}
+
+	/// Create an error with a machine-readable code, whose message is
+	/// translated in the given language.
+	pub fn localized(code: StatusCode, error_code: ErrorCode, lang: Lang) -> Self {
+		Self {
+			code,
+			error: Box::new(message(error_code, lang)),
+			error_code: Some(error_code),
 		}
 	}
 }
 
 impl From<CheckEmailInputBuilderError> for ReacherResponseError {
 	fn from(e: CheckEmailInputBuilderError) -> Self {
-		Self {
-			code: StatusCode::BAD_REQUEST,
-			error: Box::new(e),
-		}
+		ReacherResponseError::new(StatusCode::BAD_REQUEST, e)
 	}
 }
 
@@ -0,0 +1,163 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Localization of the human-readable error messages returned by the HTTP
+//! endpoints. The machine-readable `ErrorCode` stays the same whatever the
+//! language, only the message changes.
+
+use serde::Serialize;
+use std::convert::Infallible;
+use warp::Filter;
+
+/// Stable, machine-readable code of an error returned by the HTTP endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+	/// The `to_email` field of the request body is empty.
+	MissingToEmail,
+	/// The bulk request has no emails to verify.
+	EmptyInput,
+	/// None of the emails of the bulk request has a valid syntax.
+	NoValidEmail,
+	/// The client IP already has the maximum number of in-flight requests.
+	TooManyConcurrentRequests,
+	/// The bulk job has no Unknown result to verify again.
+	NoUnknownResult,
+}
+
+/// Languages for which we have translated error messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Lang {
+	#[default]
+	En,
+	Fr,
+	De,
+	Es,
+}
+
+impl Lang {
+	fn from_tag(tag: &str) -> Option<Self> {
+		// Only look at the primary subtag, i.e. "fr" in "fr-CH".
+		let primary = tag.split('-').next().unwrap_or_default();
+		match primary.trim().to_lowercase().as_str() {
+			"en" => Some(Self::En),
+			"fr" => Some(Self::Fr),
+			"de" => Some(Self::De),
+			"es" => Some(Self::Es),
+			_ => None,
+		}
+	}
+
+	/// Select the preferred supported language from the value of an
+	/// `Accept-Language` header, e.g. "fr-CH, fr;q=0.9, en;q=0.8". Defaults
+	/// to English.
+	pub fn from_accept_language(header: Option<&str>) -> Self {
+		let Some(header) = header else {
+			return Self::default();
+		};
+
+		let mut langs = header
+			.split(',')
+			.filter_map(|entry| {
+				let mut parts = entry.split(';');
+				let lang = Self::from_tag(parts.next()?)?;
+				let quality = parts
+					.find_map(|p| p.trim().strip_prefix("q="))
+					.and_then(|q| q.parse::<f32>().ok())
+					.unwrap_or(1.0);
+				Some((lang, quality))
+			})
+			.filter(|(_, quality)| *quality > 0.0)
+			.collect::<Vec<_>>();
+		// Stable sort, so that languages with the same quality keep the
+		// order given by the client.
+		langs.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+		langs.first().map(|(lang, _)| *lang).unwrap_or_default()
+	}
+}
+
+/// The message catalog, keyed by error code and language.
+pub fn message(code: ErrorCode, lang: Lang) -> &'static str {
+	match (code, lang) {
+		(ErrorCode::MissingToEmail, Lang::En) => "to_email field is required.",
+		(ErrorCode::MissingToEmail, Lang::Fr) => "Le champ to_email est obligatoire.",
+		(ErrorCode::MissingToEmail, Lang::De) => "Das Feld to_email ist erforderlich.",
+		(ErrorCode::MissingToEmail, Lang::Es) => "El campo to_email es obligatorio.",
+		(ErrorCode::EmptyInput, Lang::En) => "Empty input",
+		(ErrorCode::EmptyInput, Lang::Fr) => "Aucune adresse email fournie",
+		(ErrorCode::EmptyInput, Lang::De) => "Keine E-Mail-Adresse angegeben",
+		(ErrorCode::EmptyInput, Lang::Es) => "No se proporcionó ninguna dirección de correo",
+		(ErrorCode::NoValidEmail, Lang::En) => "None of the emails has a valid syntax",
+		(ErrorCode::NoValidEmail, Lang::Fr) => "Aucune adresse email n'a une syntaxe valide",
+		(ErrorCode::NoValidEmail, Lang::De) => "Keine der E-Mail-Adressen hat eine gültige Syntax",
+		(ErrorCode::NoValidEmail, Lang::Es) => {
+			"Ninguna de las direcciones de correo tiene una sintaxis válida"
+		}
+		(ErrorCode::TooManyConcurrentRequests, Lang::En) => {
+			"Too many concurrent requests from this IP"
+		}
+		(ErrorCode::TooManyConcurrentRequests, Lang::Fr) => {
+			"Trop de requêtes simultanées depuis cette adresse IP"
+		}
+		(ErrorCode::TooManyConcurrentRequests, Lang::De) => {
+			"Zu viele gleichzeitige Anfragen von dieser IP-Adresse"
+		}
+		(ErrorCode::TooManyConcurrentRequests, Lang::Es) => {
+			"Demasiadas solicitudes simultáneas desde esta dirección IP"
+		}
+		(ErrorCode::NoUnknownResult, Lang::En) => "The job has no Unknown result to retry",
+		(ErrorCode::NoUnknownResult, Lang::Fr) => {
+			"Le job n'a aucun résultat Unknown à vérifier de nouveau"
+		}
+		(ErrorCode::NoUnknownResult, Lang::De) => {
+			"Der Job hat kein Unknown-Ergebnis zum erneuten Prüfen"
+		}
+		(ErrorCode::NoUnknownResult, Lang::Es) => {
+			"El trabajo no tiene ningún resultado Unknown para reintentar"
+		}
+	}
+}
+
+/// Warp filter that extracts the language of the error messages from the
+/// `Accept-Language` header.
+pub fn with_lang() -> impl Filter<Extract = (Lang,), Error = Infallible> + Clone {
+	warp::header::optional::<String>("accept-language")
+		.map(|header: Option<String>| Lang::from_accept_language(header.as_deref()))
+		// The optional header filter only rejects on invalid header values,
+		// in which case we fall back to the default language.
+		.or(warp::any().map(Lang::default))
+		.unify()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Lang;
+
+	#[test]
+	fn test_from_accept_language() {
+		assert_eq!(Lang::from_accept_language(None), Lang::En);
+		assert_eq!(Lang::from_accept_language(Some("fr")), Lang::Fr);
+		assert_eq!(
+			Lang::from_accept_language(Some("fr-CH, en;q=0.8")),
+			Lang::Fr
+		);
+		assert_eq!(Lang::from_accept_language(Some("en;q=0.5, de")), Lang::De);
+		assert_eq!(Lang::from_accept_language(Some("ja, es;q=0.1")), Lang::Es);
+		assert_eq!(Lang::from_accept_language(Some("ja, *;q=0.5")), Lang::En);
+		assert_eq!(Lang::from_accept_language(Some("fr;q=0")), Lang::En);
+	}
+}
@@ -0,0 +1,244 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Support for the `Idempotency-Key` header: a request retried with the same
+//! key, e.g. after a client timeout, gets the response of the first request
+//! instead of running the verification again.
+
+use hashlink::LinkedHashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+use warp::http::StatusCode;
+use warp::Filter;
+
+use super::ReacherResponseError;
+
+/// The header which holds the idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// How long a response is kept for its idempotency key, by default.
+pub const DEFAULT_IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Maximum number of idempotency keys kept, by default.
+pub const DEFAULT_IDEMPOTENCY_MAX_KEYS: usize = 10_000;
+
+/// Interval between two removals of the expired keys, see
+/// `IdempotencyStore::spawn_pruning`.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Idempotency keys longer than this are rejected.
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Warp filter that extracts the idempotency key from the `Idempotency-Key`
+/// header, if any.
+pub fn with_idempotency_key() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone
+{
+	warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER)
+		// The optional header filter only rejects on invalid header values,
+		// in which case we ignore the header.
+		.or(warp::any().map(|| None))
+		.unify()
+}
+
+/// A request seen with an idempotency key.
+#[derive(Debug)]
+struct Entry {
+	created_at: Instant,
+	/// The request body, to reject a key reused for another request.
+	request: String,
+	/// The JSON response body, set once the first request with the key
+	/// completes.
+	response: Arc<OnceCell<String>>,
+}
+
+/// The responses of the requests with an idempotency key, kept in memory
+/// during a TTL. At most `max_keys` keys are kept: above it, the least
+/// recently used ones are evicted.
+#[derive(Debug)]
+pub struct IdempotencyStore {
+	ttl: Duration,
+	max_keys: usize,
+	/// The entries, from the least to the most recently used.
+	entries: Mutex<LinkedHashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+	pub fn new(ttl: Duration, max_keys: usize) -> Self {
+		Self {
+			ttl,
+			max_keys,
+			entries: Mutex::new(LinkedHashMap::new()),
+		}
+	}
+
+	/// Remove the expired keys every minute, in the background, until the
+	/// store is dropped. Without it, expired keys are only removed when
+	/// they're used again, or evicted.
+	pub fn spawn_pruning(self: &Arc<Self>) -> JoinHandle<()> {
+		let store = Arc::downgrade(self);
+		tokio::spawn(async move {
+			loop {
+				tokio::time::sleep(PRUNE_INTERVAL).await;
+				match store.upgrade() {
+					Some(store) => store.prune(),
+					None => return,
+				}
+			}
+		})
+	}
+
+	/// Remove the expired keys.
+	pub fn prune(&self) {
+		let now = Instant::now();
+		self.entries
+			.lock()
+			.expect("IdempotencyStore lock poisoned")
+			.retain(|_, entry| now.duration_since(entry.created_at) < self.ttl);
+	}
+
+	/// Return the response of the first request with this key, or run it if
+	/// there's none. Concurrent requests with the same key wait for the same
+	/// run. Fails if the key was used with another request body.
+	pub async fn get_or_run<F, Fut>(
+		&self,
+		key: String,
+		request: String,
+		run: F,
+	) -> Result<String, ReacherResponseError>
+	where
+		F: FnOnce() -> Fut,
+		Fut: Future<Output = String>,
+	{
+		if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+			return Err(ReacherResponseError::new(
+				StatusCode::BAD_REQUEST,
+				format!(
+					"Idempotency-Key must have between 1 and {MAX_IDEMPOTENCY_KEY_LEN} characters"
+				),
+			));
+		}
+
+		let response = {
+			let mut entries = self.entries.lock().expect("IdempotencyStore lock poisoned");
+			let now = Instant::now();
+			let expired = entries
+				.get(&key)
+				.is_some_and(|entry| now.duration_since(entry.created_at) >= self.ttl);
+			if expired {
+				entries.remove(&key);
+			}
+			if entries.to_back(&key).is_none() {
+				entries.insert(
+					key.clone(),
+					Entry {
+						created_at: now,
+						request: request.clone(),
+						response: Arc::new(OnceCell::new()),
+					},
+				);
+				while entries.len() > self.max_keys.max(1) {
+					entries.pop_front();
+				}
+			}
+			let entry = &entries[&key];
+			if entry.request != request {
+				return Err(ReacherResponseError::new(
+					StatusCode::UNPROCESSABLE_ENTITY,
+					"Idempotency-Key was already used with another request body",
+				));
+			}
+			Arc::clone(&entry.response)
+		};
+
+		Ok(response.get_or_init(run).await.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[tokio::test]
+	async fn should_run_once_per_key() {
+		let store = IdempotencyStore::new(Duration::from_secs(60), 10);
+		let runs = AtomicUsize::new(0);
+		let run = |n: u64| {
+			let runs = &runs;
+			move || async move {
+				runs.fetch_add(1, Ordering::SeqCst);
+				n.to_string()
+			}
+		};
+
+		let first = store.get_or_run("a".into(), "req".into(), run(1));
+		let second = store.get_or_run("a".into(), "req".into(), run(2));
+		let (first, second) = tokio::join!(first, second);
+		assert_eq!(first.unwrap(), second.unwrap());
+		assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+		let other = store.get_or_run("b".into(), "req".into(), run(3)).await;
+		assert_eq!(other.unwrap(), "3");
+		assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+		let err = store
+			.get_or_run("a".into(), "other".into(), run(4))
+			.await
+			.unwrap_err();
+		assert_eq!(err.code, StatusCode::UNPROCESSABLE_ENTITY);
+	}
+
+	#[tokio::test]
+	async fn should_expire_keys() {
+		let store = IdempotencyStore::new(Duration::ZERO, 10);
+		let first = store
+			.get_or_run("a".into(), "req".into(), || async { "1".to_string() })
+			.await;
+		let second = store
+			.get_or_run("a".into(), "req".into(), || async { "2".to_string() })
+			.await;
+		assert_eq!(first.unwrap(), "1");
+		assert_eq!(second.unwrap(), "2");
+
+		store.prune();
+		assert!(store.entries.lock().unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn should_evict_least_recently_used_keys() {
+		let store = IdempotencyStore::new(Duration::from_secs(60), 2);
+		let run = |key: &'static str, n: u64| {
+			store.get_or_run(
+				key.into(),
+				"req".into(),
+				move || async move { n.to_string() },
+			)
+		};
+
+		assert_eq!(run("a", 1).await.unwrap(), "1");
+		assert_eq!(run("b", 2).await.unwrap(), "2");
+		// "a" is used again, so "b" is the least recently used.
+		assert_eq!(run("a", 3).await.unwrap(), "1");
+		assert_eq!(run("c", 4).await.unwrap(), "4");
+		assert_eq!(store.entries.lock().unwrap().len(), 2);
+		assert_eq!(run("a", 5).await.unwrap(), "1");
+		assert_eq!(run("b", 6).await.unwrap(), "6");
+	}
+}
@@ -14,9 +14,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod client_ip;
+pub mod concurrency_limit;
 mod error;
+pub mod i18n;
+pub mod idempotency;
+pub mod request_id;
+pub mod result_cache;
 mod v0;
-#[cfg(feature = "worker")]
 mod v1;
 mod version;
 
@@ -46,7 +51,14 @@ pub fn create_routes(
 			pg_pool.clone(),
 		))
 		.or(v0::bulk::get::get_bulk_job_status(pg_pool.clone()))
-		.or(v0::bulk::results::get_bulk_job_result(pg_pool));
+		.or(v0::bulk::results::get_bulk_job_result(pg_pool))
+		.or(v1::check_email_stream::post_check_email_stream(Arc::clone(
+			&config,
+		)))
+		.or(v1::methods::v1_get_methods(Arc::clone(&config)))
+		.or(v1::mx::v1_get_mx(Arc::clone(&config)))
+		.or(v1::config::v1_get_config(Arc::clone(&config)))
+		.or(v1::config::v1_patch_config(Arc::clone(&config)));
 
 	#[cfg(feature = "worker")]
 	{
@@ -55,6 +67,12 @@ pub fn create_routes(
 			.or(v1::bulk::get_progress::v1_get_bulk_job_progress(
 				Arc::clone(&config),
 			))
+			.or(v1::bulk::get_progress::v1_get_bulk_job_progress_eta(
+				Arc::clone(&config),
+			))
+			.or(v1::bulk::retry_unknowns::v1_retry_bulk_job_unknowns(
+				Arc::clone(&config),
+			))
 			.or(v1::bulk::get_results::v1_get_bulk_job_results(config))
 			.recover(handle_rejection)
 	}
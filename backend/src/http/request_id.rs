@@ -0,0 +1,72 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Propagation of a request id, to correlate the reports of the clients with
+//! the logs. The id is taken from the `x-request-id` header of the request,
+//! or generated, and echoed back in the same header of the response.
+
+use check_if_email_exists::LOG_TARGET;
+use std::convert::Infallible;
+use std::future::Future;
+use tracing::Instrument;
+use warp::http::HeaderValue;
+use warp::{Filter, Rejection, Reply};
+
+use super::error::handle_rejection;
+
+/// The header which holds the request id.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Inbound request ids longer than this are replaced by a generated one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Warp filter that extracts the request id from the `x-request-id` header,
+/// or generates a new UUID if the header is absent or invalid.
+pub fn with_request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+	warp::header::optional::<String>(REQUEST_ID_HEADER)
+		.map(|header: Option<String>| {
+			header
+				.filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+				.unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+		})
+		// The optional header filter only rejects on invalid header values,
+		// in which case we generate an id.
+		.or(warp::any().map(|| uuid::Uuid::new_v4().to_string()))
+		.unify()
+}
+
+/// Run the handler inside a tracing span holding the request id, and set the
+/// `x-request-id` header on its response. Errors of the handler are turned
+/// into responses here, so that they carry the header too.
+pub async fn traced<F, R>(
+	request_id: String,
+	handler: F,
+) -> Result<warp::reply::Response, Rejection>
+where
+	F: Future<Output = Result<R, Rejection>>,
+	R: Reply,
+{
+	let span = tracing::info_span!(target: LOG_TARGET, "http_handler", request_id = %request_id);
+	let mut response = match handler.instrument(span).await {
+		Ok(reply) => reply.into_response(),
+		Err(rejection) => handle_rejection(rejection).await?.into_response(),
+	};
+
+	let value = HeaderValue::from_str(&request_id).expect("Request id is a valid header. qed.");
+	response.headers_mut().insert(REQUEST_ID_HEADER, value);
+
+	Ok(response)
+}
@@ -0,0 +1,226 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cache of the `POST /v0/check_email` outputs, so that verifying the same
+//! email again within a TTL returns the previous output instead of running
+//! a live verification. Outputs served from the cache have their
+//! `debug.from_cache` set, and `debug.cached_at` set to the time of the
+//! original verification.
+
+use check_if_email_exists::{CheckEmailInput, CheckEmailOutput, Reachable};
+use hashlink::LinkedHashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Maximum number of outputs cached, by default.
+pub const DEFAULT_RESULT_CACHE_MAX_ENTRIES: usize = 10_000;
+
+/// Interval between two removals of the expired outputs, see
+/// `ResultCache::spawn_pruning`.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The outputs of the previous verifications, kept in memory during a TTL.
+/// At most `max_entries` outputs are kept: above it, the least recently used
+/// ones are evicted.
+#[derive(Debug, Default)]
+pub struct ResultCache {
+	/// How long an output is reused. None disables the cache.
+	ttl: Option<Duration>,
+	max_entries: usize,
+	/// The JSON outputs, keyed by their normalized input, see `cache_key`,
+	/// from the least to the most recently used.
+	entries: Mutex<LinkedHashMap<String, (Instant, String)>>,
+}
+
+impl ResultCache {
+	/// Reuse the outputs for `ttl`, or never cache them if None.
+	pub fn new(ttl: Option<Duration>, max_entries: usize) -> Self {
+		Self {
+			ttl,
+			max_entries,
+			entries: Mutex::new(LinkedHashMap::new()),
+		}
+	}
+
+	/// Remove the expired outputs every minute, in the background, until
+	/// the cache is dropped. Without it, expired outputs are only removed
+	/// when they're requested again, or evicted.
+	pub fn spawn_pruning(self: &Arc<Self>) -> JoinHandle<()> {
+		let cache = Arc::downgrade(self);
+		tokio::spawn(async move {
+			loop {
+				tokio::time::sleep(PRUNE_INTERVAL).await;
+				match cache.upgrade() {
+					Some(cache) => cache.prune(),
+					None => return,
+				}
+			}
+		})
+	}
+
+	/// Remove the expired outputs.
+	pub fn prune(&self) {
+		let Some(ttl) = self.ttl else {
+			return;
+		};
+		let now = Instant::now();
+		self.entries
+			.lock()
+			.expect("ResultCache lock poisoned")
+			.retain(|_, (created_at, _)| now.duration_since(*created_at) < ttl);
+	}
+
+	/// The cached output of the input, if it hasn't expired, marked as
+	/// served from the cache.
+	pub fn get(&self, input: &CheckEmailInput) -> Option<String> {
+		let ttl = self.ttl?;
+		let key = cache_key(input);
+		let mut entries = self.entries.lock().expect("ResultCache lock poisoned");
+		let (created_at, _) = entries.to_back(&key)?;
+		if created_at.elapsed() >= ttl {
+			entries.remove(&key);
+			return None;
+		}
+
+		entries
+			.get(&key)
+			.map(|(_, output)| mark_from_cache(output, &input.to_email))
+	}
+
+	/// Cache the output of the input, if the cache is enabled. Outputs whose
+	/// `is_reachable` is "unknown" aren't cached, as they're often due to
+	/// transient errors.
+	pub fn insert(&self, input: &CheckEmailInput, output: &CheckEmailOutput) {
+		if self.ttl.is_none() || output.is_reachable == Reachable::Unknown {
+			return;
+		}
+		let output = serde_json::to_string(output).expect("CheckEmailOutput is serializable. qed.");
+		let mut entries = self.entries.lock().expect("ResultCache lock poisoned");
+		entries.insert(cache_key(input), (Instant::now(), output));
+		while entries.len() > self.max_entries.max(1) {
+			entries.pop_front();
+		}
+	}
+}
+
+/// The key of the input in the cache: the JSON input, with its email trimmed
+/// and lowercased, so that inputs differing only by these share their output.
+/// The JSON objects have sorted keys, so that the key doesn't depend on the
+/// order of the input's maps.
+fn cache_key(input: &CheckEmailInput) -> String {
+	let input = CheckEmailInput {
+		to_email: input.to_email.trim().to_lowercase(),
+		..input.clone()
+	};
+	serde_json::to_value(&input)
+		.expect("CheckEmailInput is serializable. qed.")
+		.to_string()
+}
+
+/// Set `debug.from_cache` in the JSON output, and `debug.cached_at` to the
+/// end time of its verification. Its `input` is set to `to_email`, which may
+/// differ from the cached one, see `cache_key`.
+fn mark_from_cache(output: &str, to_email: &str) -> String {
+	let Ok(mut value) = serde_json::from_str::<serde_json::Value>(output) else {
+		return output.to_string();
+	};
+	if let Some(object) = value.as_object_mut() {
+		object.insert("input".into(), to_email.into());
+	}
+	if let Some(debug) = value.get_mut("debug").and_then(|d| d.as_object_mut()) {
+		let cached_at = debug.get("end_time").cloned().unwrap_or_default();
+		debug.insert("from_cache".into(), true.into());
+		debug.insert("cached_at".into(), cached_at);
+	}
+	value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::types::chrono::{TimeZone, Utc};
+
+	fn output(is_reachable: Reachable) -> CheckEmailOutput {
+		let mut output = CheckEmailOutput {
+			is_reachable,
+			..Default::default()
+		};
+		output.debug.end_time = Utc.with_ymd_and_hms(2026, 10, 15, 9, 30, 0).unwrap();
+		output
+	}
+
+	fn input(to_email: &str) -> CheckEmailInput {
+		CheckEmailInput {
+			to_email: to_email.into(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn should_mark_cached_outputs() {
+		let cache = ResultCache::new(Some(Duration::from_secs(60)), 10);
+		assert_eq!(cache.get(&input("foo@example.com")), None);
+
+		cache.insert(&input("foo@example.com"), &output(Reachable::Invalid));
+		let cached = serde_json::from_str::<serde_json::Value>(
+			&cache.get(&input(" Foo@Example.com")).unwrap(),
+		)
+		.unwrap();
+		assert_eq!(cached["input"], " Foo@Example.com");
+		assert_eq!(cached["debug"]["from_cache"], true);
+		assert_eq!(cached["debug"]["cached_at"], "2026-10-15T09:30:00Z");
+		assert_eq!(cache.get(&input("bar@example.com")), None);
+		let other_options = CheckEmailInput {
+			smtp_port: 587,
+			..input("foo@example.com")
+		};
+		assert_eq!(cache.get(&other_options), None);
+	}
+
+	#[test]
+	fn should_not_cache_unknown_outputs_or_without_ttl() {
+		let cache = ResultCache::new(Some(Duration::from_secs(60)), 10);
+		cache.insert(&input("foo@example.com"), &output(Reachable::Unknown));
+		assert_eq!(cache.get(&input("foo@example.com")), None);
+
+		let cache = ResultCache::default();
+		cache.insert(&input("foo@example.com"), &output(Reachable::Invalid));
+		assert_eq!(cache.get(&input("foo@example.com")), None);
+	}
+
+	#[test]
+	fn should_evict_least_recently_used_outputs() {
+		let cache = ResultCache::new(Some(Duration::from_secs(60)), 2);
+		cache.insert(&input("a@example.com"), &output(Reachable::Invalid));
+		cache.insert(&input("b@example.com"), &output(Reachable::Invalid));
+		// "a" is used again, so "b" is the least recently used.
+		assert!(cache.get(&input("a@example.com")).is_some());
+		cache.insert(&input("c@example.com"), &output(Reachable::Invalid));
+		assert!(cache.get(&input("a@example.com")).is_some());
+		assert!(cache.get(&input("b@example.com")).is_none());
+		assert!(cache.get(&input("c@example.com")).is_some());
+	}
+
+	#[test]
+	fn should_prune_expired_outputs() {
+		let cache = ResultCache::new(Some(Duration::ZERO), 10);
+		cache.insert(&input("foo@example.com"), &output(Reachable::Invalid));
+		assert_eq!(cache.entries.lock().unwrap().len(), 1);
+		cache.prune();
+		assert!(cache.entries.lock().unwrap().is_empty());
+	}
+}
@@ -17,19 +17,31 @@
 //! This file implements the `POST /bulk` endpoint.
 
 use check_if_email_exists::{
-	check_email, config::ReacherConfig, CheckEmailInput, CheckEmailInputBuilder,
-	CheckEmailInputProxy, CheckEmailOutput, Reachable, SentryConfig, LOG_TARGET,
+	check_email, config::ReacherConfig, mx::create_resolver, smtp::WebDriverPool, CheckEmailInput,
+	CheckEmailInputBuilder, CheckEmailInputProxy, CheckEmailOutput, Reachable, SentryConfig,
+	LOG_TARGET,
 };
+use hickory_resolver::TokioAsyncResolver;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use sqlxmq::{job, CurrentJob};
 use std::env;
 use std::error::Error;
+use std::sync::{Arc, LazyLock};
 use tracing::{debug, error};
 use uuid::Uuid;
 
 use super::error::BulkError;
 
+/// DNS resolver shared by all the legacy bulk tasks, see the comment in
+/// `email_verification_task` on why we don't get it from the BackendConfig.
+static LEGACY_RESOLVER: LazyLock<Arc<TokioAsyncResolver>> =
+	LazyLock::new(|| Arc::new(create_resolver()));
+
+/// WebDriver sessions shared by all the legacy bulk tasks, for the same
+/// reason.
+static LEGACY_WEBDRIVER_POOL: LazyLock<Arc<WebDriverPool>> = LazyLock::new(Default::default);
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TaskInput {
 	// fields for CheckEmailInput
@@ -173,9 +185,24 @@ pub async fn email_verification_task(
 		let config = ReacherConfig {
 			backend_name: backend_name.clone(),
 			webdriver_addr,
+			webdriver_pool: Arc::clone(&LEGACY_WEBDRIVER_POOL),
+			headless: Default::default(),
 			sentry: sentry_dsn
 				.ok()
 				.map(|dsn| SentryConfig { dsn, backend_name }),
+			resolver: Arc::clone(&LEGACY_RESOLVER),
+			// The legacy bulk tasks don't use the adaptive timeout.
+			smtp_latencies: Default::default(),
+			egress_monitor: Default::default(),
+			catch_all_cache: Default::default(),
+			limiter: Default::default(),
+			smtp_pool: Default::default(),
+			disposable_list: Default::default(),
+			breach_providers: Default::default(),
+			post_classify: None,
+			http_client: None,
+			canned_results: None,
+			on_proxy_connection: None,
 		};
 
 		let to_email = check_email_input.to_email.clone();
@@ -17,13 +17,20 @@
 //! This file implements the `POST /v0/check_email` endpoint.
 
 use check_if_email_exists::{
-	check_email, CheckEmailInput, CheckEmailInputProxy, GmailVerifMethod, LOG_TARGET,
+	check_email, CheckEmailInput, CheckEmailInputProxy, GmailVerifMethod, HotmailB2BVerifMethod,
+	HotmailB2CVerifMethod, YahooVerifMethod,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use warp::http::header::CONTENT_TYPE;
 use warp::{http, Filter};
 
 use crate::config::BackendConfig;
+use crate::http::client_ip::log_with_client_ip;
+use crate::http::concurrency_limit::with_concurrency_limit;
+use crate::http::i18n::{with_lang, ErrorCode, Lang};
+use crate::http::idempotency::with_idempotency_key;
+use crate::http::request_id::{traced, with_request_id};
 use crate::http::{check_header, ReacherResponseError};
 
 /// The request body for the `POST /v0/check_email` endpoint.
@@ -32,28 +39,43 @@ pub struct CheckEmailRequest {
 	pub to_email: String,
 	pub from_email: Option<String>,
 	pub hello_name: Option<String>,
+	/// Override the verification methods of the backend config, e.g. "api"
+	/// or "smtp", see `GET /v1/methods`.
 	pub gmail_verif_method: Option<GmailVerifMethod>,
-	pub hotmailb2b_verif_method: Option<String>,
-	pub hotmailb2c_verif_method: Option<String>,
-	pub yahoo_verif_method: Option<String>,
+	pub hotmailb2b_verif_method: Option<HotmailB2BVerifMethod>,
+	pub hotmailb2c_verif_method: Option<HotmailB2CVerifMethod>,
+	pub yahoo_verif_method: Option<YahooVerifMethod>,
 	pub proxy: Option<CheckEmailInputProxy>,
+	/// Record the SMTP dialog in the output. For bulk tasks, it's stored in
+	/// its own column of the results table.
+	#[serde(default)]
+	pub smtp_transcript: bool,
 }
 
 impl CheckEmailRequest {
+	/// Create the input to `check_email`. The fields of the request body
+	/// take precedence over the backend config.
 	pub fn to_check_email_input(&self, config: Arc<BackendConfig>) -> CheckEmailInput {
+		// The default verification methods can be changed at runtime.
+		let verif_method = &config.get_runtime_config().verif_method;
 		CheckEmailInput {
 			to_email: self.to_email.clone(),
 			from_email: self.from_email.clone().unwrap_or(config.from_email.clone()),
 			hello_name: self.hello_name.clone().unwrap_or(config.hello_name.clone()),
-			gmail_verif_method: config.verif_method.gmail,
-			hotmailb2b_verif_method: config.verif_method.hotmailb2b,
-			hotmailb2c_verif_method: config.verif_method.hotmailb2c,
-			yahoo_verif_method: config.verif_method.yahoo,
+			gmail_verif_method: self.gmail_verif_method.unwrap_or(verif_method.gmail),
+			hotmailb2b_verif_method: self
+				.hotmailb2b_verif_method
+				.unwrap_or(verif_method.hotmailb2b),
+			hotmailb2c_verif_method: self
+				.hotmailb2c_verif_method
+				.unwrap_or(verif_method.hotmailb2c),
+			yahoo_verif_method: self.yahoo_verif_method.unwrap_or(verif_method.yahoo),
 			proxy: self
 				.proxy
 				.as_ref()
 				.or_else(|| config.proxy.as_ref())
 				.cloned(),
+			smtp_transcript: self.smtp_transcript,
 			..Default::default()
 		}
 	}
@@ -62,22 +84,45 @@ impl CheckEmailRequest {
 /// The main endpoint handler that implements the logic of this route.
 async fn http_handler(
 	config: Arc<BackendConfig>,
+	lang: Lang,
+	idempotency_key: Option<String>,
 	body: CheckEmailRequest,
 ) -> Result<impl warp::Reply, warp::Rejection> {
 	// The to_email field must be present
 	if body.to_email.is_empty() {
-		Err(
-			ReacherResponseError::new(http::StatusCode::BAD_REQUEST, "to_email field is required.")
-				.into(),
+		Err(ReacherResponseError::localized(
+			http::StatusCode::BAD_REQUEST,
+			ErrorCode::MissingToEmail,
+			lang,
 		)
+		.into())
 	} else {
-		// Run the future to check an email.
-		Ok(warp::reply::json(
-			&check_email(
-				&body.to_check_email_input(Arc::clone(&config)),
-				&config.get_reacher_config(),
-			)
-			.await,
+		let input = body.to_check_email_input(Arc::clone(&config));
+		let request = serde_json::to_string(&body).map_err(ReacherResponseError::from)?;
+		let verify = || async {
+			let result_cache = config.get_result_cache();
+			if let Some(output) = result_cache.get(&input) {
+				return output;
+			}
+			let output = check_email(&input, &config.get_reacher_config()).await;
+			result_cache.insert(&input, &output);
+			serde_json::to_string(&output).expect("CheckEmailOutput is serializable. qed.")
+		};
+		// Run the future to check an email, unless a request with the same
+		// idempotency key already did.
+		let output = match idempotency_key {
+			Some(key) => {
+				config
+					.get_idempotency_store()
+					.get_or_run(key, request.clone(), verify)
+					.await?
+			}
+			None => verify().await,
+		};
+		Ok(warp::reply::with_header(
+			output,
+			CONTENT_TYPE,
+			"application/json",
 		))
 	}
 }
@@ -89,19 +134,72 @@ pub fn post_check_email<'a>(
 	warp::path!("v0" / "check_email")
 		.and(warp::post())
 		.and(check_header(Arc::clone(&config)))
-		.and(with_config(config))
+		.and(with_concurrency_limit(Arc::clone(&config)))
+		.and(with_config(Arc::clone(&config)))
+		.and(with_lang())
+		.and(with_request_id())
+		.and(with_idempotency_key())
 		// When accepting a body, we want a JSON body (and to reject huge
 		// payloads)...
 		.and(warp::body::content_length_limit(1024 * 16))
 		.and(warp::body::json())
-		.and_then(http_handler)
+		.and_then(
+			|guard, config, lang, request_id, idempotency_key, body| async move {
+				let reply = traced(
+					request_id,
+					http_handler(config, lang, idempotency_key, body),
+				)
+				.await;
+				// The request is in-flight until the verification is done.
+				drop(guard);
+				reply
+			},
+		)
 		// View access logs by setting `RUST_LOG=reacher`.
-		.with(warp::log(LOG_TARGET))
+		.with(log_with_client_ip(config))
 }
 
 /// Warp filter that adds the BackendConfig to the handler.
-fn with_config(
+pub(crate) fn with_config(
 	config: Arc<BackendConfig>,
 ) -> impl Filter<Extract = (Arc<BackendConfig>,), Error = std::convert::Infallible> + Clone {
 	warp::any().map(move || Arc::clone(&config))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::CheckEmailRequest;
+	use crate::config::BackendConfig;
+	use check_if_email_exists::{GmailVerifMethod, YahooVerifMethod};
+	use std::sync::Arc;
+
+	#[test]
+	fn test_verif_method_overrides_config() {
+		let mut config = BackendConfig::default();
+		config.verif_method.gmail = GmailVerifMethod::Smtp;
+		config.verif_method.yahoo = YahooVerifMethod::Headless;
+		let config = Arc::new(config);
+
+		let request = serde_json::from_str::<CheckEmailRequest>(
+			r#"{"to_email": "foo@gmail.com", "gmail_verif_method": "Api"}"#,
+		)
+		.unwrap();
+		let input = request.to_check_email_input(Arc::clone(&config));
+
+		assert!(matches!(input.gmail_verif_method, GmailVerifMethod::Api));
+		// Absent from the request, so we fall back to the config.
+		assert_eq!(input.yahoo_verif_method, YahooVerifMethod::Headless);
+
+		// The fallback is the config changed at runtime.
+		config
+			.update_runtime_config(|current| {
+				let mut runtime_config = current.clone();
+				runtime_config.verif_method.yahoo = YahooVerifMethod::Smtp;
+				Ok::<_, ()>(runtime_config)
+			})
+			.unwrap();
+		let input = request.to_check_email_input(Arc::clone(&config));
+		assert!(matches!(input.gmail_verif_method, GmailVerifMethod::Api));
+		assert_eq!(input.yahoo_verif_method, YahooVerifMethod::Smtp);
+	}
+}
@@ -14,7 +14,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-//! This file implements the `GET /bulk/{id}` endpoint.
+//! This file implements the `GET /v1/bulk/{id}` and
+//! `GET /v1/bulk/{id}/progress` endpoints.
 
 use std::sync::Arc;
 
@@ -27,6 +28,7 @@ use warp::Filter;
 
 use crate::config::BackendConfig;
 use crate::http::{with_db, ReacherResponseError};
+use crate::worker::check_email::JobProxyUsage;
 
 /// NOTE: Type conversions from postgres to rust types
 /// are according to the table given by
@@ -69,9 +71,44 @@ struct Response {
 	total_processed: i32,
 	summary: ResponseSummary,
 	job_status: ValidStatus,
+	/// Connections the job opened through the proxy, as tracked by this
+	/// backend's worker when `worker.proxy_requests_per_job` is set.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	proxy_usage: Option<JobProxyUsage>,
 }
 
-async fn http_handler(job_id: i32, conn_pool: PgPool) -> Result<impl warp::Reply, warp::Rejection> {
+/// Counts of the verification tasks of a job which completed so far.
+#[derive(Debug)]
+struct TaskAggregate {
+	total_processed: Option<i64>,
+	safe_count: Option<i64>,
+	risky_count: Option<i64>,
+	invalid_count: Option<i64>,
+	unknown_count: Option<i64>,
+	finished_at: Option<DateTime<Utc>>,
+}
+
+impl TaskAggregate {
+	fn total_processed(&self) -> i32 {
+		self.total_processed
+			.expect("sql COUNT returns an int. qed.") as i32
+	}
+
+	fn summary(&self) -> ResponseSummary {
+		ResponseSummary {
+			total_safe: self.safe_count.expect("sql COUNT returns an int. qed.") as i32,
+			total_risky: self.risky_count.expect("sql COUNT returns an int. qed.") as i32,
+			total_invalid: self.invalid_count.expect("sql COUNT returns an int. qed.") as i32,
+			total_unknown: self.unknown_count.expect("sql COUNT returns an int. qed.") as i32,
+		}
+	}
+}
+
+/// Fetch the job, and the counts of its tasks which completed so far.
+async fn fetch_job(
+	job_id: i32,
+	conn_pool: &PgPool,
+) -> Result<(JobRecord, TaskAggregate), ReacherResponseError> {
 	let job_rec = sqlx::query_as!(
 		JobRecord,
 		r#"
@@ -81,11 +118,12 @@ async fn http_handler(job_id: i32, conn_pool: PgPool) -> Result<impl warp::Reply
 		"#,
 		job_id
 	)
-	.fetch_one(&conn_pool)
+	.fetch_one(conn_pool)
 	.await
 	.map_err(|e| ReacherResponseError::new(StatusCode::BAD_REQUEST, e))?;
 
-	let agg_info = sqlx::query!(
+	let agg_info = sqlx::query_as!(
+		TaskAggregate,
 		r#"
 		SELECT
 			COUNT(*) as total_processed,
@@ -99,15 +137,21 @@ async fn http_handler(job_id: i32, conn_pool: PgPool) -> Result<impl warp::Reply
 		"#,
 		job_id
 	)
-	.fetch_one(&conn_pool)
+	.fetch_one(conn_pool)
 	.await
 	.map_err(ReacherResponseError::from)?;
 
-	let (job_status, finished_at) = if (agg_info
-		.total_processed
-		.expect("sql COUNT() returns an int. qed.") as i32)
-		< job_rec.total_records
-	{
+	Ok((job_rec, agg_info))
+}
+
+async fn http_handler(
+	job_id: i32,
+	conn_pool: PgPool,
+	config: Arc<BackendConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let (job_rec, agg_info) = fetch_job(job_id, &conn_pool).await?;
+
+	let (job_status, finished_at) = if agg_info.total_processed() < job_rec.total_records {
 		(ValidStatus::Running, None)
 	} else {
 		(
@@ -125,32 +169,153 @@ async fn http_handler(job_id: i32, conn_pool: PgPool) -> Result<impl warp::Reply
 		created_at: job_rec.created_at,
 		finished_at,
 		total_records: job_rec.total_records,
-		total_processed: agg_info
-			.total_processed
-			.expect("sql COUNT returns an int. qed.") as i32,
-		summary: ResponseSummary {
-			total_safe: agg_info.safe_count.expect("sql COUNT returns an int. qed.") as i32,
-			total_risky: agg_info
-				.risky_count
-				.expect("sql COUNT returns an int. qed.") as i32,
-			total_invalid: agg_info
-				.invalid_count
-				.expect("sql COUNT returns an int. qed.") as i32,
-			total_unknown: agg_info
-				.unknown_count
-				.expect("sql COUNT returns an int. qed.") as i32,
-		},
+		total_processed: agg_info.total_processed(),
+		summary: agg_info.summary(),
 		job_status,
+		proxy_usage: config.get_proxy_budget().usage(job_id),
 	}))
 }
 
+/// Number of most recent task results used to compute the completion rate of
+/// a job.
+const RECENT_TASKS: i64 = 100;
+
+/// Progress of a bulk verification job, for progress bars.
+#[derive(Debug, Serialize)]
+struct ProgressResponse {
+	job_id: i32,
+	total_records: i32,
+	total_processed: i32,
+	/// Share of the tasks completed, from 0 to 100.
+	percent: f64,
+	summary: ResponseSummary,
+	/// Estimated number of seconds until the job completes, from the recent
+	/// completion rate. None if the job is completed, or if no task
+	/// completed yet.
+	eta_seconds: Option<u64>,
+}
+
+async fn job_progress(
+	job_id: i32,
+	conn_pool: &PgPool,
+) -> Result<ProgressResponse, ReacherResponseError> {
+	let (job_rec, agg_info) = fetch_job(job_id, conn_pool).await?;
+	let total_processed = agg_info.total_processed();
+	let remaining = (job_rec.total_records - total_processed).max(0);
+
+	// The rate is computed over the most recent tasks, up to now, so that
+	// the ETA grows if the workers stop processing the job.
+	let recent = sqlx::query!(
+		r#"
+		SELECT
+			COUNT(*) as count,
+			MIN(created_at) as oldest
+		FROM (
+			SELECT created_at FROM v1_task_result
+			WHERE job_id = $1
+			ORDER BY created_at DESC
+			LIMIT $2
+		) recent
+		"#,
+		job_id,
+		RECENT_TASKS
+	)
+	.fetch_one(conn_pool)
+	.await
+	.map_err(ReacherResponseError::from)?;
+	let eta_seconds = match (remaining, recent.count, recent.oldest) {
+		(0, _, _) => None,
+		(_, Some(count), Some(oldest)) if count > 0 => {
+			let elapsed = (Utc::now() - oldest).num_milliseconds().max(0) as f64 / 1000.0;
+			Some((remaining as f64 * elapsed / count as f64).ceil() as u64)
+		}
+		_ => None,
+	};
+
+	let percent = if job_rec.total_records > 0 {
+		(total_processed.min(job_rec.total_records) as f64 * 100.0) / job_rec.total_records as f64
+	} else {
+		100.0
+	};
+
+	Ok(ProgressResponse {
+		job_id: job_rec.id,
+		total_records: job_rec.total_records,
+		total_processed,
+		percent,
+		summary: agg_info.summary(),
+		eta_seconds,
+	})
+}
+
+async fn progress_handler(
+	job_id: i32,
+	conn_pool: PgPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	Ok(warp::reply::json(&job_progress(job_id, &conn_pool).await?))
+}
+
 pub fn v1_get_bulk_job_progress(
 	config: Arc<BackendConfig>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
 	warp::path!("v1" / "bulk" / i32)
 		.and(warp::get())
 		.and(with_db(config.get_pg_pool()))
+		.and(warp::any().map(move || Arc::clone(&config)))
 		.and_then(http_handler)
 		// View access logs by setting `RUST_LOG=reacher`.
 		.with(warp::log(LOG_TARGET))
 }
+
+/// Progress percentage, tallies and ETA of a bulk verification job.
+pub fn v1_get_bulk_job_progress_eta(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+	warp::path!("v1" / "bulk" / i32 / "progress")
+		.and(warp::get())
+		.and(with_db(config.get_pg_pool()))
+		.and_then(progress_handler)
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(warp::log(LOG_TARGET))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[tokio::test]
+	#[ignore = "Set DATABASE_URL to a migrated Postgres database to test this"]
+	async fn should_report_progress_percentage() {
+		let pg_pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+			.await
+			.unwrap();
+		let job_id: i32 =
+			sqlx::query_scalar("INSERT INTO v1_bulk_job (total_records) VALUES (4) RETURNING id")
+				.fetch_one(&pg_pool)
+				.await
+				.unwrap();
+		for is_reachable in ["safe", "invalid"] {
+			sqlx::query(
+				"INSERT INTO v1_task_result (job_id, payload, backend_name, result) VALUES ($1, $2, 'test', $3)",
+			)
+			.bind(job_id)
+			.bind(json!({}))
+			.bind(json!({ "is_reachable": is_reachable }))
+			.execute(&pg_pool)
+			.await
+			.unwrap();
+		}
+
+		let progress = job_progress(job_id, &pg_pool).await.unwrap();
+
+		assert_eq!(progress.total_records, 4);
+		assert_eq!(progress.total_processed, 2);
+		assert_eq!(progress.percent, 50.0);
+		assert_eq!(progress.summary.total_safe, 1);
+		assert_eq!(progress.summary.total_invalid, 1);
+		assert_eq!(progress.summary.total_risky, 0);
+		assert_eq!(progress.summary.total_unknown, 0);
+		assert!(progress.eta_seconds.is_some());
+	}
+}
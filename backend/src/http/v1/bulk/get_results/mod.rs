@@ -39,6 +39,17 @@ enum ResponseFormat {
 	Csv,
 }
 
+/// Defines the order of the results, passed in as a query param.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ResultsSort {
+	/// In the order the emails were verified.
+	#[default]
+	Id,
+	/// Grouped by the domain of the email, then by `is_reachable`.
+	Domain,
+}
+
 // limit and offset are optional in the request
 // If unspecified, offset will default to 0.
 #[derive(Serialize, Deserialize)]
@@ -46,6 +57,7 @@ struct Request {
 	format: Option<ResponseFormat>,
 	limit: Option<u64>,
 	offset: Option<u64>,
+	sort: Option<ResultsSort>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -87,9 +99,11 @@ async fn http_handler(
 	}
 
 	let format = req.format.unwrap_or(ResponseFormat::Json);
+	let sort = req.sort.unwrap_or_default();
 	match format {
 		ResponseFormat::Json => {
-			let data = job_result_json(job_id, req.limit, req.offset.unwrap_or(0), pg_pool).await?;
+			let data =
+				job_result_json(job_id, req.limit, req.offset.unwrap_or(0), sort, pg_pool).await?;
 
 			let reply = serde_json::to_vec(&Response { results: data })
 				.map_err(ReacherResponseError::from)?;
@@ -101,7 +115,8 @@ async fn http_handler(
 			))
 		}
 		ResponseFormat::Csv => {
-			let data = job_result_csv(job_id, req.limit, req.offset.unwrap_or(0), pg_pool).await?;
+			let data =
+				job_result_csv(job_id, req.limit, req.offset.unwrap_or(0), sort, pg_pool).await?;
 
 			Ok(warp::reply::with_header(data, "Content-Type", "text/csv"))
 		}
@@ -112,18 +127,25 @@ async fn job_result_as_iter(
 	job_id: i32,
 	limit: Option<u64>,
 	offset: u64,
+	sort: ResultsSort,
 	pg_pool: PgPool,
 ) -> Result<Box<dyn Iterator<Item = serde_json::Value>>, ReacherResponseError> {
+	// When sorting by domain, the domain is the part of the payload's email
+	// after the last "@".
 	let query = sqlx::query!(
 		r#"
 		SELECT result FROM v1_task_result
 		WHERE job_id = $1
-		ORDER BY id
+		ORDER BY
+			CASE WHEN $4 THEN lower(substring(payload->'input'->>'to_email' FROM '@([^@]*)$')) END,
+			CASE WHEN $4 THEN result->>'is_reachable' END,
+			id
 		LIMIT $2 OFFSET $3
 		"#,
 		job_id,
 		limit.map(|l| l as i64),
-		offset as i64
+		offset as i64,
+		sort == ResultsSort::Domain
 	);
 
 	let rows = pg_pool
@@ -141,13 +163,14 @@ async fn job_result_json(
 	job_id: i32,
 	limit: Option<u64>,
 	offset: u64,
+	sort: ResultsSort,
 	pg_pool: PgPool,
 ) -> Result<Vec<serde_json::Value>, warp::Rejection> {
 	// For JSON responses, we don't want ot return more than 50 results at a
 	// time, to avoid having a too big payload (unless client specifies a limit)
 
 	Ok(
-		job_result_as_iter(job_id, limit.or(Some(50)), offset, pg_pool)
+		job_result_as_iter(job_id, limit.or(Some(50)), offset, sort, pg_pool)
 			.await?
 			.collect(),
 	)
@@ -157,9 +180,10 @@ async fn job_result_csv(
 	job_id: i32,
 	limit: Option<u64>,
 	offset: u64,
+	sort: ResultsSort,
 	pg_pool: PgPool,
 ) -> Result<Vec<u8>, warp::Rejection> {
-	let rows = job_result_as_iter(job_id, limit, offset, pg_pool).await?;
+	let rows = job_result_as_iter(job_id, limit, offset, sort, pg_pool).await?;
 	let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
 
 	for json_value in rows {
@@ -186,3 +210,60 @@ pub fn v1_get_bulk_job_results(
 		// View access logs by setting `RUST_LOG=reacher_backend`.
 		.with(warp::log(LOG_TARGET))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[tokio::test]
+	#[ignore = "Set DATABASE_URL to a migrated Postgres database to test this"]
+	async fn should_group_results_by_domain() {
+		let pg_pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+			.await
+			.unwrap();
+		let job_id: i32 =
+			sqlx::query_scalar("INSERT INTO v1_bulk_job (total_records) VALUES (4) RETURNING id")
+				.fetch_one(&pg_pool)
+				.await
+				.unwrap();
+		for (email, is_reachable) in [
+			("a@foo.com", "safe"),
+			("b@bar.com", "risky"),
+			("c@FOO.com", "invalid"),
+			("d@bar.com", "invalid"),
+		] {
+			sqlx::query(
+				"INSERT INTO v1_task_result (job_id, payload, backend_name, result) VALUES ($1, $2, 'test', $3)",
+			)
+			.bind(job_id)
+			.bind(json!({ "input": { "to_email": email } }))
+			.bind(json!({ "input": email, "is_reachable": is_reachable }))
+			.execute(&pg_pool)
+			.await
+			.unwrap();
+		}
+		let emails = |results: Vec<serde_json::Value>| {
+			results
+				.into_iter()
+				.map(|r| r["input"].as_str().unwrap().to_string())
+				.collect::<Vec<_>>()
+		};
+
+		let results = job_result_json(job_id, None, 0, ResultsSort::Id, pg_pool.clone())
+			.await
+			.unwrap();
+		assert_eq!(
+			emails(results),
+			["a@foo.com", "b@bar.com", "c@FOO.com", "d@bar.com"]
+		);
+
+		let results = job_result_json(job_id, None, 0, ResultsSort::Domain, pg_pool)
+			.await
+			.unwrap();
+		assert_eq!(
+			emails(results),
+			["d@bar.com", "b@bar.com", "c@FOO.com", "a@foo.com"]
+		);
+	}
+}
@@ -17,3 +17,4 @@
 pub mod get_progress;
 pub mod get_results;
 pub mod post;
+pub mod retry_unknowns;
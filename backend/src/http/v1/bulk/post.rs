@@ -18,6 +18,7 @@
 
 use std::sync::Arc;
 
+use check_if_email_exists::syntax::check_syntax;
 use check_if_email_exists::LOG_TARGET;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
@@ -31,6 +32,7 @@ use warp::Filter;
 
 use crate::config::BackendConfig;
 use crate::http::check_header;
+use crate::http::i18n::{with_lang, ErrorCode, Lang};
 use crate::http::v1::with_channel;
 use crate::http::with_db;
 use crate::http::CheckEmailRequest;
@@ -45,21 +47,80 @@ const PREPROCESS_QUEUE: &str = "preprocess";
 struct Request {
 	input: Vec<String>,
 	webhook: Option<TaskWebhook>,
+	/// Store the SMTP transcript of each task with its result.
+	#[serde(default)]
+	smtp_transcript: bool,
 }
 
 /// POST v1/bulk endpoint response body.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct Response {
 	job_id: i32,
+	/// Number of emails accepted into the job.
+	total_accepted: usize,
+	/// Rows of the input which were not added to the job.
+	rejected: Vec<RejectedRow>,
+}
+
+/// Why a row of the input was not added to the job.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RejectReason {
+	InvalidSyntax,
+}
+
+/// A row of the input which was not added to the job.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct RejectedRow {
+	/// Index of the row in the input.
+	index: usize,
+	input: String,
+	reason: RejectReason,
+}
+
+/// Split the input into the emails with a valid syntax, which will be
+/// verified, and the rejected rows.
+fn validate_input(input: &[String]) -> (Vec<&String>, Vec<RejectedRow>) {
+	let mut accepted = Vec::with_capacity(input.len());
+	let mut rejected = Vec::new();
+	for (index, email) in input.iter().enumerate() {
+		if check_syntax(email).is_valid_syntax {
+			accepted.push(email);
+		} else {
+			rejected.push(RejectedRow {
+				index,
+				input: email.clone(),
+				reason: RejectReason::InvalidSyntax,
+			});
+		}
+	}
+
+	(accepted, rejected)
 }
 
 async fn http_handler(
 	channel: Arc<Channel>,
 	pg_pool: PgPool,
+	lang: Lang,
 	body: Request,
 ) -> Result<impl warp::Reply, warp::Rejection> {
 	if body.input.is_empty() {
-		return Err(ReacherResponseError::new(StatusCode::BAD_REQUEST, "Empty input").into());
+		return Err(ReacherResponseError::localized(
+			StatusCode::BAD_REQUEST,
+			ErrorCode::EmptyInput,
+			lang,
+		)
+		.into());
+	}
+
+	let (accepted, rejected) = validate_input(&body.input);
+	if accepted.is_empty() {
+		return Err(ReacherResponseError::localized(
+			StatusCode::BAD_REQUEST,
+			ErrorCode::NoValidEmail,
+			lang,
+		)
+		.into());
 	}
 
 	// create job entry
@@ -69,13 +130,13 @@ async fn http_handler(
 		VALUES ($1)
 		RETURNING id
 		"#,
-		body.input.len() as i32
+		accepted.len() as i32
 	)
 	.fetch_one(&pg_pool)
 	.await
 	.map_err(ReacherResponseError::from)?;
 
-	let payloads = body.input.iter().map(|email| {
+	let payloads = accepted.into_iter().map(|email| {
 		let input = CheckEmailRequest {
 			to_email: email.clone(),
 			from_email: None,
@@ -85,12 +146,14 @@ async fn http_handler(
 			hotmailb2b_verif_method: None,
 			hotmailb2c_verif_method: None,
 			yahoo_verif_method: None,
+			smtp_transcript: body.smtp_transcript,
 		};
 
 		Ok(PreprocessTask {
 			input,
 			job_id: Some(rec.id),
 			webhook: body.webhook.clone(),
+			check_email_input: None,
 		})
 	});
 	let payloads = payloads.collect::<Result<Vec<_>, ReacherResponseError>>()?;
@@ -114,7 +177,11 @@ async fn http_handler(
 		queue = PREPROCESS_QUEUE,
 		"Added {n} emails to the queue",
 	);
-	Ok(warp::reply::json(&Response { job_id: rec.id }))
+	Ok(warp::reply::json(&Response {
+		job_id: rec.id,
+		total_accepted: n,
+		rejected,
+	}))
 }
 
 /// Publish a task to the "preprocess" queue.
@@ -155,6 +222,7 @@ pub fn v1_create_bulk_job(
 		.and(check_header(Arc::clone(&config)))
 		.and(with_channel(config.get_preprocess_channel()))
 		.and(with_db(config.get_pg_pool()))
+		.and(with_lang())
 		// When accepting a body, we want a JSON body (and to reject huge
 		// payloads)...
 		// TODO: Configure max size limit for a bulk job
@@ -164,3 +232,28 @@ pub fn v1_create_bulk_job(
 		// View access logs by setting `RUST_LOG=reacher_backend`.
 		.with(warp::log(LOG_TARGET))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{validate_input, RejectReason, RejectedRow};
+
+	#[test]
+	fn test_validate_input() {
+		let input = vec![
+			"foo@bar.com".to_string(),
+			"not-an-email".to_string(),
+			"baz@bar.com".to_string(),
+		];
+		let (accepted, rejected) = validate_input(&input);
+
+		assert_eq!(accepted, vec!["foo@bar.com", "baz@bar.com"]);
+		assert_eq!(
+			rejected,
+			vec![RejectedRow {
+				index: 1,
+				input: "not-an-email".into(),
+				reason: RejectReason::InvalidSyntax,
+			}]
+		);
+	}
+}
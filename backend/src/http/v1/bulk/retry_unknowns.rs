@@ -0,0 +1,225 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `POST /v1/bulk/{id}/retry-unknowns` endpoint.
+
+use std::sync::Arc;
+
+use check_if_email_exists::LOG_TARGET;
+use futures::stream::StreamExt;
+use futures::stream::TryStreamExt;
+use lapin::{BasicProperties, Channel};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::info;
+use warp::http::StatusCode;
+use warp::Filter;
+
+use super::post::publish_task;
+use crate::config::BackendConfig;
+use crate::http::check_header;
+use crate::http::i18n::{with_lang, ErrorCode, Lang};
+use crate::http::v1::with_channel;
+use crate::http::with_db;
+use crate::http::CheckEmailRequest;
+use crate::http::ReacherResponseError;
+use crate::worker::check_email::CheckEmailTask;
+use crate::worker::preprocess::PreprocessTask;
+
+/// POST v1/bulk/{id}/retry-unknowns endpoint response body.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Response {
+	/// The new job verifying the Unknown emails again.
+	job_id: i32,
+	/// Number of emails added to the new job.
+	total_accepted: usize,
+}
+
+/// Create a new job for the emails of `job_id` whose result is Unknown, and
+/// return its id with the tasks to publish. Returns None if there's no
+/// Unknown result.
+async fn create_retry_job(
+	job_id: i32,
+	pg_pool: &PgPool,
+) -> Result<Option<(i32, Vec<PreprocessTask>)>, ReacherResponseError> {
+	let rows = sqlx::query!(
+		r#"
+		SELECT payload
+		FROM v1_task_result
+		WHERE job_id = $1 AND result ->> 'is_reachable' = 'unknown'
+		ORDER BY id
+		"#,
+		job_id
+	)
+	.fetch_all(pg_pool)
+	.await
+	.map_err(ReacherResponseError::from)?;
+	if rows.is_empty() {
+		return Ok(None);
+	}
+
+	let rec = sqlx::query!(
+		r#"
+		INSERT INTO v1_bulk_job (total_records)
+		VALUES ($1)
+		RETURNING id
+		"#,
+		rows.len() as i32
+	)
+	.fetch_one(pg_pool)
+	.await
+	.map_err(ReacherResponseError::from)?;
+
+	// The retries keep the input and the webhook of the original tasks.
+	let tasks = rows
+		.into_iter()
+		.map(|row| {
+			let task: CheckEmailTask = serde_json::from_value(row.payload)?;
+			Ok(PreprocessTask {
+				input: CheckEmailRequest {
+					to_email: task.input.to_email.clone(),
+					from_email: None,
+					hello_name: None,
+					proxy: None,
+					gmail_verif_method: None,
+					hotmailb2b_verif_method: None,
+					hotmailb2c_verif_method: None,
+					yahoo_verif_method: None,
+					smtp_transcript: task.input.smtp_transcript,
+				},
+				job_id: Some(rec.id),
+				webhook: task.webhook,
+				check_email_input: Some(task.input),
+			})
+		})
+		.collect::<Result<Vec<_>, ReacherResponseError>>()?;
+
+	Ok(Some((rec.id, tasks)))
+}
+
+async fn http_handler(
+	job_id: i32,
+	channel: Arc<Channel>,
+	pg_pool: PgPool,
+	lang: Lang,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	let Some((retry_job_id, tasks)) = create_retry_job(job_id, &pg_pool).await? else {
+		return Err(ReacherResponseError::localized(
+			StatusCode::BAD_REQUEST,
+			ErrorCode::NoUnknownResult,
+			lang,
+		)
+		.into());
+	};
+
+	let n = tasks.len();
+	let properties = BasicProperties::default()
+		.with_content_type("application/json".into())
+		.with_priority(1);
+
+	futures::stream::iter(tasks)
+		.map::<Result<_, ReacherResponseError>, _>(Ok)
+		.try_for_each_concurrent(10, |payload| async {
+			publish_task(Arc::clone(&channel), payload, properties.clone()).await
+		})
+		.await?;
+
+	info!(
+		target: LOG_TARGET,
+		job_id, retry_job_id, "Retrying {n} Unknown emails",
+	);
+	Ok(warp::reply::json(&Response {
+		job_id: retry_job_id,
+		total_accepted: n,
+	}))
+}
+
+/// Create the `POST /v1/bulk/{id}/retry-unknowns` endpoint.
+/// The endpoint creates a new job to verify again the emails of the job
+/// whose result is Unknown, which is often transient.
+pub fn v1_retry_bulk_job_unknowns(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+	warp::path!("v1" / "bulk" / i32 / "retry-unknowns")
+		.and(warp::post())
+		.and(check_header(Arc::clone(&config)))
+		.and(with_channel(config.get_preprocess_channel()))
+		.and(with_db(config.get_pg_pool()))
+		.and(with_lang())
+		.and_then(http_handler)
+		// View access logs by setting `RUST_LOG=reacher_backend`.
+		.with(warp::log(LOG_TARGET))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use check_if_email_exists::CheckEmailInputBuilder;
+	use serde_json::json;
+
+	#[tokio::test]
+	#[ignore = "Set DATABASE_URL to a migrated Postgres database to test this"]
+	async fn should_retry_only_unknowns() {
+		let pg_pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+			.await
+			.unwrap();
+		let job_id: i32 =
+			sqlx::query_scalar("INSERT INTO v1_bulk_job (total_records) VALUES (2) RETURNING id")
+				.fetch_one(&pg_pool)
+				.await
+				.unwrap();
+		for (email, is_reachable) in [("a@foo.com", "unknown"), ("b@foo.com", "safe")] {
+			let task = CheckEmailTask {
+				input: CheckEmailInputBuilder::default()
+					.to_email(email.into())
+					.from_email("me@bar.com".into())
+					.smtp_transcript(true)
+					.build()
+					.unwrap(),
+				job_id: Some(job_id),
+				webhook: None,
+			};
+			sqlx::query(
+				"INSERT INTO v1_task_result (job_id, payload, backend_name, result) VALUES ($1, $2, 'test', $3)",
+			)
+			.bind(job_id)
+			.bind(serde_json::to_value(&task).unwrap())
+			.bind(json!({ "input": email, "is_reachable": is_reachable }))
+			.execute(&pg_pool)
+			.await
+			.unwrap();
+		}
+
+		let (retry_job_id, tasks) = create_retry_job(job_id, &pg_pool).await.unwrap().unwrap();
+
+		assert_ne!(retry_job_id, job_id);
+		assert_eq!(tasks.len(), 1);
+		assert_eq!(tasks[0].input.to_email, "a@foo.com");
+		assert!(tasks[0].input.smtp_transcript);
+		// The retry reuses the full input of the original task.
+		let input = tasks[0].check_email_input.as_ref().unwrap();
+		assert_eq!(input.from_email, "me@bar.com");
+		assert!(input.smtp_transcript);
+		assert_eq!(tasks[0].job_id, Some(retry_job_id));
+		let total_records: i32 =
+			sqlx::query_scalar("SELECT total_records FROM v1_bulk_job WHERE id = $1")
+				.bind(retry_job_id)
+				.fetch_one(&pg_pool)
+				.await
+				.unwrap();
+		assert_eq!(total_records, 1);
+	}
+}
@@ -16,7 +16,6 @@
 
 //! This file implements the `POST /v1/check_email` endpoint.
 
-use check_if_email_exists::LOG_TARGET;
 use futures::StreamExt;
 use lapin::options::{
 	BasicAckOptions, BasicConsumeOptions, BasicRejectOptions, QueueDeclareOptions,
@@ -28,6 +27,8 @@ use warp::http::StatusCode;
 use warp::{http, Filter};
 
 use crate::config::BackendConfig;
+use crate::http::client_ip::log_with_client_ip;
+use crate::http::i18n::{with_lang, ErrorCode, Lang};
 use crate::http::v0::check_email::post::CheckEmailRequest;
 use crate::http::v1::bulk::post::publish_task;
 use crate::http::v1::with_channel;
@@ -39,13 +40,15 @@ use crate::worker::response::SingleShotReply;
 /// The main endpoint handler that implements the logic of this route.
 async fn http_handler(
 	channel: Arc<Channel>,
+	lang: Lang,
 	body: CheckEmailRequest,
 ) -> Result<impl warp::Reply, warp::Rejection> {
 	// The to_email field must be present
 	if body.to_email.is_empty() {
-		return Err(ReacherResponseError::new(
+		return Err(ReacherResponseError::localized(
 			http::StatusCode::BAD_REQUEST,
-			"to_email field is required.",
+			ErrorCode::MissingToEmail,
+			lang,
 		)
 		.into());
 	}
@@ -79,6 +82,7 @@ async fn http_handler(
 			input: body,
 			job_id: None,
 			webhook: None,
+			check_email_input: None,
 		},
 		properties,
 	)
@@ -157,11 +161,12 @@ pub fn v1_check_email(
 		.and(warp::post())
 		.and(check_header(Arc::clone(&config)))
 		.and(with_channel(config.get_preprocess_channel()))
+		.and(with_lang())
 		// When accepting a body, we want a JSON body (and to reject huge
 		// payloads)...
 		.and(warp::body::content_length_limit(1024 * 16))
 		.and(warp::body::json())
 		.and_then(http_handler)
 		// View access logs by setting `RUST_LOG=reacher`.
-		.with(warp::log(LOG_TARGET))
+		.with(log_with_client_ip(config))
 }
@@ -0,0 +1,108 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `POST /v1/check_email_stream` endpoint.
+//!
+//! Like `POST /v0/check_email`, the verifications run directly in the HTTP
+//! handler, so this endpoint doesn't need a worker. The results are streamed
+//! back as newline-delimited JSON, in the order in which they complete.
+
+use check_if_email_exists::{check_email, CheckEmailInput};
+use futures::{stream, StreamExt};
+use std::sync::Arc;
+use warp::hyper::Body;
+use warp::{http, Filter};
+
+use crate::config::BackendConfig;
+use crate::http::client_ip::log_with_client_ip;
+use crate::http::i18n::{with_lang, ErrorCode, Lang};
+use crate::http::v0::check_email::post::with_config;
+use crate::http::{check_header, CheckEmailRequest, ReacherResponseError};
+
+/// Maximum number of emails verified concurrently for one request.
+const MAX_CONCURRENCY: usize = 10;
+
+/// The main endpoint handler that implements the logic of this route.
+async fn http_handler(
+	config: Arc<BackendConfig>,
+	lang: Lang,
+	body: Vec<CheckEmailRequest>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	if body.is_empty() {
+		return Err(ReacherResponseError::localized(
+			http::StatusCode::BAD_REQUEST,
+			ErrorCode::EmptyInput,
+			lang,
+		)
+		.into());
+	}
+
+	// Validate all the inputs before starting to stream, so that errors
+	// can still be returned with a proper status code.
+	let inputs = body
+		.iter()
+		.map(|request| {
+			if request.to_email.is_empty() {
+				return Err(ReacherResponseError::localized(
+					http::StatusCode::BAD_REQUEST,
+					ErrorCode::MissingToEmail,
+					lang,
+				));
+			}
+			Ok(request.to_check_email_input(Arc::clone(&config)))
+		})
+		.collect::<Result<Vec<CheckEmailInput>, _>>()?;
+
+	let reacher_config = Arc::new(config.get_reacher_config());
+	// The stream is lazy: hyper drops it when the client disconnects, which
+	// also drops, and thus cancels, the verifications still in progress.
+	let lines = stream::iter(inputs)
+		.map(move |input| {
+			let reacher_config = Arc::clone(&reacher_config);
+			async move { check_email(&input, &reacher_config).await }
+		})
+		.buffer_unordered(MAX_CONCURRENCY)
+		.map(|output| {
+			serde_json::to_vec(&output).map(|mut line| {
+				line.push(b'\n');
+				line
+			})
+		});
+
+	Ok(warp::reply::with_header(
+		warp::reply::Response::new(Body::wrap_stream(lines)),
+		"Content-Type",
+		"application/x-ndjson",
+	))
+}
+
+/// Create the `POST /v1/check_email_stream` endpoint.
+pub fn post_check_email_stream<'a>(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone + 'a {
+	warp::path!("v1" / "check_email_stream")
+		.and(warp::post())
+		.and(check_header(Arc::clone(&config)))
+		.and(with_config(Arc::clone(&config)))
+		.and(with_lang())
+		// When accepting a body, we want a JSON body (and to reject huge
+		// payloads)...
+		.and(warp::body::content_length_limit(1024 * 256))
+		.and(warp::body::json())
+		.and_then(http_handler)
+		// View access logs by setting `RUST_LOG=reacher`.
+		.with(log_with_client_ip(config))
+}
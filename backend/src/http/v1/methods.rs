@@ -0,0 +1,157 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v1/methods` endpoint, which lists the
+//! verification methods of each provider, e.g. for a UI to build its
+//! dropdowns from. It doesn't need the worker.
+
+use check_if_email_exists::{
+	GmailVerifMethod, HotmailB2BVerifMethod, HotmailB2CVerifMethod, ProviderVerifMethod,
+	YahooVerifMethod, LOG_TARGET,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::config::BackendConfig;
+use crate::http::check_header;
+
+/// The response of the `GET /v1/methods` endpoint. Providers are named like
+/// in the `verif_method` config, e.g. "hotmailb2c".
+#[derive(Debug, Default, Serialize)]
+struct MethodsResponse {
+	/// The methods of each provider, as accepted in the requests'
+	/// `*_verif_method` fields.
+	methods: BTreeMap<&'static str, Vec<&'static str>>,
+	/// The method used for each provider when the request doesn't specify
+	/// one, i.e. the backend's `verif_method` config, as changed at runtime
+	/// by `PATCH /v1/config`.
+	defaults: BTreeMap<&'static str, &'static str>,
+}
+
+impl MethodsResponse {
+	fn add<M: ProviderVerifMethod>(&mut self, provider: &'static str, default: M) {
+		self.methods
+			.insert(provider, M::ALL.iter().map(|m| m.as_str()).collect());
+		self.defaults.insert(provider, default.as_str());
+	}
+}
+
+async fn handler(config: Arc<BackendConfig>) -> Result<impl warp::Reply, warp::Rejection> {
+	let verif_method = &config.get_runtime_config().verif_method;
+	let mut response = MethodsResponse::default();
+	response.add::<GmailVerifMethod>("gmail", verif_method.gmail);
+	response.add::<HotmailB2BVerifMethod>("hotmailb2b", verif_method.hotmailb2b);
+	response.add::<HotmailB2CVerifMethod>("hotmailb2c", verif_method.hotmailb2c);
+	response.add::<YahooVerifMethod>("yahoo", verif_method.yahoo);
+
+	Ok(warp::reply::json(&response))
+}
+
+/// Create the `GET /v1/methods` endpoint.
+pub fn v1_get_methods(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+	warp::path!("v1" / "methods")
+		.and(warp::get())
+		.and(check_header(Arc::clone(&config)))
+		.and(warp::any().map(move || Arc::clone(&config)))
+		.and_then(handler)
+		// View access logs by setting `RUST_LOG=reacher_backend`.
+		.with(warp::log(LOG_TARGET))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::error::handle_rejection;
+	use crate::http::v0::check_email::post::post_check_email;
+	use serde_json::{json, Value};
+	use warp::http::StatusCode;
+	use warp::test::request;
+
+	#[tokio::test]
+	async fn should_list_methods_per_provider() {
+		let resp = request()
+			.path("/v1/methods")
+			.method("GET")
+			.reply(&v1_get_methods(Arc::new(BackendConfig::default())).recover(handle_rejection))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["methods"]["yahoo"], json!(["api", "headless", "smtp"]));
+		assert_eq!(body["methods"]["gmail"], json!(["api", "smtp"]));
+		assert_eq!(body["defaults"]["yahoo"], "headless");
+		assert_eq!(body["defaults"]["gmail"], "smtp");
+	}
+
+	#[tokio::test]
+	async fn should_return_runtime_defaults() {
+		let config = Arc::new(BackendConfig::default());
+		config
+			.update_runtime_config(|current| {
+				let mut runtime_config = current.clone();
+				runtime_config.verif_method.gmail = GmailVerifMethod::Api;
+				Ok::<_, ()>(runtime_config)
+			})
+			.unwrap();
+		let resp = request()
+			.path("/v1/methods")
+			.method("GET")
+			.reply(&v1_get_methods(config).recover(handle_rejection))
+			.await;
+
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["defaults"]["gmail"], "api");
+	}
+
+	#[tokio::test]
+	async fn should_list_methods_accepted_by_check_email() {
+		let config = Arc::new(BackendConfig::default());
+		let resp = request()
+			.path("/v1/methods")
+			.method("GET")
+			.reply(&v1_get_methods(Arc::clone(&config)))
+			.await;
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+
+		let check_email = post_check_email(config).recover(handle_rejection);
+		for (provider, methods) in body["methods"].as_object().unwrap() {
+			for method in methods.as_array().unwrap() {
+				// An invalid email, so that nothing is verified on the network.
+				let resp = request()
+					.path("/v0/check_email")
+					.method("POST")
+					.json(&json!({
+						"to_email": "foo",
+						format!("{}_verif_method", provider): method,
+					}))
+					.reply(&check_email)
+					.await;
+				assert_eq!(
+					resp.status(),
+					StatusCode::OK,
+					"{} {}: {:?}",
+					provider,
+					method,
+					resp.body()
+				);
+			}
+		}
+	}
+}
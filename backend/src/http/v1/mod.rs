@@ -14,18 +14,32 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(feature = "worker")]
 use lapin::Channel;
+#[cfg(feature = "worker")]
 use std::sync::Arc;
+#[cfg(feature = "worker")]
 use warp::http::StatusCode;
+#[cfg(feature = "worker")]
 use warp::Filter;
 
+#[cfg(feature = "worker")]
 use super::ReacherResponseError;
 
+// The endpoints below need the worker, except `GET /v1/methods`,
+// `GET /v1/mx/{domain}`, `POST /v1/check_email_stream` and `/v1/config`.
+#[cfg(feature = "worker")]
 pub mod bulk;
+#[cfg(feature = "worker")]
 pub mod check_email;
+pub mod check_email_stream;
+pub mod config;
+pub mod methods;
+pub mod mx;
 
 /// Warp filter that extracts lapin Channel, or returns a 503 error if it's not
 /// available.
+#[cfg(feature = "worker")]
 pub fn with_channel(
 	channel: Option<Arc<Channel>>,
 ) -> impl Filter<Extract = (Arc<Channel>,), Error = warp::Rejection> + Clone {
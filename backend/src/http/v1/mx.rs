@@ -0,0 +1,152 @@
+// Reacher - Email Verification
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! This file implements the `GET /v1/mx/{domain}` endpoint, which only looks
+//! up the MX records of a domain and the mail provider behind them, without
+//! any SMTP connection. It doesn't need the worker.
+
+use check_if_email_exists::mx::check_mx;
+use check_if_email_exists::syntax::check_syntax;
+use check_if_email_exists::LOG_TARGET;
+use std::sync::Arc;
+use warp::http::header::CACHE_CONTROL;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+use crate::config::BackendConfig;
+use crate::http::{check_header, ReacherResponseError};
+
+async fn handler(
+	domain: String,
+	config: Arc<BackendConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+	// Reuse the email syntax check to validate the domain.
+	let syntax = check_syntax(&format!("postmaster@{}", domain));
+	if !syntax.is_valid_syntax {
+		return Err(ReacherResponseError::new(
+			StatusCode::BAD_REQUEST,
+			format!("Invalid domain: {}", domain),
+		)
+		.into());
+	}
+
+	let mx = check_mx(&syntax, &config.get_reacher_config())
+		.await
+		.map_err(|e| ReacherResponseError::new(StatusCode::BAD_GATEWAY, e.to_string()))?;
+
+	// The response is valid as long as the MX records are.
+	let mut response = warp::reply::json(&mx).into_response();
+	if let Some(ttl) = mx.ttl {
+		if let Ok(value) = format!("public, max-age={}", ttl).parse() {
+			response.headers_mut().insert(CACHE_CONTROL, value);
+		}
+	}
+
+	Ok(response)
+}
+
+/// Create the `GET /v1/mx/{domain}` endpoint, which returns the domain's MX
+/// details, including the `mx_provider`, e.g. "Gmail".
+pub fn v1_get_mx(
+	config: Arc<BackendConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+	warp::path!("v1" / "mx" / String)
+		.and(warp::get())
+		.and(check_header(Arc::clone(&config)))
+		.and(warp::any().map(move || Arc::clone(&config)))
+		.and_then(handler)
+		// View access logs by setting `RUST_LOG=reacher_backend`.
+		.with(warp::log(LOG_TARGET))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::http::error::handle_rejection;
+	use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+	use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+	use hickory_resolver::proto::rr::{rdata::MX, Name, RData, Record};
+	use hickory_resolver::TokioAsyncResolver;
+	use serde_json::Value;
+	use std::str::FromStr;
+	use tokio::net::UdpSocket;
+	use warp::test::request;
+
+	/// Start a DNS server answering all queries with this MX record, and
+	/// return a resolver talking to it.
+	async fn mock_resolver(exchange: &'static str) -> TokioAsyncResolver {
+		let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let addr = socket.local_addr().unwrap();
+		tokio::spawn(async move {
+			let mut buf = vec![0; 4096];
+			while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+				let Ok(query) = Message::from_vec(&buf[..n]) else {
+					continue;
+				};
+				let mut response = Message::new();
+				response
+					.set_id(query.id())
+					.set_message_type(MessageType::Response)
+					.set_recursion_available(true)
+					.set_response_code(ResponseCode::NoError)
+					.add_queries(query.queries().to_vec())
+					.add_answer(Record::from_rdata(
+						query.queries()[0].name().clone(),
+						300,
+						RData::MX(MX::new(5, Name::from_str(exchange).unwrap())),
+					));
+				let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+			}
+		});
+
+		let config = ResolverConfig::from_parts(
+			None,
+			vec![],
+			NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true),
+		);
+		TokioAsyncResolver::tokio(config, ResolverOpts::default())
+	}
+
+	#[tokio::test]
+	async fn should_return_mx_provider() {
+		let config = BackendConfig::default();
+		config.set_resolver(mock_resolver("gmail-smtp-in.l.google.com.").await);
+
+		let resp = request()
+			.path("/v1/mx/gmail.com")
+			.method("GET")
+			.reply(&v1_get_mx(Arc::new(config)).recover(handle_rejection))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+		assert_eq!(resp.headers()[CACHE_CONTROL], "public, max-age=300");
+		let body: Value = serde_json::from_slice(resp.body()).unwrap();
+		assert_eq!(body["mx_provider"], "Gmail");
+		assert_eq!(body["accepts_mail"], true);
+		assert_eq!(body["records"][0], "gmail-smtp-in.l.google.com.");
+	}
+
+	#[tokio::test]
+	async fn should_reject_invalid_domain() {
+		let resp = request()
+			.path("/v1/mx/not%20a%20domain")
+			.method("GET")
+			.reply(&v1_get_mx(Arc::new(BackendConfig::default())).recover(handle_rejection))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", resp.body());
+	}
+}
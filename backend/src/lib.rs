@@ -14,8 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+// The lapin futures are deeply nested, and building the tests with the
+// "worker" feature overflows the default limit.
+#![recursion_limit = "256"]
+
 pub mod config;
 mod db;
+pub mod disposable;
 pub mod http;
 #[cfg(feature = "worker")]
 pub mod worker;
@@ -17,13 +17,18 @@
 //! Main entry point of the `reacher_backend` binary. It has two `main`
 //! functions, depending on whether the `bulk` feature is enabled or not.
 
-use check_if_email_exists::{setup_sentry, LOG_TARGET};
+// The lapin futures are deeply nested, see the same limit in lib.rs.
+#![recursion_limit = "256"]
+
+use check_if_email_exists::{setup_sentry, warm_up, LOG_TARGET};
 #[cfg(feature = "worker")]
 use reacher_backend::worker::run_worker;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
 
 use reacher_backend::config::load_config;
+use reacher_backend::disposable::spawn_disposable_list_refresh;
 use reacher_backend::http::run_warp_server;
 
 const CARGO_PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -44,6 +49,26 @@ async fn main() -> Result<(), anyhow::Error> {
 
 	let config = Arc::new(config);
 
+	// Establish the proxy, WebDriver and DNS readiness before serving, so
+	// that the first verifications don't pay for it.
+	if warm_up(&config.get_reacher_config(), config.proxy.as_ref())
+		.await
+		.is_ok()
+	{
+		info!(target: LOG_TARGET, "Warm-up succeeded");
+	}
+
+	if let Some(url) = &config.disposable_list_url {
+		spawn_disposable_list_refresh(
+			config.http_client.clone().unwrap_or_default(),
+			url.clone(),
+			config
+				.disposable_list_refresh_interval
+				.map(Duration::from_secs),
+			config.get_disposable_list(),
+		);
+	}
+
 	#[cfg(feature = "worker")]
 	{
 		let server_future = run_warp_server(Arc::clone(&config));
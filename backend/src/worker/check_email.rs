@@ -14,20 +14,25 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use super::consume::ThrottleEvent;
 use super::response::save_to_db;
 use crate::config::BackendConfig;
 use crate::worker::response::send_single_shot_reply;
+use check_if_email_exists::config::OnProxyConnection;
 use check_if_email_exists::{
 	check_email, CheckEmailInput, CheckEmailOutput, Reachable, LOG_TARGET,
 };
 use core::time;
+use hashlink::LinkedHashMap;
 use lapin::message::Delivery;
 use lapin::{options::*, Channel};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tracing::{debug, info};
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
 use warp::http::StatusCode;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -59,6 +64,10 @@ pub enum TaskError {
 	Lapin(lapin::Error),
 	#[error("Reqwest error during webhook: {0}")]
 	Reqwest(reqwest::Error),
+	/// The task's job sent the maximum number of requests through the proxy,
+	/// see `ProxyBudget`, so the task wasn't verified.
+	#[error("Job {job_id} reached its cap of {cap} proxy requests")]
+	ProxyCapReached { job_id: i32, cap: u64 },
 }
 
 impl TaskError {
@@ -68,6 +77,7 @@ impl TaskError {
 			Self::Throttle(_) => StatusCode::TOO_MANY_REQUESTS,
 			Self::Lapin(_) => StatusCode::INTERNAL_SERVER_ERROR,
 			Self::Reqwest(_) => StatusCode::INTERNAL_SERVER_ERROR,
+			Self::ProxyCapReached { .. } => StatusCode::TOO_MANY_REQUESTS,
 		}
 	}
 }
@@ -104,12 +114,202 @@ pub struct Webhook {
 	pub extra: Option<serde_json::Value>,
 }
 
+/// Version of the webhook body's shape, sent as `schema_version`, so that
+/// receivers can tolerate future changes. Bump it whenever the serialized
+/// `WebhookOutput` (including `CheckEmailOutput`) changes shape:
+/// - 2: the result's `verdict`.
+/// - 3: the result's SMTP `fingerprint`, and its debug `from_cache`,
+///   `cached_at` and `retries_used`.
+pub const WEBHOOK_SCHEMA_VERSION: u32 = 3;
+
+/// Version of the throttle webhook body's shape, sent as `schema_version`,
+/// like `WEBHOOK_SCHEMA_VERSION`. Bump it whenever the serialized
+/// `ThrottleWebhookOutput` changes shape.
+pub const THROTTLE_WEBHOOK_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize)]
 struct WebhookOutput<'a> {
+	schema_version: u32,
 	result: &'a CheckEmailOutput,
 	extra: &'a Option<serde_json::Value>,
 }
 
+#[derive(Debug, Serialize)]
+struct ThrottleWebhookOutput<'a> {
+	schema_version: u32,
+	backend_name: &'a str,
+	event: &'a ThrottleEvent,
+	extra: &'a Option<serde_json::Value>,
+}
+
+/// Default maximum number of webhook requests in flight at the same time.
+pub const DEFAULT_WEBHOOK_CONCURRENCY: usize = 20;
+
+/// HTTP client sending the webhooks. It's shared by all tasks to reuse
+/// connections, and bounds the number of requests in flight at the same time,
+/// so that a high throughput doesn't exhaust sockets.
+#[derive(Debug)]
+pub struct WebhookClient {
+	client: reqwest::Client,
+	semaphore: Semaphore,
+}
+
+impl WebhookClient {
+	pub fn new(concurrency: usize) -> Self {
+		Self::with_client(reqwest::Client::new(), concurrency)
+	}
+
+	/// Send the webhooks with the given HTTP client.
+	pub fn with_client(client: reqwest::Client, concurrency: usize) -> Self {
+		Self {
+			client,
+			semaphore: Semaphore::new(concurrency),
+		}
+	}
+
+	/// Send the output to the webhook, waiting for a free slot if too many
+	/// requests are already in flight. Returns the response body.
+	async fn send(
+		&self,
+		webhook: &Webhook,
+		output: &CheckEmailOutput,
+	) -> Result<String, TaskError> {
+		let webhook_output = WebhookOutput {
+			schema_version: WEBHOOK_SCHEMA_VERSION,
+			result: output,
+			extra: &webhook.extra,
+		};
+
+		self.post(&webhook.url, &webhook_output).await
+	}
+
+	/// Send the worker's throttle state change to the webhook, like `send`.
+	pub async fn send_throttle_event(
+		&self,
+		webhook: &Webhook,
+		backend_name: &str,
+		event: &ThrottleEvent,
+	) -> Result<String, TaskError> {
+		let webhook_output = ThrottleWebhookOutput {
+			schema_version: THROTTLE_WEBHOOK_SCHEMA_VERSION,
+			backend_name,
+			event,
+			extra: &webhook.extra,
+		};
+
+		self.post(&webhook.url, &webhook_output).await
+	}
+
+	async fn post<T: Serialize>(&self, url: &str, body: &T) -> Result<String, TaskError> {
+		let _permit = self
+			.semaphore
+			.acquire()
+			.await
+			.expect("The webhook semaphore is never closed. qed.");
+		let res = self
+			.client
+			.post(url)
+			.json(body)
+			.header(
+				"x-reacher-secret",
+				std::env::var("RCH_HEADER_SECRET").unwrap_or_default(),
+			)
+			.send()
+			.await?
+			.text()
+			.await?;
+
+		Ok(res)
+	}
+}
+
+/// Number of requests a job sent through the proxy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct JobProxyUsage {
+	/// Connections opened through the proxy so far, SMTP or HTTP, see
+	/// `OnProxyConnection`.
+	pub requests: u64,
+	/// Whether a task was refused because the job reached the cap.
+	pub cap_reached: bool,
+}
+
+/// How long a job goes without any task before it's considered finished,
+/// and its proxy usage forgotten.
+const PROXY_BUDGET_IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Caps the number of requests each bulk job sends through the proxy, e.g.
+/// to bound the cost of a metered proxy. Once a job reaches the cap, its
+/// remaining tasks fail with `TaskError::ProxyCapReached` instead of being
+/// verified. The tasks in progress at that time still finish, so the job may
+/// go over the cap by their requests. The usage is tracked by each worker
+/// process, and forgotten after PROXY_BUDGET_IDLE_TIMEOUT without any task
+/// of the job.
+#[derive(Debug)]
+pub struct ProxyBudget {
+	/// Maximum number of proxied requests per job, None if unlimited.
+	cap: Option<u64>,
+	/// How long a job goes without any task before it's forgotten.
+	idle_timeout: Duration,
+	/// The usage of each job, with its last task's start, least recently
+	/// used first.
+	jobs: Mutex<LinkedHashMap<i32, (JobProxyUsage, Instant)>>,
+}
+
+impl ProxyBudget {
+	pub fn new(cap: Option<u64>) -> Self {
+		Self {
+			cap,
+			idle_timeout: PROXY_BUDGET_IDLE_TIMEOUT,
+			jobs: Mutex::default(),
+		}
+	}
+
+	/// Check that the job can send requests through the proxy, before
+	/// verifying one of its tasks. Fails if the job already reached the cap.
+	fn reserve(&self, job_id: i32) -> Result<(), TaskError> {
+		let Some(cap) = self.cap else {
+			return Ok(());
+		};
+		let mut jobs = self.jobs.lock().expect("Lock is not poisoned. qed.");
+		let now = Instant::now();
+		// The jobs without any task for a while are finished.
+		while jobs
+			.front()
+			.is_some_and(|(_, (_, last_task))| now.duration_since(*last_task) > self.idle_timeout)
+		{
+			jobs.pop_front();
+		}
+		if jobs.to_back(&job_id).is_none() {
+			jobs.insert(job_id, (JobProxyUsage::default(), now));
+		}
+		let (usage, last_task) = jobs.get_mut(&job_id).expect("Inserted above. qed.");
+		*last_task = now;
+		if usage.requests >= cap {
+			if !usage.cap_reached {
+				warn!(target: LOG_TARGET, job_id, cap, "Job reached its proxy request cap");
+			}
+			usage.cap_reached = true;
+			return Err(TaskError::ProxyCapReached { job_id, cap });
+		}
+
+		Ok(())
+	}
+
+	/// Record a connection through the proxy by a task of the job.
+	fn record(&self, job_id: i32) {
+		let mut jobs = self.jobs.lock().expect("Lock is not poisoned. qed.");
+		if let Some((usage, _)) = jobs.get_mut(&job_id) {
+			usage.requests += 1;
+		}
+	}
+
+	/// The proxy usage of the job, if it's tracked.
+	pub fn usage(&self, job_id: i32) -> Option<JobProxyUsage> {
+		let jobs = self.jobs.lock().expect("Lock is not poisoned. qed.");
+		jobs.get(&job_id).map(|(usage, _)| *usage)
+	}
+}
+
 /// Processes the check email task asynchronously.
 pub(crate) async fn do_check_email_work(
 	payload: &CheckEmailTask,
@@ -129,7 +329,8 @@ pub(crate) async fn do_check_email_work(
 				.await?;
 			info!(target: LOG_TARGET, email=?&payload.input.to_email, is_reachable=?Reachable::Unknown, "Requeued message");
 		}
-		(Err(e), false) => {
+		// Retrying doesn't help once the job reached its proxy request cap.
+		(Err(e), false) if !matches!(e, TaskError::ProxyCapReached { .. }) => {
 			// Same as above, if processing the message failed, we requeue it.
 			delivery
 				.reject(BasicRejectOptions { requeue: true })
@@ -156,6 +357,7 @@ pub(crate) async fn do_check_email_work(
 
 			info!(target: LOG_TARGET,
 				email=payload.input.to_email,
+				retries_used=?worker_output.as_ref().ok().map(|o| o.debug.smtp.retries_used),
 				worker_output=?worker_output.map(|o| o.is_reachable),
 				job_id=?payload.job_id,
 				"Done check",
@@ -170,32 +372,216 @@ async fn inner_check_email(
 	payload: &CheckEmailTask,
 	config: Arc<BackendConfig>,
 ) -> Result<CheckEmailOutput, TaskError> {
-	let output = check_email(&payload.input, &config.get_reacher_config()).await;
+	// Only the bulk jobs' requests through the proxy are capped.
+	let mut reacher_config = config.get_reacher_config();
+	match payload.job_id {
+		Some(job_id)
+			if config.worker.proxy_requests_per_job.is_some()
+				&& (payload.input.proxy.is_some()
+					|| !payload.input.provider_proxies.is_empty()) =>
+		{
+			let budget = config.get_proxy_budget();
+			budget.reserve(job_id)?;
+			reacher_config.on_proxy_connection =
+				Some(OnProxyConnection::new(move || budget.record(job_id)));
+		}
+		_ => {}
+	}
+
+	let output = check_email(&payload.input, &reacher_config).await;
 
 	// Check if we have a webhook to send the output to.
 	if let Some(TaskWebhook {
 		on_each_email: Some(webhook),
 	}) = &payload.webhook
 	{
-		let webhook_output = WebhookOutput {
+		let res = config.get_webhook_client().send(webhook, &output).await?;
+		debug!(target: LOG_TARGET, email=?output.input,res=?res, "Received webhook response");
+	}
+
+	Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn webhook_body_should_include_schema_version() {
+		let output = CheckEmailOutput::default();
+		let extra = Some(serde_json::json!({ "foo": "bar" }));
+		let body = serde_json::to_value(WebhookOutput {
+			schema_version: WEBHOOK_SCHEMA_VERSION,
 			result: &output,
-			extra: &webhook.extra,
+			extra: &extra,
+		})
+		.unwrap();
+
+		assert_eq!(body["schema_version"], 3);
+		assert_eq!(body["extra"]["foo"], "bar");
+		assert!(body["result"]["verdict"].is_object());
+	}
+
+	#[tokio::test]
+	async fn should_stop_proxied_requests_at_the_job_cap() {
+		use check_if_email_exists::{CheckEmailInputBuilder, CheckEmailInputProxy};
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		// A SOCKS5 proxy counting its connections, and closing them.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let proxy = CheckEmailInputProxy {
+			host: "127.0.0.1".into(),
+			port: listener.local_addr().unwrap().port(),
+			username: None,
+			password: None,
 		};
+		let connections = Arc::new(AtomicUsize::new(0));
+		let counter = Arc::clone(&connections);
+		tokio::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				counter.fetch_add(1, Ordering::SeqCst);
+				drop(stream);
+			}
+		});
 
-		let client = reqwest::Client::new();
-		let res = client
-			.post(&webhook.url)
-			.json(&webhook_output)
-			.header(
-				"x-reacher-secret",
-				std::env::var("RCH_HEADER_SECRET").unwrap_or_default(),
-			)
-			.send()
-			.await?
-			.text()
-			.await?;
-		debug!(target: LOG_TARGET, email=?webhook_output.result.input,res=?res, "Received webhook response");
+		let mut config = BackendConfig::default();
+		config.worker.proxy_requests_per_job = Some(2);
+		let config = Arc::new(config);
+		let task = CheckEmailTask {
+			input: CheckEmailInputBuilder::default()
+				.to_email("foo@example.com".into())
+				.smtp_host_override(Some(("127.0.0.1".into(), 25)))
+				.proxy(Some(proxy))
+				.retries(0)
+				.build()
+				.unwrap(),
+			job_id: Some(1),
+			webhook: None,
+		};
+
+		for _ in 0..2 {
+			assert!(inner_check_email(&task, Arc::clone(&config)).await.is_ok());
+		}
+		let before_cap = connections.load(Ordering::SeqCst);
+		assert!(before_cap >= 2);
+
+		let res = inner_check_email(&task, Arc::clone(&config)).await;
+		assert!(matches!(
+			res,
+			Err(TaskError::ProxyCapReached { job_id: 1, cap: 2 })
+		));
+		assert_eq!(connections.load(Ordering::SeqCst), before_cap);
+		assert_eq!(
+			config.get_proxy_budget().usage(1),
+			Some(JobProxyUsage {
+				requests: 2,
+				cap_reached: true
+			})
+		);
+
+		// Other jobs have their own cap.
+		let task = CheckEmailTask {
+			job_id: Some(2),
+			..task
+		};
+		assert!(inner_check_email(&task, Arc::clone(&config)).await.is_ok());
+		assert!(connections.load(Ordering::SeqCst) > before_cap);
 	}
 
-	Ok(output)
+	#[test]
+	fn proxy_budget_should_forget_idle_jobs() {
+		let mut budget = ProxyBudget::new(Some(1));
+		budget.idle_timeout = Duration::ZERO;
+		assert!(budget.reserve(1).is_ok());
+		budget.record(1);
+		assert_eq!(
+			budget.usage(1),
+			Some(JobProxyUsage {
+				requests: 1,
+				cap_reached: false
+			})
+		);
+
+		std::thread::sleep(Duration::from_millis(10));
+		assert!(budget.reserve(2).is_ok());
+		assert_eq!(budget.usage(1), None);
+		assert!(budget.usage(2).is_some());
+	}
+
+	#[tokio::test]
+	async fn webhook_client_should_use_injected_client() {
+		use warp::Filter;
+
+		let route = warp::post()
+			.and(warp::header::optional::<String>("x-trace-id"))
+			.map(|trace_id: Option<String>| trace_id.unwrap_or_default());
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		let mut headers = reqwest::header::HeaderMap::new();
+		headers.insert("x-trace-id", "abc123".parse().unwrap());
+		let client = reqwest::Client::builder()
+			.default_headers(headers)
+			.build()
+			.unwrap();
+		let webhook = Webhook {
+			url: format!("http://{addr}/"),
+			extra: None,
+		};
+
+		let res = WebhookClient::with_client(client, 1)
+			.send(&webhook, &CheckEmailOutput::default())
+			.await
+			.unwrap();
+		assert_eq!(res, "abc123");
+	}
+
+	#[tokio::test]
+	async fn webhook_client_should_bound_concurrency() {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+		use std::time::Duration;
+		use warp::Filter;
+
+		// Track the number of requests the server handles at the same time.
+		let in_flight = Arc::new(AtomicUsize::new(0));
+		let max_in_flight = Arc::new(AtomicUsize::new(0));
+		let route = {
+			let in_flight = Arc::clone(&in_flight);
+			let max_in_flight = Arc::clone(&max_in_flight);
+			warp::post().and_then(move || {
+				let in_flight = Arc::clone(&in_flight);
+				let max_in_flight = Arc::clone(&max_in_flight);
+				async move {
+					let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+					max_in_flight.fetch_max(current, Ordering::SeqCst);
+					tokio::time::sleep(Duration::from_millis(50)).await;
+					in_flight.fetch_sub(1, Ordering::SeqCst);
+					Ok::<_, warp::Rejection>("ok")
+				}
+			})
+		};
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		let client = Arc::new(WebhookClient::new(2));
+		let webhook = Webhook {
+			url: format!("http://{addr}/"),
+			extra: None,
+		};
+		let tasks = (0..8).map(|_| {
+			let client = Arc::clone(&client);
+			let webhook = webhook.clone();
+			tokio::spawn(async move {
+				client
+					.send(&webhook, &CheckEmailOutput::default())
+					.await
+					.unwrap()
+			})
+		});
+		for res in futures::future::join_all(tasks).await {
+			assert_eq!(res.unwrap(), "ok");
+		}
+
+		assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+	}
 }
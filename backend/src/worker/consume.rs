@@ -22,6 +22,7 @@ use anyhow::Context;
 use check_if_email_exists::LOG_TARGET;
 use futures::stream::StreamExt;
 use lapin::{options::*, types::FieldTable, Channel, Connection, ConnectionProperties};
+use serde::Serialize;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
@@ -179,11 +180,21 @@ async fn consume_check_email(config: Arc<BackendConfig>) -> Result<(), anyhow::E
 				throttle_clone.lock().await.reset_if_needed();
 
 				// Check if we should throttle before fetching the next message
-				if let Some(wait_duration) = throttle_clone
-					.lock()
-					.await
-					.should_throttle(&worker_config.throttle)
-				{
+				let wait = {
+					let mut throttle = throttle_clone.lock().await;
+					let wait = throttle.should_throttle(&worker_config.throttle);
+					if let Some(event) = throttle.record_state(wait, &worker_config.throttle) {
+						send_throttle_event(&config_clone, event);
+						let config = Arc::clone(&config_clone);
+						spawn_throttle_exit_timer(
+							Arc::clone(&throttle_clone),
+							worker_config.throttle.clone(),
+							move |event| send_throttle_event(&config, event),
+						);
+					}
+					wait
+				};
+				if let Some(wait_duration) = wait {
 					info!(target: LOG_TARGET, wait=?wait_duration, email=?payload.input.to_email, "Too many requests, throttling");
 
 					// For single-shot tasks, we return an error early, so that the user knows they need to retry.
@@ -234,6 +245,82 @@ async fn consume_check_email(config: Arc<BackendConfig>) -> Result<(), anyhow::E
 	Ok(())
 }
 
+/// Send the event to the `worker.throttle_webhook`, if configured, in the
+/// background.
+fn send_throttle_event(config: &Arc<BackendConfig>, event: ThrottleEvent) {
+	info!(target: LOG_TARGET, throttled=event.throttled, usage=?event.usage, "Throttle state changed");
+	let Some(webhook) = config.worker.throttle_webhook.clone() else {
+		return;
+	};
+	let config = Arc::clone(config);
+	tokio::spawn(async move {
+		let res = config
+			.get_webhook_client()
+			.send_throttle_event(&webhook, &config.backend_name, &event)
+			.await;
+		if let Err(e) = res {
+			error!(target: LOG_TARGET, error=?e, "Error sending throttle webhook");
+		}
+	});
+}
+
+/// Once the worker entered the throttled state, call `on_exit` with the exit
+/// event when it hasn't been throttled for THROTTLE_EVENT_DEBOUNCE, even if
+/// no message comes in meanwhile to record its state.
+fn spawn_throttle_exit_timer(
+	throttle: Arc<Mutex<Throttle>>,
+	config: ThrottleConfig,
+	on_exit: impl FnOnce(ThrottleEvent) + Send + 'static,
+) {
+	tokio::spawn(async move {
+		loop {
+			let deadline = match throttle.lock().await.last_throttled {
+				Some(last) => last + THROTTLE_EVENT_DEBOUNCE,
+				None => return,
+			};
+			tokio::time::sleep_until(deadline.into()).await;
+
+			let mut throttle = throttle.lock().await;
+			// A message already recorded the exit.
+			if !throttle.throttled {
+				return;
+			}
+			throttle.reset_if_needed();
+			if let Some(event) = throttle.record_state(None, &config) {
+				on_exit(event);
+				return;
+			}
+		}
+	});
+}
+
+/// How long the worker must go without being throttled before it's
+/// considered out of the throttled state, so that a per-second limit
+/// doesn't report a state change every second.
+const THROTTLE_EVENT_DEBOUNCE: Duration = Duration::from_secs(10);
+
+/// Number of requests the worker processed in the current second, minute,
+/// hour and day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ThrottleUsage {
+	pub requests_per_second: u32,
+	pub requests_per_minute: u32,
+	pub requests_per_hour: u32,
+	pub requests_per_day: u32,
+}
+
+/// The worker entered or exited the throttled state, sent to the
+/// `worker.throttle_webhook`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThrottleEvent {
+	/// Whether the worker entered the throttled state, or exited it.
+	pub throttled: bool,
+	/// The current capacity usage.
+	pub usage: ThrottleUsage,
+	/// The limits of `worker.throttle`.
+	pub limits: ThrottleConfig,
+}
+
 #[derive(Clone)]
 struct Throttle {
 	requests_per_second: u32,
@@ -244,6 +331,10 @@ struct Throttle {
 	last_reset_minute: Instant,
 	last_reset_hour: Instant,
 	last_reset_day: Instant,
+	/// Whether the last reported state is throttled.
+	throttled: bool,
+	/// When the worker was last throttled.
+	last_throttled: Option<Instant>,
 }
 
 impl Throttle {
@@ -258,9 +349,45 @@ impl Throttle {
 			last_reset_minute: now,
 			last_reset_hour: now,
 			last_reset_day: now,
+			throttled: false,
+			last_throttled: None,
 		}
 	}
 
+	/// Record the result of `should_throttle`, and return an event if the
+	/// worker entered the throttled state, or exited it, i.e. wasn't
+	/// throttled for THROTTLE_EVENT_DEBOUNCE.
+	fn record_state(
+		&mut self,
+		wait: Option<Duration>,
+		config: &ThrottleConfig,
+	) -> Option<ThrottleEvent> {
+		let now = Instant::now();
+		if wait.is_some() {
+			self.last_throttled = Some(now);
+		}
+		let throttled = match self.last_throttled {
+			_ if wait.is_some() => true,
+			Some(last) if self.throttled => now.duration_since(last) < THROTTLE_EVENT_DEBOUNCE,
+			_ => false,
+		};
+		if throttled == self.throttled {
+			return None;
+		}
+
+		self.throttled = throttled;
+		Some(ThrottleEvent {
+			throttled,
+			usage: ThrottleUsage {
+				requests_per_second: self.requests_per_second,
+				requests_per_minute: self.requests_per_minute,
+				requests_per_hour: self.requests_per_hour,
+				requests_per_day: self.requests_per_day,
+			},
+			limits: config.clone(),
+		})
+	}
+
 	fn reset_if_needed(&mut self) {
 		let now = Instant::now();
 
@@ -326,3 +453,70 @@ impl Throttle {
 		None
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_send_one_event_when_crossing_the_threshold() {
+		let config = ThrottleConfig {
+			max_requests_per_second: Some(2),
+			..ThrottleConfig::new_without_throttle()
+		};
+		let mut throttle = Throttle::new();
+
+		let mut events = vec![];
+		for _ in 0..10 {
+			throttle.reset_if_needed();
+			let wait = throttle.should_throttle(&config);
+			events.extend(throttle.record_state(wait, &config));
+			if wait.is_none() {
+				throttle.increment_counters();
+			}
+		}
+		assert_eq!(events.len(), 1);
+		assert!(events[0].throttled);
+		assert_eq!(events[0].usage.requests_per_second, 2);
+
+		// Not throttled anymore, but not for long enough yet.
+		throttle.requests_per_second = 0;
+		assert!(throttle.record_state(None, &config).is_none());
+
+		throttle.last_throttled = Some(Instant::now() - THROTTLE_EVENT_DEBOUNCE);
+		let event = throttle.record_state(None, &config).unwrap();
+		assert!(!event.throttled);
+		assert!(throttle.record_state(None, &config).is_none());
+	}
+
+	#[tokio::test]
+	async fn should_send_exit_event_without_new_messages() {
+		let config = ThrottleConfig {
+			max_requests_per_second: Some(2),
+			..ThrottleConfig::new_without_throttle()
+		};
+		let mut throttle = Throttle::new();
+		assert!(
+			throttle
+				.record_state(Some(Duration::from_secs(1)), &config)
+				.unwrap()
+				.throttled
+		);
+		// Throttled long enough ago for the timer to fire soon.
+		throttle.last_throttled =
+			Some(Instant::now() - THROTTLE_EVENT_DEBOUNCE + Duration::from_millis(50));
+		let throttle = Arc::new(Mutex::new(throttle));
+
+		let (tx, rx) = tokio::sync::oneshot::channel();
+		spawn_throttle_exit_timer(Arc::clone(&throttle), config, move |event| {
+			let _ = tx.send(event);
+		});
+
+		let event = tokio::time::timeout(Duration::from_secs(5), rx)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(!event.throttled);
+		assert!(!throttle.lock().await.throttled);
+	}
+}
@@ -21,7 +21,9 @@ use crate::http::CheckEmailRequest;
 use anyhow::anyhow;
 use check_if_email_exists::mx::check_mx;
 use check_if_email_exists::syntax::check_syntax;
-use check_if_email_exists::{is_gmail, is_hotmail_b2b, is_hotmail_b2c, is_yahoo, LOG_TARGET};
+use check_if_email_exists::{
+	is_gmail, is_hotmail_b2b, is_hotmail_b2c, is_yahoo, CheckEmailInput, LOG_TARGET,
+};
 use lapin::message::Delivery;
 use lapin::{options::*, Channel};
 use serde::{Deserialize, Serialize};
@@ -35,6 +37,10 @@ pub struct PreprocessTask {
 	// If the task is a part of a job, then this field will be set.
 	pub job_id: Option<i32>,
 	pub webhook: Option<TaskWebhook>,
+	/// The input of a retried task, reused as is instead of being built from
+	/// `input` and the backend config.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub check_email_input: Option<CheckEmailInput>,
 }
 
 /// Preprocess the email and send it to the appropriate queue for verification.
@@ -45,7 +51,7 @@ pub async fn do_preprocess_work(
 	config: Arc<BackendConfig>,
 ) -> Result<(), anyhow::Error> {
 	let syntax = check_syntax(&payload.input.to_email);
-	let mx = check_mx(&syntax).await?;
+	let mx = check_mx(&syntax, &config.get_reacher_config()).await?;
 	// Get first hostname from MX records.
 	let mx_hostname = mx
 		.lookup?
@@ -62,7 +68,10 @@ pub async fn do_preprocess_work(
 		hostname if is_yahoo(hostname) => Queue::Yahoo,
 		_ => Queue::EverythingElse,
 	};
-	let check_email_input = payload.input.to_check_email_input(config);
+	let check_email_input = match &payload.check_email_input {
+		Some(input) => input.clone(),
+		None => payload.input.to_check_email_input(config),
+	};
 	let check_email_task = CheckEmailTask {
 		input: check_email_input,
 		job_id: payload.job_id,
@@ -29,7 +29,8 @@ use warp::http::StatusCode;
 
 /// Save the task result to the database. This only happens if the task is a
 /// part of a bulk verification job. If no pool is provided, the function will
-/// simply return without doing anything.
+/// simply return without doing anything. The SMTP transcript, if enabled on
+/// the task input, is stored in its own column rather than in the result.
 ///
 /// # Panics
 ///
@@ -47,18 +48,22 @@ pub async fn save_to_db(
 
 	match worker_output {
 		Ok(output) => {
-			let output_json = serde_json::to_value(output)?;
+			let mut output_json = serde_json::to_value(output)?;
+			let smtp_transcript = output_json["debug"]["smtp"]
+				.as_object_mut()
+				.and_then(|smtp| smtp.remove("transcript"));
 
 			sqlx::query!(
 				r#"
-				INSERT INTO v1_task_result (payload, job_id, backend_name, result)
-				VALUES ($1, $2, $3, $4)
+				INSERT INTO v1_task_result (payload, job_id, backend_name, result, smtp_transcript)
+				VALUES ($1, $2, $3, $4, $5)
 				RETURNING id
 				"#,
 				payload_json,
 				job_id,
 				backend_name,
 				output_json,
+				smtp_transcript,
 			)
 			.fetch_one(&pg_pool)
 			.await?;
@@ -157,3 +162,56 @@ pub async fn send_single_shot_reply(
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use check_if_email_exists::CheckEmailInput;
+
+	#[tokio::test]
+	#[ignore = "Set DATABASE_URL to a migrated Postgres database to test this"]
+	async fn should_save_transcript_only_if_enabled() {
+		let pg_pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+			.await
+			.unwrap();
+		let job_id: i32 =
+			sqlx::query_scalar("INSERT INTO v1_bulk_job (total_records) VALUES (2) RETURNING id")
+				.fetch_one(&pg_pool)
+				.await
+				.unwrap();
+
+		for smtp_transcript in [true, false] {
+			let payload = CheckEmailTask {
+				input: CheckEmailInput {
+					to_email: format!("{smtp_transcript}@foo.com"),
+					smtp_transcript,
+					..Default::default()
+				},
+				job_id: Some(job_id),
+				webhook: None,
+			};
+			let mut output = CheckEmailOutput::default();
+			if smtp_transcript {
+				output.debug.smtp.transcript = Some(vec!["C: QUIT".into(), "S: 221 Bye".into()]);
+			}
+			save_to_db("test", Some(pg_pool.clone()), &payload, &Ok(output))
+				.await
+				.unwrap();
+		}
+
+		let rows: Vec<(Option<serde_json::Value>, serde_json::Value)> = sqlx::query_as(
+			"SELECT smtp_transcript, result FROM v1_task_result WHERE job_id = $1 ORDER BY id",
+		)
+		.bind(job_id)
+		.fetch_all(&pg_pool)
+		.await
+		.unwrap();
+
+		assert_eq!(
+			rows[0].0,
+			Some(serde_json::json!(["C: QUIT", "S: 221 Bye"]))
+		);
+		assert!(rows[0].1["debug"]["smtp"].get("transcript").is_none());
+		assert_eq!(rows[1].0, None);
+	}
+}
@@ -19,12 +19,15 @@ mod tests {
 	use std::sync::Arc;
 
 	use reacher_backend::config::BackendConfig;
+	use reacher_backend::http::client_ip::X_FORWARDED_FOR_HEADER;
+	use reacher_backend::http::idempotency::IDEMPOTENCY_KEY_HEADER;
+	use reacher_backend::http::request_id::REQUEST_ID_HEADER;
 	use reacher_backend::http::{create_routes, CheckEmailRequest, REACHER_SECRET_HEADER};
 	use warp::http::StatusCode;
 	use warp::test::request;
 
-	const FOO_BAR_RESPONSE: &str = r#"{"input":"foo@bar","is_reachable":"invalid","misc":{"is_disposable":false,"is_role_account":false,"gravatar_url":null,"haveibeenpwned":null},"mx":{"accepts_mail":false,"records":[]},"smtp":{"can_connect_smtp":false,"has_full_inbox":false,"is_catch_all":false,"is_deliverable":false,"is_disabled":false},"syntax":{"address":null,"domain":"","is_valid_syntax":false,"username":"","normalized_email":null,"suggestion":null}"#;
-	const FOO_BAR_BAZ_RESPONSE: &str = r#"{"input":"foo@bar.baz","is_reachable":"invalid","misc":{"is_disposable":false,"is_role_account":false,"gravatar_url":null,"haveibeenpwned":null},"mx":{"accepts_mail":false,"records":[]},"smtp":{"can_connect_smtp":false,"has_full_inbox":false,"is_catch_all":false,"is_deliverable":false,"is_disabled":false},"syntax":{"address":"foo@bar.baz","domain":"bar.baz","is_valid_syntax":true,"username":"foo","normalized_email":"foo@bar.baz","suggestion":null}"#;
+	const FOO_BAR_RESPONSE: &str = r#"{"input":"foo@bar","is_reachable":"invalid","misc":{"is_disposable":false,"is_role_account":false,"gravatar_url":null,"haveibeenpwned":null,"disposable_match":null,"role_match":null,"account_type":"unknown"},"mx":{"accepts_mail":false,"records":[],"ttl":null},"smtp":{"can_connect_smtp":false,"has_full_inbox":false,"is_catch_all":false,"is_deliverable":false,"is_disabled":false},"syntax":{"address":null,"domain":"","is_valid_syntax":false,"username":"","normalized_email":null,"suggestion":null}"#;
+	const FOO_BAR_BAZ_RESPONSE: &str = r#"{"input":"foo@bar.baz","is_reachable":"invalid","misc":{"is_disposable":false,"is_role_account":false,"gravatar_url":null,"haveibeenpwned":null,"disposable_match":null,"role_match":null,"account_type":"unknown"},"mx":{"accepts_mail":false,"records":[],"ttl":null},"smtp":{"can_connect_smtp":false,"has_full_inbox":false,"is_catch_all":false,"is_deliverable":false,"is_disabled":false},"syntax":{"address":"foo@bar.baz","domain":"bar.baz","is_valid_syntax":true,"username":"foo","normalized_email":"foo@bar.baz","suggestion":null}"#;
 
 	fn create_backend_config(header_secret: &str) -> Arc<BackendConfig> {
 		let mut config = BackendConfig::default();
@@ -63,6 +66,102 @@ mod tests {
 		assert!(resp.body().starts_with(FOO_BAR_BAZ_RESPONSE.as_bytes()));
 	}
 
+	#[tokio::test]
+	async fn test_idempotency_key() {
+		let routes = create_routes(create_backend_config("foobar"));
+		let check = |key: &'static str| {
+			request()
+				.path("/v0/check_email")
+				.method("POST")
+				.header(REACHER_SECRET_HEADER, "foobar")
+				.header(IDEMPOTENCY_KEY_HEADER, key)
+				.json(
+					&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": "foo@bar"}"#)
+						.unwrap(),
+				)
+				.reply(&routes)
+		};
+
+		let first = check("key-1").await;
+		let second = check("key-1").await;
+		let other = check("key-2").await;
+
+		assert_eq!(first.status(), StatusCode::OK, "{:?}", first.body());
+		// The debug timestamps would differ if the email was verified twice.
+		assert_eq!(first.body(), second.body());
+		assert_ne!(first.body(), other.body());
+	}
+
+	#[tokio::test]
+	async fn test_result_cache() {
+		let mut config = BackendConfig::default();
+		config.header_secret = Some("foobar".into());
+		config.result_cache_ttl = Some(60);
+		let routes = create_routes(Arc::new(config));
+		let check = || {
+			request()
+				.path("/v0/check_email")
+				.method("POST")
+				.header(REACHER_SECRET_HEADER, "foobar")
+				.json(
+					&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": "foo@bar"}"#)
+						.unwrap(),
+				)
+				.reply(&routes)
+		};
+
+		let first = check().await;
+		let second = check().await;
+
+		assert_eq!(first.status(), StatusCode::OK, "{:?}", first.body());
+		let first = serde_json::from_slice::<serde_json::Value>(first.body()).unwrap();
+		let second = serde_json::from_slice::<serde_json::Value>(second.body()).unwrap();
+		assert_eq!(first["debug"]["from_cache"], false);
+		assert!(first["debug"].get("cached_at").is_none());
+		assert_eq!(second["debug"]["from_cache"], true);
+		assert_eq!(second["debug"]["cached_at"], first["debug"]["end_time"]);
+		assert_eq!(second["debug"]["end_time"], first["debug"]["end_time"]);
+	}
+
+	#[tokio::test]
+	async fn test_max_concurrent_requests_per_ip() {
+		let mut config = BackendConfig::default();
+		config.max_concurrent_requests_per_ip = Some(2);
+		config.trust_x_forwarded_for = true;
+		let config = Arc::new(config);
+		let routes = create_routes(Arc::clone(&config));
+		let check = |forwarded_for: &'static str| {
+			request()
+				.path("/v0/check_email")
+				.method("POST")
+				.remote_addr("10.0.0.1:1234".parse().unwrap())
+				.header(X_FORWARDED_FOR_HEADER, forwarded_for)
+				.json(
+					&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": "foo@bar"}"#)
+						.unwrap(),
+				)
+				.reply(&routes)
+		};
+
+		// Two requests from this client IP are in flight.
+		let limiter = config.get_concurrency_limiter().unwrap();
+		let ip = "1.2.3.4".parse().unwrap();
+		let in_flight = [limiter.acquire(ip), limiter.acquire(ip)];
+
+		let resp = check("1.2.3.4").await;
+		assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+		assert!(resp
+			.body()
+			.starts_with(br#"{"error":"Too many concurrent requests from this IP""#));
+		// Other client IPs aren't limited.
+		let resp = check("5.6.7.8").await;
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+
+		drop(in_flight);
+		let resp = check("1.2.3.4").await;
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+	}
+
 	#[tokio::test]
 	async fn test_reacher_secret_missing_header() {
 		let resp = request()
@@ -121,6 +220,131 @@ mod tests {
 			.await;
 
 		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", resp.body());
-		assert_eq!(resp.body(), r#"{"error":"to_email field is required."}"#);
+		assert_eq!(
+			resp.body(),
+			r#"{"error":"to_email field is required.","code":"missing_to_email"}"#
+		);
+	}
+
+	#[tokio::test]
+	async fn test_invalid_verif_method() {
+		let resp = request()
+			.path("/v0/check_email")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.json(&serde_json::json!({
+				"to_email": "foo@gmail.com",
+				"gmail_verif_method": "foo",
+			}))
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", resp.body());
+		assert!(std::str::from_utf8(resp.body())
+			.unwrap()
+			.contains("unknown variant `foo`"));
+	}
+
+	#[tokio::test]
+	async fn test_reacher_to_mail_empty_localized() {
+		let resp = request()
+			.path("/v0/check_email")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.header("Accept-Language", "fr")
+			.json(&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": ""}"#).unwrap())
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", resp.body());
+		assert_eq!(
+			resp.body(),
+			r#"{"error":"Le champ to_email est obligatoire.","code":"missing_to_email"}"#
+		);
+	}
+
+	#[tokio::test]
+	async fn test_check_email_stream() {
+		let resp = request()
+			.path("/v1/check_email_stream")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.body(r#"[{"to_email": "foo@bar"}, {"to_email": "bar@baz"}, {"to_email": "baz"}]"#)
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+		assert_eq!(
+			resp.headers().get("Content-Type").unwrap(),
+			"application/x-ndjson"
+		);
+		let body = std::str::from_utf8(resp.body()).unwrap();
+		let mut inputs = body
+			.lines()
+			.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["input"].clone())
+			.collect::<Vec<_>>();
+		// Results come in the order in which they complete.
+		inputs.sort_by_key(|input| input.to_string());
+		assert_eq!(inputs, vec!["bar@baz", "baz", "foo@bar"]);
+	}
+
+	#[tokio::test]
+	async fn test_check_email_stream_empty() {
+		let resp = request()
+			.path("/v1/check_email_stream")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.body("[]")
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", resp.body());
+		assert_eq!(
+			resp.body(),
+			r#"{"error":"Empty input","code":"empty_input"}"#
+		);
+	}
+
+	#[tokio::test]
+	async fn test_request_id_is_echoed() {
+		let resp = request()
+			.path("/v0/check_email")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.header(REQUEST_ID_HEADER, "my-request-id")
+			.json(&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": "foo@bar"}"#).unwrap())
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+		assert_eq!(resp.headers()[REQUEST_ID_HEADER], "my-request-id");
+
+		// Error responses carry the id too.
+		let resp = request()
+			.path("/v0/check_email")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.header(REQUEST_ID_HEADER, "my-request-id")
+			.json(&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": ""}"#).unwrap())
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", resp.body());
+		assert_eq!(resp.headers()[REQUEST_ID_HEADER], "my-request-id");
+	}
+
+	#[tokio::test]
+	async fn test_request_id_is_generated() {
+		let resp = request()
+			.path("/v0/check_email")
+			.method("POST")
+			.header(REACHER_SECRET_HEADER, "foobar")
+			.json(&serde_json::from_str::<CheckEmailRequest>(r#"{"to_email": "foo@bar"}"#).unwrap())
+			.reply(&create_routes(create_backend_config("foobar")))
+			.await;
+
+		assert_eq!(resp.status(), StatusCode::OK, "{:?}", resp.body());
+		let request_id = resp.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+		assert!(uuid::Uuid::parse_str(request_id).is_ok());
 	}
 }
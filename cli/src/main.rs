@@ -80,6 +80,11 @@ pub struct Cli {
 	#[clap(long, env, parse(try_from_str))]
 	pub haveibeenpwned_api_key: Option<String>,
 
+	/// Whether to also fetch the HaveIBeenPwned pastes of the email, if the
+	/// API key is provided.
+	#[clap(long, env, default_value = "false", parse(try_from_str))]
+	pub check_haveibeenpwned_pastes: bool,
+
 	/// The email to check.
 	pub to_email: String,
 }
@@ -104,7 +109,8 @@ async fn main() -> Result<(), anyhow::Error> {
 		.hotmailb2b_verif_method(CONF.hotmailb2b_verif_method)
 		.hotmailb2c_verif_method(CONF.hotmailb2c_verif_method)
 		.check_gravatar(CONF.check_gravatar)
-		.haveibeenpwned_api_key(CONF.haveibeenpwned_api_key.clone());
+		.haveibeenpwned_api_key(CONF.haveibeenpwned_api_key.clone())
+		.check_haveibeenpwned_pastes(CONF.check_haveibeenpwned_pastes);
 
 	if let Some(proxy_host) = &CONF.proxy_host {
 		input = input.proxy(Some(CheckEmailInputProxy {
@@ -14,8 +14,18 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::limiter::GlobalLimiter;
+use crate::misc::{BreachProviders, DisposableList};
+use crate::mx::create_resolver;
+use crate::smtp::{
+	CatchAllCache, EgressMonitor, HeadlessConfig, HostLatencies, SmtpPool, WebDriverPool,
+};
 #[cfg(feature = "sentry")]
 use crate::util::sentry::SentryConfig;
+use crate::{CheckEmailOutput, Reachable};
+use hickory_resolver::TokioAsyncResolver;
+use std::fmt;
+use std::sync::Arc;
 
 /// Configuration needed to run Reacher.
 #[derive(Debug)]
@@ -24,8 +34,128 @@ pub struct ReacherConfig {
 	pub backend_name: String,
 	/// The address of the WebDriver server.
 	pub webdriver_addr: String,
+	/// Pool of WebDriver sessions used by the headless verifications. Share
+	/// it between calls to `check_email` so that the sessions are reused.
+	pub webdriver_pool: Arc<WebDriverPool>,
+	/// Selectors of the page elements the headless verifications interact
+	/// with, with fallbacks in case the providers change their markup.
+	pub headless: HeadlessConfig,
 	#[cfg(feature = "sentry")]
 	pub sentry: Option<SentryConfig>,
+	/// DNS resolver used for the MX lookups. It caches the DNS answers
+	/// according to their TTL, so share it between calls to `check_email`
+	/// to avoid looking up the same domains repeatedly.
+	pub resolver: Arc<TokioAsyncResolver>,
+	/// Response latency of the MX hosts, used by the input's
+	/// `adaptive_timeout`. Share it between calls to `check_email` so that
+	/// the latencies are learned across verifications.
+	pub smtp_latencies: Arc<HostLatencies>,
+	/// Detection of blocked outbound SMTP connections, which switches to a
+	/// degraded mode skipping the SMTP verification. Share it between calls
+	/// to `check_email` so that the failures are counted across
+	/// verifications. Defaults to no detection.
+	pub egress_monitor: Arc<EgressMonitor>,
+	/// Catch-all determination of each domain, reused by the next
+	/// verifications at the same domain. They still connect to the server,
+	/// up to MAIL FROM, to verify the address itself, but skip the `RCPT TO`
+	/// of the random addresses. Share it between calls to `check_email` so
+	/// that bulk runs benefit from it. Defaults to no caching.
+	pub catch_all_cache: Arc<CatchAllCache>,
+	/// Limit on the concurrent calls to `check_email`, and on the concurrent
+	/// SMTP verifications on each MX host. Share it between calls to
+	/// `check_email` so that independent callers share the limits. Defaults
+	/// to unlimited.
+	pub limiter: Arc<GlobalLimiter>,
+	/// Idle SMTP connections kept open after the verifications, and reused
+	/// by the next verifications on the same MX host, to skip the TCP and TLS
+	/// handshakes. Share it between calls to `check_email` so that the
+	/// connections are reused. Defaults to a disabled pool.
+	pub smtp_pool: Arc<SmtpPool>,
+	/// List of disposable domains used by the misc checks, which can be
+	/// replaced at runtime. Until a list is set, the bundled list is used.
+	pub disposable_list: Arc<DisposableList>,
+	/// Breach providers used by the misc checks, each with its own rate
+	/// limit. Share it between calls to `check_email` so that the rate
+	/// limits apply across verifications. Defaults to HaveIBeenPwned only,
+	/// which is enabled by the input's `haveibeenpwned_api_key`.
+	pub breach_providers: Arc<BreachProviders>,
+	/// Hook to override the computed `is_reachable` with custom rules, see
+	/// `PostClassify`. Defaults to None, which keeps the computed value.
+	pub post_classify: Option<PostClassify>,
+	/// HTTP client used for the Gravatar and breach providers' requests,
+	/// e.g. to add tracing or proxying middleware. The input's
+	/// `http_timeout` and `user_agent` don't apply to it, configure them on
+	/// the client instead. Defaults to None, which creates a client from
+	/// the input for each verification.
+	pub http_client: Option<reqwest::Client>,
+	/// Canned outputs returned by `check_email` for the mapped addresses
+	/// and domains, before any network call. Only meant for tests, so only
+	/// honoured with the `test-mode` feature, and ignored otherwise. Defaults
+	/// to None.
+	pub canned_results: Option<crate::test_mode::CannedResults>,
+	/// Hook called for each connection opened through the input's proxy,
+	/// see `OnProxyConnection`. Defaults to None.
+	pub on_proxy_connection: Option<OnProxyConnection>,
+}
+
+impl ReacherConfig {
+	/// Call the `on_proxy_connection` hook, if any.
+	pub(crate) fn record_proxy_connection(&self) {
+		if let Some(OnProxyConnection(hook)) = &self.on_proxy_connection {
+			hook();
+		}
+	}
+}
+
+/// Hook run on the output of `check_email`, before it's returned, to encode
+/// business rules, e.g. "our partner domain is always deliverable". It
+/// returns the `Reachable` overriding the output's `is_reachable`, with the
+/// reason of the override, or None to keep the computed value.
+#[derive(Clone)]
+pub struct PostClassify(pub Arc<PostClassifyFn>);
+
+/// The function of a `PostClassify` hook.
+pub type PostClassifyFn = dyn Fn(&CheckEmailOutput) -> Option<(Reachable, String)> + Send + Sync;
+
+impl PostClassify {
+	pub fn new<F>(hook: F) -> Self
+	where
+		F: Fn(&CheckEmailOutput) -> Option<(Reachable, String)> + Send + Sync + 'static,
+	{
+		Self(Arc::new(hook))
+	}
+}
+
+impl fmt::Debug for PostClassify {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("PostClassify")
+	}
+}
+
+/// Hook called for each connection opened through the input's proxy: the
+/// SMTP connections to each MX host, including the retries and the fresh
+/// connections after `max_rcpt_per_connection`, and the providers' HTTP API
+/// requests. It's e.g. used to track the usage of a metered proxy. The
+/// connections reused from the SMTP pool aren't counted.
+#[derive(Clone)]
+pub struct OnProxyConnection(pub Arc<OnProxyConnectionFn>);
+
+/// The function of an `OnProxyConnection` hook.
+pub type OnProxyConnectionFn = dyn Fn() + Send + Sync;
+
+impl OnProxyConnection {
+	pub fn new<F>(hook: F) -> Self
+	where
+		F: Fn() + Send + Sync + 'static,
+	{
+		Self(Arc::new(hook))
+	}
+}
+
+impl fmt::Debug for OnProxyConnection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("OnProxyConnection")
+	}
 }
 
 impl Default for ReacherConfig {
@@ -33,8 +163,22 @@ impl Default for ReacherConfig {
 		ReacherConfig {
 			backend_name: "backend-dev".into(),
 			webdriver_addr: "http://localhost:9515".into(),
+			webdriver_pool: Arc::new(WebDriverPool::default()),
+			headless: HeadlessConfig::default(),
 			#[cfg(feature = "sentry")]
 			sentry: None,
+			resolver: Arc::new(create_resolver()),
+			smtp_latencies: Arc::new(HostLatencies::default()),
+			egress_monitor: Arc::new(EgressMonitor::default()),
+			catch_all_cache: Arc::new(CatchAllCache::default()),
+			limiter: Arc::new(GlobalLimiter::default()),
+			smtp_pool: Arc::new(SmtpPool::default()),
+			disposable_list: Arc::new(DisposableList::default()),
+			breach_providers: Arc::new(BreachProviders::default()),
+			post_classify: None,
+			http_client: None,
+			canned_results: None,
+			on_proxy_connection: None,
 		}
 	}
 }
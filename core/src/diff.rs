@@ -0,0 +1,187 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare two verification outputs of the same email field by field, e.g.
+//! to detect behavior changes when re-running an address set across
+//! versions of this crate.
+
+use crate::CheckEmailOutput;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Fields which change at each verification, and are not compared, as paths
+/// in the serialized output. The verdict is derived from the other fields, so
+/// comparing it would only repeat their differences.
+const IGNORED_FIELDS: [&str; 6] = [
+	"debug.start_time",
+	"debug.end_time",
+	"debug.duration",
+	"debug.mx_lookup_duration",
+	"debug.cached_at",
+	"verdict",
+];
+
+/// A field whose value differs between two outputs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldDiff {
+	/// Path of the field in the serialized output, e.g. "smtp.is_catch_all"
+	/// or "mx.records[0]".
+	pub path: String,
+	/// The value in the first output, None if the field is missing.
+	pub before: Option<Value>,
+	/// The value in the second output, None if the field is missing.
+	pub after: Option<Value>,
+}
+
+/// The differences between two outputs, see [diff](diff).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OutputDiff {
+	/// The differing fields, sorted by path. Only the innermost fields are
+	/// listed, not their parent objects.
+	pub fields: Vec<FieldDiff>,
+}
+
+impl OutputDiff {
+	/// Whether both outputs are the same, timestamps and durations aside.
+	pub fn is_empty(&self) -> bool {
+		self.fields.is_empty()
+	}
+
+	/// The paths of the differing fields.
+	pub fn paths(&self) -> Vec<&str> {
+		self.fields.iter().map(|f| f.path.as_str()).collect()
+	}
+}
+
+/// Compare two outputs field by field, ignoring the timestamps and durations
+/// of the `debug` field. The outputs are compared as serialized, so errors
+/// are compared by their JSON representation.
+pub fn diff(a: &CheckEmailOutput, b: &CheckEmailOutput) -> OutputDiff {
+	let to_value = |output: &CheckEmailOutput| {
+		serde_json::to_value(output).expect("CheckEmailOutput is serializable. qed.")
+	};
+	let mut fields = vec![];
+	diff_values("", Some(&to_value(a)), Some(&to_value(b)), &mut fields);
+	// The fields only present in the second output come last.
+	fields.sort_by(|a, b| a.path.cmp(&b.path));
+
+	OutputDiff { fields }
+}
+
+/// Recursively compare two JSON values at `path`, pushing the differing
+/// leaves into `fields`.
+fn diff_values(path: &str, a: Option<&Value>, b: Option<&Value>, fields: &mut Vec<FieldDiff>) {
+	if IGNORED_FIELDS.contains(&path) || a == b {
+		return;
+	}
+
+	match (a, b) {
+		(Some(Value::Object(a)), Some(Value::Object(b))) => {
+			let keys = a.keys().chain(b.keys().filter(|k| !a.contains_key(*k)));
+			for key in keys {
+				let path = if path.is_empty() {
+					key.clone()
+				} else {
+					format!("{}.{}", path, key)
+				};
+				diff_values(&path, a.get(key), b.get(key), fields);
+			}
+		}
+		(Some(Value::Array(a)), Some(Value::Array(b))) => {
+			for i in 0..a.len().max(b.len()) {
+				diff_values(&format!("{}[{}]", path, i), a.get(i), b.get(i), fields);
+			}
+		}
+		_ => fields.push(FieldDiff {
+			path: path.to_string(),
+			before: a.cloned(),
+			after: b.cloned(),
+		}),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{DebugDetails, Reachable};
+	use std::time::{Duration, SystemTime};
+
+	#[test]
+	fn should_list_differing_fields_only() {
+		let a = CheckEmailOutput {
+			input: "foo@example.com".into(),
+			is_reachable: Reachable::Safe,
+			..Default::default()
+		};
+		let b = CheckEmailOutput {
+			input: "foo@example.com".into(),
+			is_reachable: Reachable::Invalid,
+			// Timestamps and durations are ignored.
+			debug: DebugDetails {
+				start_time: (SystemTime::now() + Duration::from_secs(60)).into(),
+				duration: Duration::from_secs(3),
+				..Default::default()
+			},
+			..Default::default()
+		};
+
+		let d = diff(&a, &b);
+		assert_eq!(
+			d.fields,
+			vec![FieldDiff {
+				path: "is_reachable".into(),
+				before: Some("safe".into()),
+				after: Some("invalid".into()),
+			}]
+		);
+		assert!(diff(&a, &a).is_empty());
+	}
+
+	#[test]
+	fn should_sort_fields_by_path() {
+		let a = CheckEmailOutput::default();
+		let mut b = CheckEmailOutput {
+			mta_sts: Some(Ok(Default::default())),
+			..Default::default()
+		};
+		b.syntax.is_valid_syntax = true;
+
+		assert_eq!(
+			diff(&a, &b).paths(),
+			vec!["mta_sts", "syntax.is_valid_syntax"]
+		);
+	}
+
+	#[test]
+	fn should_diff_nested_and_missing_fields() {
+		let mut fields = vec![];
+		diff_values(
+			"",
+			Some(
+				&serde_json::json!({ "mx": { "records": ["a.", "b."] }, "debug": { "end_time": 1 } }),
+			),
+			Some(
+				&serde_json::json!({ "mx": { "records": ["a."] }, "debug": { "end_time": 2 }, "mta_sts": {} }),
+			),
+			&mut fields,
+		);
+
+		let d = OutputDiff { fields };
+		assert_eq!(d.paths(), vec!["mx.records[1]", "mta_sts"]);
+		assert_eq!(d.fields[0].after, None);
+		assert_eq!(d.fields[1].before, None);
+	}
+}
@@ -0,0 +1,244 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Estimate the cost of verifying a list of emails, without running the
+//! verifications. Useful to plan bulk jobs.
+
+use crate::syntax::check_syntax;
+use crate::{CheckEmailInput, GmailVerifMethod, HotmailB2CVerifMethod, YahooVerifMethod};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Rough duration of a verification via SMTP.
+const SMTP_DURATION: Duration = Duration::from_secs(3);
+/// Rough duration of a verification via a provider's HTTP API.
+const API_DURATION: Duration = Duration::from_secs(1);
+/// Rough duration of a verification via a headless browser.
+const HEADLESS_DURATION: Duration = Duration::from_secs(10);
+/// Rough duration of each misc HTTP check, i.e. Gravatar or HaveIBeenPwned.
+const MISC_HTTP_DURATION: Duration = Duration::from_millis(500);
+
+/// The email provider, guessed from the email's domain only. Unlike the MX
+/// based detection done during the verification, Google Workspace or
+/// Microsoft 365 domains are not recognized, and fall into `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EstimateProvider {
+	/// @gmail.com and @googlemail.com.
+	Gmail,
+	/// Consumer Microsoft domains, e.g. @hotmail.com or @outlook.com.
+	HotmailB2c,
+	/// Yahoo domains, e.g. @yahoo.com or @ymail.com.
+	Yahoo,
+	/// Any other domain.
+	Other,
+}
+
+impl EstimateProvider {
+	fn from_domain(domain: &str) -> Self {
+		let domain = domain.to_lowercase();
+		match domain.as_str() {
+			"gmail.com" | "googlemail.com" => Self::Gmail,
+			"hotmail.com" | "outlook.com" | "live.com" | "msn.com" => Self::HotmailB2c,
+			"ymail.com" | "rocketmail.com" => Self::Yahoo,
+			d if d.starts_with("yahoo.") || d.contains(".yahoo.") => Self::Yahoo,
+			_ => Self::Other,
+		}
+	}
+}
+
+/// Estimated counts for a single provider, by verification method.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProviderEstimate {
+	/// Number of emails of this provider.
+	pub count: usize,
+	/// Number of emails verified via SMTP.
+	pub smtp: usize,
+	/// Number of emails verified via the provider's HTTP API.
+	pub api: usize,
+	/// Number of emails verified via a headless browser.
+	pub headless: usize,
+}
+
+/// The result of [estimate](estimate).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Estimate {
+	/// Number of inputs.
+	pub total: usize,
+	/// Number of inputs with an invalid syntax, which are not verified.
+	pub invalid_syntax: usize,
+	/// Counts by provider, for inputs with a valid syntax.
+	pub providers: BTreeMap<EstimateProvider, ProviderEstimate>,
+	/// Number of misc HTTP checks, i.e. Gravatar and HaveIBeenPwned.
+	pub misc_http_checks: usize,
+	/// Estimated time to verify all the inputs one after the other.
+	pub sequential_duration: Duration,
+}
+
+impl Estimate {
+	/// Number of inputs needing a headless browser, across all providers.
+	pub fn headless(&self) -> usize {
+		self.providers.values().map(|p| p.headless).sum()
+	}
+
+	/// Estimated time to verify all the inputs when running `concurrency`
+	/// verifications in parallel. A concurrency of 0 is treated as 1.
+	pub fn duration(&self, concurrency: usize) -> Duration {
+		let concurrency = concurrency.max(1) as u32;
+		let total = self.sequential_duration;
+		// Round up, a partial batch still takes time.
+		(total + Duration::from_nanos(concurrency as u64 - 1)) / concurrency
+	}
+}
+
+/// Estimate the cost of verifying the given inputs, using only offline
+/// information: the provider guessed from the email's domain, the
+/// verification method configured for that provider, and the enabled misc
+/// checks. No network call is made, and the durations are rough averages.
+pub fn estimate(inputs: &[CheckEmailInput]) -> Estimate {
+	let mut estimate = Estimate {
+		total: inputs.len(),
+		..Default::default()
+	};
+
+	for input in inputs {
+		let syntax = check_syntax(&input.to_email);
+		if !syntax.is_valid_syntax {
+			estimate.invalid_syntax += 1;
+			continue;
+		}
+
+		let provider = EstimateProvider::from_domain(&syntax.domain);
+		let entry = estimate.providers.entry(provider).or_default();
+		entry.count += 1;
+		let duration = match provider {
+			EstimateProvider::Gmail
+				if matches!(input.gmail_verif_method, GmailVerifMethod::Api) =>
+			{
+				entry.api += 1;
+				API_DURATION
+			}
+			EstimateProvider::Yahoo
+				if matches!(input.yahoo_verif_method, YahooVerifMethod::Api) =>
+			{
+				entry.api += 1;
+				API_DURATION
+			}
+			EstimateProvider::Yahoo
+				if matches!(input.yahoo_verif_method, YahooVerifMethod::Headless) =>
+			{
+				entry.headless += 1;
+				HEADLESS_DURATION
+			}
+			EstimateProvider::HotmailB2c
+				if matches!(
+					input.hotmailb2c_verif_method,
+					HotmailB2CVerifMethod::Headless
+				) =>
+			{
+				entry.headless += 1;
+				HEADLESS_DURATION
+			}
+			_ => {
+				entry.smtp += 1;
+				SMTP_DURATION
+			}
+		};
+
+		let misc_http_checks = input.check_gravatar as usize
+			+ input.haveibeenpwned_api_key.is_some() as usize
+			+ (input.haveibeenpwned_api_key.is_some() && input.check_haveibeenpwned_pastes)
+				as usize;
+		estimate.misc_http_checks += misc_http_checks;
+		estimate.sequential_duration += duration + MISC_HTTP_DURATION * misc_http_checks as u32;
+	}
+
+	estimate
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::CheckEmailInputBuilder;
+
+	fn input(email: &str) -> CheckEmailInput {
+		CheckEmailInputBuilder::default()
+			.to_email(email.into())
+			.build()
+			.unwrap()
+	}
+
+	#[test]
+	fn should_break_down_by_provider() {
+		let inputs = vec![
+			input("foo@gmail.com"),
+			input("bar@googlemail.com"),
+			input("foo@yahoo.com"),
+			input("foo@example.org"),
+			input("not an email"),
+		];
+
+		let estimate = estimate(&inputs);
+
+		assert_eq!(estimate.total, 5);
+		assert_eq!(estimate.invalid_syntax, 1);
+		assert_eq!(
+			estimate.providers.get(&EstimateProvider::Gmail),
+			Some(&ProviderEstimate {
+				count: 2,
+				smtp: 2,
+				..Default::default()
+			})
+		);
+		assert_eq!(
+			estimate.providers.get(&EstimateProvider::Yahoo),
+			Some(&ProviderEstimate {
+				count: 1,
+				headless: 1,
+				..Default::default()
+			})
+		);
+		assert_eq!(estimate.providers[&EstimateProvider::Other].smtp, 1);
+		assert_eq!(estimate.headless(), 1);
+		assert_eq!(estimate.misc_http_checks, 0);
+		assert_eq!(
+			estimate.sequential_duration,
+			SMTP_DURATION * 3 + HEADLESS_DURATION
+		);
+		assert_eq!(
+			estimate.duration(2),
+			(SMTP_DURATION * 3 + HEADLESS_DURATION) / 2
+		);
+	}
+
+	#[test]
+	fn should_count_misc_http_checks() {
+		let mut input = input("foo@yahoo.com");
+		input.yahoo_verif_method = YahooVerifMethod::Api;
+		input.check_gravatar = true;
+		input.haveibeenpwned_api_key = Some("key".into());
+
+		let estimate = estimate(&[input]);
+
+		assert_eq!(estimate.providers[&EstimateProvider::Yahoo].api, 1);
+		assert_eq!(estimate.misc_http_checks, 2);
+		assert_eq!(
+			estimate.sequential_duration,
+			API_DURATION + MISC_HTTP_DURATION * 2
+		);
+	}
+}
@@ -14,42 +14,361 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::LOG_TARGET;
-use pwned::api::PwnedBuilder;
+use crate::misc::{create_http_client, BreachProvider, MiscError};
+use crate::{CheckEmailInput, LOG_TARGET};
+use futures::future::BoxFuture;
+use reqwest::header::USER_AGENT;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+const API_BASE_URL: &str = "https://haveibeenpwned.com/api/v3/breachedaccount/";
+const PASTES_API_BASE_URL: &str = "https://haveibeenpwned.com/api/v3/pasteaccount/";
+
+/// HaveIBeenPwned rejects requests without a User-Agent, so we fall back to
+/// this one when none is configured.
+const DEFAULT_USER_AGENT: &str = "reacher";
+
+/// The URL of the email's account at this API base URL. The email is
+/// percent-encoded as a single path segment, so that e.g. a "/" or a "?" in
+/// its local part doesn't change the request.
+fn account_url(base_url: &str, to_email: &str) -> Url {
+	let mut url = Url::parse(base_url).expect("The API base URL is valid. qed.");
+	url.path_segments_mut()
+		.expect("The API base URL is not a cannot-be-a-base URL. qed.")
+		.pop_if_empty()
+		.push(to_email);
+	url
+}
 
 /// Check if the email has been found in any breach or paste using the
 /// HaveIBeenPwned API.
-/// This function will return the number of times the email has been found in
-/// any breach.
-pub async fn check_haveibeenpwned(to_email: &str, api_key: Option<String>) -> Option<bool> {
-	let pwned = PwnedBuilder::default()
-		.user_agent("reacher")
-		.api_key(api_key)
-		.build()
-		.unwrap();
-
-	match pwned.check_email(to_email).await {
+/// This function will return whether the email has been found in any
+/// breach, or why the API call failed, e.g. Unauthorized for a bad API key.
+pub async fn check_haveibeenpwned(
+	to_email: &str,
+	api_key: &str,
+	user_agent: Option<&str>,
+	client: &reqwest::Client,
+) -> Result<bool, MiscError> {
+	check_haveibeenpwned_at(API_BASE_URL, to_email, api_key, user_agent, client).await
+}
+
+/// Like `check_haveibeenpwned`, with the API at this base URL.
+async fn check_haveibeenpwned_at(
+	base_url: &str,
+	to_email: &str,
+	api_key: &str,
+	user_agent: Option<&str>,
+	client: &reqwest::Client,
+) -> Result<bool, MiscError> {
+	let response = client
+		.get(account_url(base_url, to_email))
+		.query(&[("truncateResponse", "false")])
+		.header(USER_AGENT, user_agent.unwrap_or(DEFAULT_USER_AGENT))
+		.header("hibp-api-key", api_key)
+		.send()
+		.await;
+
+	let result = match response {
+		// The API answers 404 for emails not found in any breach.
+		Ok(response) if response.status() == StatusCode::NOT_FOUND => Ok(vec![]),
+		Ok(response) => match MiscError::from_response(&response) {
+			Some(e) => Err(e),
+			None => response
+				.json::<Vec<serde_json::Value>>()
+				.await
+				.map_err(MiscError::from),
+		},
+		Err(e) => Err(MiscError::from(e)),
+	};
+
+	match result {
 		Ok(answer) => {
 			log::debug!(
 				target: LOG_TARGET,
 				"Email found in {} breaches",
 				answer.len()
 			);
-			Some(!answer.is_empty())
+			Ok(!answer.is_empty())
 		}
 		Err(e) => {
 			log::error!(
 				target: LOG_TARGET,
-				"Error while checking if email has been pwned: {}",
+				"Error while checking if email has been pwned: {:?}",
 				e
 			);
-			match e {
-				pwned::errors::Error::IoError(e) => match e.kind() {
-					std::io::ErrorKind::NotFound => Some(false),
-					_ => None,
-				},
-				_ => None,
-			}
+			Err(e)
 		}
 	}
 }
+
+/// A paste the email address appears in, e.g. on Pastebin.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
+pub struct PasteDetails {
+	/// The paste service, e.g. "Pastebin".
+	pub source: String,
+	/// The ID of the paste on the service.
+	pub id: String,
+	/// The title of the paste, if any.
+	pub title: Option<String>,
+	/// When the paste was posted, if known.
+	pub date: Option<String>,
+	/// The number of email addresses found in the paste.
+	pub email_count: u64,
+}
+
+/// A paste, as returned by the API.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiPaste {
+	source: String,
+	id: String,
+	title: Option<String>,
+	date: Option<String>,
+	email_count: u64,
+}
+
+impl From<ApiPaste> for PasteDetails {
+	fn from(paste: ApiPaste) -> Self {
+		Self {
+			source: paste.source,
+			id: paste.id,
+			title: paste.title,
+			date: paste.date,
+			email_count: paste.email_count,
+		}
+	}
+}
+
+/// Name of the pastes check in `MiscDetails::errors`.
+pub const HAVEIBEENPWNED_PASTES: &str = "haveibeenpwned_pastes";
+
+/// Fetch the pastes the email address appears in using the HaveIBeenPwned
+/// API. Errors are mapped like in `check_haveibeenpwned`, e.g. RateLimited
+/// on a 429.
+pub async fn check_haveibeenpwned_pastes(
+	to_email: &str,
+	api_key: &str,
+	user_agent: Option<&str>,
+	client: &reqwest::Client,
+) -> Result<Vec<PasteDetails>, MiscError> {
+	check_haveibeenpwned_pastes_at(PASTES_API_BASE_URL, to_email, api_key, user_agent, client).await
+}
+
+/// Like `check_haveibeenpwned_pastes`, with the API at this base URL.
+async fn check_haveibeenpwned_pastes_at(
+	base_url: &str,
+	to_email: &str,
+	api_key: &str,
+	user_agent: Option<&str>,
+	client: &reqwest::Client,
+) -> Result<Vec<PasteDetails>, MiscError> {
+	let response = client
+		.get(account_url(base_url, to_email))
+		.header(USER_AGENT, user_agent.unwrap_or(DEFAULT_USER_AGENT))
+		.header("hibp-api-key", api_key)
+		.send()
+		.await?;
+
+	// The API answers 404 for emails not found in any paste.
+	if response.status() == StatusCode::NOT_FOUND {
+		return Ok(vec![]);
+	}
+	if let Some(e) = MiscError::from_response(&response) {
+		return Err(e);
+	}
+	let pastes = response.json::<Vec<ApiPaste>>().await?;
+	log::debug!(
+		target: LOG_TARGET,
+		"Email found in {} pastes",
+		pastes.len()
+	);
+
+	Ok(pastes.into_iter().map(PasteDetails::from).collect())
+}
+
+/// The HaveIBeenPwned breach provider, enabled by the input's
+/// `haveibeenpwned_api_key`. Its result also fills
+/// `MiscDetails::haveibeenpwned`.
+#[derive(Debug, Default)]
+pub struct HaveIBeenPwned;
+
+/// Name of the HaveIBeenPwned breach provider.
+pub const HAVEIBEENPWNED_PROVIDER: &str = "haveibeenpwned";
+
+impl BreachProvider for HaveIBeenPwned {
+	fn name(&self) -> &str {
+		HAVEIBEENPWNED_PROVIDER
+	}
+
+	fn check<'a>(
+		&'a self,
+		email: &'a str,
+		input: &'a CheckEmailInput,
+		client: Option<&'a reqwest::Client>,
+	) -> BoxFuture<'a, Option<Result<bool, MiscError>>> {
+		Box::pin(async move {
+			let api_key = input.haveibeenpwned_api_key.as_ref()?;
+			let client = match client {
+				Some(client) => client.clone(),
+				None => create_http_client(input)
+					.map_err(|err| {
+						log::error!(
+							target: LOG_TARGET,
+							"[email={}] Error creating HTTP client: {}",
+							email,
+							err
+						)
+					})
+					.ok()?,
+			};
+			Some(check_haveibeenpwned(email, api_key, input.user_agent.as_deref(), &client).await)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+
+	/// Start an HTTP server answering one request with this response, and
+	/// return its base URL.
+	async fn serve_once(response: impl Into<String>) -> String {
+		let response = response.into();
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut buf = vec![0; 4096];
+			let _ = socket.read(&mut buf).await.unwrap();
+			socket.write_all(response.as_bytes()).await.unwrap();
+		});
+		format!("http://{addr}/")
+	}
+
+	async fn check(response: &'static str) -> Result<bool, MiscError> {
+		let base_url = serve_once(response).await;
+		let client = reqwest::Client::new();
+		check_haveibeenpwned_at(&base_url, "foo@example.com", "key", None, &client).await
+	}
+
+	#[tokio::test]
+	async fn should_map_401_to_unauthorized() {
+		let res = check("HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n").await;
+		assert!(matches!(res, Err(MiscError::Unauthorized(_))), "{:?}", res);
+	}
+
+	#[tokio::test]
+	async fn should_map_429_to_rate_limited() {
+		let res =
+			check("HTTP/1.1 429 Too Many Requests\r\nretry-after: 2\r\ncontent-length: 0\r\n\r\n")
+				.await;
+		assert_eq!(res, Err(MiscError::RateLimited(Duration::from_secs(2))));
+	}
+
+	#[tokio::test]
+	async fn should_map_invalid_body_to_deserialization() {
+		let res = check("HTTP/1.1 200 OK\r\ncontent-length: 3\r\n\r\nfoo").await;
+		assert!(
+			matches!(res, Err(MiscError::Deserialization(_))),
+			"{:?}",
+			res
+		);
+
+		let res = check("HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n").await;
+		assert_eq!(res, Ok(false));
+	}
+
+	#[tokio::test]
+	async fn should_apply_the_input_http_timeout() {
+		// Accept the connection but never answer.
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (_socket, _) = listener.accept().await.unwrap();
+			tokio::time::sleep(Duration::from_secs(10)).await;
+		});
+		let input = crate::CheckEmailInputBuilder::default()
+			.http_timeout(Some(Duration::from_millis(100)))
+			.build()
+			.unwrap();
+		let client = create_http_client(&input).unwrap();
+
+		let res = tokio::time::timeout(
+			Duration::from_secs(5),
+			check_haveibeenpwned_at(
+				&format!("http://{addr}/"),
+				"foo@example.com",
+				"key",
+				None,
+				&client,
+			),
+		)
+		.await
+		.expect("The request timed out on its own.");
+		assert!(matches!(res, Err(MiscError::Network(_))), "{:?}", res);
+	}
+
+	#[test]
+	fn should_percent_encode_the_email() {
+		assert_eq!(
+			account_url(API_BASE_URL, "foo@example.com").as_str(),
+			"https://haveibeenpwned.com/api/v3/breachedaccount/foo@example.com"
+		);
+		assert_eq!(
+			account_url(PASTES_API_BASE_URL, "a/b?c#d%@example.com").as_str(),
+			"https://haveibeenpwned.com/api/v3/pasteaccount/a%2Fb%3Fc%23d%25@example.com"
+		);
+	}
+
+	#[tokio::test]
+	async fn should_parse_pastes() {
+		const BODY: &str = r#"[
+			{"Source":"Pastebin","Id":"8Q0BvKD8","Title":"syslog","Date":"2014-03-04T19:14:54Z","EmailCount":139},
+			{"Source":"Pastie","Id":"7152479","Title":null,"Date":null,"EmailCount":30}
+		]"#;
+		let base_url = serve_once(format!(
+			"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+			BODY.len(),
+			BODY
+		))
+		.await;
+		let client = reqwest::Client::new();
+
+		let pastes =
+			check_haveibeenpwned_pastes_at(&base_url, "foo@example.com", "key", None, &client)
+				.await
+				.unwrap();
+		assert_eq!(
+			pastes,
+			vec![
+				PasteDetails {
+					source: "Pastebin".into(),
+					id: "8Q0BvKD8".into(),
+					title: Some("syslog".into()),
+					date: Some("2014-03-04T19:14:54Z".into()),
+					email_count: 139,
+				},
+				PasteDetails {
+					source: "Pastie".into(),
+					id: "7152479".into(),
+					title: None,
+					date: None,
+					email_count: 30,
+				},
+			]
+		);
+
+		let base_url = serve_once(
+			"HTTP/1.1 429 Too Many Requests\r\nretry-after: 1\r\ncontent-length: 0\r\n\r\n",
+		)
+		.await;
+		let res =
+			check_haveibeenpwned_pastes_at(&base_url, "foo@example.com", "key", None, &client)
+				.await;
+		assert_eq!(res, Err(MiscError::RateLimited(Duration::from_secs(1))));
+	}
+}
@@ -71,39 +71,95 @@
 //! ```
 
 pub mod config;
+pub mod diff;
+pub mod estimate;
 mod haveibeenpwned;
+pub mod limiter;
 pub mod misc;
+pub mod mta_sts;
 pub mod mx;
 mod rules;
 pub mod smtp;
 pub mod syntax;
+pub mod test_mode;
 mod util;
+pub mod verdict;
+pub mod warm_up;
 
+use async_smtp::smtp::error::Error as AsyncSmtpError;
 use config::ReacherConfig;
-use hickory_proto::rr::rdata::MX;
-use misc::{check_misc, MiscDetails};
-use mx::check_mx;
+pub use diff::{diff, OutputDiff};
+pub use estimate::{estimate, Estimate};
+use futures::stream::{self, Stream, StreamExt};
+use hickory_proto::rr::{rdata::MX, Name};
+use hickory_resolver::error::ResolveError;
+use misc::{
+	account_type, check_misc_with, disposable_match, find_domain_in_list, is_free_provider,
+	role_match, MiscDetails,
+};
+use mta_sts::check_mta_sts;
+use mx::{check_mx, find_parking_host, MxDetails, MxError};
+use rand::seq::SliceRandom;
 use rand::Rng;
-use smtp::{check_smtp, SmtpDetails, SmtpError};
+use smtp::{check_smtp_on_hosts, SmtpDebug, SmtpDetails, SmtpError, VerifMethod};
 pub use smtp::{is_gmail, is_hotmail, is_hotmail_b2b, is_hotmail_b2c, is_yahoo};
+use std::path::Path;
 use std::time::{Duration, SystemTime};
-use syntax::{check_syntax, get_similar_mail_provider};
+use syntax::{check_syntax, get_similar_mail_provider, SyntaxDetails};
+pub use util::es_bulk::to_es_bulk;
 pub use util::input_output::*;
+pub use util::jsonl::run_jsonl_stream;
 #[cfg(feature = "sentry")]
 pub use util::sentry::*;
+pub use warm_up::{warm_up, WarmUpReport};
 
 use crate::rules::{has_rule, Rule};
 
 /// The target where to log check-if-email-exists logs.
 pub const LOG_TARGET: &str = "reacher";
 
+/// Parse and classify an email address without any network call: no DNS,
+/// SMTP or HTTP request is made. This is useful to cheaply pre-filter a list
+/// of emails before running the full [check_email](check_email). The input's
+/// offline options, i.e. `disposable_allowlist`, are applied like in
+/// `check_email`, and its network options are ignored.
+pub fn validate_offline(email: &str, input: &CheckEmailInput) -> OfflineOutput {
+	let mut syntax = check_syntax(email);
+	if !syntax.is_valid_syntax {
+		return OfflineOutput {
+			input: email.to_string(),
+			syntax,
+			..Default::default()
+		};
+	}
+
+	get_similar_mail_provider(&mut syntax);
+
+	OfflineOutput {
+		input: email.to_string(),
+		is_disposable: disposable_match(&syntax, input, None).is_some(),
+		is_role_account: role_match(&syntax.username).is_some(),
+		is_free_provider: is_free_provider(&syntax.domain),
+		syntax,
+	}
+}
+
 /// Given an email's misc and smtp details, calculate an estimate of our
 /// confidence on how reachable the email is.
 ///
 /// Maybe we can switch to a points-based system?
 /// ref: https://github.com/reacherhq/check-if-email-exists/issues/935
 fn calculate_reachable(misc: &MiscDetails, smtp: &Result<SmtpDetails, SmtpError>) -> Reachable {
+	if let Err(SmtpError::Parked(_)) = smtp {
+		return Reachable::Invalid;
+	}
+
 	if let Ok(smtp) = smtp {
+		// The server's replies to `RCPT TO` tell nothing.
+		if smtp.accepts_at_rcpt_only {
+			return Reachable::Unknown;
+		}
+
 		if misc.is_disposable || misc.is_role_account || smtp.is_catch_all || smtp.has_full_inbox {
 			return Reachable::Risky;
 		}
@@ -118,6 +174,128 @@ fn calculate_reachable(misc: &MiscDetails, smtp: &Result<SmtpDetails, SmtpError>
 	}
 }
 
+/// Whether the SMTP verification failed with a transient (4xx) error, even
+/// after all the retries.
+fn is_transient_error(smtp: &Result<SmtpDetails, SmtpError>) -> bool {
+	matches!(
+		smtp,
+		Err(SmtpError::SmtpError(AsyncSmtpError::Transient(_)))
+	)
+}
+
+/// The reasons behind the `is_reachable` of the output, i.e. the failed
+/// step, or the SMTP and misc details which make the email not Safe.
+fn reachable_reasons(output: &CheckEmailOutput) -> Vec<&'static str> {
+	if output.debug.reachable_override.is_some() {
+		return vec!["override"];
+	}
+	if !output.syntax.is_valid_syntax {
+		return vec!["invalid_syntax"];
+	}
+	match &output.smtp {
+		Err(SmtpError::Blocklisted(_)) => return vec!["blocklisted"],
+		Err(SmtpError::Allowlisted(_)) => return vec!["allowlisted"],
+		Err(SmtpError::Parked(_)) => return vec!["parked"],
+		Err(SmtpError::Degraded) => return vec!["degraded"],
+		Err(SmtpError::MethodsDisagree(_)) => return vec!["methods_disagree"],
+		Err(SmtpError::RelayDenied(_)) => return vec!["relay_denied"],
+		_ => {}
+	}
+	match &output.mx {
+		Err(_) => return vec!["mx_error"],
+		// Over a Unix domain socket, there's no MX lookup but we still
+		// verified the email via SMTP.
+		Ok(mx)
+			if mx.lookup.is_err()
+				&& !matches!(output.debug.smtp.verif_method, VerifMethod::Smtp(_)) =>
+		{
+			return vec!["no_mx_records"]
+		}
+		Ok(_) => {}
+	}
+	let smtp = match &output.smtp {
+		Ok(smtp) => smtp,
+		Err(_) if is_transient_error(&output.smtp) && output.is_reachable != Reachable::Unknown => {
+			return vec!["persistent_transient"]
+		}
+		Err(_) => return vec!["smtp_error"],
+	};
+
+	let mut reasons = vec![];
+	if let Ok(misc) = &output.misc {
+		if misc.is_disposable {
+			reasons.push("disposable");
+		}
+		if misc.is_role_account {
+			reasons.push("role_account");
+		}
+	}
+	let flags = [
+		(smtp.is_catch_all, "catch_all"),
+		(smtp.accepts_at_rcpt_only, "accepts_at_rcpt_only"),
+		(smtp.has_full_inbox, "full_inbox"),
+		(!smtp.can_connect_smtp, "cannot_connect_smtp"),
+		(!smtp.is_deliverable, "not_deliverable"),
+		(smtp.is_disabled, "disabled"),
+	];
+	reasons.extend(flags.iter().filter(|(flag, _)| *flag).map(|(_, r)| *r));
+
+	reasons
+}
+
+/// Emit a single `tracing` event with the decision of `check_email`, and the
+/// inputs which mattered, for auditing. It doesn't log anything else about
+/// the email than the email itself and its domain.
+///
+/// Fields:
+/// - `email`, `domain`: the verified email and its domain,
+/// - `provider`: "gmail", "hotmail_b2b", "hotmail_b2c", "yahoo" or "other",
+///   from the first MX host, or "none" if there's none,
+/// - `verif_method`: "smtp", "api", "headless" or "skipped",
+/// - `is_reachable`: "safe", "risky", "invalid" or "unknown",
+/// - `reasons`: see `reachable_reasons`, comma-separated.
+fn log_decision(output: &CheckEmailOutput) {
+	let first_mx = output
+		.mx
+		.as_ref()
+		.ok()
+		.and_then(|mx| mx.lookup.as_ref().ok())
+		.and_then(|lookup| lookup.iter().next())
+		.map(|host| host.exchange().to_string());
+	let provider = match first_mx.as_deref() {
+		None => "none",
+		Some(host) if is_gmail(host) => "gmail",
+		Some(host) if is_hotmail_b2b(host) => "hotmail_b2b",
+		Some(host) if is_hotmail_b2c(host) => "hotmail_b2c",
+		Some(host) if is_yahoo(host) => "yahoo",
+		Some(_) => "other",
+	};
+	let verif_method = match output.debug.smtp.verif_method {
+		VerifMethod::Smtp(_) => "smtp",
+		VerifMethod::Api => "api",
+		VerifMethod::Headless => "headless",
+		VerifMethod::Skipped => "skipped",
+	};
+	let is_reachable = match output.is_reachable {
+		Reachable::Safe => "safe",
+		Reachable::Risky => "risky",
+		Reachable::Invalid => "invalid",
+		Reachable::Unknown => "unknown",
+	};
+
+	tracing::info!(
+		target: LOG_TARGET,
+		email = output.input.as_str(),
+		domain = output.syntax.domain.as_str(),
+		provider,
+		verif_method,
+		is_reachable,
+		reasons = reachable_reasons(output).join(",").as_str(),
+		retries_used = output.debug.smtp.retries_used,
+		"Email verification decision"
+	);
+}
+
 /// The main function of this library: verify a single email. Performs, in the
 /// following order, 4 types of verifications:
 /// - syntax check: verify the email is well-formed,
@@ -128,7 +306,79 @@ fn calculate_reachable(misc: &MiscDetails, smtp: &Result<SmtpDetails, SmtpError>
 ///
 /// Returns a `CheckEmailOutput` output, whose `is_reachable` field is one of
 /// `Safe`, `Invalid`, `Risky` or `Unknown`.
+///
+/// The verification stops at the first step which fails: an invalid syntax
+/// or a blocklisted domain skips all the network checks, and a domain
+/// without MX records skips the misc and SMTP checks. Skipped steps are left
+/// to their default values.
+///
+/// With the `test-mode` feature, an email mapped in the config's
+/// `canned_results` gets its canned output as is, before any of these steps.
+///
+/// The config's `limiter` bounds the number of concurrent calls, the calls
+/// over its limit wait for a slot before starting.
+///
+/// The config's `post_classify` hook, if set, may then override
+/// `is_reachable`. At the end, a `tracing` event with the `reacher` target
+/// records the decision, see `log_decision`.
 pub async fn check_email(input: &CheckEmailInput, config: &ReacherConfig) -> CheckEmailOutput {
+	#[cfg(feature = "test-mode")]
+	if let Some(output) = config
+		.canned_results
+		.as_ref()
+		.and_then(|canned| canned.find(input))
+	{
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Returning canned output, test mode is on",
+			input.to_email
+		);
+		return output;
+	}
+
+	let permit = config.limiter.acquire().await;
+	let mut output = verify_email(input, config).await;
+	drop(permit);
+	if let Some(post_classify) = &config.post_classify {
+		if let Some((is_reachable, reason)) = (post_classify.0)(&output) {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Overriding is_reachable {:?} with {:?}: {}",
+				output.input,
+				output.is_reachable,
+				is_reachable,
+				reason
+			);
+			output.is_reachable = is_reachable;
+			output.debug.reachable_override = Some(reason);
+		}
+	}
+	log_decision(&output);
+	output
+}
+
+/// Verify a batch of emails with [check_email](check_email), running at most
+/// `concurrency` verifications at a time.
+///
+/// Each output is yielded as soon as it's ready, so not necessarily in the
+/// order of the inputs, together with the index of its input in `inputs`.
+/// The stream is lazy: verifications only start as it's polled, which lets
+/// the caller apply their own backpressure.
+pub fn check_email_stream<'a, I>(
+	inputs: I,
+	config: &'a ReacherConfig,
+	concurrency: usize,
+) -> impl Stream<Item = (usize, CheckEmailOutput)> + 'a
+where
+	I: IntoIterator<Item = CheckEmailInput>,
+	I::IntoIter: 'a,
+{
+	stream::iter(inputs.into_iter().enumerate())
+		.map(move |(index, input)| async move { (index, check_email(&input, config).await) })
+		.buffer_unordered(concurrency.max(1))
+}
+
+async fn verify_email(input: &CheckEmailInput, config: &ReacherConfig) -> CheckEmailOutput {
 	let start_time = SystemTime::now();
 	let to_email = &input.to_email;
 
@@ -155,18 +405,76 @@ pub async fn check_email(input: &CheckEmailInput, config: &ReacherConfig) -> Che
 		my_syntax
 	);
 
-	let my_mx = match check_mx(&my_syntax).await {
+	if let Some(entry) = input
+		.domain_blocklist
+		.as_ref()
+		.and_then(|blocklist| find_domain_in_list(&my_syntax.domain, blocklist))
+	{
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping blocklisted domain {}",
+			to_email,
+			entry
+		);
+		return CheckEmailOutput {
+			input: to_email.to_string(),
+			is_reachable: Reachable::Unknown,
+			smtp: Err(SmtpError::Blocklisted(entry.clone())),
+			syntax: my_syntax,
+			..Default::default()
+		};
+	}
+
+	if let Some(entry) = input
+		.domain_allowlist
+		.as_ref()
+		.and_then(|allowlist| find_allowlisted_domain(&my_syntax.domain, allowlist, input))
+	{
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping allowlisted domain {}",
+			to_email,
+			entry
+		);
+		return CheckEmailOutput {
+			input: to_email.to_string(),
+			is_reachable: Reachable::Safe,
+			smtp: Err(SmtpError::Allowlisted(entry.clone())),
+			syntax: my_syntax,
+			..Default::default()
+		};
+	}
+
+	if let Some(unix_socket) = &input.unix_socket {
+		return verify_email_on_unix_socket(unix_socket, my_syntax, input, config, start_time)
+			.await;
+	}
+
+	if let Some((host, port)) = &input.smtp_host_override {
+		return verify_email_on_host_override(host, *port, my_syntax, input, config, start_time)
+			.await;
+	}
+
+	let mx_start_time = SystemTime::now();
+	let my_mx = check_mx(&my_syntax, config).await;
+	let mx_lookup_duration = mx_start_time.elapsed().ok();
+	let my_mx = match my_mx {
 		Ok(m) => m,
 		e => {
 			get_similar_mail_provider(&mut my_syntax);
 
-			// This happens when there's an internal error while checking MX
-			// records. Should happen fairly rarely.
+			// This happens when the DNS lookup times out or fails, or when
+			// there's an internal error while checking MX records. Unlike a
+			// domain which doesn't exist, it says nothing about the email.
 			return CheckEmailOutput {
 				input: to_email.to_string(),
 				is_reachable: Reachable::Unknown,
 				mx: e,
 				syntax: my_syntax,
+				debug: DebugDetails {
+					mx_lookup_duration,
+					..Default::default()
+				},
 				..Default::default()
 			};
 		}
@@ -181,6 +489,10 @@ pub async fn check_email(input: &CheckEmailInput, config: &ReacherConfig) -> Che
 			is_reachable: Reachable::Invalid,
 			mx: Ok(my_mx),
 			syntax: my_syntax,
+			debug: DebugDetails {
+				mx_lookup_duration,
+				..Default::default()
+			},
 			..Default::default()
 		};
 	}
@@ -198,12 +510,185 @@ pub async fn check_email(input: &CheckEmailInput, config: &ReacherConfig) -> Che
 			.collect::<Vec<String>>()
 	);
 
-	let my_misc = check_misc(
-		&my_syntax,
-		input.check_gravatar,
-		input.haveibeenpwned_api_key.clone(),
+	let mx_hosts = my_mx
+		.lookup
+		.as_ref()
+		.expect("If lookup is error, we already returned. qed.")
+		.iter()
+		.map(|host| host.exchange().to_string())
+		.collect::<Vec<_>>();
+
+	let my_mta_sts = if input.check_mta_sts {
+		let mta_sts = check_mta_sts(&my_syntax.domain, &mx_hosts, input, config).await;
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Found the following MTA-STS details: {:?}",
+			to_email,
+			mta_sts
+		);
+		Some(mta_sts)
+	} else {
+		None
+	};
+
+	let (my_smtp, smtp_debug) = if let Some(parking_host) = find_parking_host(
+		my_mx
+			.lookup
+			.as_ref()
+			.expect("If lookup is error, we already returned. qed."),
+		input,
+	) {
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping SMTP, domain is parked on {}",
+			to_email,
+			parking_host
+		);
+		(Err(SmtpError::Parked(parking_host)), SmtpDebug::default())
+	} else if config.egress_monitor.is_degraded() {
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping SMTP, outbound SMTP looks blocked",
+			to_email
+		);
+		(Err(SmtpError::Degraded), SmtpDebug::default())
+	} else {
+		check_smtp_on_mx(&my_syntax, &my_mx, input, config).await
+	};
+
+	if my_smtp.is_err() {
+		get_similar_mail_provider(&mut my_syntax);
+	}
+
+	let mut my_misc = check_misc_after_smtp(&my_syntax, &my_smtp, input, config).await;
+	my_misc.account_type = account_type(&my_syntax.domain, &mx_hosts);
+	my_misc.supports_subaddressing = smtp_debug.supports_subaddressing;
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] Found the following misc details: {:?}",
+		to_email,
+		my_misc
+	);
+
+	let end_time = SystemTime::now();
+
+	CheckEmailOutput {
+		input: to_email.to_string(),
+		is_reachable: if is_transient_error(&my_smtp) {
+			input.persistent_transient_as.clone()
+		} else {
+			calculate_reachable(&my_misc, &my_smtp)
+		},
+		misc: Ok(my_misc),
+		mx: Ok(my_mx),
+		mta_sts: my_mta_sts,
+		smtp: my_smtp,
+		syntax: my_syntax,
+		debug: DebugDetails {
+			start_time: start_time.into(),
+			end_time: end_time.into(),
+			duration: end_time
+				.duration_since(start_time)
+				.unwrap_or(Duration::from_secs(0)),
+			mx_lookup_duration,
+			smtp: smtp_debug,
+			reachable_override: None,
+			from_cache: false,
+			cached_at: None,
+			backend_name: config.backend_name.clone(),
+		},
+	}
+}
+
+/// Run the checks against the SMTP server listening on the `unix_socket`
+/// Unix domain socket. There's no MX lookup: the `mx` field of the output is
+/// left to its default, and the SMTP connection's host is the socket path.
+async fn verify_email_on_unix_socket(
+	unix_socket: &Path,
+	my_syntax: SyntaxDetails,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	start_time: SystemTime,
+) -> CheckEmailOutput {
+	let host = Name::from_ascii("localhost").expect("localhost is a valid name. qed.");
+	let mut output =
+		verify_email_on_host(&host, input.smtp_port, my_syntax, input, config, start_time).await;
+	if let VerifMethod::Smtp(connection) = &mut output.debug.smtp.verif_method {
+		connection.host = unix_socket.display().to_string();
+	}
+
+	output
+}
+
+/// Run the checks against the SMTP server of the input's
+/// `smtp_host_override`. There's no MX lookup: the `mx` field of the output
+/// is only marked as overridden.
+async fn verify_email_on_host_override(
+	host: &str,
+	port: u16,
+	my_syntax: SyntaxDetails,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	start_time: SystemTime,
+) -> CheckEmailOutput {
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] Skipping MX lookup, using [host={}:{}] from smtp_host_override",
+		input.to_email,
+		host,
+		port
+	);
+	let my_mx = MxDetails {
+		overridden: true,
+		..Default::default()
+	};
+	let host = match Name::from_ascii(host) {
+		Ok(host) => host,
+		Err(err) => {
+			return CheckEmailOutput {
+				input: input.to_email.to_string(),
+				is_reachable: Reachable::Unknown,
+				mx: Err(MxError::ResolveError(Box::new(ResolveError::from(
+					format!("Invalid smtp_host_override: {err}"),
+				)))),
+				syntax: my_syntax,
+				..Default::default()
+			}
+		}
+	};
+
+	CheckEmailOutput {
+		mx: Ok(my_mx),
+		..verify_email_on_host(&host, port, my_syntax, input, config, start_time).await
+	}
+}
+
+/// Run the checks against a single SMTP host, without MX lookup: the `mx`
+/// field of the output is left to its default.
+async fn verify_email_on_host(
+	host: &Name,
+	port: u16,
+	my_syntax: SyntaxDetails,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	start_time: SystemTime,
+) -> CheckEmailOutput {
+	let to_email = &input.to_email;
+	let (my_smtp, smtp_debug) = check_smtp_on_hosts(
+		my_syntax
+			.address
+			.as_ref()
+			.expect("We already checked that the email has valid format. qed."),
+		&[host],
+		port,
+		my_syntax.domain.as_ref(),
+		input,
+		config,
 	)
 	.await;
+
+	let mut my_misc = check_misc_after_smtp(&my_syntax, &my_smtp, input, config).await;
+	my_misc.supports_subaddressing = smtp_debug.supports_subaddressing;
 	log::debug!(
 		target: LOG_TARGET,
 		"[email={}] Found the following misc details: {:?}",
@@ -211,6 +696,116 @@ pub async fn check_email(input: &CheckEmailInput, config: &ReacherConfig) -> Che
 		my_misc
 	);
 
+	let end_time = SystemTime::now();
+
+	CheckEmailOutput {
+		input: to_email.to_string(),
+		is_reachable: if is_transient_error(&my_smtp) {
+			input.persistent_transient_as.clone()
+		} else {
+			calculate_reachable(&my_misc, &my_smtp)
+		},
+		misc: Ok(my_misc),
+		smtp: my_smtp,
+		syntax: my_syntax,
+		debug: DebugDetails {
+			start_time: start_time.into(),
+			end_time: end_time.into(),
+			duration: end_time
+				.duration_since(start_time)
+				.unwrap_or(Duration::from_secs(0)),
+			smtp: smtp_debug,
+			backend_name: config.backend_name.clone(),
+			..Default::default()
+		},
+		..Default::default()
+	}
+}
+
+/// Find the entry of the input's `domain_allowlist` matching the domain,
+/// only exactly unless `domain_allowlist_subdomains` is set.
+fn find_allowlisted_domain<'a>(
+	domain: &str,
+	allowlist: &'a [String],
+	input: &CheckEmailInput,
+) -> Option<&'a String> {
+	if input.domain_allowlist_subdomains {
+		find_domain_in_list(domain, allowlist)
+	} else {
+		allowlist
+			.iter()
+			.find(|entry| entry.trim_start_matches('.').eq_ignore_ascii_case(domain))
+	}
+}
+
+/// Whether the SMTP verification conclusively marks the email as Invalid,
+/// whatever the misc details.
+fn is_smtp_invalid(smtp: &Result<SmtpDetails, SmtpError>) -> bool {
+	match smtp {
+		Err(SmtpError::Parked(_)) => true,
+		Ok(smtp) => {
+			smtp.can_connect_smtp
+				&& (!smtp.is_deliverable || smtp.is_disabled)
+				&& !smtp.is_catch_all
+				&& !smtp.has_full_inbox
+		}
+		Err(_) => false,
+	}
+}
+
+/// The reachability of the email from the SMTP details only, i.e. before
+/// the misc checks, which can only downgrade a Safe email to Risky.
+fn reachable_before_misc(
+	smtp: &Result<SmtpDetails, SmtpError>,
+	input: &CheckEmailInput,
+) -> Reachable {
+	if is_transient_error(smtp) {
+		input.persistent_transient_as.clone()
+	} else {
+		calculate_reachable(&MiscDetails::default(), smtp)
+	}
+}
+
+/// Fetch the misc details, skipping the HTTP calls if the input's
+/// `skip_misc_on_invalid` is set and the SMTP verification marks the email
+/// as Invalid, or if `misc_only_if_reachable` is set and the reachability
+/// so far is Invalid.
+async fn check_misc_after_smtp(
+	my_syntax: &SyntaxDetails,
+	my_smtp: &Result<SmtpDetails, SmtpError>,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> MiscDetails {
+	let skip_http = if input.skip_misc_on_invalid && is_smtp_invalid(my_smtp) {
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping the misc HTTP checks, SMTP marks the email as invalid",
+			input.to_email
+		);
+		true
+	} else if input.misc_only_if_reachable
+		&& reachable_before_misc(my_smtp, input) == Reachable::Invalid
+	{
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping the misc HTTP checks, the email is not reachable",
+			input.to_email
+		);
+		true
+	} else {
+		false
+	};
+
+	check_misc_with(my_syntax, input, config, !skip_http).await
+}
+
+/// Choose one MX host of the lookup, and run the SMTP checks on it.
+async fn check_smtp_on_mx(
+	my_syntax: &SyntaxDetails,
+	my_mx: &MxDetails,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> (Result<SmtpDetails, SmtpError>, SmtpDebug) {
 	// From the list of MX records, we only choose one: we don't choose the
 	// first or last ones, because some domains put dummy MX records at the
 	// beginning or end of the list (sorted by priority). Instead, we choose a
@@ -235,49 +830,973 @@ pub async fn check_email(input: &CheckEmailInput, config: &ReacherConfig) -> Che
 			)
 		})
 		.collect::<Vec<&MX>>();
-	mx_records.sort_by_key(|a| a.preference());
-	let host = if mx_records.len() >= 3 {
-		let mut rng = rand::thread_rng();
-		let index = rng.gen_range(1..mx_records.len() - 1);
-		mx_records[index]
-	} else {
-		mx_records[mx_records.len() - 1]
+	sort_mx_records(
+		&mut mx_records,
+		input.shuffle_equal_mx && input.mx_host_index.is_none(),
+	);
+	let host = match choose_mx_host(&mx_records, input) {
+		Ok(host) => host,
+		Err(err) => return (Err(err), SmtpDebug::default()),
 	};
+	// Fall back on the other MX hosts, by preference, after the chosen one,
+	// see `check_smtp_on_hosts`.
+	let mut hosts = vec![host.exchange()];
+	if input.mx_host_index.is_none() {
+		hosts.extend(
+			mx_records
+				.iter()
+				.filter(|mx| !std::ptr::eq(**mx, host))
+				.map(|mx| mx.exchange()),
+		);
+	}
 
-	let (my_smtp, smtp_debug) = check_smtp(
+	check_smtp_on_hosts(
 		my_syntax
 			.address
 			.as_ref()
 			.expect("We already checked that the email has valid format. qed."),
-		host.exchange(),
+		&hosts,
 		input.smtp_port,
 		my_syntax.domain.as_ref(),
 		input,
 		config,
 	)
-	.await;
+	.await
+}
 
-	if my_smtp.is_err() {
-		get_similar_mail_provider(&mut my_syntax);
+/// Sort the MX records by preference. If `shuffle_ties` is set, the records
+/// with the same preference are shuffled.
+fn sort_mx_records(mx_records: &mut [&MX], shuffle_ties: bool) {
+	mx_records.sort_by_key(|a| a.preference());
+	if !shuffle_ties {
+		return;
 	}
 
-	let end_time = SystemTime::now();
+	let mut rng = rand::thread_rng();
+	for tier in mx_records.chunk_by_mut(|a, b| a.preference() == b.preference()) {
+		tier.shuffle(&mut rng);
+	}
+}
 
-	CheckEmailOutput {
-		input: to_email.to_string(),
-		is_reachable: calculate_reachable(&my_misc, &my_smtp),
-		misc: Ok(my_misc),
-		mx: Ok(my_mx),
-		smtp: my_smtp,
-		syntax: my_syntax,
-		debug: DebugDetails {
-			start_time: start_time.into(),
-			end_time: end_time.into(),
-			duration: end_time
-				.duration_since(start_time)
-				.unwrap_or(Duration::from_secs(0)),
-			smtp: smtp_debug,
-			backend_name: config.backend_name.clone(),
-		},
+/// Choose the MX host to verify the email on, among the MX records sorted by
+/// preference. If the input's `mx_host_index` is set, choose the host at
+/// this index.
+fn choose_mx_host<'a>(mx_records: &[&'a MX], input: &CheckEmailInput) -> Result<&'a MX, SmtpError> {
+	if let Some(index) = input.mx_host_index {
+		return mx_records.get(index).copied().ok_or_else(|| {
+			SmtpError::InvalidMxHostIndex(format!(
+				"index {index} is out of range, the domain has {} MX hosts",
+				mx_records.len()
+			))
+		});
+	}
+
+	if mx_records.len() >= 3 {
+		let mut rng = rand::thread_rng();
+		let index = rng.gen_range(1..mx_records.len() - 1);
+		Ok(mx_records[index])
+	} else {
+		Ok(mx_records[mx_records.len() - 1])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		calculate_reachable, check_email, check_email_stream, choose_mx_host, is_smtp_invalid,
+		reachable_reasons, sort_mx_records, validate_offline, CheckEmailInput, Reachable,
+	};
+	use crate::config::{PostClassify, ReacherConfig};
+	use crate::limiter::GlobalLimiter;
+	use crate::misc::{BreachProvider, BreachProviders, MiscDetails, MiscError};
+	use crate::mx::MxError;
+	use crate::smtp::SmtpDetails;
+	use crate::smtp::{EgressMonitor, SmtpError, SmtpErrorDesc, VerifMethod};
+	use crate::util::mock_dns::{mx_response, response, MockDnsServer};
+	use crate::util::mock_smtp::{accept_all, MockSmtpServer};
+	use crate::CheckEmailInputBuilder;
+	use futures::future::BoxFuture;
+	use futures::StreamExt;
+	use hickory_proto::op::ResponseCode;
+	use hickory_proto::rr::rdata::MX;
+	use hickory_proto::rr::Name;
+	use std::collections::{HashMap, HashSet};
+	use std::fmt;
+	use std::str::FromStr;
+	#[cfg(unix)]
+	use std::sync::atomic::AtomicBool;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+	use tracing::field::{Field, Visit};
+	use tracing::{Event, Subscriber};
+	use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+	use tracing_subscriber::Registry;
+
+	// This is a plain test, not run inside any async runtime: any DNS, SMTP
+	// or HTTP call made by this crate would need a Tokio reactor, and panic.
+	#[test]
+	fn should_validate_offline_without_network() {
+		let input = CheckEmailInput::default();
+
+		let output = validate_offline("support@protonmial.com", &input);
+		assert!(output.syntax.is_valid_syntax);
+		assert_eq!(
+			output.syntax.suggestion,
+			Some("support@protonmail.com".into())
+		);
+		assert!(output.is_role_account);
+		assert!(!output.is_disposable);
+		assert!(!output.is_free_provider);
+
+		let output = validate_offline("foo.bar+baz@gmail.com", &input);
+		assert_eq!(
+			output.syntax.normalized_email,
+			Some("foobar@gmail.com".into())
+		);
+		assert!(output.is_free_provider);
+
+		let output = validate_offline("foo@yopmail.com", &input);
+		assert!(output.syntax.is_valid_syntax);
+		assert!(output.is_disposable);
+		let allowlisted = CheckEmailInput {
+			disposable_allowlist: Some(vec!["yopmail.com".into()]),
+			..Default::default()
+		};
+		let output = validate_offline("foo@yopmail.com", &allowlisted);
+		assert!(output.syntax.is_valid_syntax);
+		assert!(!output.is_disposable);
+
+		let output = validate_offline("foo", &input);
+		assert!(!output.syntax.is_valid_syntax);
+	}
+
+	#[tokio::test]
+	async fn should_tell_nxdomain_from_servfail() {
+		let check = |code: ResponseCode| async move {
+			let server = MockDnsServer::start(move |query| response(query, code)).await;
+			let config = ReacherConfig {
+				resolver: Arc::new(server.resolver()),
+				..Default::default()
+			};
+			let input = CheckEmailInput {
+				to_email: "foo@example.com".into(),
+				..Default::default()
+			};
+			check_email(&input, &config).await
+		};
+
+		// The domain doesn't exist.
+		let output = check(ResponseCode::NXDomain).await;
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert!(output.mx.is_ok());
+
+		// The DNS server failed, we can't tell.
+		let output = check(ResponseCode::ServFail).await;
+		assert_eq!(output.is_reachable, Reachable::Unknown);
+		let error = output.mx.unwrap_err();
+		assert!(matches!(error, MxError::ServFail));
+		assert!(error.is_retryable());
+	}
+
+	#[tokio::test]
+	async fn should_stream_outputs_with_their_index() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let emails = ["foo@example.com", "not-an-email", "bar@example.org", "baz"];
+		let inputs = emails.iter().map(|email| CheckEmailInput {
+			to_email: email.to_string(),
+			..Default::default()
+		});
+
+		let mut outputs = check_email_stream(inputs, &config, 2)
+			.collect::<Vec<_>>()
+			.await;
+
+		outputs.sort_by_key(|(index, _)| *index);
+		assert_eq!(outputs.len(), emails.len());
+		for (i, (index, output)) in outputs.iter().enumerate() {
+			assert_eq!(*index, i);
+			assert_eq!(output.input, emails[i]);
+			assert_eq!(output.is_reachable, Reachable::Invalid);
+		}
+	}
+
+	#[test]
+	fn should_choose_mx_host_by_index() {
+		let records = [
+			MX::new(10, Name::from_str("mx1.example.com.").unwrap()),
+			MX::new(20, Name::from_str("mx2.example.com.").unwrap()),
+			MX::new(30, Name::from_str("mx3.example.com.").unwrap()),
+		];
+		let mx_records = records.iter().collect::<Vec<_>>();
+		let input = CheckEmailInputBuilder::default()
+			.mx_host_index(Some(1))
+			.build()
+			.unwrap();
+
+		let host = choose_mx_host(&mx_records, &input).unwrap();
+		assert_eq!(host.exchange().to_string(), "mx2.example.com.");
+
+		let input = CheckEmailInput {
+			mx_host_index: Some(3),
+			..input
+		};
+		assert!(matches!(
+			choose_mx_host(&mx_records, &input),
+			Err(SmtpError::InvalidMxHostIndex(_))
+		));
+	}
+
+	#[test]
+	fn should_shuffle_mx_hosts_within_preference() {
+		let records = [
+			MX::new(20, Name::from_str("backup.example.com.").unwrap()),
+			MX::new(10, Name::from_str("mx1.example.com.").unwrap()),
+			MX::new(10, Name::from_str("mx2.example.com.").unwrap()),
+			MX::new(10, Name::from_str("mx3.example.com.").unwrap()),
+		];
+		let order = |shuffle_ties| {
+			let mut mx_records = records.iter().collect::<Vec<_>>();
+			sort_mx_records(&mut mx_records, shuffle_ties);
+			mx_records
+				.iter()
+				.map(|mx| mx.exchange().to_string())
+				.collect::<Vec<_>>()
+		};
+
+		assert_eq!(
+			order(false),
+			[
+				"mx1.example.com.",
+				"mx2.example.com.",
+				"mx3.example.com.",
+				"backup.example.com."
+			]
+		);
+
+		let mut orders = HashSet::new();
+		for _ in 0..100 {
+			let order = order(true);
+			// The less preferred host always comes last.
+			assert_eq!(order[3], "backup.example.com.");
+			orders.insert(order);
+		}
+		// 6 possible orders, the odds of seeing only one are negligible.
+		assert!(orders.len() > 1);
+	}
+
+	#[test]
+	fn should_never_be_safe_on_catch_all() {
+		let misc = MiscDetails::default();
+		let smtp = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			..Default::default()
+		};
+		assert_eq!(calculate_reachable(&misc, &Ok(smtp)), Reachable::Safe);
+
+		let smtp = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			is_catch_all: true,
+			..Default::default()
+		};
+		assert_eq!(calculate_reachable(&misc, &Ok(smtp)), Reachable::Risky);
+	}
+
+	#[tokio::test]
+	async fn should_skip_network_on_invalid_syntax() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.to_email("not-an-email".into())
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert!(output.mx.unwrap().lookup.is_err());
+		assert_eq!(server.queries(), 0);
+	}
+
+	/// A tracing layer recording the fields of each event, as strings.
+	#[derive(Clone, Default)]
+	struct CaptureLayer(Arc<Mutex<Vec<HashMap<String, String>>>>);
+
+	impl<S: Subscriber> Layer<S> for CaptureLayer {
+		fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+			struct Visitor(HashMap<String, String>);
+			impl Visit for Visitor {
+				fn record_str(&mut self, field: &Field, value: &str) {
+					self.0.insert(field.name().into(), value.into());
+				}
+				fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+					self.0.insert(field.name().into(), format!("{:?}", value));
+				}
+			}
+
+			let mut visitor = Visitor(HashMap::new());
+			event.record(&mut visitor);
+			self.0.lock().unwrap().push(visitor.0);
+		}
+	}
+
+	#[tokio::test]
+	async fn should_log_decision() {
+		let layer = CaptureLayer::default();
+		let _guard = tracing::subscriber::set_default(Registry::default().with(layer.clone()));
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.build()
+			.unwrap();
+
+		check_email(&input, &config).await;
+
+		let events = layer.0.lock().unwrap();
+		let event = events
+			.iter()
+			.find(|e| e.get("message").map(String::as_str) == Some("Email verification decision"))
+			.expect("The decision event is emitted.");
+		assert_eq!(event["email"], "foo@example.com");
+		assert_eq!(event["domain"], "example.com");
+		assert_eq!(event["provider"], "none");
+		assert_eq!(event["verif_method"], "skipped");
+		assert_eq!(event["is_reachable"], "invalid");
+		assert_eq!(event["reasons"], "no_mx_records");
+		assert_eq!(event["retries_used"], "0");
+	}
+
+	#[tokio::test]
+	async fn should_apply_post_classify() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			post_classify: Some(PostClassify::new(|output| {
+				(output.syntax.domain == "partner.com")
+					.then(|| (Reachable::Safe, "Partner domain".into()))
+			})),
+			..Default::default()
+		};
+		let check = |email: &str| {
+			let input = CheckEmailInputBuilder::default()
+				.to_email(email.into())
+				.build()
+				.unwrap();
+			let config = &config;
+			async move { check_email(&input, config).await }
+		};
+
+		// Without MX records, both emails are computed as invalid.
+		let output = check("foo@partner.com").await;
+		assert_eq!(output.is_reachable, Reachable::Safe);
+		assert_eq!(
+			output.debug.reachable_override.as_deref(),
+			Some("Partner domain")
+		);
+
+		let output = check("foo@example.com").await;
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert!(output.debug.reachable_override.is_none());
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn should_verify_over_unix_socket() {
+		// Without MX records, the email would be invalid if we looked them up.
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(dns.resolver()),
+			..Default::default()
+		};
+		let path = std::env::temp_dir().join(format!("reacher-smtp-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let server = MockSmtpServer::start_unix(&path, |command| {
+			if command.starts_with("RCPT TO:<foo@") {
+				"250 OK\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.unix_socket(Some(path.clone()))
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+		let _ = std::fs::remove_file(&path);
+
+		assert_eq!(output.is_reachable, Reachable::Safe);
+		assert!(output.smtp.unwrap().is_deliverable);
+		match output.debug.smtp.verif_method {
+			VerifMethod::Smtp(connection) => {
+				assert_eq!(connection.host, path.display().to_string());
+				assert!(!connection.used_proxy);
+			}
+			method => panic!("Expected SMTP verification, got {:?}", method),
+		}
+		assert!(server
+			.commands()
+			.contains(&"RCPT TO:<foo@example.com>".to_string()));
+	}
+
+	#[tokio::test]
+	async fn should_connect_to_smtp_host_override() {
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(dns.resolver()),
+			..Default::default()
+		};
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") && !command.contains("<bob@") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("bob@example.com".into())
+			.smtp_host_override(Some(("127.0.0.1".into(), server.port())))
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+
+		// example.com's MX isn't looked up.
+		assert_eq!(dns.queries(), 0);
+		let mx = output.mx.unwrap();
+		assert!(mx.overridden);
+		assert_eq!(serde_json::to_value(&mx).unwrap()["overridden"], true);
+		assert!(output.smtp.unwrap().is_deliverable);
+		match output.debug.smtp.verif_method {
+			VerifMethod::Smtp(connection) => {
+				assert_eq!(connection.host, "127.0.0.1");
+				assert_eq!(connection.port, server.port());
+			}
+			method => panic!("Expected SMTP verification, got {:?}", method),
+		}
+		assert!(server
+			.commands()
+			.contains(&"RCPT TO:<bob@example.com>".to_string()));
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn should_classify_persistent_transient_errors() {
+		let path =
+			std::env::temp_dir().join(format!("reacher-transient-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let server = MockSmtpServer::start_unix(&path, |command| {
+			if command.starts_with("RCPT TO:<foo@") {
+				"450 4.7.1 Try again later\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let config = ReacherConfig::default();
+		let mut input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.unix_socket(Some(path.clone()))
+			.retries(2)
+			.build()
+			.unwrap();
+
+		// By default, a transient error is Unknown.
+		let output = check_email(&input, &config).await;
+		assert_eq!(output.is_reachable, Reachable::Unknown);
+		assert_eq!(reachable_reasons(&output), vec!["smtp_error"]);
+
+		input.persistent_transient_as = Reachable::Invalid;
+		let output = check_email(&input, &config).await;
+		let _ = std::fs::remove_file(&path);
+
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert_eq!(reachable_reasons(&output), vec!["persistent_transient"]);
+		// Both attempts of both checks got the 4xx reply.
+		let rcpt = server
+			.commands()
+			.iter()
+			.filter(|c| c.starts_with("RCPT TO:<foo@"))
+			.count();
+		assert_eq!(rcpt, 4);
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn should_count_retries_used() {
+		// Greylisting: the first RCPT TO for the email gets a 4xx.
+		let greylisted = Arc::new(AtomicBool::new(true));
+		let path =
+			std::env::temp_dir().join(format!("reacher-retries-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let server_greylisted = Arc::clone(&greylisted);
+		let _server = MockSmtpServer::start_unix(&path, move |command| {
+			if command.starts_with("RCPT TO:<foo@") {
+				if server_greylisted.swap(false, Ordering::SeqCst) {
+					"450 4.7.1 Greylisted, try again later\r\n".into()
+				} else {
+					"250 OK\r\n".into()
+				}
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 No such user\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let config = ReacherConfig::default();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.unix_socket(Some(path.clone()))
+			.retries(3)
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+		assert_eq!(output.is_reachable, Reachable::Safe);
+		assert_eq!(output.debug.smtp.retries_used, 1);
+
+		// Without greylisting, no retry.
+		let output = check_email(&input, &config).await;
+		let _ = std::fs::remove_file(&path);
+		assert_eq!(output.debug.smtp.retries_used, 0);
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn should_record_smtp_transcript() {
+		let path =
+			std::env::temp_dir().join(format!("reacher-transcript-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let _server = MockSmtpServer::start_unix(&path, |command| {
+			if command.starts_with("RCPT TO:<foo@") {
+				"250 OK\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 No such user\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let config = ReacherConfig::default();
+		let mut input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.from_email("me@example.org".into())
+			.unix_socket(Some(path.clone()))
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+		assert!(output.debug.smtp.transcript.is_none());
+
+		input.smtp_transcript = true;
+		let output = check_email(&input, &config).await;
+		let _ = std::fs::remove_file(&path);
+
+		let transcript = output.debug.smtp.transcript.unwrap();
+		assert!(transcript[0].starts_with("* Connected to "));
+		assert_eq!(transcript[1], "C: MAIL FROM:<me@example.org>");
+		assert!(transcript[2].starts_with("S: 250"));
+		assert!(transcript.contains(&"S: 550 5.1.1 No such user".to_string()));
+		assert_eq!(
+			transcript[transcript.len() - 2..],
+			["C: RCPT TO:<foo@example.com>", "S: 250 OK"]
+		);
+	}
+
+	#[test]
+	fn should_tell_when_smtp_is_invalid() {
+		let smtp = SmtpDetails {
+			can_connect_smtp: true,
+			..Default::default()
+		};
+		assert!(is_smtp_invalid(&Ok(smtp)));
+
+		// Not conclusive on catch-all domains and full inboxes.
+		let smtp = SmtpDetails {
+			can_connect_smtp: true,
+			is_catch_all: true,
+			..Default::default()
+		};
+		assert!(!is_smtp_invalid(&Ok(smtp)));
+		let smtp = SmtpDetails {
+			can_connect_smtp: true,
+			has_full_inbox: true,
+			..Default::default()
+		};
+		assert!(!is_smtp_invalid(&Ok(smtp)));
+
+		let smtp = SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable: true,
+			..Default::default()
+		};
+		assert!(!is_smtp_invalid(&Ok(smtp)));
+		assert!(!is_smtp_invalid(&Err(SmtpError::Blocklisted(
+			"example.com".into()
+		))));
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn should_skip_misc_http_checks_on_invalid() {
+		let path = std::env::temp_dir().join(format!("reacher-misc-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let server = MockSmtpServer::start_unix(&path, |command| {
+			if command.starts_with("RCPT TO") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.unix_socket(Some(path.clone()))
+			.check_gravatar(true)
+			.haveibeenpwned_api_key(Some("key".into()))
+			.skip_misc_on_invalid(true)
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let output = check_email(&input, &config).await;
+		let _ = std::fs::remove_file(&path);
+
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		let misc = output.misc.unwrap();
+		assert!(misc.gravatar_url.is_none());
+		assert!(misc.haveibeenpwned.is_none());
+		assert!(!server.commands().is_empty());
+	}
+
+	/// A breach provider counting its calls.
+	#[derive(Default)]
+	struct CountingProvider(AtomicUsize);
+
+	impl BreachProvider for CountingProvider {
+		fn name(&self) -> &str {
+			"counting"
+		}
+
+		fn check<'a>(
+			&'a self,
+			_email: &'a str,
+			_input: &'a CheckEmailInput,
+			_client: Option<&'a reqwest::Client>,
+		) -> BoxFuture<'a, Option<Result<bool, MiscError>>> {
+			self.0.fetch_add(1, Ordering::SeqCst);
+			Box::pin(async { Some(Ok(false)) })
+		}
+	}
+
+	#[tokio::test]
+	async fn should_run_misc_http_checks_only_if_reachable() {
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let provider = Arc::new(CountingProvider::default());
+		let config = ReacherConfig {
+			resolver: Arc::new(dns.resolver()),
+			breach_providers: Arc::new(BreachProviders::new(vec![provider.clone()])),
+			..Default::default()
+		};
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") && !command.contains("<bob@") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = |to_email: &str| {
+			CheckEmailInputBuilder::default()
+				.to_email(to_email.into())
+				.smtp_host_override(Some(("127.0.0.1".into(), server.port())))
+				.misc_only_if_reachable(true)
+				.build()
+				.unwrap()
+		};
+
+		let output = check_email(&input("alice@example.com"), &config).await;
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert!(output.misc.unwrap().breaches.is_empty());
+		assert_eq!(provider.0.load(Ordering::SeqCst), 0);
+
+		let output = check_email(&input("bob@example.com"), &config).await;
+		assert_eq!(output.is_reachable, Reachable::Safe);
+		assert_eq!(output.misc.unwrap().breaches.len(), 1);
+		assert_eq!(provider.0.load(Ordering::SeqCst), 1);
+	}
+
+	#[tokio::test]
+	async fn should_not_exceed_the_global_limit() {
+		// An SMTP server holding each connection a bit before closing it,
+		// recording the maximum number of connections open at a time.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let in_flight = Arc::new(AtomicUsize::new(0));
+		let max_in_flight = Arc::new(AtomicUsize::new(0));
+		let (counter, max) = (in_flight.clone(), max_in_flight.clone());
+		tokio::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				let current = counter.fetch_add(1, Ordering::SeqCst) + 1;
+				max.fetch_max(current, Ordering::SeqCst);
+				let counter = counter.clone();
+				tokio::spawn(async move {
+					tokio::time::sleep(Duration::from_millis(50)).await;
+					counter.fetch_sub(1, Ordering::SeqCst);
+					drop(stream);
+				});
+			}
+		});
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(dns.resolver()),
+			limiter: Arc::new(GlobalLimiter::new(Some(2), None)),
+			..Default::default()
+		};
+		let inputs = (0..6).map(|i| {
+			CheckEmailInputBuilder::default()
+				.to_email(format!("foo{}@example.com", i))
+				.smtp_host_override(Some(("127.0.0.1".into(), port)))
+				.build()
+				.unwrap()
+		});
+
+		// Independent callers, each running several verifications at a time.
+		let (first, second) = futures::join!(
+			check_email_stream(inputs.clone().take(3), &config, 3).count(),
+			check_email_stream(inputs.skip(3), &config, 3).count(),
+		);
+
+		assert_eq!(first + second, 6);
+		assert!(max_in_flight.load(Ordering::SeqCst) > 0);
+		assert!(max_in_flight.load(Ordering::SeqCst) <= 2);
+	}
+
+	#[tokio::test]
+	async fn should_probe_subaddressing() {
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(dns.resolver()),
+			..Default::default()
+		};
+		let supports_subaddressing = |accept_plus: bool| {
+			let config = &config;
+			async move {
+				let server = MockSmtpServer::start(move |command| {
+					if command == "RCPT TO:<foo@example.com>"
+						|| (accept_plus && command.starts_with("RCPT TO:<foo+"))
+						|| !command.starts_with("RCPT TO")
+					{
+						accept_all(command)
+					} else {
+						"550 5.1.1 No such user\r\n".into()
+					}
+				})
+				.await;
+				let input = CheckEmailInputBuilder::default()
+					.to_email("foo@example.com".into())
+					.smtp_host_override(Some(("127.0.0.1".into(), server.port())))
+					.probe_subaddressing(true)
+					.build()
+					.unwrap();
+
+				let output = check_email(&input, config).await;
+
+				assert_eq!(output.is_reachable, Reachable::Safe);
+				// The probe reuses the connection.
+				let commands = server.commands();
+				assert_eq!(
+					commands
+						.iter()
+						.filter(|c| c.starts_with("MAIL FROM"))
+						.count(),
+					1
+				);
+				assert!(commands.iter().any(|c| c.starts_with("RCPT TO:<foo+")));
+				output.misc.unwrap().supports_subaddressing
+			}
+		};
+
+		assert_eq!(supports_subaddressing(true).await, Some(true));
+		assert_eq!(supports_subaddressing(false).await, Some(false));
+	}
+
+	#[tokio::test]
+	async fn should_skip_smtp_without_mx_records() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@no-mx.example.com".into())
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert_eq!(server.queries(), 1);
+		assert_eq!(output.debug.smtp.verif_method, VerifMethod::Skipped);
+		assert!(!output.smtp.unwrap().can_connect_smtp);
+	}
+
+	#[tokio::test]
+	async fn should_not_contact_blocklisted_domains() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@agency.example.gov".into())
+			.domain_blocklist(Some(vec!["customer.com".into(), ".gov".into()]))
+			.check_gravatar(true)
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+
+		assert_eq!(output.is_reachable, Reachable::Unknown);
+		assert_eq!(server.queries(), 0);
+		let err = output.smtp.unwrap_err();
+		assert!(matches!(
+			err.get_description(),
+			Some(SmtpErrorDesc::Blocklisted)
+		));
+		assert!(output.misc.unwrap().gravatar_url.is_none());
+	}
+
+	#[tokio::test]
+	async fn should_return_allowlisted_domains_safe() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NoError)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@eu.corp.example.com".into())
+			.domain_allowlist(Some(vec!["corp.example.com".into()]))
+			.check_gravatar(true)
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+
+		assert_eq!(output.is_reachable, Reachable::Safe);
+		assert_eq!(reachable_reasons(&output), vec!["allowlisted"]);
+		assert_eq!(server.queries(), 0);
+		assert_eq!(output.debug.smtp.verif_method, VerifMethod::Skipped);
+		let err = output.smtp.unwrap_err();
+		assert!(matches!(
+			err.get_description(),
+			Some(SmtpErrorDesc::Allowlisted)
+		));
+		assert!(output.misc.unwrap().gravatar_url.is_none());
+
+		// Without subdomain matching, only the exact domain is allowlisted.
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@eu.corp.example.com".into())
+			.domain_allowlist(Some(vec!["corp.example.com".into()]))
+			.domain_allowlist_subdomains(false)
+			.build()
+			.unwrap();
+		let output = check_email(&input, &config).await;
+		assert_ne!(output.is_reachable, Reachable::Safe);
+		assert_eq!(server.queries(), 1);
+	}
+
+	#[tokio::test]
+	async fn should_switch_to_degraded_mode_when_egress_is_blocked() {
+		// Each domain has its own MX host, all on a port which refuses
+		// connections, as if outbound SMTP was blocked.
+		let server = MockDnsServer::start(|query| {
+			let exchange = if query.queries()[0].name().to_string().starts_with("one.") {
+				"127.0.0.1."
+			} else {
+				"localhost."
+			};
+			mx_response(query, 300, &[(10, exchange)])
+		})
+		.await;
+		let port = {
+			let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+			listener.local_addr().unwrap().port()
+		};
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			egress_monitor: Arc::new(EgressMonitor::new(Some(2))),
+			..Default::default()
+		};
+		let check = |email: &str| {
+			let input = CheckEmailInputBuilder::default()
+				.to_email(email.into())
+				.smtp_port(port)
+				.build()
+				.unwrap();
+			let config = &config;
+			async move { check_email(&input, config).await }
+		};
+
+		let output = check("foo@one.example.com").await;
+		assert!(matches!(output.smtp, Err(SmtpError::SmtpError(_))));
+		assert!(!config.egress_monitor.is_degraded());
+		check("foo@two.example.com").await;
+		assert!(config.egress_monitor.is_degraded());
+
+		let output = check("foo@three.example.com").await;
+		assert_eq!(output.is_reachable, Reachable::Unknown);
+		assert!(matches!(output.smtp, Err(SmtpError::Degraded)));
+		assert_eq!(reachable_reasons(&output), vec!["degraded"]);
+		assert_eq!(output.debug.smtp.verif_method, VerifMethod::Skipped);
+		assert!(output.mx.unwrap().lookup.is_ok());
+
+		// The mode can be overridden.
+		config.egress_monitor.force(Some(false));
+		let output = check("foo@three.example.com").await;
+		assert!(matches!(output.smtp, Err(SmtpError::SmtpError(_))));
+	}
+
+	#[tokio::test]
+	async fn should_mark_parked_domains_invalid() {
+		let server =
+			MockDnsServer::start(|query| mx_response(query, 300, &[(10, "park-mx.above.com.")]))
+				.await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@parked.example.com".into())
+			.build()
+			.unwrap();
+
+		let output = check_email(&input, &config).await;
+
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert_eq!(output.debug.smtp.verif_method, VerifMethod::Skipped);
+		let err = output.smtp.unwrap_err();
+		assert!(matches!(err.get_description(), Some(SmtpErrorDesc::Parked)));
 	}
 }
@@ -0,0 +1,132 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Bound the number of concurrent verifications of the whole process, so that
+//! independent callers of `check_email` share the same limit.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+/// Limits the concurrent calls to `check_email`, and optionally the
+/// concurrent SMTP verifications on each MX host. Calls over the limits wait
+/// for a slot instead of failing. It's meant to be shared between
+/// verifications, see `ReacherConfig`.
+#[derive(Debug, Default)]
+pub struct GlobalLimiter {
+	/// Slots of the concurrent calls to `check_email`, None if unlimited.
+	global: Option<Semaphore>,
+	/// Maximum number of concurrent SMTP verifications on a single MX host,
+	/// None if unlimited.
+	max_per_host: Option<usize>,
+	/// Slots of each MX host seen so far.
+	hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl GlobalLimiter {
+	/// Run at most `max_concurrent` calls to `check_email` at a time, and at
+	/// most `max_per_host` SMTP verifications on the same MX host at a time.
+	/// None means unlimited.
+	pub fn new(max_concurrent: Option<usize>, max_per_host: Option<usize>) -> Self {
+		Self {
+			global: max_concurrent.map(|max| Semaphore::new(max.max(1))),
+			max_per_host: max_per_host.map(|max| max.max(1)),
+			hosts: Mutex::default(),
+		}
+	}
+
+	/// Wait for a slot to run `check_email`. The slot is released when the
+	/// returned permit is dropped.
+	pub(crate) async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+		match &self.global {
+			Some(semaphore) => Some(
+				semaphore
+					.acquire()
+					.await
+					.expect("Semaphore is never closed. qed."),
+			),
+			None => None,
+		}
+	}
+
+	/// Wait for a slot to run an SMTP verification on this MX host. The slot
+	/// is released when the returned permit is dropped.
+	pub(crate) async fn acquire_host(&self, host: &str) -> Option<OwnedSemaphorePermit> {
+		let max = self.max_per_host?;
+		let semaphore = {
+			let mut hosts = self.hosts.lock().expect("Lock is not poisoned. qed.");
+			Arc::clone(
+				hosts
+					.entry(host.to_lowercase())
+					.or_insert_with(|| Arc::new(Semaphore::new(max))),
+			)
+		};
+
+		Some(
+			semaphore
+				.acquire_owned()
+				.await
+				.expect("Semaphore is never closed. qed."),
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::GlobalLimiter;
+	use futures::FutureExt;
+
+	#[tokio::test]
+	async fn should_be_unlimited_by_default() {
+		let limiter = GlobalLimiter::default();
+		let _permits: Vec<_> = (0..10)
+			.map(|_| limiter.acquire().now_or_never().unwrap())
+			.collect();
+		let _host_permits: Vec<_> = (0..10)
+			.map(|_| {
+				limiter
+					.acquire_host("mx.example.com.")
+					.now_or_never()
+					.unwrap()
+			})
+			.collect();
+	}
+
+	#[tokio::test]
+	async fn should_limit_each_host_separately() {
+		let limiter = GlobalLimiter::new(None, Some(1));
+
+		let permit = limiter
+			.acquire_host("mx.example.com.")
+			.now_or_never()
+			.unwrap();
+		assert!(permit.is_some());
+		assert!(limiter
+			.acquire_host("MX.example.com.")
+			.now_or_never()
+			.is_none());
+		assert!(limiter
+			.acquire_host("mx.example.org.")
+			.now_or_never()
+			.is_some());
+
+		drop(permit);
+		assert!(limiter
+			.acquire_host("mx.example.com.")
+			.now_or_never()
+			.is_some());
+	}
+}
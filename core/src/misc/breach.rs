@@ -0,0 +1,302 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Breach providers, i.e. APIs telling if an email address was found in a
+//! data breach, such as HaveIBeenPwned.
+
+use super::MiscError;
+use crate::{CheckEmailInput, LOG_TARGET};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// An API telling if an email address was found in a data breach. The
+/// providers used by the misc checks are registered in
+/// `ReacherConfig::breach_providers`.
+pub trait BreachProvider: Send + Sync {
+	/// Name of the provider, used to attribute its result in
+	/// `MiscDetails::breaches`.
+	fn name(&self) -> &str;
+
+	/// Minimum interval between two requests to this provider. Requests are
+	/// delayed to respect it. Defaults to None, i.e. no rate limit.
+	fn min_interval(&self) -> Option<Duration> {
+		None
+	}
+
+	/// Check if the email was found in a breach. Returns None if the provider
+	/// is not enabled for this input, and the error if the check failed, which
+	/// is reported in `MiscDetails::errors`. The provider should send its
+	/// requests with `client`, the HTTP client injected in
+	/// `ReacherConfig::http_client`, if any.
+	fn check<'a>(
+		&'a self,
+		email: &'a str,
+		input: &'a CheckEmailInput,
+		client: Option<&'a reqwest::Client>,
+	) -> BoxFuture<'a, Option<Result<bool, MiscError>>>;
+}
+
+/// The result of a breach provider for an email address.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
+pub struct BreachResult {
+	/// Name of the provider.
+	pub provider: String,
+	/// Was the email found in a breach?
+	pub is_breached: bool,
+}
+
+/// A provider with its own rate limit state.
+struct RateLimitedProvider {
+	provider: Arc<dyn BreachProvider>,
+	/// Earliest time of the next request to the provider.
+	next_request: Mutex<Instant>,
+}
+
+impl RateLimitedProvider {
+	/// Wait until the provider's rate limit allows a new request, and book
+	/// the slot.
+	async fn wait_turn(&self) {
+		let Some(interval) = self.provider.min_interval() else {
+			return;
+		};
+		let wait = {
+			let mut next_request = self
+				.next_request
+				.lock()
+				.expect("RateLimitedProvider lock poisoned");
+			let now = Instant::now();
+			let slot = (*next_request).max(now);
+			*next_request = slot + interval;
+			slot - now
+		};
+		tokio::time::sleep(wait).await;
+	}
+
+	async fn check(
+		&self,
+		email: &str,
+		input: &CheckEmailInput,
+		client: Option<&reqwest::Client>,
+	) -> Option<Result<BreachResult, MiscError>> {
+		self.wait_turn().await;
+		let is_breached = match self.provider.check(email, input, client).await? {
+			Ok(is_breached) => is_breached,
+			Err(err) => {
+				log::debug!(
+					target: LOG_TARGET,
+					"[email={}] Breach provider {} failed: {:?}",
+					email,
+					self.provider.name(),
+					err
+				);
+				return Some(Err(err));
+			}
+		};
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Breach provider {} answered: {}",
+			email,
+			self.provider.name(),
+			is_breached
+		);
+
+		Some(Ok(BreachResult {
+			provider: self.provider.name().to_string(),
+			is_breached,
+		}))
+	}
+}
+
+/// The breach providers used by the misc checks, each with its own rate
+/// limit. Share it between calls to `check_email` so that the rate limits
+/// apply across verifications. Defaults to HaveIBeenPwned only.
+pub struct BreachProviders(Vec<RateLimitedProvider>);
+
+impl BreachProviders {
+	/// Use the given providers, in this order.
+	pub fn new(providers: Vec<Arc<dyn BreachProvider>>) -> Self {
+		let now = Instant::now();
+		Self(
+			providers
+				.into_iter()
+				.map(|provider| RateLimitedProvider {
+					provider,
+					next_request: Mutex::new(now),
+				})
+				.collect(),
+		)
+	}
+
+	/// Register an additional provider.
+	pub fn with_provider(mut self, provider: Arc<dyn BreachProvider>) -> Self {
+		self.0.push(RateLimitedProvider {
+			provider,
+			next_request: Mutex::new(Instant::now()),
+		});
+		self
+	}
+
+	/// Check the email against all the providers concurrently, and return
+	/// the results of the providers which answered, and the errors of the
+	/// providers which failed, by provider name.
+	pub(crate) async fn check(
+		&self,
+		email: &str,
+		input: &CheckEmailInput,
+		client: Option<&reqwest::Client>,
+	) -> (Vec<BreachResult>, Vec<(String, MiscError)>) {
+		let results = futures::future::join_all(
+			self.0
+				.iter()
+				.map(|provider| provider.check(email, input, client)),
+		)
+		.await;
+
+		let mut breaches = vec![];
+		let mut errors = vec![];
+		for (provider, result) in self.0.iter().zip(results) {
+			match result {
+				Some(Ok(breach)) => breaches.push(breach),
+				Some(Err(err)) => errors.push((provider.provider.name().to_string(), err)),
+				None => {}
+			}
+		}
+		(breaches, errors)
+	}
+}
+
+impl Default for BreachProviders {
+	fn default() -> Self {
+		Self::new(vec![Arc::new(crate::haveibeenpwned::HaveIBeenPwned)])
+	}
+}
+
+impl fmt::Debug for BreachProviders {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_list()
+			.entries(self.0.iter().map(|p| p.provider.name()))
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::ReacherConfig;
+	use crate::misc::check_misc;
+	use crate::syntax::check_syntax;
+
+	struct MockProvider {
+		name: &'static str,
+		is_breached: bool,
+		min_interval: Option<Duration>,
+		/// Time of each call to `check`.
+		calls: Mutex<Vec<Instant>>,
+	}
+
+	impl MockProvider {
+		fn new(name: &'static str, is_breached: bool) -> Self {
+			Self {
+				name,
+				is_breached,
+				min_interval: None,
+				calls: Mutex::new(vec![]),
+			}
+		}
+	}
+
+	impl BreachProvider for MockProvider {
+		fn name(&self) -> &str {
+			self.name
+		}
+
+		fn min_interval(&self) -> Option<Duration> {
+			self.min_interval
+		}
+
+		fn check<'a>(
+			&'a self,
+			_email: &'a str,
+			_input: &'a CheckEmailInput,
+			_client: Option<&'a reqwest::Client>,
+		) -> BoxFuture<'a, Option<Result<bool, MiscError>>> {
+			self.calls.lock().unwrap().push(Instant::now());
+			Box::pin(async move { Some(Ok(self.is_breached)) })
+		}
+	}
+
+	#[tokio::test]
+	async fn should_aggregate_breach_providers() {
+		let config = ReacherConfig {
+			breach_providers: Arc::new(BreachProviders::new(vec![
+				Arc::new(MockProvider::new("clean", false)),
+				Arc::new(MockProvider::new("leaky", true)),
+			])),
+			..Default::default()
+		};
+
+		let misc = check_misc(
+			&check_syntax("foo@example.com"),
+			&CheckEmailInput::default(),
+			&config,
+		)
+		.await;
+
+		assert_eq!(
+			misc.breaches,
+			vec![
+				BreachResult {
+					provider: "clean".into(),
+					is_breached: false,
+				},
+				BreachResult {
+					provider: "leaky".into(),
+					is_breached: true,
+				},
+			]
+		);
+		assert_eq!(misc.is_breached(), Some(true));
+		// Without an API key, HaveIBeenPwned isn't called.
+		assert_eq!(misc.haveibeenpwned, None);
+	}
+
+	#[tokio::test]
+	async fn should_rate_limit_each_provider_independently() {
+		let slow = Arc::new(MockProvider {
+			min_interval: Some(Duration::from_millis(200)),
+			..MockProvider::new("slow", false)
+		});
+		let fast = Arc::new(MockProvider::new("fast", false));
+		let providers = BreachProviders::new(vec![slow.clone(), fast.clone()]);
+		let input = CheckEmailInput::default();
+
+		let start = Instant::now();
+		providers.check("foo@example.com", &input, None).await;
+		let (results, _) = providers.check("foo@example.com", &input, None).await;
+
+		assert_eq!(results.len(), 2);
+		let slow_calls = slow.calls.lock().unwrap().clone();
+		let fast_calls = fast.calls.lock().unwrap().clone();
+		assert_eq!(slow_calls.len(), 2);
+		assert!(slow_calls[1] - start >= Duration::from_millis(200));
+		// The slow provider's rate limit doesn't delay the fast one.
+		assert_eq!(fast_calls.len(), 2);
+		assert!(fast_calls[1] - start < Duration::from_millis(200));
+	}
+}
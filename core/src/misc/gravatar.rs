@@ -14,14 +14,20 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use super::MiscError;
 use crate::LOG_TARGET;
 use md5::Digest;
 
 const API_BASE_URL: &str = "https://www.gravatar.com/avatar/";
 
-pub async fn check_gravatar(to_email: &str) -> Option<String> {
-	let client = reqwest::Client::new();
+/// Name of the Gravatar check in `MiscDetails::errors`.
+pub(crate) const GRAVATAR: &str = "gravatar";
 
+/// The URL of the email's Gravatar, or None if it has none.
+pub async fn check_gravatar(
+	to_email: &str,
+	client: &reqwest::Client,
+) -> Result<Option<String>, MiscError> {
 	let mail_hash: Digest = md5::compute(to_email);
 
 	let url = format!("{API_BASE_URL}{mail_hash:x}");
@@ -48,13 +54,14 @@ pub async fn check_gravatar(to_email: &str) -> Option<String> {
 		response
 	);
 
-	let response = match response {
-		Ok(response) => response,
-		Err(_) => return None,
-	};
-
-	match response.status() {
-		reqwest::StatusCode::OK => Some(url),
-		_ => None,
+	let response = response?;
+	// Gravatar answers 404 for emails without a Gravatar.
+	if response.status() == reqwest::StatusCode::NOT_FOUND {
+		return Ok(None);
+	}
+	if let Some(err) = MiscError::from_response(&response) {
+		return Err(err);
 	}
+
+	Ok(Some(url))
 }
@@ -14,16 +14,54 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod breach;
 mod gravatar;
-use crate::haveibeenpwned::check_haveibeenpwned;
+pub use crate::haveibeenpwned::{
+	HaveIBeenPwned, PasteDetails, HAVEIBEENPWNED_PASTES, HAVEIBEENPWNED_PROVIDER,
+};
+pub use breach::{BreachProvider, BreachProviders, BreachResult};
 
+use once_cell::sync::Lazy;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use crate::config::ReacherConfig;
+use crate::haveibeenpwned::check_haveibeenpwned_pastes;
+use crate::smtp::{is_gmail, is_hotmail_b2b, is_hotmail_b2c, is_yahoo};
 use crate::syntax::SyntaxDetails;
-use gravatar::check_gravatar;
+use crate::{CheckEmailInput, LOG_TARGET};
+use gravatar::{check_gravatar, GRAVATAR};
 
-const ROLE_ACCOUNTS: &str = include_str!("./roles.json");
+static ROLE_ACCOUNTS: Lazy<Vec<String>> = Lazy::new(|| {
+	serde_json::from_str(include_str!("./roles.json")).expect("roles.json is a valid json. qed.")
+});
+
+static DISPOSABLE_DOMAINS: Lazy<HashSet<&'static str>> =
+	Lazy::new(|| mailchecker::blacklist().into_iter().collect());
+
+static FREE_PROVIDERS: Lazy<Vec<String>> = Lazy::new(|| {
+	serde_json::from_str(include_str!("./free_providers.json"))
+		.expect("free_providers.json is a valid json. qed.")
+});
+
+/// Whether the email address belongs to a business or to a consumer.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountType {
+	/// The domain is hosted on a business offering, e.g. Google Workspace.
+	Business,
+	/// The domain is a free email provider, or is hosted on a consumer
+	/// offering, e.g. gmail.com.
+	Consumer,
+	/// We couldn't tell, e.g. because the domain runs its own mail servers.
+	#[default]
+	Unknown,
+}
 
 /// Miscelleanous details about the email address.
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -36,24 +74,260 @@ pub struct MiscDetails {
 	/// Is this email address listed in the haveibeenpwned database for
 	/// previous breaches?
 	pub haveibeenpwned: Option<bool>,
+	/// The results of the breach providers which answered, see
+	/// `ReacherConfig::breach_providers`.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub breaches: Vec<BreachResult>,
+	/// The pastes the email appears in, according to HaveIBeenPwned. Only
+	/// set if `check_haveibeenpwned_pastes` is set in the input, and the
+	/// API answered.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub pastes: Option<Vec<PasteDetails>>,
+	/// The entry of the disposable domains list which matched, if
+	/// `is_disposable` is true. It can be a parent domain of the email's
+	/// domain.
+	pub disposable_match: Option<String>,
+	/// The entry of the role accounts list which matched, if
+	/// `is_role_account` is true.
+	pub role_match: Option<String>,
+	/// Is this a business or a consumer email address? This is a heuristic,
+	/// based on the free email providers list and on the MX records.
+	pub account_type: AccountType,
+	/// Whether the server accepts `user+tag@domain` for this existing
+	/// mailbox, a weak signal of a personal inbox. Only set if
+	/// `probe_subaddressing` is set in the input, the email is deliverable
+	/// and its domain is not a catch-all.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub supports_subaddressing: Option<bool>,
+	/// The errors of the HTTP APIs which failed, by name: "gravatar",
+	/// "haveibeenpwned_pastes", or the breach provider's name, e.g.
+	/// "haveibeenpwned".
+	#[serde(default, skip_serializing_if = "HashMap::is_empty")]
+	pub errors: HashMap<String, MiscError>,
 }
 
-/// Error occured connecting to this email server via SMTP. Right now this
-/// enum has no variant, as `check_misc` cannot fail. But putting a placeholder
-/// right now to avoid future breaking changes.
-#[derive(Debug, Serialize)]
+impl MiscDetails {
+	/// Whether any breach provider found the email in a breach, or None if
+	/// no provider answered.
+	pub fn is_breached(&self) -> Option<bool> {
+		if self.breaches.is_empty() {
+			None
+		} else {
+			Some(self.breaches.iter().any(|b| b.is_breached))
+		}
+	}
+}
+
+/// Error of an HTTP API called by the misc checks, e.g. Gravatar or a
+/// breach provider. They're reported in `MiscDetails::errors`, as the other
+/// misc checks still succeed.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Serialize)]
 #[serde(tag = "type", content = "message")]
-pub enum MiscError {}
+pub enum MiscError {
+	/// The request failed, e.g. on a connection error, a timeout or an
+	/// unexpected HTTP status, with this reason.
+	Network(String),
+	/// The API rate limited us, and asked to retry after this delay, or zero
+	/// if it didn't say.
+	RateLimited(Duration),
+	/// The API rejected our credentials, e.g. a bad API key, with this
+	/// reason.
+	Unauthorized(String),
+	/// The API's response couldn't be parsed, with this reason.
+	Deserialization(String),
+}
+
+impl From<reqwest::Error> for MiscError {
+	fn from(e: reqwest::Error) -> Self {
+		if e.is_decode() {
+			MiscError::Deserialization(e.to_string())
+		} else {
+			MiscError::Network(e.to_string())
+		}
+	}
+}
+
+impl MiscError {
+	/// The error of an API's response with this error status, if any. 401
+	/// and 403 are Unauthorized, 429 is RateLimited with the delay of the
+	/// `Retry-After` header, in seconds.
+	pub(crate) fn from_response(response: &reqwest::Response) -> Option<Self> {
+		let status = response.status();
+		match status {
+			StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+				Some(MiscError::Unauthorized(status.to_string()))
+			}
+			StatusCode::TOO_MANY_REQUESTS => {
+				let retry_after = response
+					.headers()
+					.get(RETRY_AFTER)
+					.and_then(|value| value.to_str().ok())
+					.and_then(|value| value.trim().parse().ok())
+					.map(Duration::from_secs)
+					.unwrap_or_default();
+				Some(MiscError::RateLimited(retry_after))
+			}
+			_ if status.is_client_error() || status.is_server_error() => {
+				Some(MiscError::Network(status.to_string()))
+			}
+			_ => None,
+		}
+	}
+}
+
+/// Find the entry of the list which is this domain or one of its parent
+/// domains, if any. Entries may start with a dot, e.g. ".gov".
+pub(crate) fn find_domain_in_list<'a>(domain: &str, list: &'a [String]) -> Option<&'a String> {
+	let domain = domain.to_lowercase();
+	list.iter().find(|entry| {
+		let d = entry.trim_start_matches('.').to_lowercase();
+		domain == d || domain.ends_with(&format!(".{d}"))
+	})
+}
+
+/// A list of DEA (disposable email account) domains which can be replaced at
+/// runtime, e.g. by a list fetched periodically. Until a list is set, the
+/// list bundled with mailchecker is used.
+#[derive(Debug, Default)]
+pub struct DisposableList(RwLock<Option<Arc<HashSet<String>>>>);
+
+impl DisposableList {
+	/// Replace the list of disposable domains.
+	pub fn replace(&self, domains: HashSet<String>) {
+		let domains = domains.into_iter().map(|d| d.to_lowercase()).collect();
+		*self.0.write().expect("DisposableList lock poisoned") = Some(Arc::new(domains));
+	}
+
+	/// The list set with `replace`, if any.
+	pub fn get(&self) -> Option<Arc<HashSet<String>>> {
+		self.0.read().expect("DisposableList lock poisoned").clone()
+	}
+}
+
+/// Find the entry of the DEA (disposable email account) providers list
+/// matching this email's domain, if any. The given list replaces the bundled
+/// one. Domains in the input's `disposable_allowlist` are never disposable.
+/// This check is purely offline.
+pub(crate) fn disposable_match(
+	syntax: &SyntaxDetails,
+	input: &CheckEmailInput,
+	list: Option<&HashSet<String>>,
+) -> Option<String> {
+	if !syntax.is_valid_syntax {
+		return None;
+	}
+	if let Some(allowlist) = &input.disposable_allowlist {
+		if find_domain_in_list(&syntax.domain, allowlist).is_some() {
+			return None;
+		}
+	}
+
+	// Like mailchecker, match the domain and all its parent domains, starting
+	// with the most specific one.
+	let domain = syntax.domain.to_lowercase();
+	let mut suffix = domain.as_str();
+	loop {
+		let is_disposable = match list {
+			Some(list) => list.contains(suffix),
+			None => DISPOSABLE_DOMAINS.contains(suffix),
+		};
+		if is_disposable {
+			return Some(suffix.to_string());
+		}
+		suffix = suffix.split_once('.')?.1;
+	}
+}
+
+/// Find the entry of the role accounts list matching this username, e.g.
+/// "support" or "admin", if any.
+pub(crate) fn role_match(username: &str) -> Option<String> {
+	let username = username.to_lowercase();
+	ROLE_ACCOUNTS.contains(&username).then_some(username)
+}
+
+/// Is this domain a free email provider, e.g. "gmail.com"?
+pub(crate) fn is_free_provider(domain: &str) -> bool {
+	FREE_PROVIDERS.contains(&domain.to_lowercase())
+}
+
+/// Guess whether the domain is used by a business or by consumers, from the
+/// free email providers list and from its MX hosts (e.g.
+/// "aspmx.l.google.com."). This check is purely offline.
+pub(crate) fn account_type(domain: &str, mx_hosts: &[String]) -> AccountType {
+	if is_free_provider(domain) {
+		return AccountType::Consumer;
+	}
+
+	// Consumer offerings of the big providers. gmail.com itself is in the
+	// free providers list, but not all the domains it hosts.
+	let is_consumer_host = |host: &str| {
+		host.to_lowercase().ends_with("gmail-smtp-in.l.google.com.")
+			|| is_hotmail_b2c(host)
+			|| is_yahoo(host)
+	};
+	// Business offerings, e.g. Google Workspace or Microsoft 365.
+	let is_business_host = |host: &str| is_gmail(host) || is_hotmail_b2b(host);
+
+	if mx_hosts.iter().any(|host| is_consumer_host(host)) {
+		AccountType::Consumer
+	} else if mx_hosts.iter().any(|host| is_business_host(host)) {
+		AccountType::Business
+	} else {
+		AccountType::Unknown
+	}
+}
+
+/// Create the HTTP client used for the misc checks, with the timeout and
+/// User-Agent configured in the input.
+pub(crate) fn create_http_client(
+	input: &CheckEmailInput,
+) -> Result<reqwest::Client, reqwest::Error> {
+	http_client_builder(input).build()
+}
+
+/// The HTTP client injected in the config, or else a client created from
+/// the input, see `create_http_client`.
+pub(crate) fn http_client(
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Result<reqwest::Client, reqwest::Error> {
+	match &config.http_client {
+		Some(client) => Ok(client.clone()),
+		None => create_http_client(input),
+	}
+}
+
+/// Builder of the HTTP client used for the misc checks, see
+/// `create_http_client`.
+pub(crate) fn http_client_builder(input: &CheckEmailInput) -> reqwest::ClientBuilder {
+	let mut builder = reqwest::Client::builder();
+	if let Some(timeout) = input.http_timeout {
+		builder = builder.timeout(timeout);
+	}
+	if let Some(user_agent) = &input.user_agent {
+		builder = builder.user_agent(user_agent);
+	}
+
+	builder
+}
 
 /// Fetch misc details about the email address, such as whether it's disposable.
 pub async fn check_misc(
 	syntax: &SyntaxDetails,
-	cfg_check_gravatar: bool,
-	haveibeenpwned_api_key: Option<String>,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
 ) -> MiscDetails {
-	let role_accounts: Vec<&str> =
-		serde_json::from_str(ROLE_ACCOUNTS).expect("roles.json is a valid json. qed.");
+	check_misc_with(syntax, input, config, true).await
+}
 
+/// Like `check_misc`, but only call the Gravatar and breach providers' APIs,
+/// if enabled in the input, when `http_checks` is set.
+pub(crate) async fn check_misc_with(
+	syntax: &SyntaxDetails,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	http_checks: bool,
+) -> MiscDetails {
 	let address = syntax
 		.address
 		.as_ref()
@@ -61,24 +335,256 @@ pub async fn check_misc(
 		.to_string();
 
 	let mut gravatar_url: Option<String> = None;
+	let mut errors = HashMap::new();
 
-	if cfg_check_gravatar {
-		gravatar_url = check_gravatar(address.as_ref()).await;
+	if input.check_gravatar && http_checks {
+		match http_client(input, config) {
+			Ok(client) => match check_gravatar(address.as_ref(), &client).await {
+				Ok(url) => gravatar_url = url,
+				Err(err) => {
+					errors.insert(GRAVATAR.to_string(), err);
+				}
+			},
+			Err(err) => log::error!(
+				target: LOG_TARGET,
+				"[email={}] Error creating HTTP client: {}",
+				address,
+				err
+			),
+		}
 	}
 
-	let mut haveibeenpwned: Option<bool> = None;
-
-	if haveibeenpwned_api_key.is_some() {
-		haveibeenpwned = check_haveibeenpwned(address.as_ref(), haveibeenpwned_api_key).await;
+	let breaches = if http_checks {
+		let (breaches, breach_errors) = config
+			.breach_providers
+			.check(&address, input, config.http_client.as_ref())
+			.await;
+		errors.extend(breach_errors);
+		breaches
+	} else {
+		vec![]
+	};
+	let mut pastes = None;
+	if let (Some(api_key), true, true) = (
+		&input.haveibeenpwned_api_key,
+		input.check_haveibeenpwned_pastes,
+		http_checks,
+	) {
+		match http_client(input, config) {
+			Ok(client) => {
+				match check_haveibeenpwned_pastes(
+					&address,
+					api_key,
+					input.user_agent.as_deref(),
+					&client,
+				)
+				.await
+				{
+					Ok(p) => pastes = Some(p),
+					Err(err) => {
+						errors.insert(HAVEIBEENPWNED_PASTES.to_string(), err);
+					}
+				}
+			}
+			Err(err) => log::error!(
+				target: LOG_TARGET,
+				"[email={}] Error creating HTTP client: {}",
+				address,
+				err
+			),
+		}
 	}
 
+	let haveibeenpwned = breaches
+		.iter()
+		.find(|b| b.provider == HAVEIBEENPWNED_PROVIDER)
+		.map(|b| b.is_breached);
+
+	let disposable_match = disposable_match(syntax, input, config.disposable_list.get().as_deref());
+	let role_match = role_match(&syntax.username);
+
 	MiscDetails {
-		// mailchecker::is_valid checks also if the syntax is valid. But if
-		// we're here, it means we're sure the syntax is valid, so is_valid
-		// actually will only check if it's disposable.
-		is_disposable: !mailchecker::is_valid(address.as_ref()),
-		is_role_account: role_accounts.contains(&syntax.username.to_lowercase().as_ref()),
+		is_disposable: disposable_match.is_some(),
+		is_role_account: role_match.is_some(),
 		gravatar_url,
 		haveibeenpwned,
+		breaches,
+		pastes,
+		disposable_match,
+		role_match,
+		// Needs the MX records, filled by `check_email`.
+		account_type: AccountType::Unknown,
+		// Needs the SMTP connection, filled by `check_email`.
+		supports_subaddressing: None,
+		errors,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{account_type, check_misc, create_http_client, disposable_match, AccountType};
+	use crate::config::ReacherConfig;
+	use crate::syntax::check_syntax;
+	use crate::CheckEmailInputBuilder;
+	use std::collections::HashSet;
+	use std::time::{Duration, Instant};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+
+	#[test]
+	fn should_not_flag_allowlisted_disposable_domain() {
+		let syntax = check_syntax("foo@yopmail.com");
+		let input = CheckEmailInputBuilder::default().build().unwrap();
+		assert!(disposable_match(&syntax, &input, None).is_some());
+
+		let input = CheckEmailInputBuilder::default()
+			.disposable_allowlist(Some(vec!["YOPmail.com".into()]))
+			.build()
+			.unwrap();
+		assert_eq!(disposable_match(&syntax, &input, None), None);
+		let syntax = check_syntax("foo@alias.yopmail.com");
+		assert_eq!(disposable_match(&syntax, &input, None), None);
+	}
+
+	#[test]
+	fn should_guess_account_type() {
+		let gmail_mx = vec!["gmail-smtp-in.l.google.com.".to_string()];
+		assert_eq!(account_type("gmail.com", &gmail_mx), AccountType::Consumer);
+
+		let workspace_mx = vec![
+			"aspmx.l.google.com.".to_string(),
+			"alt1.aspmx.l.google.com.".to_string(),
+		];
+		assert_eq!(
+			account_type("acme-corp.com", &workspace_mx),
+			AccountType::Business
+		);
+
+		let own_mx = vec!["mx.acme-corp.com.".to_string()];
+		assert_eq!(account_type("acme-corp.com", &own_mx), AccountType::Unknown);
+	}
+
+	#[tokio::test]
+	async fn should_report_matched_disposable_and_role_entries() {
+		let syntax = check_syntax("Info@mailinator.com");
+		let input = CheckEmailInputBuilder::default().build().unwrap();
+		let config = ReacherConfig::default();
+		let misc = check_misc(&syntax, &input, &config).await;
+
+		assert!(misc.is_disposable);
+		assert_eq!(misc.disposable_match, Some("mailinator.com".into()));
+		assert!(misc.is_role_account);
+		assert_eq!(misc.role_match, Some("info".into()));
+
+		let syntax = check_syntax("foo@sub.mailinator.com");
+		let misc = check_misc(&syntax, &input, &config).await;
+		assert_eq!(misc.disposable_match, Some("mailinator.com".into()));
+		assert_eq!(misc.role_match, None);
+	}
+
+	#[tokio::test]
+	async fn should_use_replaced_disposable_list() {
+		let input = CheckEmailInputBuilder::default().build().unwrap();
+		let config = ReacherConfig::default();
+		config
+			.disposable_list
+			.replace(HashSet::from(["Example.com".to_string()]));
+
+		let misc = check_misc(&check_syntax("foo@mail.example.com"), &input, &config).await;
+		assert_eq!(misc.disposable_match, Some("example.com".into()));
+		// The bundled list is replaced, not extended.
+		let misc = check_misc(&check_syntax("foo@mailinator.com"), &input, &config).await;
+		assert!(!misc.is_disposable);
+	}
+
+	#[tokio::test]
+	async fn should_send_configured_user_agent() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut buf = vec![0; 4096];
+			let n = socket.read(&mut buf).await.unwrap();
+			socket
+				.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+				.await
+				.unwrap();
+			String::from_utf8_lossy(&buf[..n]).to_lowercase()
+		});
+
+		let input = CheckEmailInputBuilder::default()
+			.user_agent(Some("my-app/1.0 (contact@example.com)".into()))
+			.build()
+			.unwrap();
+		let client = create_http_client(&input).unwrap();
+		let response = client.get(format!("http://{addr}")).send().await.unwrap();
+		assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+		let request = server.await.unwrap();
+		assert!(request.contains("user-agent: my-app/1.0 (contact@example.com)\r\n"));
+	}
+
+	#[tokio::test]
+	async fn should_use_injected_http_client() {
+		// A proxy which records the tunnel request of the injected client.
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut buf = vec![0; 4096];
+			let n = socket.read(&mut buf).await.unwrap();
+			socket
+				.write_all(b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n")
+				.await
+				.unwrap();
+			String::from_utf8_lossy(&buf[..n]).to_lowercase()
+		});
+
+		let client = reqwest::Client::builder()
+			.proxy(reqwest::Proxy::all(format!("http://{addr}")).unwrap())
+			.user_agent("injected-client")
+			.build()
+			.unwrap();
+		let config = ReacherConfig {
+			http_client: Some(client),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.check_gravatar(true)
+			.build()
+			.unwrap();
+
+		let misc = check_misc(&check_syntax("foo@example.com"), &input, &config).await;
+		assert!(misc.gravatar_url.is_none());
+
+		let request = server.await.unwrap();
+		assert!(request.starts_with("connect www.gravatar.com:443 "));
+		assert!(request.contains("user-agent: injected-client\r\n"));
+	}
+
+	#[tokio::test]
+	async fn should_apply_configured_timeout() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		// Accept the connection but never answer.
+		let _server = tokio::spawn(async move {
+			let (_socket, _) = listener.accept().await.unwrap();
+			tokio::time::sleep(Duration::from_secs(10)).await;
+		});
+
+		let input = CheckEmailInputBuilder::default()
+			.http_timeout(Some(Duration::from_millis(100)))
+			.build()
+			.unwrap();
+		let client = create_http_client(&input).unwrap();
+		let start = Instant::now();
+		let err = client
+			.get(format!("http://{addr}"))
+			.send()
+			.await
+			.unwrap_err();
+
+		assert!(err.is_timeout());
+		assert!(start.elapsed() < Duration::from_secs(5));
 	}
 }
@@ -0,0 +1,308 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fetch the MTA-STS policy of a domain, as defined in RFC 8461.
+
+use crate::config::ReacherConfig;
+use crate::misc::http_client_builder;
+use crate::util::ser_with_display::ser_with_display;
+use crate::{CheckEmailInput, LOG_TARGET};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use serde::Serialize;
+use thiserror::Error;
+
+/// Mode of an MTA-STS policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MtaStsMode {
+	/// Sending servers must not deliver to MX hosts which don't match the
+	/// policy, or which don't have a valid certificate.
+	Enforce,
+	/// Sending servers should only report policy failures.
+	Testing,
+	/// The domain doesn't use MTA-STS anymore.
+	None,
+}
+
+/// Details about the MTA-STS policy of the email's domain.
+#[derive(Debug, Default, Serialize)]
+pub struct MtaStsDetails {
+	/// Does the domain publish an MTA-STS policy?
+	pub has_policy: bool,
+	/// Mode of the policy.
+	pub mode: Option<MtaStsMode>,
+	/// MX patterns of the policy, e.g. "mail.example.com" or
+	/// "*.example.net".
+	pub mx: Vec<String>,
+	/// Time, in seconds, during which the policy can be cached.
+	pub max_age: Option<u64>,
+	/// MX hosts of the domain which don't match any of the policy's MX
+	/// patterns.
+	pub mismatched_mx_hosts: Vec<String>,
+}
+
+/// Errors that can happen when fetching the MTA-STS policy.
+#[derive(Debug, Error, Serialize)]
+#[serde(tag = "type", content = "message")]
+pub enum MtaStsError {
+	/// Error while looking up the `_mta-sts` TXT record.
+	#[serde(serialize_with = "ser_with_display")]
+	#[error("Resolve error: {0}")]
+	ResolveError(Box<ResolveError>),
+	/// Error while fetching the policy file.
+	#[serde(serialize_with = "ser_with_display")]
+	#[error("Reqwest error: {0}")]
+	ReqwestError(reqwest::Error),
+	/// The policy file is invalid.
+	#[error("Invalid policy: {0}")]
+	InvalidPolicy(String),
+}
+
+impl From<ResolveError> for MtaStsError {
+	fn from(e: ResolveError) -> Self {
+		MtaStsError::ResolveError(Box::new(e))
+	}
+}
+
+impl From<reqwest::Error> for MtaStsError {
+	fn from(e: reqwest::Error) -> Self {
+		MtaStsError::ReqwestError(e)
+	}
+}
+
+/// Check the MTA-STS policy of the domain: look up its `_mta-sts` TXT record
+/// and, if there's one, fetch and parse the policy file, and compare the
+/// policy's MX patterns to the domain's MX hosts.
+pub async fn check_mta_sts(
+	domain: &str,
+	mx_hosts: &[String],
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Result<MtaStsDetails, MtaStsError> {
+	let url = format!("https://mta-sts.{domain}/.well-known/mta-sts.txt");
+	check_mta_sts_at(domain, &url, mx_hosts, input, config).await
+}
+
+async fn check_mta_sts_at(
+	domain: &str,
+	url: &str,
+	mx_hosts: &[String],
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Result<MtaStsDetails, MtaStsError> {
+	// Add a trailing dot, so that the domain is not resolved relative to the
+	// system's search domains.
+	let record = format!("_mta-sts.{domain}.");
+	let has_record = match config.resolver.txt_lookup(record).await {
+		Ok(lookup) => lookup
+			.iter()
+			.any(|txt| txt.to_string().trim_start().starts_with("v=STSv1")),
+		Err(err) => match err.kind() {
+			ResolveErrorKind::NoRecordsFound { .. } => false,
+			_ => return Err(err.into()),
+		},
+	};
+	if !has_record {
+		return Ok(MtaStsDetails::default());
+	}
+
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] Fetching MTA-STS policy at {}",
+		input.to_email,
+		url
+	);
+	// RFC 8461 forbids following redirects when fetching the policy.
+	let client = http_client_builder(input)
+		.redirect(reqwest::redirect::Policy::none())
+		.build()?;
+	let response = client.get(url).send().await?.error_for_status()?;
+	let body = response.text().await?;
+
+	let mut details = parse_policy(&body)?;
+	details.mismatched_mx_hosts = mx_hosts
+		.iter()
+		.filter(|host| !details.mx.iter().any(|pattern| mx_matches(pattern, host)))
+		.cloned()
+		.collect();
+
+	Ok(details)
+}
+
+/// Parse an MTA-STS policy file, made of "key: value" lines.
+fn parse_policy(body: &str) -> Result<MtaStsDetails, MtaStsError> {
+	let mut version = None;
+	let mut details = MtaStsDetails {
+		has_policy: true,
+		..Default::default()
+	};
+
+	for line in body.lines() {
+		let Some((key, value)) = line.split_once(':') else {
+			continue;
+		};
+		let value = value.trim();
+		match key.trim() {
+			"version" => version = Some(value.to_string()),
+			"mode" => {
+				details.mode = Some(match value {
+					"enforce" => MtaStsMode::Enforce,
+					"testing" => MtaStsMode::Testing,
+					"none" => MtaStsMode::None,
+					_ => {
+						return Err(MtaStsError::InvalidPolicy(format!(
+							"unknown mode \"{value}\""
+						)))
+					}
+				})
+			}
+			"max_age" => {
+				details.max_age = Some(value.parse().map_err(|_| {
+					MtaStsError::InvalidPolicy(format!("invalid max_age \"{value}\""))
+				})?)
+			}
+			"mx" => details.mx.push(value.to_string()),
+			// Unknown keys must be ignored.
+			_ => {}
+		}
+	}
+
+	if version.as_deref() != Some("STSv1") {
+		return Err(MtaStsError::InvalidPolicy("missing version STSv1".into()));
+	}
+	if details.mode.is_none() {
+		return Err(MtaStsError::InvalidPolicy("missing mode".into()));
+	}
+	if details.max_age.is_none() {
+		return Err(MtaStsError::InvalidPolicy("missing max_age".into()));
+	}
+
+	Ok(details)
+}
+
+/// Check if the MX host matches the policy's MX pattern. A wildcard pattern,
+/// like "*.example.net", only matches one label, e.g. "mx1.example.net" but
+/// not "a.mx1.example.net".
+fn mx_matches(pattern: &str, host: &str) -> bool {
+	let pattern = pattern.trim_end_matches('.').to_lowercase();
+	let host = host.trim_end_matches('.').to_lowercase();
+	match pattern.strip_prefix("*.") {
+		Some(suffix) => host
+			.split_once('.')
+			.is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+		None => host == pattern,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::mock_dns::{response, txt_response, MockDnsServer};
+	use hickory_proto::op::ResponseCode;
+	use std::sync::Arc;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+	const POLICY: &str = "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.example.net\r\nmax_age: 604800\r\n";
+
+	/// Serve the policy over HTTP on localhost, and return its URL.
+	async fn serve_policy(policy: &'static str) -> String {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			while let Ok((mut socket, _)) = listener.accept().await {
+				let mut buf = vec![0; 4096];
+				let _ = socket.read(&mut buf).await;
+				let reply = format!(
+					"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+					policy.len(),
+					policy
+				);
+				let _ = socket.write_all(reply.as_bytes()).await;
+			}
+		});
+		format!("http://{addr}/.well-known/mta-sts.txt")
+	}
+
+	#[test]
+	fn should_parse_policy() {
+		let details = parse_policy(POLICY).unwrap();
+		assert!(details.has_policy);
+		assert_eq!(details.mode, Some(MtaStsMode::Enforce));
+		assert_eq!(details.mx, vec!["mail.example.com", "*.example.net"]);
+		assert_eq!(details.max_age, Some(604800));
+
+		assert!(parse_policy("mode: enforce\nmax_age: 1\n").is_err());
+		assert!(parse_policy("version: STSv1\nmode: strict\nmax_age: 1\n").is_err());
+	}
+
+	#[test]
+	fn should_match_mx_patterns() {
+		assert!(mx_matches("mail.example.com", "mail.example.com."));
+		assert!(mx_matches("*.example.net", "MX1.example.net."));
+		assert!(!mx_matches("*.example.net", "a.mx1.example.net."));
+		assert!(!mx_matches("*.example.net", "example.net."));
+		assert!(!mx_matches("mail.example.com", "mail2.example.com."));
+	}
+
+	#[tokio::test]
+	async fn should_fetch_policy_and_flag_mismatched_mx() {
+		let server =
+			MockDnsServer::start(|query| txt_response(query, 300, &["v=STSv1; id=20240101"])).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let url = serve_policy(POLICY).await;
+		let mx_hosts = vec![
+			"mail.example.com.".to_string(),
+			"mx1.example.net.".to_string(),
+			"mx.other.org.".to_string(),
+		];
+
+		let details = check_mta_sts_at(
+			"example.com",
+			&url,
+			&mx_hosts,
+			&CheckEmailInput::default(),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert_eq!(details.mode, Some(MtaStsMode::Enforce));
+		assert_eq!(details.mismatched_mx_hosts, vec!["mx.other.org."]);
+	}
+
+	#[tokio::test]
+	async fn should_skip_fetch_without_txt_record() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+
+		// The URL is never fetched.
+		let details = check_mta_sts_at(
+			"example.com",
+			"http://127.0.0.1:1/",
+			&[],
+			&CheckEmailInput::default(),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(!details.has_policy);
+	}
+}
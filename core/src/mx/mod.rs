@@ -14,34 +14,101 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::config::ReacherConfig;
+use crate::misc::find_domain_in_list;
 use crate::syntax::SyntaxDetails;
 use crate::util::ser_with_display::ser_with_display;
+use crate::CheckEmailInput;
+use crate::LOG_TARGET;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
 use hickory_resolver::error::{ResolveError, ResolveErrorKind};
 use hickory_resolver::lookup::MxLookup;
 use hickory_resolver::system_conf::read_system_conf;
 use hickory_resolver::TokioAsyncResolver;
-use serde::{ser::SerializeMap, Serialize, Serializer};
+use once_cell::sync::Lazy;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 use std::io;
 use thiserror::Error;
 
+/// Domains of the mail servers of known domain parking services. Parked
+/// domains usually accept all emails, without any real mailbox behind.
+static PARKING_MX_HOSTS: Lazy<Vec<String>> = Lazy::new(|| {
+	serde_json::from_str(include_str!("./parking_mx.json"))
+		.expect("parking_mx.json is a valid json. qed.")
+});
+
+/// An entry of the MX provider table: MX hosts containing `pattern` belong
+/// to `provider`.
+#[derive(Debug, Deserialize)]
+struct MxProviderPattern {
+	pattern: String,
+	provider: String,
+}
+
+/// Known mail providers, by a substring of their MX hosts. The first
+/// matching entry wins, so more specific patterns come first.
+static MX_PROVIDERS: Lazy<Vec<MxProviderPattern>> = Lazy::new(|| {
+	serde_json::from_str(include_str!("./mx_providers.json"))
+		.expect("mx_providers.json is a valid json. qed.")
+});
+
+/// Return the canonical name of the mail provider behind an MX host, e.g.
+/// "Google Workspace" for "aspmx.l.google.com", if it's a known one.
+pub fn find_mx_provider(host: &str) -> Option<String> {
+	let host = host.to_lowercase();
+	MX_PROVIDERS
+		.iter()
+		.find(|entry| host.contains(&entry.pattern))
+		.map(|entry| entry.provider.clone())
+}
+
 /// Details about the MX lookup.
 #[derive(Debug)]
 pub struct MxDetails {
 	/// MX lookup of this DNS.
 	pub lookup: Result<MxLookup, ResolveError>,
+	/// The TTL, in seconds, of the MX records. If the records have different
+	/// TTLs, this is the smallest one.
+	pub ttl: Option<u32>,
+	/// The canonical name of the mail provider, e.g. "Google Workspace" or
+	/// "Microsoft 365", from the first MX host matching a known provider.
+	pub mx_provider: Option<String>,
+	/// Whether the MX lookup was skipped because of the input's
+	/// `smtp_host_override`.
+	pub overridden: bool,
 }
 
 impl Default for MxDetails {
 	fn default() -> Self {
 		MxDetails {
 			lookup: Err(ResolveError::from("Skipped")),
+			ttl: None,
+			mx_provider: None,
+			overridden: false,
 		}
 	}
 }
 
 impl From<MxLookup> for MxDetails {
 	fn from(lookup: MxLookup) -> Self {
-		MxDetails { lookup: Ok(lookup) }
+		let ttl = lookup
+			.as_lookup()
+			.record_iter()
+			.filter(|record| record.record_type() == RecordType::MX)
+			.map(|record| record.ttl())
+			.min();
+		let mx_provider = lookup
+			.iter()
+			.find_map(|host| find_mx_provider(&host.exchange().to_string()));
+
+		MxDetails {
+			lookup: Ok(lookup),
+			ttl,
+			mx_provider,
+			overridden: false,
+		}
 	}
 }
 
@@ -61,9 +128,17 @@ impl Serialize for MxDetails {
 			})
 			.unwrap_or_else(|_| Vec::new()); // In case of a resolve error, we don't serialize the error.
 
-		let mut map = serializer.serialize_map(Some(2))?;
+		let mut map = serializer.serialize_map(Some(3))?;
 		map.serialize_entry("accepts_mail", &!records.is_empty())?;
 		map.serialize_entry("records", &records)?;
+		map.serialize_entry("ttl", &self.ttl)?;
+		// Only serialized if known, to keep the output unchanged otherwise.
+		if let Some(mx_provider) = &self.mx_provider {
+			map.serialize_entry("mx_provider", mx_provider)?;
+		}
+		if self.overridden {
+			map.serialize_entry("overridden", &true)?;
+		}
 		map.end()
 	}
 }
@@ -80,6 +155,23 @@ pub enum MxError {
 	#[serde(serialize_with = "ser_with_display")]
 	#[error("Resolve error: {0}")]
 	ResolveError(Box<ResolveError>),
+	/// The DNS server didn't answer in time. Unlike a domain which doesn't
+	/// exist (NXDOMAIN), it doesn't say anything about the domain.
+	#[error("DNS lookup timed out")]
+	Timeout,
+	/// The DNS server failed to answer (SERVFAIL), e.g. because the domain's
+	/// name servers are down. Unlike a domain which doesn't exist (NXDOMAIN),
+	/// it doesn't say anything about the domain.
+	#[error("DNS server failure")]
+	ServFail,
+}
+
+impl MxError {
+	/// Whether the MX lookup may succeed if retried later, as opposed to an
+	/// internal error.
+	pub fn is_retryable(&self) -> bool {
+		matches!(self, MxError::Timeout | MxError::ServFail)
+	}
 }
 
 impl From<io::Error> for MxError {
@@ -94,18 +186,203 @@ impl From<ResolveError> for MxError {
 	}
 }
 
-/// Make a MX lookup.
-pub async fn check_mx(syntax: &SyntaxDetails) -> Result<MxDetails, MxError> {
-	// Construct a new Resolver with default configuration options
-	let (config, opts) = read_system_conf()?;
-	let resolver = TokioAsyncResolver::tokio(config, opts);
+/// Number of DNS answers kept in the resolver's cache. The default of the
+/// resolver is quite low for bulk verifications.
+const DNS_CACHE_SIZE: usize = 4096;
+
+/// Create a DNS resolver from the system configuration, falling back to the
+/// default configuration if it can't be read. The resolver caches answers
+/// according to their TTL, so it should be created once and shared between
+/// calls, see `ReacherConfig::resolver`.
+pub fn create_resolver() -> TokioAsyncResolver {
+	let (config, mut opts) = read_system_conf().unwrap_or_else(|err| {
+		log::warn!(
+			target: LOG_TARGET,
+			"Cannot read system DNS configuration, using default: {}",
+			err
+		);
+		(ResolverConfig::default(), ResolverOpts::default())
+	});
+	opts.cache_size = opts.cache_size.max(DNS_CACHE_SIZE);
+
+	TokioAsyncResolver::tokio(config, opts)
+}
+
+/// If all the MX records of the lookup point to a domain parking service,
+/// return the first of them. The list of parking mail servers can be
+/// overridden in the input's `parking_mx_hosts`.
+pub(crate) fn find_parking_host(lookup: &MxLookup, input: &CheckEmailInput) -> Option<String> {
+	let parking_hosts = input.parking_mx_hosts.as_ref().unwrap_or(&PARKING_MX_HOSTS);
+	let is_parking_host =
+		|host: &str| find_domain_in_list(host.trim_end_matches('.'), parking_hosts).is_some();
+
+	let hosts = lookup
+		.iter()
+		.map(|mx| mx.exchange().to_string())
+		.collect::<Vec<_>>();
+	if !hosts.is_empty() && hosts.iter().all(|host| is_parking_host(host)) {
+		hosts.into_iter().next()
+	} else {
+		None
+	}
+}
 
-	match resolver.mx_lookup(&syntax.domain).await {
+/// Make a MX lookup, using the shared resolver of the config.
+///
+/// A domain which doesn't exist (NXDOMAIN) or has no MX records gets an
+/// empty MX lookup, so that the email is Invalid. A DNS timeout or server
+/// failure (SERVFAIL) returns an error instead, so that the email is
+/// Unknown.
+pub async fn check_mx(
+	syntax: &SyntaxDetails,
+	config: &ReacherConfig,
+) -> Result<MxDetails, MxError> {
+	match config.resolver.mx_lookup(&syntax.domain).await {
 		Ok(lookup) => Ok(MxDetails::from(lookup)),
 		Err(err) => match err.kind() {
+			// The resolver also reports SERVFAIL as no records.
+			ResolveErrorKind::NoRecordsFound {
+				response_code: ResponseCode::ServFail,
+				..
+			} => Err(MxError::ServFail),
+			ResolveErrorKind::Timeout => Err(MxError::Timeout),
 			// Prefer to return an empty MX lookup if there are no records.
-			ResolveErrorKind::NoRecordsFound { .. } => Ok(MxDetails { lookup: Err(err) }),
+			ResolveErrorKind::NoRecordsFound { .. } => Ok(MxDetails {
+				lookup: Err(err),
+				ttl: None,
+				mx_provider: None,
+				overridden: false,
+			}),
 			_ => Err(err.into()),
 		},
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{check_mx, find_mx_provider, find_parking_host};
+	use crate::config::ReacherConfig;
+	use crate::syntax::check_syntax;
+	use crate::util::mock_dns::{mx_response, MockDnsServer};
+	use crate::{CheckEmailInput, CheckEmailInputBuilder};
+	use std::sync::Arc;
+
+	#[tokio::test]
+	async fn should_cache_mx_lookups() {
+		let server =
+			MockDnsServer::start(|query| mx_response(query, 300, &[(10, "mx.example.com.")])).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+
+		let syntax = check_syntax("foo@example.com");
+		let first = check_mx(&syntax, &config).await.unwrap();
+		let second = check_mx(&syntax, &config).await.unwrap();
+
+		assert_eq!(first.lookup.unwrap().iter().count(), 1);
+		assert_eq!(second.lookup.unwrap().iter().count(), 1);
+		assert_eq!(server.queries(), 1);
+	}
+
+	#[tokio::test]
+	async fn should_find_parking_host() {
+		let server =
+			MockDnsServer::start(|query| mx_response(query, 300, &[(10, "park-mx.above.com.")]))
+				.await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let mx = check_mx(&check_syntax("foo@parked.com"), &config)
+			.await
+			.unwrap();
+		let lookup = mx.lookup.unwrap();
+
+		let input = CheckEmailInput::default();
+		assert_eq!(
+			find_parking_host(&lookup, &input),
+			Some("park-mx.above.com.".into())
+		);
+
+		// The list of parking hosts is overridable.
+		let input = CheckEmailInputBuilder::default()
+			.parking_mx_hosts(Some(vec!["parking.example.com".into()]))
+			.build()
+			.unwrap();
+		assert_eq!(find_parking_host(&lookup, &input), None);
+	}
+
+	#[tokio::test]
+	async fn should_return_mx_ttl() {
+		let server = MockDnsServer::start(|query| {
+			mx_response(
+				query,
+				1234,
+				&[(10, "mx1.example.com."), (20, "mx2.example.com.")],
+			)
+		})
+		.await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+
+		let mx = check_mx(&check_syntax("foo@example.com"), &config)
+			.await
+			.unwrap();
+
+		assert_eq!(mx.ttl, Some(1234));
+		assert_eq!(
+			serde_json::to_value(&mx).unwrap()["ttl"],
+			serde_json::json!(1234)
+		);
+	}
+
+	#[test]
+	fn should_find_mx_provider() {
+		assert_eq!(
+			find_mx_provider("aspmx.l.google.com."),
+			Some("Google Workspace".into())
+		);
+		assert_eq!(
+			find_mx_provider("example-com.mail.protection.outlook.com."),
+			Some("Microsoft 365".into())
+		);
+		assert_eq!(
+			find_mx_provider("eur.olc.protection.outlook.com."),
+			Some("Outlook.com".into())
+		);
+		assert_eq!(
+			find_mx_provider("mx1.example.com.pphosted.com."),
+			Some("Proofpoint".into())
+		);
+		assert_eq!(find_mx_provider("mx1.example.com."), None);
+	}
+
+	#[tokio::test]
+	async fn should_return_mx_provider() {
+		let server = MockDnsServer::start(|query| {
+			mx_response(
+				query,
+				300,
+				&[(1, "aspmx.l.google.com."), (5, "alt1.aspmx.l.google.com.")],
+			)
+		})
+		.await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+
+		let mx = check_mx(&check_syntax("foo@example.com"), &config)
+			.await
+			.unwrap();
+
+		assert_eq!(mx.mx_provider, Some("Google Workspace".into()));
+		assert_eq!(
+			serde_json::to_value(&mx).unwrap()["mx_provider"],
+			serde_json::json!("Google Workspace")
+		);
+	}
+}
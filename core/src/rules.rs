@@ -20,6 +20,7 @@
 //!
 //! IMPORTANT: This is still a beta feature, and probably needs refining.
 
+use crate::mx::find_mx_provider;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -32,6 +33,13 @@ pub enum Rule {
 	SmtpTimeout45s,
 	/// Honey pot.
 	HoneyPot,
+	/// The provider supports plus-addressing, i.e. user+tag@domain is
+	/// delivered to user@domain.
+	PlusAddressing,
+	/// The provider doesn't support a true catch-all, so a single accepted
+	/// random probe isn't conclusive: probe at least twice, and only flag a
+	/// catch-all if every probe is accepted.
+	StrictCatchAll,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -48,6 +56,9 @@ struct AllRules {
 	/// Apply rules by the MX host. Since each domain potentially has multiple
 	/// MX records, we match by their suffix.
 	by_mx_suffix: HashMap<String, RulesByDomain>,
+	/// Apply rules by the mail provider behind the MX host, as classified in
+	/// `mx_provider`, e.g. "Google Workspace".
+	by_mx_provider: HashMap<String, RulesByDomain>,
 }
 
 static ALL_RULES: Lazy<AllRules> =
@@ -79,9 +90,43 @@ fn does_mx_suffix_have_rule(host: &str, rule: &Rule) -> bool {
 	false
 }
 
-/// Check if either the domain or the MX host has any given rule.
+fn does_mx_provider_have_rule(host: &str, rule: &Rule) -> bool {
+	if let Some(v) = find_mx_provider(host).and_then(|p| ALL_RULES.by_mx_provider.get(&p)) {
+		return v.rules.contains(rule);
+	}
+
+	false
+}
+
+/// Check if either the domain, the MX host or its provider has any given
+/// rule.
 pub fn has_rule(domain: &str, host: &str, rule: &Rule) -> bool {
 	does_domain_have_rule(domain, rule)
 		|| does_mx_have_rule(host, rule)
 		|| does_mx_suffix_have_rule(host, rule)
+		|| does_mx_provider_have_rule(host, rule)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_match_rules_by_mx_provider() {
+		assert!(has_rule(
+			"example.com",
+			"aspmx.l.google.com.",
+			&Rule::StrictCatchAll
+		));
+		assert!(has_rule(
+			"example.com",
+			"example-com.mail.protection.outlook.com.",
+			&Rule::StrictCatchAll
+		));
+		assert!(!has_rule(
+			"example.com",
+			"mx.example.com.",
+			&Rule::StrictCatchAll
+		));
+	}
 }
@@ -0,0 +1,138 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cache the catch-all determination of each domain, so that verifying many
+//! addresses at the same domain only probes a random address once.
+
+use hashlink::LinkedHashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Maximum number of cached domains, beyond which the oldest are evicted.
+const MAX_ENTRIES: usize = 10_000;
+
+/// A cached catch-all determination.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CachedCatchAll {
+	/// Whether the domain is a catch-all.
+	pub is_catch_all: bool,
+	/// The share of random addresses accepted, if more than one was probed.
+	pub confidence: Option<f32>,
+	/// Whether the server accepts every recipient at `RCPT TO`, see
+	/// `SmtpDetails::accepts_at_rcpt_only`.
+	pub accepts_at_rcpt_only: bool,
+}
+
+/// Catch-all determination of each domain, kept for a TTL. It's meant to be
+/// shared between verifications, see `ReacherConfig`.
+#[derive(Debug, Default)]
+pub struct CatchAllCache {
+	/// How long a determination is reused. None disables the cache.
+	ttl: Option<Duration>,
+	/// Ordered from the oldest to the most recently inserted.
+	entries: Mutex<LinkedHashMap<String, (Instant, CachedCatchAll)>>,
+}
+
+impl CatchAllCache {
+	/// Reuse the catch-all determination of a domain for `ttl`, or never
+	/// cache it if None.
+	pub fn new(ttl: Option<Duration>) -> Self {
+		Self {
+			ttl,
+			entries: Mutex::default(),
+		}
+	}
+
+	/// The cached determination of the domain, if it hasn't expired.
+	pub(crate) fn get(&self, domain: &str) -> Option<CachedCatchAll> {
+		let ttl = self.ttl?;
+		let mut entries = self.entries.lock().expect("Lock is not poisoned. qed.");
+		let domain = domain.to_lowercase();
+		match entries.get(&domain) {
+			Some((inserted_at, cached)) if inserted_at.elapsed() < ttl => Some(*cached),
+			Some(_) => {
+				entries.remove(&domain);
+				None
+			}
+			None => None,
+		}
+	}
+
+	/// Cache the determination of the domain, if the cache is enabled.
+	pub(crate) fn insert(&self, domain: &str, cached: CachedCatchAll) {
+		let Some(ttl) = self.ttl else {
+			return;
+		};
+		let mut entries = self.entries.lock().expect("Lock is not poisoned. qed.");
+		// The entries share the TTL, so the expired ones are at the front.
+		while let Some((_, (inserted_at, _))) = entries.front() {
+			if inserted_at.elapsed() < ttl && entries.len() < MAX_ENTRIES {
+				break;
+			}
+			entries.pop_front();
+		}
+		// Inserting moves an existing domain to the back.
+		entries.insert(domain.to_lowercase(), (Instant::now(), cached));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CachedCatchAll, CatchAllCache, MAX_ENTRIES};
+	use std::thread::sleep;
+	use std::time::Duration;
+
+	const CATCH_ALL: CachedCatchAll = CachedCatchAll {
+		is_catch_all: true,
+		confidence: None,
+		accepts_at_rcpt_only: false,
+	};
+
+	#[test]
+	fn should_not_cache_without_ttl() {
+		let cache = CatchAllCache::default();
+		cache.insert("example.com", CATCH_ALL);
+		assert_eq!(cache.get("example.com"), None);
+	}
+
+	#[test]
+	fn should_expire_after_ttl() {
+		let cache = CatchAllCache::new(Some(Duration::from_millis(50)));
+		cache.insert("Example.com", CATCH_ALL);
+		assert_eq!(cache.get("example.com"), Some(CATCH_ALL));
+		assert_eq!(cache.get("example.org"), None);
+
+		sleep(Duration::from_millis(60));
+		assert_eq!(cache.get("example.com"), None);
+	}
+
+	#[test]
+	fn should_evict_the_oldest_entries() {
+		let cache = CatchAllCache::new(Some(Duration::from_secs(60)));
+		for i in 0..MAX_ENTRIES {
+			cache.insert(&format!("example{}.com", i), CATCH_ALL);
+		}
+		// Refreshing a domain makes it the most recent one.
+		cache.insert("example0.com", CATCH_ALL);
+		cache.insert("example.org", CATCH_ALL);
+
+		assert_eq!(cache.entries.lock().unwrap().len(), MAX_ENTRIES);
+		assert_eq!(cache.get("example1.com"), None);
+		assert_eq!(cache.get("example0.com"), Some(CATCH_ALL));
+		assert_eq!(cache.get("example2.com"), Some(CATCH_ALL));
+		assert_eq!(cache.get("example.org"), Some(CATCH_ALL));
+	}
+}
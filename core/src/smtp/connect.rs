@@ -14,47 +14,234 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use async_native_tls::TlsConnector;
+use async_native_tls::{Error as TlsError, TlsConnector};
 use async_recursion::async_recursion;
 use async_smtp::{
-	smtp::{commands::*, extension::ClientId, ServerAddress, Socks5Config},
-	ClientTlsParameters, EmailAddress, SmtpClient, SmtpTransport,
+	smtp::{
+		commands::*,
+		error::Error as AsyncSmtpError,
+		extension::{ClientId, Extension, MailParameter, ServerInfo},
+		response::Response,
+		ServerAddress, Socks5Config,
+	},
+	ClientTlsParameters,
 };
 use rand::rngs::SmallRng;
 use rand::{distributions::Alphanumeric, Rng, SeedableRng};
+use std::fmt::Display;
+use std::future::Future;
+use std::io;
 use std::iter;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use super::parser;
-use super::{SmtpDetails, SmtpError};
+use super::catch_all_cache::CachedCatchAll;
+use super::parser::{self, MailboxStatus};
+use super::proxy_protocol::start_relay;
+use super::relay::{start_bound_relay, start_unix_relay, Relay};
+use super::transcript;
+use super::transport::{response_too_large, ResponseLimits, SmtpTransport, STARTTLS_UNSUPPORTED};
+use super::zoho::{self, ZohoReply};
+use super::{SmtpDetails, SmtpError, SmtpErrorDesc};
+use crate::config::ReacherConfig;
+use crate::misc::find_domain_in_list;
+use crate::syntax::EmailAddress;
 use crate::LOG_TARGET;
 use crate::{
 	rules::{has_rule, Rule},
-	util::input_output::CheckEmailInput,
+	util::input_output::{
+		CheckEmailInput, CheckEmailInputProxy, Rcpt252Policy, SmtpSecurity, TlsVersion,
+	},
 };
 
 /// Try to send an smtp command, close and return Err if fails.
 macro_rules! try_smtp (
-    ($res: expr, $client: ident, $to_email: expr, $host: expr, $port: expr) => ({
-		if let Err(err) = $res {
-			log::debug!(target: LOG_TARGET, "[email={}] Closing [host={}:{}], because of error '{:?}'.", $to_email, $host, $port, err);
-			// Try to close the connection, but ignore if there's an error.
-			let _ = $client.close().await;
+    ($res: expr, $client: expr, $input: expr, $host: expr, $port: expr) => ({
+		match $res {
+			Ok(val) => val,
+			Err(err) => {
+				log::debug!(target: LOG_TARGET, "[email={}] Closing [host={}:{}], because of error '{:?}'.", $input.to_email, $host, $port, err);
+				// Try to close the connection, but ignore if there's an error.
+				let in_sync = is_reply(&err);
+				let _ = close_transport($client, $input, in_sync).await;
 
-			return Err(SmtpError::SmtpError(err));
+				check_error_size(&err)?;
+				return Err(classify_tls_error(err));
+			}
 		}
     })
 );
 
+/// Find the timeout of the input's `smtp_timeouts` which applies to this
+/// domain, or else to this MX host, if any.
+fn find_timeout_override(domain: &str, host: &str, input: &CheckEmailInput) -> Option<Duration> {
+	let timeouts = input.smtp_timeouts.as_ref()?;
+	let keys = timeouts.keys().cloned().collect::<Vec<_>>();
+	find_domain_in_list(domain, &keys)
+		.or_else(|| find_domain_in_list(host.trim_end_matches('.'), &keys))
+		.map(|key| timeouts[key])
+}
+
+/// The OpenSSL errors when the client and the server have no TLS version in
+/// common.
+const TLS_VERSION_ERRORS: [&str; 3] = [
+	"unsupported protocol",
+	"no protocols available",
+	"protocol version",
+];
+
+/// The input's `min_tls_version` if TLS is required, or None to accept the
+/// versions of the system's default: opportunistic TLS takes whatever the
+/// server offers.
+fn min_tls_version(security: SmtpSecurity, input: &CheckEmailInput) -> Option<TlsVersion> {
+	match security {
+		SmtpSecurity::Required | SmtpSecurity::Wrapper => Some(input.min_tls_version),
+		SmtpSecurity::Opportunistic | SmtpSecurity::None => None,
+	}
+}
+
+/// Tell TLS failures apart from other SMTP errors.
+///
+/// async-smtp wraps the TLS handshake errors into IO errors, and returns a
+/// client error if the server doesn't support STARTTLS when it's required.
+fn classify_tls_error(err: AsyncSmtpError) -> SmtpError {
+	let tls_err = match &err {
+		AsyncSmtpError::Tls(e) => e.to_string(),
+		AsyncSmtpError::Io(e) => match e.get_ref().and_then(|e| e.downcast_ref::<TlsError>()) {
+			Some(e) => e.to_string(),
+			None => return SmtpError::SmtpError(err),
+		},
+		AsyncSmtpError::Client(STARTTLS_UNSUPPORTED) => {
+			return SmtpError::TlsHandshake("the server doesn't support STARTTLS".into());
+		}
+		_ => return SmtpError::SmtpError(err),
+	};
+
+	// OpenSSL reports "certificate verify failed", followed by the reason,
+	// and "unsupported protocol" if the server only offers TLS versions below
+	// our minimum.
+	let lowercase = tls_err.to_lowercase();
+	if lowercase.contains("certificate") {
+		SmtpError::TlsCertInvalid(tls_err)
+	} else if TLS_VERSION_ERRORS.iter().any(|e| lowercase.contains(e)) {
+		SmtpError::TlsVersionTooLow(tls_err)
+	} else {
+		SmtpError::TlsHandshake(tls_err)
+	}
+}
+
+/// Timeout of the connection attempt checking if the SOCKS5 proxy is up.
+const PROXY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimum number of random addresses probed for catch-all on providers with
+/// the `StrictCatchAll` rule.
+const STRICT_CATCH_ALL_PROBES: usize = 2;
+
+/// Tell failures of the SOCKS5 proxy itself apart from failures of the SMTP
+/// server behind it, or return None if the error doesn't come from the
+/// proxy.
+///
+/// async-smtp wraps the proxy errors into IO errors. As the proxy client
+/// reports a refused connection to the proxy like a refused connection to
+/// the SMTP server, we try to connect to the proxy again to tell them apart.
+async fn classify_proxy_error(
+	err: &AsyncSmtpError,
+	proxy: &CheckEmailInputProxy,
+) -> Option<SmtpError> {
+	let AsyncSmtpError::Io(e) = err else {
+		return None;
+	};
+	let Some(AsyncSmtpError::Socks5Error(socks_err)) =
+		e.get_ref().and_then(|e| e.downcast_ref::<AsyncSmtpError>())
+	else {
+		return None;
+	};
+
+	// "Auth method unacceptable", "Authentication failed" or "Authentication
+	// rejected".
+	let message = socks_err.to_string();
+	if message.starts_with("Auth") {
+		return Some(SmtpError::ProxyAuthFailed(message));
+	}
+
+	let probe = tokio::time::timeout(
+		PROXY_PROBE_TIMEOUT,
+		tokio::net::TcpStream::connect((proxy.host.as_str(), proxy.port)),
+	)
+	.await;
+	match probe {
+		Ok(Ok(_)) => None,
+		Ok(Err(e)) => Some(SmtpError::ProxyUnreachable(e.to_string())),
+		Err(_) => Some(SmtpError::ProxyUnreachable("timeout".into())),
+	}
+}
+
+/// The `ResponseTooLarge` error, if the transport stopped reading a reply
+/// above the input's limits.
+fn check_error_size(err: &AsyncSmtpError) -> Result<(), SmtpError> {
+	match response_too_large(err) {
+		Some(reason) => Err(SmtpError::ResponseTooLarge(reason)),
+		None => Ok(()),
+	}
+}
+
+/// Start a local relay to the SMTP server, within the SMTP timeout if set.
+async fn with_smtp_timeout<F>(relay: F, smtp_timeout: Option<Duration>) -> Result<Relay, SmtpError>
+where
+	F: Future<Output = io::Result<Relay>>,
+{
+	match smtp_timeout {
+		Some(t) => tokio::time::timeout(t, relay)
+			.await
+			.unwrap_or_else(|elapsed| Err(elapsed.into())),
+		None => relay.await,
+	}
+	.map_err(|err| SmtpError::SmtpError(err.into()))
+}
+
 /// Attempt to connect to host via SMTP, and return SMTP client on success.
+/// Connect to the host, and send `MAIL FROM`. If `smtp_utf8` is set, i.e.
+/// the email has a non-ASCII local part, the host must support SMTPUTF8.
 async fn connect_to_host(
 	domain: &str,
 	host: &str,
 	port: u16,
 	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	smtp_utf8: bool,
 ) -> Result<SmtpTransport, SmtpError> {
-	let smtp_timeout = if let Some(t) = input.smtp_timeout {
+	let mut smtp_transport = open_connection(domain, host, port, input, config, smtp_utf8).await?;
+	send_mail_from(
+		&mut smtp_transport,
+		host.trim_end_matches('.'),
+		port,
+		input,
+		config,
+		smtp_utf8,
+	)
+	.await?;
+
+	Ok(smtp_transport)
+}
+
+/// Connect to the host, up to the EHLO, checking the extensions the input
+/// requires.
+async fn open_connection(
+	domain: &str,
+	host: &str,
+	port: u16,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	smtp_utf8: bool,
+) -> Result<SmtpTransport, SmtpError> {
+	let smtp_timeout = if let Some(t) = find_timeout_override(domain, host, input) {
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Using SMTP timeout {t:?} from smtp_timeouts",
+			input.to_email,
+		);
+		Some(t)
+	} else if let Some(t) = input.smtp_timeout {
 		if has_rule(domain, host, &Rule::SmtpTimeout45s) {
 			let duration = t.max(Duration::from_secs(45));
 			log::debug!(
@@ -63,6 +250,16 @@ async fn connect_to_host(
 				input.to_email,
 			);
 			Some(duration)
+		} else if input.adaptive_timeout {
+			let duration = config
+				.smtp_latencies
+				.effective_timeout(host.trim_end_matches('.'), input.smtp_timeout);
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Using adaptive SMTP timeout {duration:?} for [host={host}]",
+				input.to_email,
+			);
+			duration
 		} else {
 			input.smtp_timeout
 		}
@@ -75,28 +272,115 @@ async fn connect_to_host(
 	let host = host.trim_end_matches('.').to_string();
 
 	let security = {
-		let tls_params: ClientTlsParameters = ClientTlsParameters::new(
-			host.clone(),
-			TlsConnector::new()
-				.use_sni(true)
-				.danger_accept_invalid_certs(true)
-				.danger_accept_invalid_hostnames(true),
-		);
+		let security = match (input.smtp_security, input.require_starttls_capability) {
+			_ if input.unix_socket.is_some() => SmtpSecurity::None,
+			// Opportunistic TLS goes on over plaintext if the server doesn't
+			// offer STARTTLS, and we couldn't tell afterwards.
+			(SmtpSecurity::Opportunistic, true) => SmtpSecurity::Required,
+			(security, _) => security,
+		};
+		let mut connector = TlsConnector::new()
+			.use_sni(true)
+			.danger_accept_invalid_certs(!input.smtp_verify_certificates)
+			.danger_accept_invalid_hostnames(!input.smtp_verify_certificates);
+		if let Some(version) = min_tls_version(security, input) {
+			connector = connector.min_protocol_version(Some(version.into()));
+		}
 
-		input.smtp_security.to_client_security(tls_params)
+		security.to_client_security(ClientTlsParameters::new(host.clone(), connector))
 	};
 
-	let mut smtp_client = SmtpClient::with_security(
-		ServerAddress {
-			host: host.clone(),
-			port,
-		},
+	// With the PROXY protocol, the SMTP client connects to a local relay,
+	// which sent the header to the server before forwarding the SMTP dialog.
+	// Same with a Unix domain socket, which async-smtp can't connect to, or
+	// with a local address to bind to.
+	let relay = match (
+		&input.unix_socket,
+		input.proxy_protocol,
+		&input.proxy,
+		input.bind_addr,
+	) {
+		(Some(path), _, _, _) => {
+			let relay = start_unix_relay(path)
+				.await
+				.map_err(|err| SmtpError::SmtpError(err.into()))?;
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Relaying [socket={}] through {}",
+				input.to_email,
+				path.display(),
+				relay.addr
+			);
+
+			Some(relay)
+		}
+		(None, Some(version), None, bind_addr) => {
+			let relay = start_relay(&host, port, version, input.proxy_protocol_source, bind_addr);
+			let relay = with_smtp_timeout(relay, smtp_timeout).await?;
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Relaying [host={}:{}] through {} with PROXY protocol {:?}",
+				input.to_email,
+				host,
+				port,
+				relay.addr,
+				version
+			);
+
+			Some(relay)
+		}
+		(None, None, None, Some(bind_addr)) => {
+			let relay = start_bound_relay(&host, port, bind_addr);
+			let relay = with_smtp_timeout(relay, smtp_timeout).await?;
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Relaying [host={}:{}] through {} from {}",
+				input.to_email,
+				host,
+				port,
+				relay.addr,
+				bind_addr
+			);
+
+			Some(relay)
+		}
+		(None, proxy_protocol, Some(_), bind_addr) => {
+			if proxy_protocol.is_some() {
+				log::warn!(
+					target: LOG_TARGET,
+					"[email={}] The PROXY protocol is not supported with a SOCKS5 proxy, ignoring it",
+					input.to_email
+				);
+			}
+			if bind_addr.is_some() {
+				log::warn!(
+					target: LOG_TARGET,
+					"[email={}] bind_addr is not supported with a SOCKS5 proxy, ignoring it",
+					input.to_email
+				);
+			}
+			None
+		}
+		(None, None, None, None) => None,
+	};
+	let server_address = ServerAddress {
+		host: host.clone(),
+		port,
+	};
+
+	let mut smtp_transport = SmtpTransport::new(
+		server_address,
 		security,
+		ClientId::Domain(input.hello_name.clone()),
 	)
-	.hello_name(ClientId::Domain(input.hello_name.clone()))
-	.timeout(smtp_timeout);
+	.timeout(smtp_timeout)
+	.greeting_timeout(input.smtp_greeting_timeout)
+	.response_limits(ResponseLimits::from_input(input));
 
-	if let Some(proxy) = &input.proxy {
+	// The relays are SOCKS5 servers, so that only this client can use them.
+	if let Some(relay) = &relay {
+		smtp_transport = smtp_transport.use_socks5(relay.socks5_config());
+	} else if let Some(proxy) = &input.proxy {
 		let socks5_config = match (&proxy.username, &proxy.password) {
 			(Some(username), Some(password)) => Socks5Config::new_with_user_pass(
 				proxy.host.clone(),
@@ -107,40 +391,191 @@ async fn connect_to_host(
 			_ => Socks5Config::new(proxy.host.clone(), proxy.port),
 		};
 
-		smtp_client = smtp_client.use_socks5(socks5_config);
+		smtp_transport = smtp_transport.use_socks5(socks5_config);
+		config.record_proxy_connection();
+	}
+
+	// The transport reads the whole 220 greeting before sending EHLO.
+	let connected = smtp_transport.connect().await;
+	if let (Some(proxy), None, Err(err)) = (&input.proxy, &input.unix_socket, &connected) {
+		if let Some(proxy_err) = classify_proxy_error(err, proxy).await {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] SOCKS5 proxy {}:{} failed: {:?}",
+				input.to_email,
+				proxy.host,
+				proxy.port,
+				proxy_err
+			);
+			let _ = smtp_transport.close().await;
+			return Err(proxy_err);
+		}
+	}
+	if input.require_starttls_capability {
+		if let Err(AsyncSmtpError::Client(STARTTLS_UNSUPPORTED)) = connected {
+			let _ = smtp_transport.close().await;
+			return Err(SmtpError::NoTlsSupport);
+		}
 	}
+	// Through a proxy or a socket, failures don't tell about our egress.
+	if input.proxy.is_none() && input.unix_socket.is_none() {
+		match &connected {
+			Ok(_) => config.egress_monitor.record_success(),
+			Err(err) if is_connect_failure(err) => config.egress_monitor.record_failure(&host),
+			Err(_) => {}
+		}
+	}
+	try_smtp!(connected, &mut smtp_transport, input, host, port);
+	// The transport doesn't expose the greeting and the EHLO reply.
+	transcript::push(format!("* Connected to {}:{}", host, port));
 
-	let mut smtp_transport = smtp_client.into_transport();
+	// Without TLS, the transport doesn't look at STARTTLS, so we check it below.
+	let check_starttls = input.require_starttls_capability
+		&& input.unix_socket.is_none()
+		&& matches!(input.smtp_security, SmtpSecurity::None);
 
-	try_smtp!(
-		smtp_transport.connect().await,
-		smtp_transport,
-		input.to_email,
-		host,
-		port
-	);
+	// The transport doesn't expose the EHLO reply it parsed on connection, so
+	// we send EHLO again, which is allowed at any time, to read the extensions.
+	// After STARTTLS, the transport already sent EHLO on the upgraded
+	// connection, as servers may advertise different extensions over TLS
+	// (RFC 3207), and this EHLO also reads the post-upgrade extensions.
+	if smtp_utf8 || check_starttls {
+		let response = send_command(
+			&mut smtp_transport,
+			EhloCommand::new(ClientId::Domain(input.hello_name.clone())),
+		)
+		.await;
+		let response = try_smtp!(response, &mut smtp_transport, input, host, port);
+		let server_info = ServerInfo::from_response(&response).ok();
+		let supports = |extension| {
+			server_info
+				.as_ref()
+				.map(|info| info.supports_feature(extension))
+				== Some(true)
+		};
+		if check_starttls && !supports(Extension::StartTls) {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] [host={}:{}] doesn't offer STARTTLS",
+				input.to_email,
+				host,
+				port
+			);
+			let _ = close_transport(&mut smtp_transport, input, true).await;
+			return Err(SmtpError::NoTlsSupport);
+		}
+		if smtp_utf8 && !supports(Extension::SmtpUtfEight) {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] [host={}:{}] doesn't support SMTPUTF8",
+				input.to_email,
+				host,
+				port
+			);
+			let _ = close_transport(&mut smtp_transport, input, true).await;
+			return Err(SmtpError::SmtpUtf8Unsupported);
+		}
+	}
 
-	// "MAIL FROM: user@example.org"
-	let from_email = EmailAddress::from_str(input.from_email.as_ref()).unwrap_or_else(|_| {
-		log::warn!(
-			"Inputted from_email \"{}\" is not a valid email, using \"user@example.org\" instead",
-			input.from_email
-		);
-		EmailAddress::from_str("user@example.org").expect("This is a valid email. qed.")
-	});
-	try_smtp!(
-		smtp_transport
-			.command(MailCommand::new(Some(from_email), vec![],))
-			.await,
-		smtp_transport,
-		input.to_email,
-		host,
-		port
-	);
+	if input.polite_dialog {
+		let response = send_command(&mut smtp_transport, NoopCommand).await;
+		try_smtp!(response, &mut smtp_transport, input, host, port);
+	}
 
 	Ok(smtp_transport)
 }
 
+/// Send `MAIL FROM` on the connection, with the SMTPUTF8 parameter if
+/// `smtp_utf8` is set. The connection is closed if it fails.
+async fn send_mail_from(
+	smtp_transport: &mut SmtpTransport,
+	host: &str,
+	port: u16,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+	smtp_utf8: bool,
+) -> Result<(), SmtpError> {
+	// "MAIL FROM: user@example.org", or "MAIL FROM:<>" for the null sender.
+	let from_email = if input.use_null_sender {
+		None
+	} else {
+		Some(
+			async_smtp::EmailAddress::from_str(input.from_email.as_ref()).unwrap_or_else(|_| {
+				log::warn!(
+					"Inputted from_email \"{}\" is not a valid email, using \"user@example.org\" instead",
+					input.from_email
+				);
+				async_smtp::EmailAddress::from_str("user@example.org")
+					.expect("This is a valid email. qed.")
+			}),
+		)
+	};
+	let parameters = if smtp_utf8 {
+		vec![MailParameter::SmtpUtfEight]
+	} else {
+		vec![]
+	};
+	let start = Instant::now();
+	let response = send_command(smtp_transport, MailCommand::new(from_email, parameters)).await;
+	// The MAIL FROM command is a single round-trip, so it's a good measure of
+	// the host's latency. Errors and timeouts are recorded too, so that the
+	// timeout grows back if the host gets slower.
+	if input.adaptive_timeout {
+		config.smtp_latencies.record(host, start.elapsed());
+	}
+	try_smtp!(response, smtp_transport, input, host, port);
+
+	Ok(())
+}
+
+/// Whether the error is a negative reply of the server, after which the
+/// dialog is still in sync, unlike after a timeout or an I/O error.
+fn is_reply(err: &AsyncSmtpError) -> bool {
+	matches!(
+		err,
+		AsyncSmtpError::Transient(_) | AsyncSmtpError::Permanent(_)
+	)
+}
+
+/// Send QUIT and close the connection, after a RSET if the input's
+/// `polite_dialog` is set and the dialog is `in_sync`: otherwise the reply to
+/// the RSET may never come, or be mistaken for a late reply.
+async fn close_transport(
+	smtp_transport: &mut SmtpTransport,
+	input: &CheckEmailInput,
+	in_sync: bool,
+) -> Result<(), AsyncSmtpError> {
+	if input.polite_dialog && in_sync {
+		let _ = send_command(smtp_transport, RsetCommand).await;
+	}
+	smtp_transport.close().await
+}
+
+/// Whether the error means that we couldn't open the connection at all, i.e.
+/// it was refused or timed out, as when outbound SMTP is blocked.
+fn is_connect_failure(err: &AsyncSmtpError) -> bool {
+	match err {
+		AsyncSmtpError::Timeout(_) => true,
+		AsyncSmtpError::Io(err) => matches!(
+			err.kind(),
+			io::ErrorKind::ConnectionRefused | io::ErrorKind::TimedOut
+		),
+		_ => false,
+	}
+}
+
+/// Send a command on the SMTP connection, recording it and the server's
+/// reply in the transcript.
+async fn send_command<C: Display>(
+	smtp_transport: &mut SmtpTransport,
+	command: C,
+) -> Result<Response, AsyncSmtpError> {
+	transcript::push_command(&command);
+	let reply = smtp_transport.command(command).await;
+	transcript::push_reply(&reply);
+	reply
+}
+
 /// Description of the deliverability information we can gather from
 /// communicating with the SMTP server.
 struct Deliverability {
@@ -150,21 +585,46 @@ struct Deliverability {
 	is_deliverable: bool,
 	/// Is the email blocked or disabled by the provider?
 	is_disabled: bool,
+	/// The enhanced status code of the reply to `RCPT TO`, if any.
+	enhanced_status: Option<String>,
+	/// The fingerprint of the reply to `RCPT TO`, if negative.
+	fingerprint: Option<String>,
+}
+
+impl Deliverability {
+	/// Why the server rejected the email, if it did: a disabled account, as
+	/// opposed to a mailbox which never existed.
+	fn description(&self) -> Option<SmtpErrorDesc> {
+		if self.is_disabled {
+			Some(SmtpErrorDesc::AccountDisabled)
+		} else if !self.is_deliverable && !self.has_full_inbox {
+			Some(SmtpErrorDesc::NonExistent)
+		} else {
+			None
+		}
+	}
 }
 
 /// Check if `to_email` exists on host SMTP server. This is the core logic of
 /// this tool.
 async fn email_deliverable(
 	smtp_transport: &mut SmtpTransport,
+	host: &str,
 	to_email: &EmailAddress,
+	input: &CheckEmailInput,
 ) -> Result<Deliverability, SmtpError> {
-	// "RCPT TO: <target email>"
-	// FIXME Do not clone `to_email`?
-	match smtp_transport
-		.command(RcptCommand::new(to_email.clone(), vec![]))
-		.await
-	{
-		Ok(_) => {
+	// "RCPT TO: <target email>". async-smtp's `RcptCommand` only takes ASCII
+	// addresses, while an internationalized one is sent as is, in UTF-8.
+	match send_command(smtp_transport, format!("RCPT TO:<{}>\r\n", to_email)).await {
+		Ok(response) => {
+			let enhanced_status = parser::enhanced_status(&response);
+
+			// 252 means the server accepts the message without saying if the
+			// mailbox exists.
+			if response.has_code(252) && input.rcpt_252_policy == Rcpt252Policy::Unknown {
+				return Err(SmtpError::CannotVerify(response.message.join("\n")));
+			}
+
 			// According to RFC 5321, `RCPT TO` command succeeds with 250 and
 			// 251 codes only (no 3xx codes at all):
 			// https://tools.ietf.org/html/rfc5321#page-56
@@ -180,19 +640,56 @@ async fn email_deliverable(
 				has_full_inbox: false,
 				is_deliverable: true, // response.is_positive()
 				is_disabled: false,
+				enhanced_status,
+				fingerprint: None,
 			})
 		}
 		Err(err) => {
+			check_error_size(&err)?;
+			let (enhanced_status, fingerprint) = match &err {
+				AsyncSmtpError::Transient(response) | AsyncSmtpError::Permanent(response) => (
+					parser::enhanced_status(response),
+					Some(parser::fingerprint(response)),
+				),
+				_ => (None, None),
+			};
+
 			// We cast to lowercase, because our matched strings below are all
 			// lowercase.
 			let err_string = err.to_string().to_lowercase();
 
+			// The server doesn't handle the domain, so it can't tell about
+			// the mailbox: another MX host might.
+			if parser::is_relay_denied(err_string.as_str()) {
+				return Err(SmtpError::RelayDenied(err.to_string()));
+			}
+
+			// Zoho has its own way of rejecting unknown recipients, and of
+			// throttling us.
+			if zoho::is_zoho(host) {
+				match zoho::interpret_rcpt_error(err_string.as_str()) {
+					Some(ZohoReply::NotFound) => {
+						return Ok(Deliverability {
+							has_full_inbox: false,
+							is_deliverable: false,
+							is_disabled: false,
+							enhanced_status,
+							fingerprint,
+						});
+					}
+					Some(ZohoReply::Unknown) => return Err(SmtpError::SmtpError(err)),
+					None => {}
+				}
+			}
+
 			// Check if the email account has been disabled or blocked.
 			if parser::is_disabled_account(err_string.as_str()) {
 				return Ok(Deliverability {
 					has_full_inbox: false,
 					is_deliverable: false,
 					is_disabled: true,
+					enhanced_status,
+					fingerprint,
 				});
 			}
 
@@ -202,6 +699,8 @@ async fn email_deliverable(
 					has_full_inbox: true,
 					is_deliverable: false,
 					is_disabled: false,
+					enhanced_status,
+					fingerprint,
 				});
 			}
 
@@ -215,6 +714,23 @@ async fn email_deliverable(
 					has_full_inbox: false,
 					is_deliverable: true,
 					is_disabled: false,
+					enhanced_status,
+					fingerprint,
+				});
+			}
+
+			// The enhanced status code is more precise than the phrases of
+			// the reply, e.g. 5.1.1 means that the mailbox doesn't exist.
+			if let Some(status) = enhanced_status
+				.as_deref()
+				.and_then(parser::classify_enhanced_status)
+			{
+				return Ok(Deliverability {
+					has_full_inbox: status == MailboxStatus::Full,
+					is_deliverable: false,
+					is_disabled: status == MailboxStatus::Disabled,
+					enhanced_status,
+					fingerprint,
 				});
 			}
 
@@ -224,6 +740,8 @@ async fn email_deliverable(
 					has_full_inbox: false,
 					is_deliverable: false,
 					is_disabled: false,
+					enhanced_status,
+					fingerprint,
 				});
 			}
 
@@ -233,123 +751,614 @@ async fn email_deliverable(
 	}
 }
 
-/// Verify the existence of a catch-all on the domain.
-async fn smtp_is_catch_all(
-	smtp_transport: &mut SmtpTransport,
-	domain: &str,
-	host: &str,
-	input: &CheckEmailInput,
-) -> Result<bool, SmtpError> {
-	// Skip catch-all check for known providers.
-	if has_rule(domain, host, &Rule::SkipCatchAll) {
-		log::debug!(
-			target: LOG_TARGET,
-			"[email={}] Skipping catch-all check for [domain={domain}]",
-			input.to_email
-		);
-		return Ok(false);
-	}
-
-	// Create a random 15-char alphanumerical string.
-	let mut rng = SmallRng::from_entropy();
-	let random_email: String = iter::repeat(())
-		.map(|()| rng.sample(Alphanumeric))
-		.map(char::from)
-		.take(15)
-		.collect();
-	let random_email = EmailAddress::new(format!("{random_email}@{domain}"));
-
-	email_deliverable(
-		smtp_transport,
-		&random_email.expect("Email is correctly constructed. qed."),
-	)
-	.await
-	.map(|deliverability| deliverability.is_deliverable)
+/// An SMTP connection on which we send several `RCPT TO` commands, e.g. for
+/// the catch-all check and the email itself. After the input's
+/// `max_rcpt_per_connection` commands, it's closed and a fresh connection is
+/// opened, so that we don't look like a harvester.
+struct SmtpSession<'a> {
+	transport: SmtpTransport,
+	/// Number of `RCPT TO` commands sent on the current connection.
+	rcpt_count: usize,
+	/// When we got the reply to the last `RCPT TO` on the current
+	/// connection, see the input's `rcpt_pacing`.
+	last_rcpt: Option<Instant>,
+	domain: &'a str,
+	host: &'a str,
+	port: u16,
+	input: &'a CheckEmailInput,
+	config: &'a ReacherConfig,
+	smtp_utf8: bool,
+	/// The key of the connection in the config's `smtp_pool`, or None if
+	/// it's not pooled.
+	pool_key: Option<String>,
 }
 
-async fn create_smtp_future(
-	to_email: &EmailAddress,
+/// The key of the connections to this host in the config's `smtp_pool`,
+/// starting with "host:port", then all the settings they depend on, or None
+/// if they can't be pooled.
+fn pool_key(
 	host: &str,
 	port: u16,
-	domain: &str,
 	input: &CheckEmailInput,
-) -> Result<(bool, Deliverability), SmtpError> {
-	// FIXME If the SMTP is not connectable, we should actually return an
-	// Ok(SmtpDetails { can_connect_smtp: false, ... }).
-	let mut smtp_transport = connect_to_host(domain, host, port, input).await?;
+	config: &ReacherConfig,
+	smtp_utf8: bool,
+) -> Option<String> {
+	if !config.smtp_pool.is_enabled()
+		|| input.unix_socket.is_some()
+		|| input.proxy_protocol.is_some()
+	{
+		return None;
+	}
 
-	let is_catch_all = smtp_is_catch_all(&mut smtp_transport, domain, host, input)
-		.await
-		.unwrap_or(false);
-	let deliverability = if is_catch_all {
-		Deliverability {
-			has_full_inbox: false,
-			is_deliverable: true,
-			is_disabled: false,
+	Some(format!(
+		"{}:{} {} {:?} {:?} {:?} {} {} {:?} {}",
+		host.trim_end_matches('.').to_lowercase(),
+		port,
+		input.hello_name,
+		input
+			.proxy
+			.as_ref()
+			.map(|proxy| (&proxy.host, proxy.port, &proxy.username)),
+		input.smtp_security,
+		input.min_tls_version,
+		input.smtp_verify_certificates,
+		input.require_starttls_capability,
+		input.bind_addr,
+		smtp_utf8
+	))
+}
+
+impl<'a> SmtpSession<'a> {
+	/// Open a session on an idle connection of the config's `smtp_pool` if
+	/// there's one, or else on a fresh connection.
+	async fn connect(
+		domain: &'a str,
+		host: &'a str,
+		port: u16,
+		input: &'a CheckEmailInput,
+		config: &'a ReacherConfig,
+		smtp_utf8: bool,
+	) -> Result<SmtpSession<'a>, SmtpError> {
+		let pool_key = pool_key(host, port, input, config, smtp_utf8);
+		let pooled = match &pool_key {
+			Some(key) => config.smtp_pool.take_idle(key).await,
+			None => None,
+		};
+		if let Some((mut transport, rcpt_count, last_rcpt)) = pooled {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Reusing a pooled connection to [host={}:{}]",
+				input.to_email,
+				host,
+				port
+			);
+			transcript::push(format!(
+				"* Reusing a pooled connection to {}:{}",
+				host, port
+			));
+			let host_name = host.trim_end_matches('.');
+			// Otherwise the connection is closed, and we open a fresh one.
+			if send_mail_from(&mut transport, host_name, port, input, config, smtp_utf8)
+				.await
+				.is_ok()
+			{
+				return Ok(SmtpSession {
+					transport,
+					rcpt_count,
+					last_rcpt,
+					domain,
+					host,
+					port,
+					input,
+					config,
+					smtp_utf8,
+					pool_key,
+				});
+			}
 		}
-	} else {
-		let mut result = email_deliverable(&mut smtp_transport, to_email).await;
 
-		// Some SMTP servers automatically close the connection after an error,
-		// so we should reconnect to perform a next command.
-		//
-		// Unfortunately `smtp_transport.is_connected()` doesn't report about this,
-		// so we can only check for "io: incomplete" SMTP error being returned.
-		// https://github.com/async-email/async-smtp/issues/37
-		if let Err(e) = &result {
-			if parser::is_err_io_errors(e) {
-				log::debug!(
-					target: LOG_TARGET,
-					"[email={}] Got `io: incomplete` error, reconnecting.",
-					input.to_email
-				);
+		let transport = connect_to_host(domain, host, port, input, config, smtp_utf8).await?;
+
+		Ok(SmtpSession {
+			transport,
+			rcpt_count: 0,
+			last_rcpt: None,
+			domain,
+			host,
+			port,
+			input,
+			config,
+			smtp_utf8,
+			pool_key,
+		})
+	}
+
+	/// Close the current connection, and open a fresh one.
+	async fn reconnect(&mut self) -> Result<(), SmtpError> {
+		let _ = self.quit().await;
+		self.transport = connect_to_host(
+			self.domain,
+			self.host,
+			self.port,
+			self.input,
+			self.config,
+			self.smtp_utf8,
+		)
+		.await?;
+		self.rcpt_count = 0;
+		self.last_rcpt = None;
+
+		Ok(())
+	}
+
+	/// Check if `to_email` is deliverable, on a fresh connection if the
+	/// current one reached `max_rcpt_per_connection`, and at least
+	/// `rcpt_pacing` after the previous `RCPT TO` on the connection.
+	async fn email_deliverable(
+		&mut self,
+		to_email: &EmailAddress,
+	) -> Result<Deliverability, SmtpError> {
+		if self.rcpt_count >= self.input.max_rcpt_per_connection.max(1) {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Reached {} RCPT TO on [host={}:{}], reconnecting",
+				self.input.to_email,
+				self.rcpt_count,
+				self.host,
+				self.port
+			);
+			self.reconnect().await?;
+		}
+		if let (Some(pacing), Some(last_rcpt)) = (self.input.rcpt_pacing, self.last_rcpt) {
+			let wait = pacing.saturating_sub(last_rcpt.elapsed());
+			if !wait.is_zero() {
+				tokio::time::sleep(wait).await;
+			}
+		}
+		self.rcpt_count += 1;
 
-				let _ = smtp_transport.close().await;
-				smtp_transport = connect_to_host(domain, host, port, input).await?;
-				result = email_deliverable(&mut smtp_transport, to_email).await;
+		let result = email_deliverable(&mut self.transport, self.host, to_email, self.input).await;
+		self.last_rcpt = Some(Instant::now());
+		// After a timeout, a late reply may still be pending on the
+		// connection, so it mustn't be reused.
+		if let Err(SmtpError::SmtpError(err)) = &result {
+			if !is_reply(err) {
+				self.pool_key = None;
 			}
 		}
+		result
+	}
 
-		result?
-	};
+	/// Close the connection after this error, without returning it to the
+	/// config's `smtp_pool`: a late reply may still be pending on it.
+	async fn abort(mut self, err: &SmtpError) {
+		let in_sync = match err {
+			SmtpError::SmtpError(err) => is_reply(err),
+			_ => true,
+		};
+		let _ = close_transport(&mut self.transport, self.input, in_sync).await;
+	}
+
+	/// Close the connection, or return it to the config's `smtp_pool`.
+	async fn close(mut self) -> Result<(), SmtpError> {
+		if let Some(key) = &self.pool_key {
+			self.config
+				.smtp_pool
+				.put_idle(key, self.transport, self.rcpt_count, self.last_rcpt)
+				.await;
+			return Ok(());
+		}
 
-	smtp_transport.close().await.map_err(SmtpError::SmtpError)?;
+		self.quit().await.map_err(SmtpError::SmtpError)
+	}
 
-	Ok((is_catch_all, deliverability))
+	/// Send QUIT, after a RSET if the input's `polite_dialog` is set.
+	async fn quit(&mut self) -> Result<(), AsyncSmtpError> {
+		close_transport(&mut self.transport, self.input, true).await
+	}
 }
 
-/// Get all email details we can from one single `EmailAddress`, without
-/// retries.
-async fn check_smtp_without_retry(
+/// Result of the catch-all check.
+struct CatchAll {
+	/// Did at least half of the random addresses get accepted? All of them
+	/// for providers with the `StrictCatchAll` rule.
+	is_catch_all: bool,
+	/// The share of random addresses accepted, only set if more than one was
+	/// probed.
+	confidence: Option<f32>,
+	/// Whether the server rejected `postmaster@domain`, see
+	/// `probe_postmaster`. Only set if the input's `probe_postmaster` is set.
+	honest_responder: Option<bool>,
+	/// Whether the server also accepted an address at a domain which can't
+	/// exist, see `probe_invalid_domain`.
+	accepts_at_rcpt_only: bool,
+}
+
+/// Verify the existence of a catch-all on the domain, by probing the input's
+/// `catch_all_probes` random addresses on the same connection. Providers
+/// with the `StrictCatchAll` rule get at least `STRICT_CATCH_ALL_PROBES`.
+async fn smtp_is_catch_all(session: &mut SmtpSession<'_>) -> Result<CatchAll, SmtpError> {
+	let (domain, host, input) = (session.domain, session.host, session.input);
+	// Skip catch-all check for known providers.
+	if has_rule(domain, host, &Rule::SkipCatchAll) {
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Skipping catch-all check for [domain={domain}]",
+			input.to_email
+		);
+		return Ok(CatchAll {
+			is_catch_all: false,
+			confidence: None,
+			honest_responder: None,
+			accepts_at_rcpt_only: false,
+		});
+	}
+
+	if let Some(cached) = session.config.catch_all_cache.get(domain) {
+		log::debug!(
+			target: LOG_TARGET,
+			"[email={}] Using cached catch-all check for [domain={domain}]",
+			input.to_email
+		);
+		return Ok(CatchAll {
+			is_catch_all: cached.is_catch_all,
+			confidence: cached.confidence,
+			honest_responder: None,
+			accepts_at_rcpt_only: cached.accepts_at_rcpt_only,
+		});
+	}
+
+	// Some providers don't support a true catch-all: ask for more evidence
+	// before flagging one.
+	let strict = has_rule(domain, host, &Rule::StrictCatchAll);
+	let probes = if strict {
+		input.catch_all_probes.max(STRICT_CATCH_ALL_PROBES)
+	} else {
+		input.catch_all_probes.max(1)
+	};
+	let mut rng = SmallRng::from_entropy();
+	let mut accepted = 0;
+	for _ in 0..probes {
+		// Create a random 15-char alphanumerical string.
+		let random_email: String = iter::repeat(())
+			.map(|()| rng.sample(Alphanumeric))
+			.map(char::from)
+			.take(15)
+			.collect();
+		let random_email = EmailAddress::new(format!("{random_email}@{domain}"));
+
+		let deliverability = session
+			.email_deliverable(&random_email.expect("Email is correctly constructed. qed."))
+			.await?;
+		if deliverability.is_deliverable {
+			accepted += 1;
+		}
+	}
+
+	let cached = CachedCatchAll {
+		is_catch_all: if strict {
+			accepted == probes
+		} else {
+			accepted * 2 >= probes
+		},
+		confidence: (probes > 1).then(|| accepted as f32 / probes as f32),
+		accepts_at_rcpt_only: false,
+	};
+	// A catch-all accepts any address at its domain, but should still refuse
+	// to relay to other domains.
+	let cached = if cached.is_catch_all && input.probe_postmaster {
+		CachedCatchAll {
+			accepts_at_rcpt_only: probe_invalid_domain(session).await == Some(true),
+			..cached
+		}
+	} else {
+		cached
+	};
+	session.config.catch_all_cache.insert(domain, cached);
+
+	Ok(CatchAll {
+		is_catch_all: cached.is_catch_all,
+		confidence: cached.confidence,
+		honest_responder: None,
+		accepts_at_rcpt_only: cached.accepts_at_rcpt_only,
+	})
+}
+
+/// Probe a random address at a random `.invalid` domain, which can't exist
+/// (RFC 2606), on the same connection. A server accepting it doesn't check
+/// the recipients at `RCPT TO` at all, not even their domain, so it can only
+/// reject the message after `DATA`, which we never send: Some(true). Some(false)
+/// if it rejects it, and None if we can't tell. Nothing is ever relayed, as
+/// we don't send `DATA`.
+async fn probe_invalid_domain(session: &mut SmtpSession<'_>) -> Option<bool> {
+	let random: String = SmallRng::from_entropy()
+		.sample_iter(&Alphanumeric)
+		.map(char::from)
+		.take(15)
+		.collect::<String>()
+		.to_lowercase();
+	let probe = EmailAddress::new(format!("{random}@{random}.invalid")).ok()?;
+	let accepted = match session.email_deliverable(&probe).await {
+		Ok(deliverability) => Some(deliverability.is_deliverable),
+		Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(_)) | SmtpError::RelayDenied(_)) => {
+			Some(false)
+		}
+		Err(_) => None,
+	};
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] .invalid domain probe, [accepted={:?}]",
+		session.input.to_email,
+		accepted
+	);
+
+	accepted
+}
+
+/// Probe `postmaster@domain`, which RFC 5321 requires every domain to
+/// accept, on the same connection. A server rejecting it validates the
+/// recipients against its mailboxes, instead of accepting them blindly, so
+/// it's an honest responder: Some(true). Some(false) if it accepts it, and
+/// None if we can't tell.
+async fn probe_postmaster(session: &mut SmtpSession<'_>) -> Option<bool> {
+	let (domain, input) = (session.domain, session.input);
+	let postmaster = EmailAddress::new(format!("postmaster@{domain}")).ok()?;
+	let honest_responder = match session.email_deliverable(&postmaster).await {
+		Ok(deliverability) => Some(!deliverability.is_deliverable),
+		Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(_))) => Some(true),
+		Err(_) => None,
+	};
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] postmaster@{} probe, [honest_responder={:?}]",
+		input.to_email,
+		domain,
+		honest_responder
+	);
+
+	honest_responder
+}
+
+/// Probe `user+tag@domain`, with a random tag, on the same connection, to
+/// tell if the server honors sub-addressing for this existing mailbox:
+/// Some(true) if it accepts it, Some(false) if it rejects it, and None if we
+/// can't tell.
+async fn probe_subaddressing(
+	session: &mut SmtpSession<'_>,
+	to_email: &EmailAddress,
+) -> Option<bool> {
+	let address: &str = to_email.as_ref();
+	let (username, domain) = address.rsplit_once('@')?;
+	let tag: String = SmallRng::from_entropy()
+		.sample_iter(&Alphanumeric)
+		.map(char::from)
+		.take(8)
+		.collect::<String>()
+		.to_lowercase();
+	let probe = EmailAddress::new(format!("{username}+{tag}@{domain}")).ok()?;
+	let supports_subaddressing = match session.email_deliverable(&probe).await {
+		Ok(deliverability) => Some(deliverability.is_deliverable),
+		Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(_))) => Some(false),
+		Err(_) => None,
+	};
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] Sub-addressing probe, [supports_subaddressing={:?}]",
+		session.input.to_email,
+		supports_subaddressing
+	);
+
+	supports_subaddressing
+}
+
+/// Remove the +tag of the email's username, e.g. "user+tag@domain" becomes
+/// "user@domain".
+fn strip_plus_tag(email: &EmailAddress) -> EmailAddress {
+	let address: &str = email.as_ref();
+	match address.rsplit_once('@') {
+		Some((username, domain)) => match username.split_once('+') {
+			Some((base, _)) if !base.is_empty() => {
+				EmailAddress::new(format!("{base}@{domain}")).unwrap_or_else(|_| email.clone())
+			}
+			_ => email.clone(),
+		},
+		None => email.clone(),
+	}
+}
+
+/// The email to send the `RCPT TO` for. On plus-addressing providers,
+/// user+tag is accepted as soon as user exists, so we verify the base address
+/// instead, unless the input's `strip_plus_tag_before_smtp` is unset.
+pub(super) fn rcpt_email(
+	to_email: &EmailAddress,
+	host: &str,
+	domain: &str,
+	input: &CheckEmailInput,
+) -> EmailAddress {
+	if input.strip_plus_tag_before_smtp && has_rule(domain, host, &Rule::PlusAddressing) {
+		strip_plus_tag(to_email)
+	} else {
+		to_email.clone()
+	}
+}
+
+async fn create_smtp_future(
 	to_email: &EmailAddress,
 	host: &str,
 	port: u16,
 	domain: &str,
 	input: &CheckEmailInput,
-) -> Result<SmtpDetails, SmtpError> {
-	let fut = create_smtp_future(to_email, host, port, domain, input);
-	let (is_catch_all, deliverability) = fut.await?;
-
-	Ok(SmtpDetails {
-		can_connect_smtp: true,
-		has_full_inbox: deliverability.has_full_inbox,
-		is_catch_all,
-		is_deliverable: deliverability.is_deliverable,
-		is_disabled: deliverability.is_disabled,
-	})
+	config: &ReacherConfig,
+) -> Result<(CatchAll, Deliverability, Option<bool>), SmtpError> {
+	// The random address of the catch-all check never has a +tag.
+	let to_email = &rcpt_email(to_email, host, domain, input);
+
+	// The `RCPT TO` of an internationalized address is sent in UTF-8.
+	let smtp_utf8 = !AsRef::<str>::as_ref(to_email).is_ascii();
+
+	// FIXME If the SMTP is not connectable, we should actually return an
+	// Ok(SmtpDetails { can_connect_smtp: false, ... }).
+	let mut session = SmtpSession::connect(domain, host, port, input, config, smtp_utf8).await?;
+
+	let catch_all = match smtp_is_catch_all(&mut session).await {
+		Ok(catch_all) => catch_all,
+		// In strict mode, a domain which we couldn't confirm is not a
+		// catch-all is considered one.
+		Err(err) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Catch-all check failed with '{:?}'",
+				input.to_email,
+				err
+			);
+			CatchAll {
+				is_catch_all: input.strict_safe,
+				confidence: None,
+				honest_responder: None,
+				accepts_at_rcpt_only: false,
+			}
+		}
+	};
+	let catch_all = if input.probe_postmaster {
+		CatchAll {
+			honest_responder: probe_postmaster(&mut session).await,
+			..catch_all
+		}
+	} else {
+		catch_all
+	};
+	let deliverability = if catch_all.is_catch_all {
+		Deliverability {
+			has_full_inbox: false,
+			is_deliverable: true,
+			is_disabled: false,
+			enhanced_status: None,
+			fingerprint: None,
+		}
+	} else {
+		let mut result = session.email_deliverable(to_email).await;
+
+		// Some SMTP servers automatically close the connection after an error,
+		// so we should reconnect to perform a next command.
+		//
+		// Unfortunately `smtp_transport.is_connected()` doesn't report about this,
+		// so we can only check for "io: incomplete" SMTP error being returned.
+		// https://github.com/async-email/async-smtp/issues/37
+		if let Err(e) = &result {
+			if parser::is_err_io_errors(e) {
+				log::debug!(
+					target: LOG_TARGET,
+					"[email={}] Got `io: incomplete` error, reconnecting.",
+					input.to_email
+				);
+
+				session.reconnect().await?;
+				result = session.email_deliverable(to_email).await;
+			}
+		}
+
+		// An honest responder's permanent rejection of the mailbox means it
+		// doesn't exist, even if we can't parse the reply. Other rejections,
+		// e.g. 5.7.x of our IP, don't tell about the mailbox.
+		match result {
+			Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(response)))
+				if catch_all.honest_responder == Some(true)
+					&& parser::enhanced_status(&response)
+						.is_some_and(|status| status.starts_with("5.1.")) =>
+			{
+				log::debug!(
+					target: LOG_TARGET,
+					"[email={}] Honest responder rejected the email: {:?}",
+					input.to_email,
+					response
+				);
+				Deliverability {
+					has_full_inbox: false,
+					is_deliverable: false,
+					is_disabled: false,
+					enhanced_status: parser::enhanced_status(&response),
+					fingerprint: Some(parser::fingerprint(&response)),
+				}
+			}
+			Ok(deliverability) => deliverability,
+			Err(err) => {
+				session.abort(&err).await;
+				return Err(err);
+			}
+		}
+	};
+
+	// On a catch-all, any address is accepted, so it tells nothing.
+	let supports_subaddressing =
+		if input.probe_subaddressing && deliverability.is_deliverable && !catch_all.is_catch_all {
+			probe_subaddressing(&mut session, &strip_plus_tag(to_email)).await
+		} else {
+			None
+		};
+
+	session.close().await?;
+
+	Ok((catch_all, deliverability, supports_subaddressing))
+}
+
+/// The results of the optional probes sent along the SMTP verification.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SmtpProbes {
+	/// See `SmtpDebug::honest_responder`.
+	pub honest_responder: Option<bool>,
+	/// See `MiscDetails::supports_subaddressing`.
+	pub supports_subaddressing: Option<bool>,
+}
+
+/// Get all email details we can from one single `EmailAddress`, without
+/// retries, with the results of the optional probes.
+async fn check_smtp_without_retry(
+	to_email: &EmailAddress,
+	host: &str,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Result<(SmtpDetails, SmtpProbes), SmtpError> {
+	let fut = create_smtp_future(to_email, host, port, domain, input, config);
+	let (catch_all, deliverability, supports_subaddressing) = fut.await?;
+
+	Ok((
+		SmtpDetails {
+			can_connect_smtp: true,
+			has_full_inbox: deliverability.has_full_inbox,
+			is_catch_all: catch_all.is_catch_all,
+			catch_all_confidence: catch_all.confidence,
+			is_deliverable: deliverability.is_deliverable,
+			is_disabled: deliverability.is_disabled,
+			description: deliverability.description(),
+			enhanced_status: deliverability.enhanced_status,
+			fingerprint: deliverability.fingerprint,
+			accepts_at_rcpt_only: catch_all.accepts_at_rcpt_only,
+		},
+		SmtpProbes {
+			honest_responder: catch_all.honest_responder,
+			supports_subaddressing,
+		},
+	))
 }
 
-/// Get all email details we can from one single `EmailAddress`.
-/// Retry the SMTP connection on error, in particular to avoid greylisting.
+/// Get all email details we can from one single `EmailAddress`, with the
+/// results of the optional probes.
+/// Retry the SMTP connection on error, in particular to avoid greylisting,
+/// counting the retries in `retries_used`.
 #[async_recursion]
+#[allow(clippy::too_many_arguments)]
 pub async fn check_smtp_with_retry(
 	to_email: &EmailAddress,
 	host: &str,
 	port: u16,
 	domain: &str,
 	input: &CheckEmailInput,
+	config: &ReacherConfig,
 	count: usize,
-) -> Result<SmtpDetails, SmtpError> {
+	retries_used: &mut usize,
+) -> Result<(SmtpDetails, SmtpProbes), SmtpError> {
 	log::debug!(
 		target: LOG_TARGET,
 		"[email={}] Check SMTP [attempt={}] on [host={}:{}]",
@@ -359,7 +1368,7 @@ pub async fn check_smtp_with_retry(
 		port
 	);
 
-	let result = check_smtp_without_retry(to_email, host, port, domain, input).await;
+	let result = check_smtp_without_retry(to_email, host, port, domain, input, config).await;
 
 	log::debug!(
 		target: LOG_TARGET,
@@ -378,17 +1387,43 @@ pub async fn check_smtp_with_retry(
 		Err(SmtpError::HeadlessError(_)) => result,
 		Err(SmtpError::YahooError(_)) => result,
 		Err(SmtpError::GmailError(_)) => result,
-		// Only retry if the SMTP error was unknown.
-		Err(err) if err.get_description().is_none() => {
+		// Don't retry on a server that sends oversized responses.
+		Err(SmtpError::ResponseTooLarge(_)) => result,
+		// A 252 reply won't change on the next attempt.
+		Err(SmtpError::CannotVerify(_)) => result,
+		// Neither will the server's extensions.
+		Err(SmtpError::SmtpUtf8Unsupported) => result,
+		// Only retry if the SMTP error was unknown, or if the server reset the
+		// connection, in which case we reconnect.
+		Err(err) if err.get_description().is_none() || parser::is_err_connection_reset(err) => {
 			if count <= 1 {
 				result
 			} else {
-				log::debug!(
-					target: LOG_TARGET,
-					"[email={}] Potential greylisting detected, retrying.",
-					input.to_email,
-				);
-				check_smtp_with_retry(to_email, host, port, domain, input, count - 1).await
+				if parser::is_err_connection_reset(err) {
+					log::debug!(
+						target: LOG_TARGET,
+						"[email={}] Connection reset during SMTP dialog, reconnecting.",
+						input.to_email,
+					);
+				} else {
+					log::debug!(
+						target: LOG_TARGET,
+						"[email={}] Potential greylisting detected, retrying.",
+						input.to_email,
+					);
+				}
+				*retries_used += 1;
+				check_smtp_with_retry(
+					to_email,
+					host,
+					port,
+					domain,
+					input,
+					config,
+					count - 1,
+					retries_used,
+				)
+				.await
 			}
 		}
 		_ => result,
@@ -398,22 +1433,2008 @@ pub async fn check_smtp_with_retry(
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::config::ReacherConfig;
+	use crate::smtp::{
+		CatchAllCache, ProxyProtocol, SmtpErrorDesc, SmtpPool, DEFAULT_SMTP_POOL_IDLE_TIMEOUT,
+		DEFAULT_SMTP_POOL_KEEP_ALIVE,
+	};
+	use crate::util::input_output::{SmtpSecurity, TlsVersion};
+	use crate::util::mock_smtp::{accept_all, MockSmtpServer};
+	use crate::CheckEmailInputBuilder;
+	use async_smtp::ClientSecurity;
+	use std::collections::HashMap;
+	use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 
 	#[tokio::test]
 	async fn should_skip_catch_all() {
-		let smtp_client = SmtpClient::new("gmail.com".into());
-		let mut smtp_transport = smtp_client.into_transport();
+		let transport = SmtpTransport::new(
+			ServerAddress::new("gmail.com".into(), 25),
+			ClientSecurity::None,
+			ClientId::Domain("localhost".into()),
+		);
+		let input = CheckEmailInput::default();
+		let config = ReacherConfig::default();
+		let mut session = SmtpSession {
+			transport,
+			rcpt_count: 0,
+			last_rcpt: None,
+			domain: "gmail.com",
+			host: "alt4.aspmx.l.google.com.",
+			port: 25,
+			input: &input,
+			config: &config,
+			smtp_utf8: false,
+			pool_key: None,
+		};
 
-		let r = smtp_is_catch_all(
-			&mut smtp_transport,
-			"gmail.com",
-			"alt4.aspmx.l.google.com.",
-			&CheckEmailInput::default(),
+		let r = smtp_is_catch_all(&mut session).await;
+
+		assert!(!session.transport.is_connected()); // We shouldn't connect to google servers.
+		assert!(r.is_ok());
+		assert!(!r.unwrap().is_catch_all)
+	}
+
+	#[test]
+	fn should_strip_plus_tag() {
+		let strip = |email: &str| strip_plus_tag(&EmailAddress::new(email.into()).unwrap());
+		assert_eq!(
+			strip("user+tag@fastmail.com").as_ref() as &str,
+			"user@fastmail.com"
+		);
+		assert_eq!(
+			strip("user+a+b@fastmail.com").as_ref() as &str,
+			"user@fastmail.com"
+		);
+		assert_eq!(
+			strip("user@fastmail.com").as_ref() as &str,
+			"user@fastmail.com"
+		);
+		assert_eq!(
+			strip("+tag@fastmail.com").as_ref() as &str,
+			"+tag@fastmail.com"
+		);
+	}
+
+	#[tokio::test]
+	async fn should_verify_base_address_on_plus_addressing_provider() {
+		// Only the "user" mailbox exists, with or without a +tag.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO:<user@") || command.starts_with("RCPT TO:<user+") {
+				"250 OK\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("user+newsletter@fastmail.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+
+		let (catch_all, deliverability, _) = create_smtp_future(
+			&EmailAddress::new("user+newsletter@fastmail.com".into()).unwrap(),
+			"127.0.0.1",
+			server.port(),
+			"fastmail.com",
+			&input,
+			&ReacherConfig::default(),
+		)
+		.await
+		.unwrap();
+
+		assert!(!catch_all.is_catch_all);
+		assert!(deliverability.is_deliverable);
+		let rcpt_to = server
+			.commands()
+			.into_iter()
+			.filter(|command| command.starts_with("RCPT TO"))
+			.collect::<Vec<_>>();
+		assert_eq!(rcpt_to.len(), 2);
+		assert!(!rcpt_to[0].contains('+'));
+		assert_eq!(rcpt_to[1], "RCPT TO:<user@fastmail.com>");
+	}
+
+	#[tokio::test]
+	async fn should_learn_host_latency() {
+		let server = MockSmtpServer::start(accept_all).await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(30)))
+			.adaptive_timeout(true)
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		for _ in 0..3 {
+			connect_to_host(
+				"example.com",
+				"127.0.0.1.",
+				server.port(),
+				&input,
+				&config,
+				false,
+			)
+			.await
+			.unwrap();
+		}
+
+		assert!(config.smtp_latencies.average("127.0.0.1").is_some());
+		assert_eq!(
+			config
+				.smtp_latencies
+				.effective_timeout("127.0.0.1", input.smtp_timeout),
+			Some(Duration::from_secs(1))
+		);
+	}
+
+	#[tokio::test]
+	async fn should_send_proxy_protocol_header_before_greeting() {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let server = tokio::spawn(async move {
+			let (socket, _) = listener.accept().await.unwrap();
+			let (reader, mut writer) = socket.into_split();
+			let mut lines = BufReader::new(reader).lines();
+			// The header must come before we send the greeting.
+			let header = lines.next_line().await.unwrap().unwrap();
+			writer.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+			while let Ok(Some(command)) = lines.next_line().await {
+				let reply = accept_all(&command);
+				writer.write_all(reply.as_bytes()).await.unwrap();
+				if command.starts_with("MAIL FROM") {
+					break;
+				}
+			}
+			header
+		});
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.proxy_protocol(Some(ProxyProtocol::V1))
+			.proxy_protocol_source(Some("192.0.2.1:4242".parse().unwrap()))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		connect_to_host("example.com", "127.0.0.1", port, &input, &config, false)
+			.await
+			.unwrap();
+
+		let header = server.await.unwrap();
+		assert_eq!(
+			header,
+			format!("PROXY TCP4 192.0.2.1 127.0.0.1 4242 {port}")
+		);
+	}
+
+	#[tokio::test]
+	async fn should_connect_from_bind_addr() {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let server = tokio::spawn(async move {
+			let (socket, peer) = listener.accept().await.unwrap();
+			let (reader, mut writer) = socket.into_split();
+			let mut lines = BufReader::new(reader).lines();
+			writer.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+			while let Ok(Some(command)) = lines.next_line().await {
+				let reply = accept_all(&command);
+				writer.write_all(reply.as_bytes()).await.unwrap();
+				if command.starts_with("MAIL FROM") {
+					break;
+				}
+			}
+			peer
+		});
+		// Any address of 127.0.0.0/8 is a loopback address on Linux.
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.bind_addr(Some("127.0.0.2".parse().unwrap()))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		connect_to_host("example.com", "127.0.0.1", port, &input, &config, false)
+			.await
+			.unwrap();
+
+		let peer = server.await.unwrap();
+		assert_eq!(peer.ip().to_string(), "127.0.0.2");
+	}
+
+	#[tokio::test]
+	async fn should_tell_disabled_accounts_from_nonexistent_ones() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		let deliverability = |reply: &'static str| {
+			let input = &input;
+			let config = &config;
+			let to_email = &to_email;
+			async move {
+				let server = MockSmtpServer::start(move |command| {
+					if command.starts_with("RCPT TO:<foo@") {
+						reply.into()
+					} else if command.starts_with("RCPT TO") {
+						"550 5.1.1 No such user\r\n".into()
+					} else {
+						accept_all(command)
+					}
+				})
+				.await;
+				create_smtp_future(
+					to_email,
+					"127.0.0.1",
+					server.port(),
+					"example.com",
+					input,
+					config,
+				)
+				.await
+				.unwrap()
+				.1
+			}
+		};
+
+		let disabled = deliverability("550 5.2.1 User not active\r\n").await;
+		assert!(!disabled.is_deliverable);
+		assert!(disabled.is_disabled);
+		assert_eq!(disabled.description(), Some(SmtpErrorDesc::AccountDisabled));
+
+		let nonexistent = deliverability("550 5.1.1 No such user\r\n").await;
+		assert!(!nonexistent.is_deliverable);
+		assert!(!nonexistent.is_disabled);
+		assert_eq!(nonexistent.description(), Some(SmtpErrorDesc::NonExistent));
+
+		// A rejection of `MAIL FROM` is about our sender, not the email.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("MAIL FROM") {
+				"550 5.7.1 Sender account suspended\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let err = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
 		)
+		.await
+		.err()
+		.unwrap();
+		assert!(err.get_description().is_none());
+
+		// Not a relay denied reply, despite its wording.
+		let nonexistent =
+			deliverability("550 User not local or invalid address – Relay denied\r\n").await;
+		assert!(!nonexistent.is_deliverable);
+		assert!(!nonexistent.is_disabled);
+	}
+
+	#[tokio::test]
+	async fn should_classify_enhanced_status() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		// The replies to the email have no phrase we know of.
+		let result = |reply: &'static str| {
+			let input = &input;
+			let config = &config;
+			let to_email = &to_email;
+			async move {
+				let server = MockSmtpServer::start(move |command| {
+					if command.starts_with("RCPT TO:<foo@") {
+						reply.into()
+					} else if command.starts_with("RCPT TO") {
+						"550 5.1.1 No such user\r\n".into()
+					} else {
+						accept_all(command)
+					}
+				})
+				.await;
+				check_smtp_without_retry(
+					to_email,
+					"127.0.0.1",
+					server.port(),
+					"example.com",
+					input,
+					config,
+				)
+				.await
+			}
+		};
+		let details = |reply: &'static str| async move { result(reply).await.unwrap().0 };
+
+		let not_found = details("550 5.1.1 Nope\r\n").await;
+		assert_eq!(not_found.enhanced_status.as_deref(), Some("5.1.1"));
+		assert_eq!(not_found.fingerprint.as_deref(), Some("550 5.1.1 nope"));
+		assert!(!not_found.is_deliverable);
+		assert!(!not_found.is_disabled);
+		assert_eq!(
+			crate::calculate_reachable(&Default::default(), &Ok(not_found)),
+			crate::Reachable::Invalid
+		);
+
+		let disabled = details("550 5.2.1 Nope\r\n").await;
+		assert_eq!(disabled.enhanced_status.as_deref(), Some("5.2.1"));
+		assert!(!disabled.is_deliverable);
+		assert!(disabled.is_disabled);
+		assert!(!disabled.has_full_inbox);
+
+		// 4.x.x codes are transient, e.g. Gmail rate limiting us.
+		match result("450 4.2.1 Try again later\r\n").await {
+			Err(err @ SmtpError::SmtpError(_)) => assert!(crate::is_transient_error(&Err(err))),
+			res => panic!("{:?}", res),
+		}
+
+		let deliverable = details("250 2.1.5 Ok\r\n").await;
+		assert_eq!(deliverable.enhanced_status.as_deref(), Some("2.1.5"));
+		assert!(deliverable.is_deliverable);
+		assert_eq!(deliverable.fingerprint, None);
+	}
+
+	#[tokio::test]
+	async fn should_apply_rcpt_252_policy() {
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				"252 2.1.5 Cannot VRFY user, but will accept message\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
 		.await;
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let config = ReacherConfig::default();
 
-		assert!(!smtp_transport.is_connected()); // We shouldn't connect to google servers.
-		assert!(r.is_ok());
-		assert!(!r.unwrap())
+		// By default, a 252 makes the verification fail.
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let mut smtp_transport = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await
+		.unwrap();
+		let r = email_deliverable(&mut smtp_transport, "127.0.0.1", &to_email, &input).await;
+		assert!(matches!(r, Err(SmtpError::CannotVerify(_))));
+
+		let input = CheckEmailInput {
+			rcpt_252_policy: Rcpt252Policy::Deliverable,
+			..input
+		};
+		let mut smtp_transport = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await
+		.unwrap();
+		let r = email_deliverable(&mut smtp_transport, "127.0.0.1", &to_email, &input).await;
+		assert!(r.unwrap().is_deliverable);
+	}
+
+	#[tokio::test]
+	async fn should_flag_unconfirmed_catch_all_in_strict_mode() {
+		// The random address of the catch-all check gets a temporary error.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO:<foo@") {
+				"250 OK\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"451 4.7.1 Please try again later\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let (catch_all, deliverability, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(!catch_all.is_catch_all);
+		assert!(deliverability.is_deliverable);
+
+		let input = CheckEmailInput {
+			strict_safe: true,
+			..input
+		};
+		let (catch_all, _, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(catch_all.is_catch_all);
+	}
+
+	#[tokio::test]
+	async fn should_report_catch_all_confidence() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.catch_all_probes(3)
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		// All the random addresses are accepted.
+		let server = MockSmtpServer::start(accept_all).await;
+		let (catch_all, _, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(catch_all.is_catch_all);
+		assert_eq!(catch_all.confidence, Some(1.0));
+		// The probes share one connection.
+		let commands = server.commands();
+		let count = |prefix: &str| commands.iter().filter(|c| c.starts_with(prefix)).count();
+		assert_eq!(count("MAIL FROM"), 1);
+		assert_eq!(count("RCPT TO"), 3);
+
+		// Only the first random address is accepted.
+		let rcpt_count = Arc::new(AtomicUsize::new(0));
+		let server = MockSmtpServer::start(move |command| {
+			if command.starts_with("RCPT TO") && rcpt_count.fetch_add(1, Ordering::SeqCst) > 0 {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let (catch_all, _, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(!catch_all.is_catch_all);
+		assert_eq!(catch_all.confidence, Some(1.0 / 3.0));
+	}
+
+	#[tokio::test]
+	async fn should_apply_strict_catch_all_by_mx_provider() {
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		// Only the first random address is accepted.
+		let start_server = || async {
+			let rcpt_count = Arc::new(AtomicUsize::new(0));
+			MockSmtpServer::start(move |command| {
+				if command.starts_with("RCPT TO") && rcpt_count.fetch_add(1, Ordering::SeqCst) > 0 {
+					"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+				} else {
+					accept_all(command)
+				}
+			})
+			.await
+		};
+		let is_catch_all = |host: &'static str, port: u16| {
+			let (input, config) = (&input, &config);
+			async move {
+				let mut session =
+					SmtpSession::connect("example.com", "127.0.0.1", port, input, config, false)
+						.await
+						.unwrap();
+				// Pretend the connection goes to `host`.
+				session.host = host;
+				smtp_is_catch_all(&mut session).await.unwrap()
+			}
+		};
+
+		// On an unknown MX, the single accepted probe flags a catch-all.
+		let server = start_server().await;
+		let catch_all = is_catch_all("mx.example.com.", server.port()).await;
+		assert!(catch_all.is_catch_all);
+		assert_eq!(catch_all.confidence, None);
+
+		// On Google Workspace, the same responses aren't conclusive: a 2nd
+		// probe is sent and rejected.
+		let server = start_server().await;
+		let catch_all = is_catch_all("aspmx.l.google.com.", server.port()).await;
+		assert!(!catch_all.is_catch_all);
+		assert_eq!(catch_all.confidence, Some(0.5));
+		let commands = server.commands();
+		assert_eq!(
+			commands.iter().filter(|c| c.starts_with("RCPT TO")).count(),
+			2
+		);
+	}
+
+	#[tokio::test]
+	async fn should_interpret_zoho_replies_on_zoho_mx_only() {
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO:<alice@") {
+				"250 Recipient <alice@example.com> OK\r\n".into()
+			} else if command.starts_with("RCPT TO:<bob@") {
+				"553 Relaying disallowed as bob@example.com\r\n".into()
+			} else if command.starts_with("RCPT TO:<carol@") {
+				"554 5.7.1 : Recipient address rejected: Access denied\r\n".into()
+			} else if command.starts_with("RCPT TO:<dave@") {
+				"550 5.1.1 User does not exist - dave@example.com\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("alice@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		let mut smtp_transport = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await
+		.unwrap();
+		// Whether the address is deliverable, or None if it can't be verified.
+		let cases = [
+			("mx.zoho.com.", "alice@example.com", Some(true)),
+			("mx.zoho.com.", "bob@example.com", Some(false)),
+			("mx.zoho.com.", "dave@example.com", Some(false)),
+			// Zoho blocking us doesn't mean that the mailbox doesn't exist.
+			("mx.zoho.com.", "carol@example.com", None),
+			// Other providers' replies are parsed as before.
+			("mx.example.com.", "bob@example.com", None),
+			("mx.example.com.", "carol@example.com", Some(false)),
+		];
+		for (host, email, expected) in cases {
+			let to_email = EmailAddress::new(email.into()).unwrap();
+			let r = email_deliverable(&mut smtp_transport, host, &to_email, &input).await;
+			assert_eq!(
+				r.ok().map(|d| d.is_deliverable),
+				expected,
+				"{} on {}",
+				email,
+				host
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn should_reuse_cached_catch_all_per_domain() {
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig {
+			catch_all_cache: Arc::new(CatchAllCache::new(Some(Duration::from_secs(60)))),
+			..Default::default()
+		};
+
+		// Only foo@ and bar@ exist.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				if command.contains("<foo@") || command.contains("<bar@") {
+					"250 2.1.5 OK\r\n".into()
+				} else {
+					"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+				}
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let rcpt_count = || {
+			server
+				.commands()
+				.iter()
+				.filter(|c| c.starts_with("RCPT TO"))
+				.count()
+		};
+
+		for (email, expected_rcpt_count) in [("foo@example.com", 2), ("bar@example.com", 3)] {
+			let to_email = EmailAddress::new(email.into()).unwrap();
+			let (catch_all, deliverability, _) = create_smtp_future(
+				&to_email,
+				"127.0.0.1",
+				server.port(),
+				"example.com",
+				&input,
+				&config,
+			)
+			.await
+			.unwrap();
+			assert!(!catch_all.is_catch_all);
+			assert!(deliverability.is_deliverable);
+			// The second address at the domain doesn't probe a random
+			// address again.
+			assert_eq!(rcpt_count(), expected_rcpt_count);
+		}
+	}
+
+	#[tokio::test]
+	async fn should_reconnect_after_max_rcpt_per_connection() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.catch_all_probes(3)
+			.max_rcpt_per_connection(2)
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let (catch_all, deliverability, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(!catch_all.is_catch_all);
+		assert!(!deliverability.is_deliverable);
+		// 3 random addresses and the email, 2 per connection.
+		let commands = server.commands();
+		let count = |prefix: &str| commands.iter().filter(|c| c.starts_with(prefix)).count();
+		assert_eq!(count("RCPT TO"), 4);
+		assert_eq!(count("MAIL FROM"), 2);
+		assert_eq!(count("QUIT"), 2);
+	}
+
+	#[tokio::test]
+	async fn should_pace_rcpt_commands() {
+		let pacing = Duration::from_millis(200);
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.catch_all_probes(2)
+			.rcpt_pacing(Some(pacing))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let rcpt_times = Arc::new(std::sync::Mutex::new(vec![]));
+		let server_rcpt_times = Arc::clone(&rcpt_times);
+		let server = MockSmtpServer::start(move |command| {
+			if command.starts_with("RCPT TO") {
+				server_rcpt_times.lock().unwrap().push(Instant::now());
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+
+		// 2 random addresses and the email, on the same connection.
+		let rcpt_times = rcpt_times.lock().unwrap();
+		assert_eq!(rcpt_times.len(), 3);
+		for pair in rcpt_times.windows(2) {
+			assert!(pair[1] - pair[0] >= pacing, "{:?}", pair[1] - pair[0]);
+		}
+	}
+
+	#[tokio::test]
+	async fn should_probe_postmaster() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let builder = |probe_postmaster: bool| {
+			CheckEmailInputBuilder::default()
+				.to_email("foo@example.com".into())
+				.smtp_timeout(Some(Duration::from_secs(5)))
+				.probe_postmaster(probe_postmaster)
+				.build()
+				.unwrap()
+		};
+		let config = ReacherConfig::default();
+
+		// The honest server rejects all the recipients, postmaster included,
+		// with a reply we can't parse.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				"550 5.1.0 Nope\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let (catch_all, deliverability, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(true),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert_eq!(catch_all.honest_responder, Some(true));
+		assert!(!deliverability.is_deliverable);
+		assert!(server
+			.commands()
+			.iter()
+			.any(|c| c == "RCPT TO:<postmaster@example.com>"));
+		// Without the probe, the rejection isn't trusted.
+		assert!(create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(false),
+			&config,
+		)
+		.await
+		.is_err());
+
+		// Rejections which aren't about the mailbox, e.g. of our IP, aren't
+		// trusted either.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				"550 5.7.1 Nope\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		assert!(create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(true),
+			&config,
+		)
+		.await
+		.is_err());
+
+		// The dishonest server accepts everything.
+		let server = MockSmtpServer::start(accept_all).await;
+		let (catch_all, _, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(true),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert_eq!(catch_all.honest_responder, Some(false));
+	}
+
+	#[tokio::test]
+	async fn should_flag_servers_accepting_at_rcpt_only() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let builder = |probe_postmaster: bool| {
+			CheckEmailInputBuilder::default()
+				.to_email("foo@example.com".into())
+				.smtp_timeout(Some(Duration::from_secs(5)))
+				.probe_postmaster(probe_postmaster)
+				.build()
+				.unwrap()
+		};
+		let config = ReacherConfig::default();
+		// A catch-all which also accepts postmaster, so not an honest
+		// responder.
+		let server = MockSmtpServer::start(accept_all).await;
+		let check = |input| {
+			check_smtp_without_retry(
+				&to_email,
+				"127.0.0.1",
+				server.port(),
+				"example.com",
+				input,
+				&config,
+			)
+		};
+
+		let input = builder(true);
+		let (details, probes) = check(&input).await.unwrap();
+		assert!(details.is_catch_all);
+		assert_eq!(probes.honest_responder, Some(false));
+		assert!(details.accepts_at_rcpt_only);
+		assert!(server
+			.commands()
+			.iter()
+			.any(|command| command.starts_with("RCPT TO") && command.ends_with(".invalid>")));
+		assert_eq!(
+			crate::calculate_reachable(&Default::default(), &Ok(details)),
+			crate::Reachable::Unknown
+		);
+
+		// Without the probe, we can't tell.
+		let input = builder(false);
+		let (details, _) = check(&input).await.unwrap();
+		assert!(details.is_catch_all);
+		assert!(!details.accepts_at_rcpt_only);
+
+		// A regular catch-all refuses to relay to other domains, even if it
+		// accepts postmaster.
+		let catch_all = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") && command.ends_with(".invalid>") {
+				"554 5.7.1 Relay access denied\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let config = ReacherConfig {
+			catch_all_cache: Arc::new(CatchAllCache::new(Some(Duration::from_secs(60)))),
+			..Default::default()
+		};
+		let (details, probes) = check_smtp_without_retry(
+			&to_email,
+			"127.0.0.1",
+			catch_all.port(),
+			"example.com",
+			&builder(true),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(details.is_catch_all);
+		assert_eq!(probes.honest_responder, Some(false));
+		assert!(!details.accepts_at_rcpt_only);
+		assert_eq!(
+			crate::calculate_reachable(&Default::default(), &Ok(details)),
+			crate::Reachable::Risky
+		);
+
+		// The determination is cached with the catch-all one.
+		let (details, _) = check_smtp_without_retry(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.org",
+			&builder(true),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(details.accepts_at_rcpt_only);
+		let (details, _) = check_smtp_without_retry(
+			&to_email,
+			"127.0.0.1",
+			catch_all.port(),
+			"example.org",
+			&builder(true),
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(details.accepts_at_rcpt_only);
+	}
+
+	#[tokio::test]
+	async fn should_send_polite_dialog() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let builder = |polite_dialog| CheckEmailInput {
+			polite_dialog,
+			smtp_timeout: Some(Duration::from_secs(5)),
+			..Default::default()
+		};
+		let config = ReacherConfig::default();
+
+		let server = MockSmtpServer::start(accept_all).await;
+		create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(true),
+			&config,
+		)
+		.await
+		.unwrap();
+		let commands = server.commands();
+		let ehlo = commands
+			.iter()
+			.position(|command| command.starts_with("EHLO"))
+			.unwrap();
+		assert_eq!(commands[ehlo + 1], "NOOP");
+		assert_eq!(commands[commands.len() - 2..], ["RSET", "QUIT"]);
+
+		let server = MockSmtpServer::start(accept_all).await;
+		create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(false),
+			&config,
+		)
+		.await
+		.unwrap();
+		let commands = server.commands();
+		assert!(!commands.iter().any(|command| command == "NOOP"));
+		assert_eq!(commands.last().unwrap(), "QUIT");
+
+		// Also when the server rejects our sender.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("MAIL FROM") {
+				"550 5.7.1 Sender rejected\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let res = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&builder(true),
+			&config,
+		)
+		.await;
+		assert!(res.is_err());
+		let commands = server.commands();
+		assert_eq!(commands[commands.len() - 2..], ["RSET", "QUIT"]);
+	}
+
+	#[tokio::test]
+	async fn should_not_pool_connection_after_error() {
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO:<foo@") {
+				"451 4.3.0 Try again later\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 No such user\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.polite_dialog(true)
+			.build()
+			.unwrap();
+		let config = ReacherConfig {
+			smtp_pool: Arc::new(SmtpPool::new(
+				1,
+				DEFAULT_SMTP_POOL_IDLE_TIMEOUT,
+				DEFAULT_SMTP_POOL_KEEP_ALIVE,
+			)),
+			..Default::default()
+		};
+
+		for _ in 0..2 {
+			let res = create_smtp_future(
+				&input.to_email.parse().unwrap(),
+				"127.0.0.1",
+				server.port(),
+				"example.com",
+				&input,
+				&config,
+			)
+			.await;
+			assert!(res.is_err());
+		}
+
+		let commands = server.commands();
+		let count = |prefix: &str| {
+			commands
+				.iter()
+				.filter(|command| command.starts_with(prefix))
+				.count()
+		};
+		// Each verification opened its own connection, and closed it.
+		assert_eq!(count("EHLO"), 2);
+		assert_eq!(count("QUIT"), 2);
+	}
+
+	#[tokio::test]
+	async fn should_reuse_pooled_connection() {
+		let server = MockSmtpServer::start(accept_all).await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig {
+			smtp_pool: Arc::new(SmtpPool::new(
+				1,
+				DEFAULT_SMTP_POOL_IDLE_TIMEOUT,
+				DEFAULT_SMTP_POOL_KEEP_ALIVE,
+			)),
+			..Default::default()
+		};
+
+		for _ in 0..2 {
+			create_smtp_future(
+				&input.to_email.parse().unwrap(),
+				"127.0.0.1",
+				server.port(),
+				"example.com",
+				&input,
+				&config,
+			)
+			.await
+			.unwrap();
+		}
+
+		let commands = server.commands();
+		let count = |prefix: &str| {
+			commands
+				.iter()
+				.filter(|command| command.starts_with(prefix))
+				.count()
+		};
+		// The second verification skipped the greeting and the EHLO, and
+		// started over from MAIL FROM on the reset connection.
+		assert_eq!(count("EHLO"), 1);
+		assert_eq!(count("MAIL FROM"), 2);
+		assert!(count("RSET") >= 1);
+		assert_eq!(count("QUIT"), 0);
+	}
+
+	#[tokio::test]
+	async fn should_keep_alive_pooled_connections() {
+		let server = MockSmtpServer::start(accept_all).await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let port = server.port();
+		let verify = |config: ReacherConfig| {
+			let input = input.clone();
+			async move {
+				create_smtp_future(
+					&input.to_email.parse().unwrap(),
+					"127.0.0.1",
+					port,
+					"example.com",
+					&input,
+					&config,
+				)
+				.await
+				.unwrap();
+				config
+			}
+		};
+		let count = |prefix: &str| {
+			server
+				.commands()
+				.iter()
+				.filter(|command| command.starts_with(prefix))
+				.count()
+		};
+
+		// The pooled connection gets a NOOP, and is still reused.
+		let config = ReacherConfig {
+			smtp_pool: Arc::new(SmtpPool::new(
+				1,
+				DEFAULT_SMTP_POOL_IDLE_TIMEOUT,
+				DEFAULT_SMTP_POOL_KEEP_ALIVE,
+			)),
+			..Default::default()
+		};
+		let config = verify(config).await;
+		config.smtp_pool.keep_alive().await;
+		assert_eq!(count("NOOP"), 1);
+		verify(config).await;
+		assert_eq!(count("EHLO"), 1);
+
+		// The expired one is closed with a QUIT.
+		let config = ReacherConfig {
+			smtp_pool: Arc::new(SmtpPool::new(
+				1,
+				Duration::ZERO,
+				DEFAULT_SMTP_POOL_KEEP_ALIVE,
+			)),
+			..Default::default()
+		};
+		let config = verify(config).await;
+		config.smtp_pool.keep_alive().await;
+		assert_eq!(count("NOOP"), 1);
+		assert_eq!(count("QUIT"), 1);
+	}
+
+	#[tokio::test]
+	async fn should_send_utf8_address_with_smtputf8() {
+		let handler = |smtputf8: bool| {
+			move |command: &str| {
+				if command.starts_with("EHLO") {
+					if smtputf8 {
+						"250-mock\r\n250 SMTPUTF8\r\n".into()
+					} else {
+						"250 mock\r\n".into()
+					}
+				} else if command.starts_with("RCPT TO:<jöe@") {
+					"250 OK\r\n".into()
+				} else if command.starts_with("RCPT TO") {
+					"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+				} else {
+					accept_all(command)
+				}
+			}
+		};
+		let to_email = crate::syntax::check_syntax("jöe@example.com")
+			.address
+			.unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("jöe@example.com".into())
+			.from_email("me@example.org".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let server = MockSmtpServer::start(handler(true)).await;
+		let (catch_all, deliverability, _) = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await
+		.unwrap();
+		assert!(!catch_all.is_catch_all);
+		assert!(deliverability.is_deliverable);
+		let commands = server.commands();
+		assert!(commands.contains(&"MAIL FROM:<me@example.org> SMTPUTF8".to_string()));
+		assert!(commands.contains(&"RCPT TO:<jöe@example.com>".to_string()));
+
+		let server = MockSmtpServer::start(handler(false)).await;
+		let res = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await;
+		assert!(matches!(res, Err(SmtpError::SmtpUtf8Unsupported)));
+		assert!(!server
+			.commands()
+			.iter()
+			.any(|command| command.starts_with("RCPT TO")));
+	}
+
+	#[tokio::test]
+	async fn should_use_null_sender() {
+		let server = MockSmtpServer::start(accept_all).await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.from_email("me@example.org".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await
+		.unwrap();
+		let input = CheckEmailInput {
+			use_null_sender: true,
+			..input
+		};
+		connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await
+		.unwrap();
+
+		let mail_from = server
+			.commands()
+			.into_iter()
+			.filter(|command| command.starts_with("MAIL FROM"))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			mail_from,
+			vec!["MAIL FROM:<me@example.org>", "MAIL FROM:<>"]
+		);
+	}
+
+	#[tokio::test]
+	async fn should_reject_too_large_response() {
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("MAIL FROM") {
+				"250-OK\r\n".repeat(150) + "250 OK\r\n"
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let r = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await;
+		assert!(matches!(r, Err(SmtpError::ResponseTooLarge(_))));
+
+		// The same response is fine with a higher limit.
+		let input = CheckEmailInput {
+			smtp_max_response_lines: Some(200),
+			..input
+		};
+		let r = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await;
+		assert!(r.is_ok());
+
+		// The EHLO response we read the extensions from is checked too.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("EHLO") {
+				"250-localhost\r\n".to_string() + &"250-OK\r\n".repeat(150) + "250 SMTPUTF8\r\n"
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let r = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			true,
+		)
+		.await;
+		assert!(r.is_ok());
+		let input = CheckEmailInput {
+			smtp_max_response_lines: None,
+			..input
+		};
+		let r = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			true,
+		)
+		.await;
+		assert!(matches!(r, Err(SmtpError::ResponseTooLarge(_))));
+	}
+
+	#[tokio::test]
+	async fn should_reject_too_large_error_response() {
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				format!("550 {}\r\n", "x".repeat(1024))
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.smtp_max_response_bytes(Some(512))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let mut smtp_transport = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			server.port(),
+			&input,
+			&config,
+			false,
+		)
+		.await
+		.unwrap();
+		let r = email_deliverable(
+			&mut smtp_transport,
+			"127.0.0.1",
+			&EmailAddress::new("foo@example.com".into()).unwrap(),
+			&input,
+		)
+		.await;
+		assert!(matches!(r, Err(SmtpError::ResponseTooLarge(_))));
+	}
+
+	#[tokio::test]
+	async fn should_stop_reading_endless_response() {
+		// A server which never ends its greeting: we must stop reading it
+		// once above the limits, not wait for the SMTP timeout.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			while socket.write_all(b"220-flood\r\n").await.is_ok() {}
+		});
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(60)))
+			.build()
+			.unwrap();
+
+		let r = tokio::time::timeout(
+			Duration::from_secs(10),
+			connect_to_host(
+				"example.com",
+				"127.0.0.1",
+				port,
+				&input,
+				&ReacherConfig::default(),
+				false,
+			),
+		)
+		.await
+		.expect("the endless greeting should be cut short");
+		assert!(matches!(r, Err(SmtpError::ResponseTooLarge(_))));
+	}
+
+	/// An EHLO handler advertising STARTTLS.
+	fn accept_starttls(command: &str) -> String {
+		if command.starts_with("EHLO") {
+			"250-mock\r\n250 STARTTLS\r\n".into()
+		} else {
+			accept_all(command)
+		}
+	}
+
+	#[tokio::test]
+	async fn should_use_extensions_advertised_after_starttls() {
+		// A submission server advertising different extensions before and
+		// after the upgrade: only the first EHLO is before STARTTLS.
+		let start = |before: &'static [&'static str], after: &'static [&'static str]| {
+			let ehlo_count = AtomicUsize::new(0);
+			MockSmtpServer::start_starttls(move |command| {
+				if command.starts_with("EHLO") {
+					let extensions = if ehlo_count.fetch_add(1, Ordering::SeqCst) == 0 {
+						before
+					} else {
+						after
+					};
+					// One extension per line, the last one with "250 ".
+					let mut reply = "250-mock\r\n".to_string();
+					for (i, extension) in extensions.iter().enumerate() {
+						let separator = if i + 1 == extensions.len() { ' ' } else { '-' };
+						reply.push_str(&format!("250{}{}\r\n", separator, extension));
+					}
+					reply
+				} else {
+					accept_all(command)
+				}
+			})
+		};
+		let to_email = crate::syntax::check_syntax("jöe@example.com")
+			.address
+			.unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("jöe@example.com".into())
+			.smtp_security(SmtpSecurity::Required)
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		let check = |port: u16| {
+			create_smtp_future(&to_email, "localhost", port, "example.com", &input, &config)
+		};
+
+		// SMTPUTF8 is only advertised after the upgrade.
+		let server = start(&["STARTTLS"], &["SMTPUTF8"]).await;
+		let res = check(server.port()).await;
+		assert!(res.is_ok(), "{:?}", res.err());
+		let commands = server.commands();
+		let starttls = commands.iter().position(|c| c == "STARTTLS").unwrap();
+		assert!(commands[starttls + 1].starts_with("EHLO"));
+		assert!(commands
+			.iter()
+			.any(|c| c.starts_with("MAIL FROM") && c.ends_with("SMTPUTF8")));
+
+		// SMTPUTF8 is only advertised before the upgrade.
+		let server = start(&["STARTTLS", "SMTPUTF8"], &["8BITMIME"]).await;
+		let res = check(server.port()).await;
+		assert!(matches!(res, Err(SmtpError::SmtpUtf8Unsupported)));
+	}
+
+	#[tokio::test]
+	async fn should_fail_on_invalid_certificate() {
+		let server = MockSmtpServer::start_starttls(accept_starttls).await;
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_security(SmtpSecurity::Required)
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		// The self-signed certificate is accepted by default.
+		let res = create_smtp_future(
+			&to_email,
+			"localhost",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await;
+		assert!(res.is_ok(), "{:?}", res.err());
+
+		let input = CheckEmailInput {
+			smtp_verify_certificates: true,
+			..input
+		};
+		let res = create_smtp_future(
+			&to_email,
+			"localhost",
+			server.port(),
+			"example.com",
+			&input,
+			&config,
+		)
+		.await;
+		match res {
+			Err(err @ SmtpError::TlsCertInvalid(_)) => {
+				assert!(matches!(
+					err.get_description(),
+					Some(SmtpErrorDesc::TlsCertInvalid)
+				));
+			}
+			other => panic!("expected TlsCertInvalid, got {:?}", other.err()),
+		}
+	}
+
+	#[test]
+	fn should_only_require_min_tls_version_with_required_tls() {
+		let input = CheckEmailInput {
+			min_tls_version: TlsVersion::Tls11,
+			..Default::default()
+		};
+		assert_eq!(
+			min_tls_version(SmtpSecurity::Required, &input),
+			Some(TlsVersion::Tls11)
+		);
+		assert_eq!(
+			min_tls_version(SmtpSecurity::Wrapper, &input),
+			Some(TlsVersion::Tls11)
+		);
+		assert_eq!(min_tls_version(SmtpSecurity::Opportunistic, &input), None);
+	}
+
+	#[tokio::test]
+	async fn should_reject_tls_version_below_minimum() {
+		use tokio::io::AsyncReadExt;
+
+		// A server offering STARTTLS, which answers the ClientHello with a
+		// TLS 1.0 ServerHello.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			let (socket, _) = listener.accept().await.unwrap();
+			let mut socket = BufReader::new(socket);
+			socket.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+			let mut line = String::new();
+			while socket.read_line(&mut line).await.unwrap() > 0 {
+				if line.starts_with("STARTTLS") {
+					socket.write_all(b"220 Ready\r\n").await.unwrap();
+					break;
+				}
+				socket
+					.write_all(accept_starttls(line.trim_end()).as_bytes())
+					.await
+					.unwrap();
+				line.clear();
+			}
+			let mut client_hello = [0; 4096];
+			let _ = socket.read(&mut client_hello).await;
+			// Handshake record with the TLS 1.0 version, a random, no session
+			// id, TLS_RSA_WITH_AES_128_CBC_SHA and no compression.
+			let mut server_hello = vec![
+				0x16, 0x03, 0x01, 0x00, 0x2a, 0x02, 0x00, 0x00, 0x26, 0x03, 0x01,
+			];
+			server_hello.extend([0x42; 32]);
+			server_hello.extend([0x00, 0x00, 0x2f, 0x00]);
+			socket.write_all(&server_hello).await.unwrap();
+			let _ = socket.read(&mut client_hello).await;
+		});
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_security(SmtpSecurity::Required)
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		assert_eq!(input.min_tls_version, TlsVersion::Tls12);
+		let config = ReacherConfig::default();
+
+		let res =
+			create_smtp_future(&to_email, "localhost", port, "example.com", &input, &config).await;
+		match res {
+			Err(err @ SmtpError::TlsVersionTooLow(_)) => {
+				assert!(matches!(
+					err.get_description(),
+					Some(SmtpErrorDesc::TlsVersionTooLow)
+				));
+			}
+			other => panic!("expected TlsVersionTooLow, got {:?}", other.err()),
+		}
+	}
+
+	#[tokio::test]
+	async fn should_fail_handshake_without_starttls() {
+		let server = MockSmtpServer::start(accept_all).await;
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_security(SmtpSecurity::Required)
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+
+		let res = create_smtp_future(
+			&to_email,
+			"127.0.0.1",
+			server.port(),
+			"example.com",
+			&input,
+			&ReacherConfig::default(),
+		)
+		.await;
+		assert!(
+			matches!(res, Err(SmtpError::TlsHandshake(_))),
+			"{:?}",
+			res.err()
+		);
+	}
+
+	#[tokio::test]
+	async fn should_require_starttls_capability() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.require_starttls_capability(true)
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		let check = |port: u16, input: CheckEmailInput| {
+			let to_email = &to_email;
+			let config = &config;
+			async move {
+				create_smtp_future(to_email, "localhost", port, "example.com", &input, config).await
+			}
+		};
+
+		let plaintext = MockSmtpServer::start(accept_all).await;
+		let with_security = |security| CheckEmailInput {
+			smtp_security: security,
+			..input.clone()
+		};
+		for security in [SmtpSecurity::Opportunistic, SmtpSecurity::None] {
+			let res = check(plaintext.port(), with_security(security)).await;
+			match res {
+				Err(err @ SmtpError::NoTlsSupport) => {
+					assert!(matches!(
+						err.get_description(),
+						Some(SmtpErrorDesc::NoTlsSupport)
+					));
+				}
+				other => panic!("expected NoTlsSupport, got {:?}", other.err()),
+			}
+		}
+		// No MAIL FROM was sent over plaintext.
+		assert!(!plaintext
+			.commands()
+			.iter()
+			.any(|command| command.starts_with("MAIL FROM")));
+
+		let starttls = MockSmtpServer::start_starttls(accept_starttls).await;
+		for security in [SmtpSecurity::Opportunistic, SmtpSecurity::None] {
+			let res = check(starttls.port(), with_security(security)).await;
+			assert!(res.is_ok(), "{:?}", res.err());
+		}
+	}
+
+	#[tokio::test]
+	async fn should_record_each_proxy_connection() {
+		// A proxy closing the connections, which we retry.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			while let Ok((socket, _)) = listener.accept().await {
+				drop(socket);
+			}
+		});
+		let connections = Arc::new(AtomicUsize::new(0));
+		let counter = Arc::clone(&connections);
+		let config = ReacherConfig {
+			on_proxy_connection: Some(crate::config::OnProxyConnection::new(move || {
+				counter.fetch_add(1, Ordering::SeqCst);
+			})),
+			..Default::default()
+		};
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.proxy(Some(CheckEmailInputProxy {
+				host: "127.0.0.1".into(),
+				port,
+				username: None,
+				password: None,
+			}))
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+
+		let mut retries_used = 0;
+		let res = check_smtp_with_retry(
+			&to_email,
+			"localhost",
+			25,
+			"example.com",
+			&input,
+			&config,
+			2,
+			&mut retries_used,
+		)
+		.await;
+
+		assert!(res.is_err());
+		assert_eq!(retries_used, 1);
+		assert_eq!(connections.load(Ordering::SeqCst), 2);
+	}
+
+	#[tokio::test]
+	async fn should_tell_proxy_failures_apart() {
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let config = ReacherConfig::default();
+		let check = |proxy: CheckEmailInputProxy| {
+			let to_email = &to_email;
+			let config = &config;
+			async move {
+				let input = CheckEmailInputBuilder::default()
+					.to_email("foo@example.com".into())
+					.proxy(Some(proxy))
+					.smtp_timeout(Some(Duration::from_secs(5)))
+					.build()
+					.unwrap();
+				create_smtp_future(to_email, "localhost", 25, "example.com", &input, config).await
+			}
+		};
+
+		// A proxy refusing the connection.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		drop(listener);
+		let res = check(CheckEmailInputProxy {
+			host: "127.0.0.1".into(),
+			port,
+			username: None,
+			password: None,
+		})
+		.await;
+		match res {
+			Err(err @ SmtpError::ProxyUnreachable(_)) => assert!(matches!(
+				err.get_description(),
+				Some(SmtpErrorDesc::ProxyUnreachable)
+			)),
+			other => panic!("expected ProxyUnreachable, got {:?}", other.err()),
+		}
+
+		// A proxy rejecting the credentials.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			use tokio::io::AsyncReadExt;
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut buf = [0; 512];
+			// Greeting, then choose username/password authentication.
+			let _ = socket.read(&mut buf).await;
+			socket.write_all(&[5, 2]).await.unwrap();
+			// Credentials, then reject them.
+			let _ = socket.read(&mut buf).await;
+			socket.write_all(&[1, 1]).await.unwrap();
+		});
+		let res = check(CheckEmailInputProxy {
+			host: "127.0.0.1".into(),
+			port,
+			username: Some("user".into()),
+			password: Some("wrong".into()),
+		})
+		.await;
+		match res {
+			Err(err @ SmtpError::ProxyAuthFailed(_)) => assert!(matches!(
+				err.get_description(),
+				Some(SmtpErrorDesc::ProxyAuthFailed)
+			)),
+			other => panic!("expected ProxyAuthFailed, got {:?}", other.err()),
+		}
+	}
+
+	#[test]
+	fn should_find_timeout_override() {
+		let input = CheckEmailInputBuilder::default()
+			.smtp_timeouts(Some(HashMap::from([
+				("gmail.com".to_string(), Duration::from_secs(5)),
+				(".outlook.com".to_string(), Duration::from_secs(60)),
+			])))
+			.build()
+			.unwrap();
+
+		assert_eq!(
+			find_timeout_override("gmail.com", "gmail-smtp-in.l.google.com.", &input),
+			Some(Duration::from_secs(5))
+		);
+		assert_eq!(
+			find_timeout_override(
+				"customer.com",
+				"customer-com.mail.protection.outlook.com.",
+				&input
+			),
+			Some(Duration::from_secs(60))
+		);
+		assert_eq!(
+			find_timeout_override("example.com", "mx.example.com.", &input),
+			None
+		);
+	}
+
+	#[tokio::test]
+	async fn should_use_timeout_override() {
+		// A server which never sends its greeting.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		tokio::spawn(async move {
+			let mut sockets = vec![];
+			while let Ok((socket, _)) = listener.accept().await {
+				sockets.push(socket);
+			}
+		});
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(60)))
+			.smtp_timeouts(Some(HashMap::from([(
+				"example.com".to_string(),
+				Duration::from_millis(200),
+			)])))
+			.build()
+			.unwrap();
+
+		let start = Instant::now();
+		let res = connect_to_host(
+			"example.com",
+			"127.0.0.1",
+			port,
+			&input,
+			&ReacherConfig::default(),
+			false,
+		)
+		.await;
+		assert!(res.is_err());
+		assert!(start.elapsed() < Duration::from_secs(5));
+	}
+
+	#[tokio::test]
+	async fn should_retry_on_connection_reset() {
+		// When `reset` is set, the server resets the connection on the next
+		// RCPT TO, and unsets it.
+		let reset = Arc::new(AtomicBool::new(true));
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let server_reset = Arc::clone(&reset);
+		tokio::spawn(async move {
+			while let Ok((socket, _)) = listener.accept().await {
+				let (reader, mut writer) = socket.into_split();
+				let mut lines = BufReader::new(reader).lines();
+				writer.write_all(b"220 mock ESMTP\r\n").await.unwrap();
+				while let Ok(Some(command)) = lines.next_line().await {
+					if command.starts_with("RCPT TO") && server_reset.swap(false, Ordering::SeqCst)
+					{
+						let socket = lines.into_inner().into_inner().reunite(writer).unwrap();
+						socket.set_linger(Some(Duration::ZERO)).unwrap();
+						break;
+					}
+					let reply = accept_all(&command);
+					writer.write_all(reply.as_bytes()).await.unwrap();
+				}
+			}
+		});
+		let to_email = EmailAddress::new("foo@example.com".into()).unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		let res = check_smtp_with_retry(
+			&to_email,
+			"127.0.0.1",
+			port,
+			"example.com",
+			&input,
+			&config,
+			1,
+			&mut 0,
+		)
+		.await;
+		match res {
+			Err(err) => assert!(matches!(
+				err.get_description(),
+				Some(SmtpErrorDesc::ConnectionReset)
+			)),
+			Ok(_) => panic!("expected a connection reset"),
+		}
+
+		reset.store(true, Ordering::SeqCst);
+		let mut retries_used = 0;
+		let res = check_smtp_with_retry(
+			&to_email,
+			"127.0.0.1",
+			port,
+			"example.com",
+			&input,
+			&config,
+			2,
+			&mut retries_used,
+		)
+		.await
+		.unwrap();
+		assert!(res.0.is_deliverable);
+		assert_eq!(retries_used, 1);
+		assert!(!reset.load(Ordering::SeqCst));
+	}
+
+	#[tokio::test]
+	async fn should_wait_for_delayed_greeting() {
+		// The server delays its banner by 1s, and records if the client
+		// talked before it.
+		let early_talker = Arc::new(AtomicBool::new(false));
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let server_early_talker = Arc::clone(&early_talker);
+		tokio::spawn(async move {
+			while let Ok((socket, _)) = listener.accept().await {
+				let early_talker = Arc::clone(&server_early_talker);
+				tokio::spawn(async move {
+					tokio::time::sleep(Duration::from_secs(1)).await;
+					let mut buf = [0; 64];
+					if matches!(socket.try_read(&mut buf), Ok(n) if n > 0) {
+						early_talker.store(true, Ordering::SeqCst);
+					}
+					let (reader, mut writer) = socket.into_split();
+					let mut lines = BufReader::new(reader).lines();
+					if writer.write_all(b"220 mock ESMTP\r\n").await.is_err() {
+						return;
+					}
+					while let Ok(Some(command)) = lines.next_line().await {
+						let reply = accept_all(&command);
+						if writer.write_all(reply.as_bytes()).await.is_err() {
+							return;
+						}
+					}
+				});
+			}
+		});
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_timeout(Some(Duration::from_millis(500)))
+			.smtp_greeting_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+
+		connect_to_host("example.com", "127.0.0.1", port, &input, &config, false)
+			.await
+			.unwrap();
+		assert!(!early_talker.load(Ordering::SeqCst));
+
+		let input = CheckEmailInput {
+			smtp_timeout: Some(Duration::from_secs(5)),
+			smtp_greeting_timeout: Some(Duration::from_millis(200)),
+			..input
+		};
+		let res = connect_to_host("example.com", "127.0.0.1", port, &input, &config, false).await;
+		assert!(matches!(
+			res,
+			Err(SmtpError::SmtpError(AsyncSmtpError::Io(ref e))) if e.kind() == io::ErrorKind::TimedOut
+		));
 	}
 }
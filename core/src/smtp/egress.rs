@@ -0,0 +1,207 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detect that outbound SMTP connections are blocked, e.g. by a cloud
+//! provider blocking port 25, to switch to a degraded mode which skips the
+//! SMTP verification and only returns the syntax and MX results.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Minimum number of different hosts among the consecutive connection
+/// failures: failures on a single host only say that this host is down.
+const MIN_FAILED_HOSTS: usize = 2;
+/// Default of `EgressMonitor::with_retry_after`.
+pub const DEFAULT_DEGRADED_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default)]
+struct EgressState {
+	/// Number of connection failures since the last successful connection.
+	consecutive_failures: usize,
+	/// The hosts of these failures.
+	failed_hosts: HashSet<String>,
+	/// When the failures crossed the threshold, if they did.
+	detected: Option<Instant>,
+	/// The mode set with `force`, which overrides the detection.
+	forced: Option<bool>,
+}
+
+/// Tracks the SMTP connection failures to tell if we're in degraded mode.
+/// It's meant to be shared between verifications, see `ReacherConfig`.
+#[derive(Debug)]
+pub struct EgressMonitor {
+	/// Number of consecutive connection failures, on at least two different
+	/// hosts, after which we switch to the degraded mode. None disables the
+	/// detection.
+	threshold: Option<usize>,
+	/// How long the detected degraded mode lasts, see `with_retry_after`.
+	retry_after: Duration,
+	state: Mutex<EgressState>,
+}
+
+impl Default for EgressMonitor {
+	fn default() -> Self {
+		Self::new(None)
+	}
+}
+
+impl EgressMonitor {
+	/// Switch to the degraded mode after `threshold` consecutive connection
+	/// failures, or never if None.
+	pub fn new(threshold: Option<usize>) -> Self {
+		Self {
+			threshold,
+			retry_after: DEFAULT_DEGRADED_RETRY_AFTER,
+			state: Mutex::default(),
+		}
+	}
+
+	/// Try SMTP again `retry_after` the detection of the degraded mode:
+	/// otherwise, as the SMTP verifications are skipped, we'd never see
+	/// outbound SMTP being unblocked. A successful connection leaves the
+	/// degraded mode, while a single failed one enters it again for as long.
+	pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+		self.retry_after = retry_after;
+		self
+	}
+
+	/// Whether outbound SMTP connections look blocked, so the SMTP
+	/// verification should be skipped.
+	pub fn is_degraded(&self) -> bool {
+		let state = self.state.lock().expect("Lock is not poisoned. qed.");
+		state.forced.unwrap_or_else(|| self.is_detected(&state))
+	}
+
+	/// Whether the failures crossed the threshold less than `retry_after`
+	/// ago.
+	fn is_detected(&self, state: &EgressState) -> bool {
+		state
+			.detected
+			.is_some_and(|detected| detected.elapsed() < self.retry_after)
+	}
+
+	/// Force the degraded mode on or off, or go back to the detection with
+	/// None, which also forgets the failures recorded so far.
+	pub fn force(&self, degraded: Option<bool>) {
+		let mut state = self.state.lock().expect("Lock is not poisoned. qed.");
+		*state = EgressState {
+			forced: degraded,
+			..Default::default()
+		};
+	}
+
+	/// Record a successful connection to an SMTP server.
+	pub(crate) fn record_success(&self) {
+		let mut state = self.state.lock().expect("Lock is not poisoned. qed.");
+		state.consecutive_failures = 0;
+		state.failed_hosts.clear();
+		if state.detected.take().is_some() {
+			log::info!(
+				target: crate::LOG_TARGET,
+				"SMTP connection succeeded, leaving degraded mode"
+			);
+		}
+	}
+
+	/// Record a failure to connect to the host, i.e. the connection was
+	/// refused or timed out.
+	pub(crate) fn record_failure(&self, host: &str) {
+		let Some(threshold) = self.threshold else {
+			return;
+		};
+		let mut state = self.state.lock().expect("Lock is not poisoned. qed.");
+		state.consecutive_failures += 1;
+		state.failed_hosts.insert(host.to_lowercase());
+		// After `retry_after`, the failures are still counted, so a single
+		// failed connection enters the degraded mode again.
+		if !self.is_detected(&state)
+			&& state.consecutive_failures >= threshold
+			&& state.failed_hosts.len() >= MIN_FAILED_HOSTS
+		{
+			log::warn!(
+				target: crate::LOG_TARGET,
+				"{} consecutive SMTP connection failures on {} hosts, outbound SMTP looks blocked: switching to degraded mode",
+				state.consecutive_failures,
+				state.failed_hosts.len()
+			);
+			state.detected = Some(Instant::now());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::EgressMonitor;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn should_detect_failures_on_several_hosts() {
+		let monitor = EgressMonitor::new(Some(3));
+		for _ in 0..5 {
+			monitor.record_failure("mx.down.com");
+		}
+		// A single host being down doesn't tell that egress is blocked.
+		assert!(!monitor.is_degraded());
+
+		monitor.record_success();
+		monitor.record_failure("mx1.example.com");
+		monitor.record_failure("mx2.example.com");
+		assert!(!monitor.is_degraded());
+		monitor.record_failure("mx3.example.com");
+		assert!(monitor.is_degraded());
+
+		monitor.force(Some(false));
+		assert!(!monitor.is_degraded());
+		monitor.force(None);
+		assert!(!monitor.is_degraded());
+	}
+
+	#[test]
+	fn should_try_smtp_again_after_retry_after() {
+		let monitor = EgressMonitor::new(Some(2)).with_retry_after(Duration::from_millis(50));
+		monitor.record_failure("mx1.example.com");
+		monitor.record_failure("mx2.example.com");
+		assert!(monitor.is_degraded());
+
+		// Still blocked: a single failure enters the degraded mode again.
+		thread::sleep(Duration::from_millis(60));
+		assert!(!monitor.is_degraded());
+		monitor.record_failure("mx3.example.com");
+		assert!(monitor.is_degraded());
+
+		// Unblocked: a success leaves the degraded mode.
+		thread::sleep(Duration::from_millis(60));
+		assert!(!monitor.is_degraded());
+		monitor.record_success();
+		monitor.record_failure("mx1.example.com");
+		thread::sleep(Duration::from_millis(60));
+		assert!(!monitor.is_degraded());
+	}
+
+	#[test]
+	fn should_not_detect_without_threshold() {
+		let monitor = EgressMonitor::default();
+		for host in ["mx1.example.com", "mx2.example.com", "mx3.example.com"] {
+			monitor.record_failure(host);
+		}
+		assert!(!monitor.is_degraded());
+
+		monitor.force(Some(true));
+		assert!(monitor.is_degraded());
+	}
+}
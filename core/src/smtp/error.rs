@@ -23,7 +23,7 @@ use super::yahoo::YahooError;
 use crate::util::ser_with_display::ser_with_display;
 use async_smtp::smtp::error::Error as AsyncSmtpError;
 use fast_socks5::SocksError;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// Error occured connecting to this email server via SMTP.
 #[derive(Debug, Serialize)]
@@ -43,6 +43,55 @@ pub enum SmtpError {
 	HeadlessError(HeadlessError),
 	/// Error when verifying a Microsoft 365 email via HTTP request.
 	Microsoft365Error(Microsoft365Error),
+	/// The domain matched this entry of the input's `domain_blocklist`, so
+	/// we didn't contact it.
+	Blocklisted(String),
+	/// The domain matched this entry of the input's `domain_allowlist`, so
+	/// we returned Safe without contacting it.
+	Allowlisted(String),
+	/// All the MX records point to a domain parking service, like this host,
+	/// so we didn't contact it.
+	Parked(String),
+	/// The SMTP server sent a response larger than the input's
+	/// `smtp_max_response_bytes` or `smtp_max_response_lines`.
+	ResponseTooLarge(String),
+	/// The server replied 252 to `RCPT TO`, with this message, and the
+	/// input's `rcpt_252_policy` is Unknown.
+	CannotVerify(String),
+	/// The TLS handshake with the SMTP server failed, with this reason (e.g.
+	/// no STARTTLS support when TLS is required).
+	TlsHandshake(String),
+	/// The SMTP server only offers TLS versions below the input's
+	/// `min_tls_version`, with this reason.
+	TlsVersionTooLow(String),
+	/// The SMTP server presented an invalid TLS certificate, with this
+	/// reason. Only checked if the input's `smtp_verify_certificates` is set.
+	TlsCertInvalid(String),
+	/// The input's `mx_host_index` doesn't match any of the domain's MX
+	/// hosts.
+	InvalidMxHostIndex(String),
+	/// The email has a non-ASCII local part, but the SMTP server doesn't
+	/// advertise the SMTPUTF8 extension, so we can't verify it.
+	SmtpUtf8Unsupported,
+	/// The SMTP server doesn't offer STARTTLS, and the input's
+	/// `require_starttls_capability` is set.
+	NoTlsSupport,
+	/// The input's SOCKS5 proxy doesn't accept connections, with this
+	/// reason.
+	ProxyUnreachable(String),
+	/// The input's SOCKS5 proxy rejected our credentials, with this reason.
+	ProxyAuthFailed(String),
+	/// Outbound SMTP connections look blocked, see `EgressMonitor`, so we
+	/// skipped the SMTP verification and only checked the syntax and MX.
+	Degraded,
+	/// The input's `cross_check` is set, and the provider's method and SMTP
+	/// disagree, with the outcome of each, e.g. "headless: deliverable,
+	/// smtp: not deliverable".
+	MethodsDisagree(String),
+	/// The SMTP server refused to relay the email, with this reply: it
+	/// doesn't handle the email's domain, e.g. because of a misrouted MX
+	/// record.
+	RelayDenied(String),
 }
 
 impl From<SocksError> for SmtpError {
@@ -80,13 +129,40 @@ impl SmtpError {
 	/// SmtpErrorDesc. This only parses the following known errors:
 	/// - IP blacklisted
 	/// - IP needs reverse DNS
+	/// - Domain blocklisted
+	/// - Domain allowlisted
+	/// - Domain parked
+	/// - TLS handshake failed
+	/// - TLS certificate invalid
+	/// - TLS version too low
+	/// - Connection reset
+	/// - SMTPUTF8 unsupported
+	/// - No STARTTLS support
+	/// - Proxy unreachable
+	/// - Proxy authentication failed
+	/// - Degraded mode
+	/// - Relay denied
 	pub fn get_description(&self) -> Option<SmtpErrorDesc> {
 		match self {
+			SmtpError::Blocklisted(_) => Some(SmtpErrorDesc::Blocklisted),
+			SmtpError::Allowlisted(_) => Some(SmtpErrorDesc::Allowlisted),
+			SmtpError::Parked(_) => Some(SmtpErrorDesc::Parked),
+			SmtpError::TlsHandshake(_) => Some(SmtpErrorDesc::TlsHandshake),
+			SmtpError::TlsCertInvalid(_) => Some(SmtpErrorDesc::TlsCertInvalid),
+			SmtpError::TlsVersionTooLow(_) => Some(SmtpErrorDesc::TlsVersionTooLow),
+			SmtpError::SmtpUtf8Unsupported => Some(SmtpErrorDesc::SmtpUtf8Unsupported),
+			SmtpError::NoTlsSupport => Some(SmtpErrorDesc::NoTlsSupport),
+			SmtpError::ProxyUnreachable(_) => Some(SmtpErrorDesc::ProxyUnreachable),
+			SmtpError::ProxyAuthFailed(_) => Some(SmtpErrorDesc::ProxyAuthFailed),
+			SmtpError::Degraded => Some(SmtpErrorDesc::Degraded),
+			SmtpError::RelayDenied(_) => Some(SmtpErrorDesc::RelayDenied),
 			SmtpError::SmtpError(_) => {
 				if parser::is_err_ip_blacklisted(self) {
 					Some(SmtpErrorDesc::IpBlacklisted)
 				} else if parser::is_err_needs_rdns(self) {
 					Some(SmtpErrorDesc::NeedsRDNS)
+				} else if parser::is_err_connection_reset(self) {
+					Some(SmtpErrorDesc::ConnectionReset)
 				} else {
 					None
 				}
@@ -94,9 +170,23 @@ impl SmtpError {
 			_ => None,
 		}
 	}
+
+	/// Get the server's reply normalized to group similar failures: the reply
+	/// code, the enhanced status code, and the text with the email addresses,
+	/// IPs, hostnames and numbers replaced by placeholders. Two replies
+	/// differing only by e.g. the IP they mention get the same fingerprint.
+	/// None if the error isn't an SMTP reply.
+	pub fn fingerprint(&self) -> Option<String> {
+		match self {
+			SmtpError::SmtpError(
+				AsyncSmtpError::Transient(response) | AsyncSmtpError::Permanent(response),
+			) => Some(parser::fingerprint(response)),
+			_ => None,
+		}
+	}
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 /// SmtpErrorDesc describes a description of which category the error belongs
 /// to.
 pub enum SmtpErrorDesc {
@@ -104,4 +194,38 @@ pub enum SmtpErrorDesc {
 	IpBlacklisted,
 	/// The IP needs a reverse DNS entry.
 	NeedsRDNS,
+	/// The domain is in the input's blocklist, so we didn't contact it.
+	Blocklisted,
+	/// The domain is in the input's allowlist, so we didn't contact it.
+	Allowlisted,
+	/// The domain is parked: its MX records point to a domain parking service.
+	Parked,
+	/// The TLS handshake with the server failed.
+	TlsHandshake,
+	/// The server's TLS certificate is invalid.
+	TlsCertInvalid,
+	/// The server only offers TLS versions below the input's minimum.
+	TlsVersionTooLow,
+	/// The server reset the connection during the SMTP dialog.
+	ConnectionReset,
+	/// The server doesn't support the internationalized email.
+	SmtpUtf8Unsupported,
+	/// The server rejected the email at `RCPT TO` because its account has
+	/// been disabled or suspended, as opposed to never existed.
+	AccountDisabled,
+	/// The server rejected the email at `RCPT TO` because its mailbox
+	/// doesn't exist.
+	NonExistent,
+	/// The server doesn't offer STARTTLS, which the input requires.
+	NoTlsSupport,
+	/// The SOCKS5 proxy doesn't accept connections.
+	ProxyUnreachable,
+	/// The SOCKS5 proxy rejected our credentials.
+	ProxyAuthFailed,
+	/// Outbound SMTP connections look blocked, so we skipped the SMTP
+	/// verification.
+	Degraded,
+	/// The server refused to relay the email, i.e. it doesn't handle the
+	/// email's domain. It says nothing about the mailbox.
+	RelayDenied,
 }
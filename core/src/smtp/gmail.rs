@@ -15,13 +15,14 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::SmtpDetails;
+use crate::config::ReacherConfig;
+use crate::syntax::EmailAddress;
 use crate::LOG_TARGET;
 use crate::{
 	smtp::http_api::create_client,
 	util::{input_output::CheckEmailInput, ser_with_display::ser_with_display},
 };
-use async_smtp::EmailAddress;
-use reqwest::Error as ReqwestError;
+use reqwest::{Error as ReqwestError, StatusCode};
 use serde::Serialize;
 use thiserror::Error;
 
@@ -34,6 +35,9 @@ pub enum GmailError {
 	#[serde(serialize_with = "ser_with_display")]
 	#[error("Error serializing or deserializing HTTP requests and responses: {0}")]
 	ReqwestError(ReqwestError),
+	/// Gmail rate-limited our requests.
+	#[error("Gmail rate-limited our requests")]
+	RateLimited,
 }
 
 impl From<ReqwestError> for GmailError {
@@ -42,18 +46,35 @@ impl From<ReqwestError> for GmailError {
 	}
 }
 
-/// Use HTTP request to verify if a Gmail email address exists.
+/// Use HTTP request to verify if a Gmail email address exists: Gmail sets a
+/// cookie on its GXLU page only for existing accounts. This only works for
+/// @gmail.com and @googlemail.com addresses, see [is_gmail_domain].
 /// See: <https://blog.0day.rocks/abusing-gmail-to-get-previously-unlisted-e-mail-addresses-41544b62b2>
 pub async fn check_gmail_via_api(
 	to_email: &EmailAddress,
 	input: &CheckEmailInput,
+	config: &ReacherConfig,
 ) -> Result<SmtpDetails, GmailError> {
-	let response = create_client(input, "gmail")?
-		.head(GLXU_PAGE)
+	check_gmail_via_api_at(GLXU_PAGE, to_email, input, config).await
+}
+
+async fn check_gmail_via_api_at(
+	url: &str,
+	to_email: &EmailAddress,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Result<SmtpDetails, GmailError> {
+	let response = create_client(input, config, "gmail")?
+		.head(url)
 		.query(&[("email", to_email)])
 		.send()
 		.await?;
 
+	if response.status() == StatusCode::TOO_MANY_REQUESTS {
+		return Err(GmailError::RateLimited);
+	}
+	let response = response.error_for_status()?;
+
 	let email_exists = response.headers().contains_key("Set-Cookie");
 
 	log::debug!(
@@ -76,14 +97,71 @@ pub fn is_gmail(host: &str) -> bool {
 	host.to_lowercase().ends_with(".google.com.")
 }
 
+/// Check if the domain is Gmail's own, as opposed to a Google Suite domain,
+/// whose addresses can't be verified via Gmail's API.
+pub fn is_gmail_domain(domain: &str) -> bool {
+	matches!(
+		domain.to_lowercase().as_str(),
+		"gmail.com" | "googlemail.com"
+	)
+}
+
 #[cfg(test)]
 mod tests {
 	use std::str::FromStr;
 
 	use crate::CheckEmailInputBuilder;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
 
 	use super::*;
 
+	/// Serve a single HTTP response on a random local port, and return its
+	/// URL.
+	async fn serve_once(response: &'static str) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut buf = vec![0; 4096];
+			let _ = socket.read(&mut buf).await.unwrap();
+			socket.write_all(response.as_bytes()).await.unwrap();
+		});
+		format!("http://{}/", addr)
+	}
+
+	async fn check(response: &'static str) -> Result<SmtpDetails, GmailError> {
+		let url = serve_once(response).await;
+		let to_email = EmailAddress::from_str("someone@gmail.com").unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("someone@gmail.com".to_owned())
+			.build()
+			.unwrap();
+		check_gmail_via_api_at(&url, &to_email, &input, &ReacherConfig::default()).await
+	}
+
+	#[tokio::test]
+	async fn should_be_deliverable_if_cookie_is_set() {
+		let res = check("HTTP/1.1 204 No Content\r\nset-cookie: GX=abc\r\n\r\n").await;
+		assert!(res.unwrap().is_deliverable);
+
+		let res = check("HTTP/1.1 204 No Content\r\n\r\n").await;
+		assert!(!res.unwrap().is_deliverable);
+	}
+
+	#[tokio::test]
+	async fn should_map_429_to_rate_limited() {
+		let res = check("HTTP/1.1 429 Too Many Requests\r\ncontent-length: 0\r\n\r\n").await;
+		assert!(matches!(res, Err(GmailError::RateLimited)), "{:?}", res);
+	}
+
+	#[test]
+	fn should_only_match_gmail_domains() {
+		assert!(is_gmail_domain("gmail.com"));
+		assert!(is_gmail_domain("GoogleMail.com"));
+		assert!(!is_gmail_domain("example.com"));
+	}
+
 	#[tokio::test]
 	#[ignore] // ref: https://github.com/reacherhq/check-if-email-exists/issues/1431
 	async fn should_return_is_deliverable_true() {
@@ -93,7 +171,7 @@ mod tests {
 			.build()
 			.unwrap();
 
-		let smtp_details = check_gmail_via_api(&to_email, &input).await;
+		let smtp_details = check_gmail_via_api(&to_email, &input, &ReacherConfig::default()).await;
 
 		assert!(smtp_details.is_ok());
 		assert!(smtp_details.unwrap().is_deliverable);
@@ -14,21 +14,105 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::time::{Duration, Instant};
+
 use fantoccini::{
+	elements::Element,
 	error::{CmdError, NewSessionError},
-	Client, ClientBuilder,
+	Client, ClientBuilder, Locator,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Map;
 
 use crate::util::ser_with_display::ser_with_display;
 
+/// How long to wait for any of the selectors of an element to match.
+const ELEMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval between two lookups of the selectors of an element.
+const ELEMENT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 #[derive(Debug, Serialize)]
 pub enum HeadlessError {
 	#[serde(serialize_with = "ser_with_display")]
 	Cmd(CmdError),
 	#[serde(serialize_with = "ser_with_display")]
 	NewSession(NewSessionError),
+	/// None of the selectors of an element matched.
+	ElementNotFound(Vec<String>),
+}
+
+/// Selectors of the page elements the headless verifications interact with.
+/// Each element has a list of CSS selectors, or XPath expressions if they
+/// start with "/", tried in order: the first one is the primary selector,
+/// and the next ones are fallbacks in case the provider slightly changes its
+/// markup.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HeadlessConfig {
+	/// The email input of the Hotmail password recovery page.
+	pub hotmail_email_input: Vec<String>,
+	/// The "Next" button of the Hotmail password recovery page.
+	pub hotmail_submit: Vec<String>,
+	/// The email input of the Yahoo password recovery page.
+	pub yahoo_email_input: Vec<String>,
+	/// The "Continue" button of the Yahoo password recovery page.
+	pub yahoo_submit: Vec<String>,
+}
+
+impl Default for HeadlessConfig {
+	fn default() -> Self {
+		let selectors = |s: &[&str]| s.iter().map(|s| s.to_string()).collect();
+		HeadlessConfig {
+			hotmail_email_input: selectors(&[
+				"#iSigninName",
+				"input[name=\"iSigninName\"]",
+				"input[type=\"email\"]",
+			]),
+			hotmail_submit: selectors(&[
+				"#resetPwdHipAction",
+				"input[type=\"submit\"]",
+				"button[type=\"submit\"]",
+			]),
+			yahoo_email_input: selectors(&[
+				"#username",
+				"input[name=\"username\"]",
+				"input[type=\"email\"]",
+			]),
+			yahoo_submit: selectors(&["button[name=\"verifyYid\"]", "button[type=\"submit\"]"]),
+		}
+	}
+}
+
+/// Parse a selector of the `HeadlessConfig` into a WebDriver locator.
+fn locator(selector: &str) -> Locator<'_> {
+	if selector.starts_with('/') {
+		Locator::XPath(selector)
+	} else {
+		Locator::Css(selector)
+	}
+}
+
+/// Wait for an element matching one of `selectors`, trying them in order on
+/// each lookup. Returns the element with the selector which matched.
+pub async fn find_element(
+	c: &Client,
+	selectors: &[String],
+) -> Result<(Element, String), HeadlessError> {
+	let start = Instant::now();
+	loop {
+		for selector in selectors {
+			match c.find(locator(selector)).await {
+				Ok(element) => return Ok((element, selector.clone())),
+				Err(err) if err.is_no_such_element() => {}
+				Err(err) => return Err(err.into()),
+			}
+		}
+		if start.elapsed() >= ELEMENT_TIMEOUT {
+			return Err(HeadlessError::ElementNotFound(selectors.to_vec()));
+		}
+		tokio::time::sleep(ELEMENT_POLL_INTERVAL).await;
+	}
 }
 
 impl From<CmdError> for HeadlessError {
@@ -67,3 +151,37 @@ pub async fn create_headless_client(webdriver: &str) -> Result<Client, HeadlessE
 
 	Ok(c)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{create_headless_client, find_element, locator};
+	use fantoccini::Locator;
+
+	#[test]
+	fn should_parse_css_and_xpath_selectors() {
+		assert_eq!(locator("#username"), Locator::Css("#username"));
+		assert_eq!(
+			locator("//input[@type='email']"),
+			Locator::XPath("//input[@type='email']")
+		);
+	}
+
+	// Ignoring this test as it requires a local process of WebDriver running on
+	// "http://localhost:9515".
+	#[tokio::test]
+	#[ignore = "Run a webdriver server locally to test this"]
+	async fn should_fall_back_to_next_selector() {
+		let c = create_headless_client("http://localhost:9515")
+			.await
+			.unwrap();
+		c.goto("data:text/html,<form><input type='email' name='login'></form>")
+			.await
+			.unwrap();
+
+		let selectors = vec!["#username".into(), "input[type=\"email\"]".into()];
+		let (_, selector) = find_element(&c, &selectors).await.unwrap();
+		assert_eq!(selector, "input[type=\"email\"]");
+
+		c.close().await.unwrap();
+	}
+}
@@ -14,16 +14,21 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::config::ReacherConfig;
 use crate::util::input_output::CheckEmailInput;
 use crate::LOG_TARGET;
 use reqwest::Error as ReqwestError;
 
-/// Helper function to create a reqwest client, with optional proxy.
+/// Helper function to create a reqwest client, with optional proxy. The
+/// client is created for a single request, so it's recorded as a proxy
+/// connection, see `ReacherConfig::on_proxy_connection`.
 pub fn create_client(
 	input: &CheckEmailInput,
+	config: &ReacherConfig,
 	api_name: &str,
 ) -> Result<reqwest::Client, ReqwestError> {
 	if let Some(proxy) = &input.proxy {
+		config.record_proxy_connection();
 		log::debug!(
 			target: LOG_TARGET,
 			"[email={}] Using proxy socks5://{}:{} for {} API",
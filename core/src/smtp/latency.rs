@@ -0,0 +1,142 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Track the response latency of each MX host, to adapt the SMTP timeout to
+//! how fast the host usually answers.
+
+use hashlink::LinkedHashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Maximum number of tracked hosts, beyond which the least recently recorded
+/// are evicted.
+const MAX_HOSTS: usize = 10_000;
+/// Weight of the newest sample in the moving average.
+const EWMA_ALPHA: f64 = 0.3;
+/// The adaptive timeout is this many times the average latency.
+const TIMEOUT_FACTOR: u32 = 3;
+/// The adaptive timeout never goes below this.
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Exponentially weighted moving average of the response latency of each
+/// MX host. It's meant to be shared between verifications, see
+/// `ReacherConfig`.
+#[derive(Debug, Default)]
+pub struct HostLatencies {
+	/// Ordered from the least to the most recently recorded host.
+	averages: Mutex<LinkedHashMap<String, Duration>>,
+}
+
+impl HostLatencies {
+	/// Record the latency of a response from the host.
+	pub fn record(&self, host: &str, latency: Duration) {
+		let mut averages = self.averages.lock().expect("Lock is not poisoned. qed.");
+		let host_key = host.to_lowercase();
+		let average = match averages.get(&host_key) {
+			Some(average) => average.mul_f64(1.0 - EWMA_ALPHA) + latency.mul_f64(EWMA_ALPHA),
+			None => latency,
+		};
+		// Inserting moves an existing host to the back.
+		averages.insert(host_key, average);
+		while averages.len() > MAX_HOSTS {
+			averages.pop_front();
+		}
+		log::trace!(target: crate::LOG_TARGET, "Average latency of [host={}] is {:?}", host, average);
+	}
+
+	/// The average latency of the host, if we recorded any.
+	pub fn average(&self, host: &str) -> Option<Duration> {
+		self.averages
+			.lock()
+			.expect("Lock is not poisoned. qed.")
+			.get(&host.to_lowercase())
+			.copied()
+	}
+
+	/// The timeout to use for the host: a multiple of its average latency,
+	/// bounded by `MIN_TIMEOUT` and `max_timeout`. Without any recorded
+	/// latency, or without `max_timeout`, returns `max_timeout`.
+	pub fn effective_timeout(&self, host: &str, max_timeout: Option<Duration>) -> Option<Duration> {
+		let max_timeout = max_timeout?;
+		match self.average(host) {
+			Some(average) => Some((average * TIMEOUT_FACTOR).max(MIN_TIMEOUT).min(max_timeout)),
+			None => Some(max_timeout),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{HostLatencies, MAX_HOSTS};
+	use std::time::Duration;
+
+	#[test]
+	fn should_shrink_timeout_after_fast_responses() {
+		let latencies = HostLatencies::default();
+		let max_timeout = Some(Duration::from_secs(30));
+		assert_eq!(
+			latencies.effective_timeout("mx.example.com.", max_timeout),
+			max_timeout
+		);
+
+		latencies.record("mx.example.com.", Duration::from_secs(8));
+		let mut previous = latencies
+			.effective_timeout("mx.example.com.", max_timeout)
+			.unwrap();
+		assert_eq!(previous, Duration::from_secs(24));
+
+		for _ in 0..20 {
+			latencies.record("mx.example.com.", Duration::from_millis(500));
+			let timeout = latencies
+				.effective_timeout("mx.example.com.", max_timeout)
+				.unwrap();
+			assert!(timeout <= previous);
+			previous = timeout;
+		}
+		// 3 times the observed latency.
+		assert!(previous < Duration::from_millis(1600), "{:?}", previous);
+
+		// Other hosts are not affected, and the timeout has a lower bound.
+		assert_eq!(
+			latencies.effective_timeout("mx.other.com.", max_timeout),
+			max_timeout
+		);
+		latencies.record("mx.fast.com.", Duration::from_millis(10));
+		assert_eq!(
+			latencies.effective_timeout("mx.fast.com.", max_timeout),
+			Some(Duration::from_secs(1))
+		);
+		// Without timeout, we don't add one.
+		assert_eq!(latencies.effective_timeout("mx.fast.com.", None), None);
+	}
+
+	#[test]
+	fn should_evict_the_least_recently_recorded_hosts() {
+		let latencies = HostLatencies::default();
+		for i in 0..MAX_HOSTS {
+			latencies.record(&format!("mx{}.example.com", i), Duration::from_secs(1));
+		}
+		// Recording a host makes it the most recent one.
+		latencies.record("mx0.example.com", Duration::from_secs(1));
+		latencies.record("mx.example.org", Duration::from_secs(1));
+
+		assert_eq!(latencies.averages.lock().unwrap().len(), MAX_HOSTS);
+		assert_eq!(latencies.average("mx1.example.com"), None);
+		assert!(latencies.average("mx0.example.com").is_some());
+		assert!(latencies.average("mx2.example.com").is_some());
+		assert!(latencies.average("mx.example.org").is_some());
+	}
+}
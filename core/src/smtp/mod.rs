@@ -14,28 +14,50 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod catch_all_cache;
 mod connect;
+mod egress;
 mod error;
 mod gmail;
 
 mod headless;
 mod http_api;
+mod latency;
 mod outlook;
 mod parser;
+mod pool;
+mod proxy_protocol;
+mod relay;
+mod spf;
+mod transcript;
+mod transport;
+mod webdriver_pool;
 mod yahoo;
+mod zoho;
 
 use std::default::Default;
 
-use async_smtp::EmailAddress;
+use crate::syntax::EmailAddress;
 use hickory_proto::rr::Name;
 use serde::{Deserialize, Serialize};
 
 use crate::{
 	config::ReacherConfig, util::input_output::CheckEmailInput, GmailVerifMethod,
-	HotmailB2CVerifMethod, YahooVerifMethod,
+	HotmailB2CVerifMethod, Provider, YahooVerifMethod, LOG_TARGET,
 };
-use connect::check_smtp_with_retry;
+pub use catch_all_cache::CatchAllCache;
+use connect::{check_smtp_with_retry, rcpt_email};
+pub use egress::{EgressMonitor, DEFAULT_DEGRADED_RETRY_AFTER};
 pub use error::*;
+use gmail::{is_gmail_domain, GmailError};
+pub use headless::HeadlessConfig;
+use headless::HeadlessError;
+pub use latency::HostLatencies;
+pub use pool::{SmtpPool, DEFAULT_SMTP_POOL_IDLE_TIMEOUT, DEFAULT_SMTP_POOL_KEEP_ALIVE};
+pub use proxy_protocol::ProxyProtocol;
+pub use webdriver_pool::{
+	WebDriverPool, DEFAULT_WEBDRIVER_IDLE_TIMEOUT, DEFAULT_WEBDRIVER_MAX_SESSIONS,
+};
 
 pub use self::{
 	gmail::is_gmail,
@@ -68,7 +90,7 @@ pub enum VerifMethod {
 }
 
 /// Details that we gathered from connecting to this email via SMTP
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct SmtpDetails {
 	/// Are we able to connect to the SMTP server?
 	pub can_connect_smtp: bool,
@@ -76,16 +98,199 @@ pub struct SmtpDetails {
 	pub has_full_inbox: bool,
 	/// Does this domain have a catch-all email address?
 	pub is_catch_all: bool,
+	/// The share of random addresses the server accepted in the catch-all
+	/// check, from 0 to 1. Only set if the input's `catch_all_probes` is
+	/// more than 1.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub catch_all_confidence: Option<f32>,
 	/// Can we send an email to this address?
 	pub is_deliverable: bool,
 	/// Is the email blocked or disabled by the provider?
 	pub is_disabled: bool,
+	/// The enhanced status code (RFC 3463) of the server's reply to
+	/// `RCPT TO` for the email, e.g. "5.1.1" for a mailbox which doesn't
+	/// exist. Only set if the server sent one.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub enhanced_status: Option<String>,
+	/// The server's negative reply to `RCPT TO` for the email, normalized
+	/// to group similar replies, see `SmtpError::fingerprint`. Only set if
+	/// the server rejected the email.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fingerprint: Option<String>,
+	/// Why the server rejected the email at `RCPT TO`: its account is
+	/// disabled (`AccountDisabled`), or its mailbox never existed
+	/// (`NonExistent`). Not set if the server accepted the email, or if its
+	/// inbox is full.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub description: Option<SmtpErrorDesc>,
+	/// Does the server accept every recipient at `RCPT TO`, so that it can
+	/// only reject the message after `DATA`, which we never send? Set if the
+	/// domain is a catch-all, and the server also accepts an address at a
+	/// `.invalid` domain, which can't exist: unlike a regular catch-all, it
+	/// doesn't even check that it hosts the recipient's domain. Only probed
+	/// with the input's `probe_postmaster`, and only serialized if true.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub accepts_at_rcpt_only: bool,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct SmtpDebug {
 	/// The verification method used for the email.
 	pub verif_method: VerifMethod,
+	/// Whether the domain of `from_email` passes SPF for the IP we connected
+	/// from. Only set if `check_from_spf` is set in the input, and we could
+	/// tell.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub from_spf_aligned: Option<bool>,
+	/// The result on each MX host tried, in order. Only set if
+	/// `smtp_per_host_debug` is set in the input.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub per_host: Option<Vec<HostSmtpResult>>,
+	/// Whether the server rejected `postmaster@domain`, which every domain
+	/// must accept: if it does, it validates recipients against its
+	/// mailboxes, so its rejections are trusted. Only set if
+	/// `probe_postmaster` is set in the input, and we could tell.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub honest_responder: Option<bool>,
+	/// The SMTP commands we sent and the server's replies, prefixed with
+	/// "C: " and "S: ", across all the attempts and MX hosts. Only set if
+	/// `smtp_transcript` is set in the input.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub transcript: Option<Vec<String>>,
+	/// Number of times we retried the SMTP verification, with a new
+	/// connection, across all the MX hosts. At most the input's `retries`
+	/// minus one on each host.
+	#[serde(default)]
+	pub retries_used: usize,
+	/// The selectors which matched the page elements of a headless
+	/// verification, in the order they were used, see `HeadlessConfig`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub headless_selectors: Option<Vec<String>>,
+	/// The email we sent the `RCPT TO` for, in SMTP verifications: the
+	/// input's email, without its `+tag` on plus-addressing providers if
+	/// `strip_plus_tag_before_smtp` is set in the input.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rcpt_to: Option<String>,
+	/// The result of each method, the provider's one first, then SMTP. Only
+	/// set if `cross_check` is set in the input, and the provider's email
+	/// is verified with another method than SMTP.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cross_check: Option<Vec<MethodResult>>,
+	/// The result of the sub-addressing probe, moved to the output's
+	/// `MiscDetails::supports_subaddressing` by `check_email`.
+	#[serde(skip)]
+	pub supports_subaddressing: Option<bool>,
+}
+
+/// The result of the SMTP verification on one MX host.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HostSmtpResult {
+	/// The MX host we verified the email on.
+	pub host: String,
+	/// The SMTP details, if the verification on this host succeeded.
+	pub details: Option<SmtpDetails>,
+	/// The error, if the verification on this host failed, serialized like
+	/// the output's SMTP error.
+	pub error: Option<serde_json::Value>,
+	/// Why we moved on to the next MX host, if we did.
+	pub moved_on: Option<String>,
+}
+
+/// The result of one verification method, see `SmtpDebug::cross_check`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MethodResult {
+	/// The method: "api", "headless" or "smtp".
+	pub method: String,
+	/// The SMTP details, if the verification with this method succeeded.
+	pub details: Option<SmtpDetails>,
+	/// The error, if the verification with this method failed.
+	pub error: Option<serde_json::Value>,
+}
+
+/// Get all email details we can from one `EmailAddress`, trying these MX
+/// hosts in order: we only move on to the next host if the current one
+/// refuses to relay the email, or fails with a transient error and the
+/// input's `smtp_mx_fallback` is set. Returns the result on the last host
+/// tried.
+///
+/// # Panics
+///
+/// Panics if `hosts` is empty.
+pub async fn check_smtp_on_hosts(
+	to_email: &EmailAddress,
+	hosts: &[&Name],
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> (Result<SmtpDetails, SmtpError>, SmtpDebug) {
+	let mut per_host = vec![];
+	let mut transcript = vec![];
+	let mut retries_used = 0;
+	let mut hosts = hosts.iter().peekable();
+	loop {
+		let host = hosts.next().expect("There's at least one MX host. qed.");
+		let (result, mut smtp_debug) =
+			check_smtp(to_email, host, port, domain, input, config).await;
+		transcript.extend(smtp_debug.transcript.take().unwrap_or_default());
+		retries_used += smtp_debug.retries_used;
+
+		let moved_on = match (&result, hosts.peek()) {
+			(Err(SmtpError::RelayDenied(_)), Some(_)) => Some("Relay denied"),
+			(Err(err), Some(_)) if input.smtp_mx_fallback => parser::transient_reason(err),
+			_ => None,
+		};
+		if input.smtp_per_host_debug {
+			per_host.push(HostSmtpResult {
+				host: host.to_string(),
+				details: result.as_ref().ok().cloned(),
+				error: result
+					.as_ref()
+					.err()
+					.and_then(|err| serde_json::to_value(err).ok()),
+				moved_on: moved_on.map(String::from),
+			});
+		}
+
+		match moved_on {
+			Some(reason) => log::debug!(
+				target: LOG_TARGET,
+				"[email={}] {} on [host={}], trying the next MX host",
+				input.to_email,
+				reason,
+				host
+			),
+			None => {
+				if input.smtp_per_host_debug {
+					smtp_debug.per_host = Some(per_host);
+				}
+				if input.smtp_transcript {
+					smtp_debug.transcript = Some(transcript);
+				}
+				smtp_debug.retries_used = retries_used;
+				return (result, smtp_debug);
+			}
+		}
+	}
+}
+
+/// Split the result of a headless verification into the SMTP result and its
+/// debug details.
+fn from_headless(
+	result: Result<(SmtpDetails, Vec<String>), HeadlessError>,
+) -> (Result<SmtpDetails, SmtpError>, SmtpDebug) {
+	let (result, headless_selectors) = match result {
+		Ok((details, selectors)) => (Ok(details), Some(selectors)),
+		Err(err) => (Err(err.into()), None),
+	};
+	(
+		result,
+		SmtpDebug {
+			verif_method: VerifMethod::Headless,
+			headless_selectors,
+			..Default::default()
+		},
+	)
 }
 
 /// Get all email details we can from one single `EmailAddress`, without
@@ -99,74 +304,221 @@ pub async fn check_smtp(
 	config: &ReacherConfig,
 ) -> (Result<SmtpDetails, SmtpError>, SmtpDebug) {
 	let host_str = host.to_string();
+
+	// The rest of the verification goes through the provider's proxy, if any.
+	let routed_input;
+	let input = match input
+		.provider_proxies
+		.get(&Provider::from_mx_host(&host_str))
+	{
+		Some(proxy) => {
+			routed_input = CheckEmailInput {
+				proxy: Some(proxy.clone()),
+				..input.clone()
+			};
+			&routed_input
+		}
+		_ => input,
+	};
+
+	let primary = check_provider_method(to_email, &host_str, domain, input, config).await;
+	match primary {
+		Some(primary) if input.cross_check => {
+			let smtp = check_smtp_via_smtp(to_email, host_str, port, domain, input, config).await;
+			cross_check(primary, smtp)
+		}
+		Some(primary) => primary,
+		None => check_smtp_via_smtp(to_email, host_str, port, domain, input, config).await,
+	}
+}
+
+/// Reconcile the results of the provider's method and of SMTP: the
+/// provider's one is kept if both succeeded and agree on the deliverability,
+/// or if both failed. Otherwise, it's a `MethodsDisagree` error.
+fn cross_check(
+	(primary, mut primary_debug): (Result<SmtpDetails, SmtpError>, SmtpDebug),
+	(smtp, smtp_debug): (Result<SmtpDetails, SmtpError>, SmtpDebug),
+) -> (Result<SmtpDetails, SmtpError>, SmtpDebug) {
+	let method = match primary_debug.verif_method {
+		VerifMethod::Api => "api",
+		VerifMethod::Headless => "headless",
+		VerifMethod::Smtp(_) => "smtp",
+		VerifMethod::Skipped => "skipped",
+	};
+	let outcome = |result: &Result<SmtpDetails, SmtpError>| match result {
+		Ok(details) if details.is_deliverable => "deliverable",
+		Ok(_) => "not deliverable",
+		Err(_) => "error",
+	};
+	let (primary_outcome, smtp_outcome) = (outcome(&primary), outcome(&smtp));
+	let to_result = |method: &str, result: &Result<SmtpDetails, SmtpError>| MethodResult {
+		method: method.into(),
+		details: result.as_ref().ok().cloned(),
+		error: result
+			.as_ref()
+			.err()
+			.and_then(|err| serde_json::to_value(err).ok()),
+	};
+
+	primary_debug.cross_check = Some(vec![to_result(method, &primary), to_result("smtp", &smtp)]);
+	primary_debug.transcript = smtp_debug.transcript;
+	primary_debug.retries_used = smtp_debug.retries_used;
+	primary_debug.rcpt_to = smtp_debug.rcpt_to;
+
+	let result = if primary_outcome == smtp_outcome {
+		primary
+	} else {
+		Err(SmtpError::MethodsDisagree(format!(
+			"{}: {}, smtp: {}",
+			method, primary_outcome, smtp_outcome
+		)))
+	};
+	(result, primary_debug)
+}
+
+/// Verify the email with the method selected in the input for the MX host's
+/// provider, e.g. Yahoo's API. None if it's SMTP.
+async fn check_provider_method(
+	to_email: &EmailAddress,
+	host_str: &str,
+	domain: &str,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Option<(Result<SmtpDetails, SmtpError>, SmtpDebug)> {
 	let to_email_str = to_email.to_string();
+	let api_debug = || SmtpDebug {
+		verif_method: VerifMethod::Api,
+		..Default::default()
+	};
 
-	if is_hotmail_b2c(&host_str) {
+	if is_hotmail_b2c(host_str) {
 		if let HotmailB2CVerifMethod::Headless = &input.hotmailb2c_verif_method {
-			return (
-				outlook::headless::check_password_recovery(&to_email_str, &config.webdriver_addr)
-					.await
-					.map_err(Into::into),
-				SmtpDebug {
-					verif_method: VerifMethod::Headless,
-				},
-			);
+			return Some(from_headless(
+				outlook::headless::check_password_recovery(
+					&to_email_str,
+					&config.webdriver_addr,
+					&config.webdriver_pool,
+					&config.headless,
+				)
+				.await,
+			));
 		}
-	} else if is_gmail(&host_str) {
-		if let GmailVerifMethod::Api = &input.gmail_verif_method {
-			return (
-				gmail::check_gmail_via_api(to_email, input)
-					.await
-					.map_err(Into::into),
-				SmtpDebug {
-					verif_method: VerifMethod::Api,
-				},
-			);
+	} else if is_gmail(host_str) {
+		// Google Suite domains also have Google MX hosts, but Gmail's API
+		// only knows about Gmail's own addresses.
+		if matches!(input.gmail_verif_method, GmailVerifMethod::Api) && is_gmail_domain(domain) {
+			match gmail::check_gmail_via_api(to_email, input, config).await {
+				// Fall back to SMTP if Gmail rate-limited us.
+				Err(GmailError::RateLimited) => {
+					log::debug!(
+						target: LOG_TARGET,
+						"[email={}] Gmail API rate-limited, falling back to SMTP",
+						to_email
+					);
+				}
+				result => return Some((result.map_err(Into::into), api_debug())),
+			}
 		}
-	} else if is_yahoo(&host_str) {
+	} else if is_yahoo(host_str) {
 		match &input.yahoo_verif_method {
 			YahooVerifMethod::Api => {
-				return (
-					yahoo::check_api(&to_email_str, input)
-						.await
-						.map_err(Into::into),
-					SmtpDebug {
-						verif_method: VerifMethod::Api,
-					},
-				);
+				let result = yahoo::check_api(&to_email_str, input, config).await;
+				return Some((result.map_err(Into::into), api_debug()));
 			}
 			YahooVerifMethod::Headless => {
-				return (
-					yahoo::check_headless(&to_email_str, &config.webdriver_addr)
-						.await
-						.map_err(Into::into),
-					SmtpDebug {
-						verif_method: VerifMethod::Headless,
-					},
-				);
+				return Some(from_headless(
+					yahoo::check_headless(
+						&to_email_str,
+						&config.webdriver_addr,
+						&config.webdriver_pool,
+						&config.headless,
+					)
+					.await,
+				));
 			}
 			_ => {} // For everything else, we use SMTP
 		}
 	}
 
+	None
+}
+
+/// Verify the email via SMTP on the MX host.
+async fn check_smtp_via_smtp(
+	to_email: &EmailAddress,
+	host_str: String,
+	port: u16,
+	domain: &str,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> (Result<SmtpDetails, SmtpError>, SmtpDebug) {
+	let from_spf_aligned = if input.check_from_spf && input.unix_socket.is_none() {
+		spf::from_spf_aligned(&host_str, port, input, config).await
+	} else {
+		None
+	};
+
+	let _permit = config.limiter.acquire_host(&host_str).await;
+	let mut retries_used = 0;
+	let fut = check_smtp_with_retry(
+		to_email,
+		&host_str,
+		port,
+		domain,
+		input,
+		config,
+		input.retries,
+		&mut retries_used,
+	);
+	let (result, transcript) = if input.smtp_transcript {
+		let (result, transcript) = transcript::record(fut).await;
+		(result, Some(transcript))
+	} else {
+		(fut.await, None)
+	};
+	let probes = result
+		.as_ref()
+		.map(|(_, probes)| *probes)
+		.unwrap_or_default();
+	let rcpt_to = rcpt_email(to_email, &host_str, domain, input).to_string();
+
 	(
-		check_smtp_with_retry(to_email, &host_str, port, domain, input, input.retries).await,
+		result.map(|(details, _)| details),
 		SmtpDebug {
 			verif_method: VerifMethod::Smtp(SmtpConnection {
 				host: host_str,
 				port,
-				used_proxy: input.proxy.is_some(),
+				used_proxy: input.proxy.is_some() && input.unix_socket.is_none(),
 			}),
+			from_spf_aligned,
+			honest_responder: probes.honest_responder,
+			supports_subaddressing: probes.supports_subaddressing,
+			transcript,
+			retries_used,
+			rcpt_to: Some(rcpt_to),
+			..Default::default()
 		},
 	)
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{check_smtp, SmtpConnection, SmtpError};
-	use crate::{config::ReacherConfig, CheckEmailInputBuilder};
-	use async_smtp::{smtp::error::Error, EmailAddress};
+	use super::{
+		check_smtp, check_smtp_on_hosts, cross_check, SmtpConnection, SmtpDebug, SmtpDetails,
+		SmtpError, VerifMethod,
+	};
+	use crate::misc::MiscDetails;
+	use crate::syntax::EmailAddress;
+	use crate::util::mock_smtp::{accept_all, MockSmtpServer};
+	use crate::{
+		config::ReacherConfig, CheckEmailInput, CheckEmailInputBuilder, CheckEmailInputProxy,
+		GmailVerifMethod, HotmailB2CVerifMethod, Provider, Reachable,
+	};
+	use async_smtp::smtp::error::Error;
 	use hickory_proto::rr::Name;
+	use std::collections::HashMap;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
 	use std::{str::FromStr, time::Duration};
 	use tokio::runtime::Runtime;
 
@@ -205,4 +557,326 @@ mod tests {
 			_ => panic!("check_smtp did not time out"),
 		}
 	}
+
+	#[tokio::test]
+	async fn should_move_on_to_next_host_on_transient_error() {
+		// Both servers listen on the same port, on two loopback IPs.
+		let primary = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				"451 4.7.1 Greylisted, try again later\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let backup = MockSmtpServer::start_on(
+			format!("127.0.0.2:{}", primary.port()).parse().unwrap(),
+			accept_all,
+		)
+		.await;
+
+		let to_email = EmailAddress::from_str("foo@example.com").unwrap();
+		let primary_host = Name::from_str("127.0.0.1").unwrap();
+		let backup_host = Name::from_str("127.0.0.2").unwrap();
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_port(primary.port())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.smtp_mx_fallback(true)
+			.smtp_per_host_debug(true)
+			.build()
+			.unwrap();
+
+		let (res, smtp_debug) = check_smtp_on_hosts(
+			&to_email,
+			&[&primary_host, &backup_host],
+			primary.port(),
+			"example.com",
+			&input,
+			&ReacherConfig::default(),
+		)
+		.await;
+
+		assert!(res.unwrap().is_deliverable);
+		assert!(!backup.commands().is_empty());
+		let per_host = smtp_debug.per_host.unwrap();
+		assert_eq!(per_host.len(), 2);
+		assert_eq!(per_host[0].host, "127.0.0.1");
+		assert!(per_host[0].details.is_none());
+		assert_eq!(per_host[0].error.as_ref().unwrap()["type"], "SmtpError");
+		assert_eq!(per_host[0].moved_on.as_deref(), Some("Transient reply"));
+		assert_eq!(per_host[1].host, "127.0.0.2");
+		assert!(per_host[1].details.as_ref().unwrap().is_deliverable);
+		assert!(per_host[1].moved_on.is_none());
+	}
+
+	#[tokio::test]
+	async fn should_move_on_to_next_host_on_relay_denied() {
+		let primary = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO") {
+				"554 5.7.1 <foo@example.com>: Relay access denied\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let backup = MockSmtpServer::start_on(
+			format!("127.0.0.2:{}", primary.port()).parse().unwrap(),
+			|command| {
+				if command.starts_with("RCPT TO") && !command.starts_with("RCPT TO:<foo@") {
+					"550 5.1.1 User unknown\r\n".into()
+				} else {
+					accept_all(command)
+				}
+			},
+		)
+		.await;
+
+		let to_email = EmailAddress::from_str("foo@example.com").unwrap();
+		let primary_host = Name::from_str("127.0.0.1").unwrap();
+		let backup_host = Name::from_str("127.0.0.2").unwrap();
+		// No `smtp_mx_fallback`: relay denied hosts are always skipped.
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@example.com".into())
+			.smtp_port(primary.port())
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.smtp_per_host_debug(true)
+			.build()
+			.unwrap();
+
+		let (res, smtp_debug) = check_smtp_on_hosts(
+			&to_email,
+			&[&primary_host, &backup_host],
+			primary.port(),
+			"example.com",
+			&input,
+			&ReacherConfig::default(),
+		)
+		.await;
+
+		let details = res.unwrap();
+		assert!(details.is_deliverable);
+		assert!(!details.is_catch_all);
+		assert!(!backup.commands().is_empty());
+		let per_host = smtp_debug.per_host.unwrap();
+		assert_eq!(per_host.len(), 2);
+		assert_eq!(per_host[0].error.as_ref().unwrap()["type"], "RelayDenied");
+		assert_eq!(per_host[0].moved_on.as_deref(), Some("Relay denied"));
+		assert!(per_host[1].moved_on.is_none());
+	}
+
+	#[tokio::test]
+	async fn should_route_to_the_provider_proxy() {
+		// A SOCKS5 proxy counting its connections, and closing them.
+		let start_proxy = || async {
+			let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let proxy = CheckEmailInputProxy {
+				host: "127.0.0.1".into(),
+				port: listener.local_addr().unwrap().port(),
+				username: None,
+				password: None,
+			};
+			let connections = Arc::new(AtomicUsize::new(0));
+			let counter = connections.clone();
+			tokio::spawn(async move {
+				while let Ok((stream, _)) = listener.accept().await {
+					counter.fetch_add(1, Ordering::SeqCst);
+					drop(stream);
+				}
+			});
+			(proxy, connections)
+		};
+		let (default_proxy, default_connections) = start_proxy().await;
+		let (hotmail_proxy, hotmail_connections) = start_proxy().await;
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@hotmail.com".into())
+			.hotmailb2c_verif_method(HotmailB2CVerifMethod::Smtp)
+			.proxy(Some(default_proxy))
+			.provider_proxies(HashMap::from([(Provider::HotmailB2c, hotmail_proxy)]))
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		let check = |email: &'static str, host: &'static str| {
+			let (input, config) = (&input, &config);
+			async move {
+				let to_email = EmailAddress::from_str(email).unwrap();
+				let host = Name::from_str(host).unwrap();
+				let domain = email.split('@').nth(1).unwrap();
+				check_smtp(&to_email, &host, 25, domain, input, config).await
+			}
+		};
+
+		let (res, _) = check("foo@hotmail.com", "eur.olc.protection.outlook.com.").await;
+		assert!(res.is_err());
+		assert!(hotmail_connections.load(Ordering::SeqCst) > 0);
+		assert_eq!(default_connections.load(Ordering::SeqCst), 0);
+
+		let (res, _) = check("foo@example.com", "mx.example.com.").await;
+		assert!(res.is_err());
+		assert!(default_connections.load(Ordering::SeqCst) > 0);
+	}
+
+	#[tokio::test]
+	async fn should_verify_gmail_via_api() {
+		// A SOCKS5 proxy closing all connections, so that neither the API nor
+		// SMTP reach Google.
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let proxy = CheckEmailInputProxy {
+			host: "127.0.0.1".into(),
+			port: listener.local_addr().unwrap().port(),
+			username: None,
+			password: None,
+		};
+		tokio::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				drop(stream);
+			}
+		});
+		let input = CheckEmailInputBuilder::default()
+			.to_email("foo@gmail.com".into())
+			.gmail_verif_method(GmailVerifMethod::Api)
+			.proxy(Some(proxy))
+			.smtp_timeout(Some(Duration::from_secs(5)))
+			.build()
+			.unwrap();
+		let config = ReacherConfig::default();
+		let host = Name::from_str("gmail-smtp-in.l.google.com.").unwrap();
+
+		let to_email = EmailAddress::from_str("foo@gmail.com").unwrap();
+		let (res, smtp_debug) =
+			check_smtp(&to_email, &host, 25, "gmail.com", &input, &config).await;
+		assert_eq!(smtp_debug.verif_method, VerifMethod::Api);
+		assert!(matches!(res, Err(SmtpError::GmailError(_))), "{:?}", res);
+
+		// Google Suite domains are verified via SMTP.
+		let to_email = EmailAddress::from_str("foo@example.com").unwrap();
+		let (_, smtp_debug) =
+			check_smtp(&to_email, &host, 25, "example.com", &input, &config).await;
+		assert!(matches!(smtp_debug.verif_method, VerifMethod::Smtp(_)));
+
+		// When cross-checking, SMTP is tried too. Both fail here.
+		let input = CheckEmailInput {
+			cross_check: true,
+			..input
+		};
+		let to_email = EmailAddress::from_str("foo@gmail.com").unwrap();
+		let (res, smtp_debug) =
+			check_smtp(&to_email, &host, 25, "gmail.com", &input, &config).await;
+		assert_eq!(smtp_debug.verif_method, VerifMethod::Api);
+		assert!(matches!(res, Err(SmtpError::GmailError(_))), "{:?}", res);
+		let methods = smtp_debug
+			.cross_check
+			.unwrap()
+			.into_iter()
+			.map(|result| result.method)
+			.collect::<Vec<_>>();
+		assert_eq!(methods, vec!["api", "smtp"]);
+	}
+
+	#[test]
+	fn should_be_unknown_if_methods_disagree() {
+		let details = |is_deliverable| SmtpDetails {
+			can_connect_smtp: true,
+			is_deliverable,
+			..Default::default()
+		};
+		let headless = |is_deliverable| {
+			(
+				Ok(details(is_deliverable)),
+				SmtpDebug {
+					verif_method: VerifMethod::Headless,
+					..Default::default()
+				},
+			)
+		};
+		let smtp = |is_deliverable| {
+			(
+				Ok(details(is_deliverable)),
+				SmtpDebug {
+					verif_method: VerifMethod::Smtp(SmtpConnection::default()),
+					rcpt_to: Some("foo@yahoo.com".into()),
+					..Default::default()
+				},
+			)
+		};
+
+		let (res, smtp_debug) = cross_check(headless(true), smtp(false));
+		match &res {
+			Err(SmtpError::MethodsDisagree(outcomes)) => {
+				assert_eq!(outcomes, "headless: deliverable, smtp: not deliverable")
+			}
+			_ => panic!("{:?}", res),
+		}
+		assert_eq!(
+			crate::calculate_reachable(&MiscDetails::default(), &res),
+			Reachable::Unknown
+		);
+		assert_eq!(smtp_debug.verif_method, VerifMethod::Headless);
+		assert_eq!(smtp_debug.rcpt_to.as_deref(), Some("foo@yahoo.com"));
+		let results = smtp_debug.cross_check.unwrap();
+		assert_eq!(results.len(), 2);
+		assert_eq!(results[0].method, "headless");
+		assert!(results[0].details.as_ref().unwrap().is_deliverable);
+		assert_eq!(results[1].method, "smtp");
+		assert!(!results[1].details.as_ref().unwrap().is_deliverable);
+
+		// If both agree, the provider's result is kept.
+		let (res, _) = cross_check(headless(true), smtp(true));
+		assert!(res.unwrap().is_deliverable);
+	}
+
+	#[tokio::test]
+	async fn should_strip_plus_tag_before_smtp_if_set() {
+		// Only the "foo" mailbox exists, with or without a +tag.
+		let server = MockSmtpServer::start(|command| {
+			if command.starts_with("RCPT TO:<foo") {
+				"250 OK\r\n".into()
+			} else if command.starts_with("RCPT TO") {
+				"550 5.1.1 The email account that you tried to reach does not exist\r\n".into()
+			} else {
+				accept_all(command)
+			}
+		})
+		.await;
+		let config = ReacherConfig::default();
+		let host = Name::from_str("127.0.0.1").unwrap();
+		let check = |email: &'static str, strip: bool| {
+			let (server, config, host) = (&server, &config, &host);
+			async move {
+				let input = CheckEmailInputBuilder::default()
+					.to_email(email.into())
+					.strip_plus_tag_before_smtp(strip)
+					.smtp_timeout(Some(Duration::from_secs(5)))
+					.build()
+					.unwrap();
+				let to_email = EmailAddress::from_str(email).unwrap();
+				let domain = email.split('@').nth(1).unwrap();
+				let (res, smtp_debug) =
+					check_smtp(&to_email, host, server.port(), domain, &input, config).await;
+				assert!(res.unwrap().is_deliverable);
+				let last_rcpt = server
+					.commands()
+					.into_iter()
+					.rfind(|command| command.starts_with("RCPT TO"))
+					.unwrap();
+				(smtp_debug.rcpt_to.unwrap(), last_rcpt)
+			}
+		};
+
+		// Gmail supports plus-addressing: the base mailbox is verified.
+		let (rcpt_to, last_rcpt) = check("foo+news@gmail.com", true).await;
+		assert_eq!(rcpt_to, "foo@gmail.com");
+		assert_eq!(last_rcpt, "RCPT TO:<foo@gmail.com>");
+
+		// Unless the literal email is asked for.
+		let (rcpt_to, last_rcpt) = check("foo+news@gmail.com", false).await;
+		assert_eq!(rcpt_to, "foo+news@gmail.com");
+		assert_eq!(last_rcpt, "RCPT TO:<foo+news@gmail.com>");
+
+		// Other providers always get the literal email.
+		let (rcpt_to, last_rcpt) = check("foo+news@example.com", true).await;
+		assert_eq!(rcpt_to, "foo+news@example.com");
+		assert_eq!(last_rcpt, "RCPT TO:<foo+news@example.com>");
+	}
 }
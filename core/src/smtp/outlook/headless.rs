@@ -21,8 +21,8 @@ use futures::{future::select_ok, Future, TryFutureExt};
 
 use crate::{
 	smtp::{
-		headless::{create_headless_client, HeadlessError},
-		SmtpDetails,
+		headless::{find_element, HeadlessConfig, HeadlessError},
+		SmtpDetails, WebDriverPool,
 	},
 	LOG_TARGET,
 };
@@ -30,94 +30,106 @@ use crate::{
 /// Check if a Hotmail/Outlook email exists by connecting to the password
 /// recovery page https://account.live.com/password/reset using a headless
 /// browser. Make sure you have a WebDriver server running locally before
-/// running this, or this will error.
+/// running this, or this will error. The WebDriver session is taken from the
+/// pool, and the page elements are found with the `headless` selectors.
+///
+/// Returns the details, with the selectors which matched the page elements.
 pub async fn check_password_recovery(
 	to_email: &str,
 	webdriver: &str,
-) -> Result<SmtpDetails, HeadlessError> {
+	pool: &WebDriverPool,
+	headless: &HeadlessConfig,
+) -> Result<(SmtpDetails, Vec<String>), HeadlessError> {
 	let to_email = to_email.to_string();
+	let headless = headless.clone();
 	log::debug!(
 		target: LOG_TARGET,
 		"[email={}] Using Hotmail password recovery in headless navigator",
 		to_email,
 	);
 
-	let c = create_headless_client(webdriver).await?;
-
-	// Navigate to Microsoft password recovery page.
-	c.goto("https://account.live.com/password/reset").await?;
-
-	// Wait for network/javascript/dom to make the input-box available
-	// and click it.
-	let input = c.wait().for_element(Locator::Id("iSigninName")).await?;
-	// Sometimes I get "input ... is not reachable by keyboard". Addind this
-	// small sleep helps.
-	sleep(Duration::from_millis(200));
-	input.send_keys(to_email.as_str()).await?;
-
-	// Click on "Next"
-	c.find(Locator::Id("resetPwdHipAction"))
-		.await?
-		.click()
-		.await?;
-
-	// "Try entering your Microsoft account again. We don't recognise this one." means the account does not exist.
-	let f1 = c
-		.wait()
-		.for_element(Locator::Id("pMemberNameErr"))
-		.and_then(|_| async { Ok(false) });
-	// "Try entering your Microsoft account again. We don't recognise this one."
-	let f2 = c
-		.wait()
-		.for_element(Locator::Id("iSigninNameError"))
-		.and_then(|_| async { Ok(false) });
-	// "We need to verify your identity" means that the account exists.
-	let f3 = c
-		.wait()
-		.for_element(Locator::Id("iSelectProofTitle"))
-		.and_then(|_| async { Ok(true) });
-	// "Enter the code generated by your authenticator app..."
-	let f4 = c
-		.wait()
-		.for_element(Locator::Id("iEnterVerification"))
-		.and_then(|_| async { Ok(true) });
-
-	let vec = vec![
-		Box::pin(f1) as Pin<Box<dyn Future<Output = Result<bool, CmdError>> + Send>>,
-		Box::pin(f2),
-		Box::pin(f3),
-		Box::pin(f4),
-	];
-	let (is_deliverable, _) = select_ok(vec).await?;
-
-	if is_deliverable {
-		log::debug!(
-			target: LOG_TARGET,
-			"[email={}] Did not find error message in password recovery, email exists",
-			to_email,
-		);
-	} else {
-		log::debug!(
-			target: LOG_TARGET,
-			"[email={}] Found error message in password recovery, email does not exist",
-			to_email,
-		);
-	}
-
-	c.close().await?;
+	pool.run(webdriver, |c| async move {
+		// Navigate to Microsoft password recovery page.
+		c.goto("https://account.live.com/password/reset").await?;
+
+		// Wait for network/javascript/dom to make the input-box available
+		// and click it.
+		let (input, input_selector) = find_element(&c, &headless.hotmail_email_input).await?;
+		// Sometimes I get "input ... is not reachable by keyboard". Addind this
+		// small sleep helps.
+		sleep(Duration::from_millis(200));
+		input.send_keys(to_email.as_str()).await?;
+
+		// Click on "Next"
+		let (submit, submit_selector) = find_element(&c, &headless.hotmail_submit).await?;
+		submit.click().await?;
+
+		// "Try entering your Microsoft account again. We don't recognise this one." means the account does not exist.
+		let f1 = c
+			.wait()
+			.for_element(Locator::Id("pMemberNameErr"))
+			.and_then(|_| async { Ok(false) });
+		// "Try entering your Microsoft account again. We don't recognise this one."
+		let f2 = c
+			.wait()
+			.for_element(Locator::Id("iSigninNameError"))
+			.and_then(|_| async { Ok(false) });
+		// "We need to verify your identity" means that the account exists.
+		let f3 = c
+			.wait()
+			.for_element(Locator::Id("iSelectProofTitle"))
+			.and_then(|_| async { Ok(true) });
+		// "Enter the code generated by your authenticator app..."
+		let f4 = c
+			.wait()
+			.for_element(Locator::Id("iEnterVerification"))
+			.and_then(|_| async { Ok(true) });
+
+		let vec = vec![
+			Box::pin(f1) as Pin<Box<dyn Future<Output = Result<bool, CmdError>> + Send>>,
+			Box::pin(f2),
+			Box::pin(f3),
+			Box::pin(f4),
+		];
+		let (is_deliverable, _) = select_ok(vec).await?;
+
+		if is_deliverable {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Did not find error message in password recovery, email exists",
+				to_email,
+			);
+		} else {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Found error message in password recovery, email does not exist",
+				to_email,
+			);
+		}
 
-	Ok(SmtpDetails {
-		can_connect_smtp: true,
-		has_full_inbox: false,
-		is_catch_all: false,
-		is_deliverable,
-		is_disabled: false,
+		Ok((
+			SmtpDetails {
+				can_connect_smtp: true,
+				has_full_inbox: false,
+				is_catch_all: false,
+				catch_all_confidence: None,
+				enhanced_status: None,
+				fingerprint: None,
+				description: None,
+				accepts_at_rcpt_only: false,
+				is_deliverable,
+				is_disabled: false,
+			},
+			vec![input_selector, submit_selector],
+		))
 	})
+	.await
 }
 
 #[cfg(test)]
 mod tests {
 	use super::check_password_recovery;
+	use crate::smtp::{HeadlessConfig, WebDriverPool};
 	use futures::future::join;
 
 	// Ignoring this test as it requires a local process of WebDriver running on
@@ -127,19 +139,31 @@ mod tests {
 	#[tokio::test]
 	#[ignore = "Run a webdriver server locally to test this"]
 	async fn test_hotmail_address() {
+		let pool = WebDriverPool::default();
+		let headless = HeadlessConfig::default();
 		// Run 10 headless sessions with dummy emails.
 		// It should not error.
 		for _ in 0..10 {
 			// This email does not exist.
-			let res = check_password_recovery("test42134@hotmail.com", "http://localhost:9515")
-				.await
-				.unwrap();
+			let (res, _) = check_password_recovery(
+				"test42134@hotmail.com",
+				"http://localhost:9515",
+				&pool,
+				&headless,
+			)
+			.await
+			.unwrap();
 			assert!(!res.is_deliverable);
 
 			// This email does exist.
-			let res = check_password_recovery("test@hotmail.com", "http://localhost:9515")
-				.await
-				.unwrap();
+			let (res, _) = check_password_recovery(
+				"test@hotmail.com",
+				"http://localhost:9515",
+				&pool,
+				&headless,
+			)
+			.await
+			.unwrap();
 			assert!(res.is_deliverable);
 		}
 	}
@@ -151,9 +175,11 @@ mod tests {
 	#[tokio::test]
 	#[ignore = "Run a webdriver server locally to test this"]
 	async fn test_parallel() {
+		let pool = WebDriverPool::default();
+		let headless = HeadlessConfig::default();
 		// This email does not exist.
-		let f1 = check_password_recovery("foo@bar.baz", "http://localhost:9515");
-		let f2 = check_password_recovery("foo@bar.baz", "http://localhost:9515");
+		let f1 = check_password_recovery("foo@bar.baz", "http://localhost:9515", &pool, &headless);
+		let f2 = check_password_recovery("foo@bar.baz", "http://localhost:9515", &pool, &headless);
 
 		let f = join(f1, f2).await;
 		assert!(f.0.is_ok(), "{:?}", f);
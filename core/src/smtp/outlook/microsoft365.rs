@@ -14,11 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use async_smtp::EmailAddress;
+use crate::syntax::EmailAddress;
 use reqwest::Error as ReqwestError;
 use serde::Serialize;
 
 use crate::{
+	config::ReacherConfig,
 	smtp::{http_api::create_client, SmtpDetails},
 	util::ser_with_display::ser_with_display,
 	CheckEmailInput, LOG_TARGET,
@@ -66,10 +67,11 @@ fn get_onedrive_url(email_address: &str) -> String {
 pub async fn check_microsoft365_api(
 	to_email: &EmailAddress,
 	input: &CheckEmailInput,
+	config: &ReacherConfig,
 ) -> Result<Option<SmtpDetails>, Microsoft365Error> {
 	let url = get_onedrive_url(to_email.as_ref());
 
-	let response = create_client(input, "microsoft365")?
+	let response = create_client(input, config, "microsoft365")?
 		.head(url)
 		.send()
 		.await?;
@@ -17,7 +17,109 @@
 //! Parse the SMTP responses to get information about the email address.
 
 use super::error::SmtpError;
-use async_smtp::{smtp::error::Error as AsyncSmtpError, EmailAddress};
+use crate::syntax::EmailAddress;
+use async_smtp::smtp::{error::Error as AsyncSmtpError, response::Response};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io::ErrorKind;
+
+/// Parts of a reply's text which vary between otherwise identical replies,
+/// with their placeholder in the fingerprint. The order matters: e.g. the
+/// email addresses contain hostnames.
+static FINGERPRINT_PATTERNS: Lazy<Vec<(Regex, &'static str)>> = Lazy::new(|| {
+	[
+		(r"[^\s<>()\[\]@:;,]+@[^\s<>()\[\]@:;,]+", "<email>"),
+		(r"\b\d{1,3}(\.\d{1,3}){3}\b", "<ip>"),
+		(r"\b[0-9a-f]{1,4}(:[0-9a-f]{0,4}){2,7}\b", "<ip>"),
+		(r"\b([a-z0-9-]+\.)+[a-z]{2,}\b", "<host>"),
+		(r"\b[\w-]*\d[\w-]*\b", "<n>"),
+	]
+	.iter()
+	.map(|(pattern, placeholder)| {
+		(
+			Regex::new(pattern).expect("Correct regex. qed."),
+			*placeholder,
+		)
+	})
+	.collect()
+});
+
+/// Parse the enhanced status code (RFC 3463) at the start of the reply's
+/// text, e.g. "5.1.1" in "550 5.1.1 User unknown". It's made of a class
+/// (2, 4 or 5), a subject and a detail, and is more precise than the 3-digit
+/// reply code.
+pub fn enhanced_status(response: &Response) -> Option<String> {
+	let code = response.message.first()?.split_whitespace().next()?;
+	let code = code.trim_end_matches([':', ',', ';']);
+	let mut parts = code.split('.');
+	let class = parts.next()?;
+	let subject = parts.next()?;
+	let detail = parts.next()?;
+	let is_number =
+		|part: &str| (1..=3).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_digit());
+	if parts.next().is_some()
+		|| !matches!(class, "2" | "4" | "5")
+		|| !is_number(subject)
+		|| !is_number(detail)
+	{
+		return None;
+	}
+
+	Some(code.to_string())
+}
+
+/// Normalize the reply into a fingerprint shared by similar replies, to group
+/// the failures: the reply code, the enhanced status code (or "-") and the
+/// lowercase text, with the email addresses, IPs, hostnames and numbers
+/// replaced by placeholders, e.g. "550 5.1.1 <email>: recipient address
+/// rejected: user unknown in <host>".
+pub fn fingerprint(response: &Response) -> String {
+	let status = enhanced_status(response);
+	let mut text = response.message.join(" ").to_lowercase();
+	if let Some(status) = &status {
+		text = text.trim_start().trim_start_matches(status.as_str()).into();
+	}
+	for (pattern, placeholder) in FINGERPRINT_PATTERNS.iter() {
+		text = pattern.replace_all(&text, *placeholder).into_owned();
+	}
+	let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+	format!(
+		"{} {} {}",
+		response.code,
+		status.as_deref().unwrap_or("-"),
+		text
+	)
+	.trim_end()
+	.to_string()
+}
+
+/// What an enhanced status code says about the mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxStatus {
+	/// The mailbox doesn't exist.
+	NotFound,
+	/// The mailbox is disabled, and doesn't accept messages.
+	Disabled,
+	/// The mailbox is full.
+	Full,
+}
+
+/// Classify the enhanced status code of a negative reply to `RCPT TO`, or
+/// return None if it doesn't say anything definitive about the mailbox. Only
+/// permanent 5.x.x codes are definitive: 4.x.x codes are transient, e.g.
+/// Gmail replies 4.2.1 when it rate limits us.
+pub fn classify_enhanced_status(code: &str) -> Option<MailboxStatus> {
+	match code {
+		// Bad destination mailbox address, or the domain has a null MX.
+		"5.1.1" | "5.1.10" => Some(MailboxStatus::NotFound),
+		// Mailbox disabled, not accepting messages.
+		"5.2.1" => Some(MailboxStatus::Disabled),
+		// Mailbox full.
+		"5.2.2" => Some(MailboxStatus::Full),
+		_ => None,
+	}
+}
 
 /// is_invalid checks for SMTP responses meaning that the email is invalid,
 /// i.e. that the mailbox doesn't exist.
@@ -122,6 +224,12 @@ pub fn is_disabled_account(e: &str) -> bool {
  || e.contains("discontinued")
  //550 5.2.1 RACT MY.IP: Mailbox is inactive: <USER@hanmail.net><CRLF> (on hanmail.net)
  || e.contains("inactive")
+	// 550 5.2.1 User not active
+	|| e.contains("not active")
+	// 550 5.2.1 Account suspended
+	|| e.contains("suspended")
+	// 550 5.2.1 This account has been deactivated
+	|| e.contains("deactivated")
 }
 
 /// Check if the error is an IO "incomplete" error.
@@ -202,6 +310,46 @@ pub fn is_err_ip_blacklisted(e: &SmtpError) -> bool {
 	|| e.contains("not yet authorized")
 }
 
+/// Check if the server refused to relay the email, i.e. it doesn't handle
+/// the email's domain, e.g. because of a misrouted MX record. It says
+/// nothing about the mailbox.
+pub fn is_relay_denied(e: &str) -> bool {
+	// 554 5.7.1 <EMAIL>: Relay access denied
+	e.contains("relay access denied")
+	// 550 5.7.1 Relaying denied
+	|| e.contains("relaying denied")
+	// 550 relay not permitted
+	|| e.contains("relay not permitted")
+	// 550 Relaying not allowed
+	|| e.contains("relaying not allowed")
+	// 550 5.7.1 Unable to relay
+	|| e.contains("unable to relay")
+	// 550 we do not relay
+	|| e.contains("we do not relay")
+}
+
+/// Check if the server reset the connection during the SMTP dialog.
+pub fn is_err_connection_reset(e: &SmtpError) -> bool {
+	match e {
+		SmtpError::SmtpError(AsyncSmtpError::Io(err)) => matches!(
+			err.kind(),
+			ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+		),
+		_ => false,
+	}
+}
+
+/// Check if the error is transient, i.e. another MX host of the domain might
+/// give an answer, and return the reason.
+pub fn transient_reason(e: &SmtpError) -> Option<&'static str> {
+	match e {
+		SmtpError::SmtpError(AsyncSmtpError::Transient(_)) => Some("Transient reply"),
+		SmtpError::SmtpError(AsyncSmtpError::Io(_)) => Some("Connection error"),
+		SmtpError::SmtpError(AsyncSmtpError::Timeout(_)) => Some("Timeout"),
+		_ => None,
+	}
+}
+
 /// Check if the IP needs a reverse DNS.
 pub fn is_err_needs_rdns(e: &SmtpError) -> bool {
 	let e = match e {
@@ -226,12 +374,15 @@ pub fn is_err_needs_rdns(e: &SmtpError) -> bool {
 #[cfg(test)]
 mod tests {
 
-	use super::{is_err_ip_blacklisted, is_invalid};
+	use super::{
+		classify_enhanced_status, enhanced_status, fingerprint, is_disabled_account,
+		is_err_ip_blacklisted, is_invalid, is_relay_denied, MailboxStatus,
+	};
+	use crate::syntax::EmailAddress;
 	use crate::SmtpError::SmtpError;
 	use async_smtp::{
 		smtp::error::Error,
 		smtp::response::{Category, Code, Detail, Response, Severity},
-		EmailAddress,
 	};
 	use std::str::FromStr;
 
@@ -255,6 +406,19 @@ mod tests {
 		));
 	}
 
+	#[test]
+	fn test_is_relay_denied() {
+		assert!(is_relay_denied(
+			"permanent: 5.7.1 <foo@bar.baz>: relay access denied"
+		));
+		assert!(is_relay_denied("permanent: 5.7.1 unable to relay"));
+		assert!(!is_relay_denied("permanent: 5.1.1 user unknown"));
+		// The mailbox doesn't exist, see `is_invalid`.
+		assert!(!is_relay_denied(
+			"permanent: user not local or invalid address – relay denied"
+		));
+	}
+
 	#[test]
 	fn test_is_err_ip_blacklisted() {
 		let err = Error::Permanent(Response::new(
@@ -273,4 +437,118 @@ mod tests {
 
 		assert!(is_err_ip_blacklisted(&SmtpError(err)))
 	}
+
+	#[test]
+	fn should_tell_disabled_accounts_from_nonexistent_ones() {
+		let permanent = |message: &str| {
+			SmtpError(Error::Permanent(Response::new(
+				Code::new(
+					Severity::PermanentNegativeCompletion,
+					Category::MailSystem,
+					Detail::Zero,
+				),
+				vec![message.to_string()],
+			)))
+		};
+
+		let email = EmailAddress::from_str("foo@bar.baz").unwrap();
+		for message in [
+			"550 5.2.1 account disabled",
+			"550 5.2.1 <foo@bar.baz>: user not active",
+			"550 5.2.1 mailbox suspended",
+		] {
+			assert!(is_disabled_account(message), "{}", message);
+			assert!(!is_invalid(message, &email), "{}", message);
+		}
+
+		let message = "550 5.1.1 no such user here";
+		assert!(!is_disabled_account(message));
+		assert!(is_invalid(message, &email));
+
+		// The accounts are only classified from the replies to `RCPT TO`: a
+		// rejection of e.g. `MAIL FROM` is about our sender.
+		assert!(permanent("5.7.1 Sender account suspended")
+			.get_description()
+			.is_none());
+	}
+
+	#[test]
+	fn should_parse_enhanced_status() {
+		let response = |message: &str| {
+			Response::new(
+				Code::new(
+					Severity::PermanentNegativeCompletion,
+					Category::MailSystem,
+					Detail::Zero,
+				),
+				vec![message.to_string()],
+			)
+		};
+
+		assert_eq!(
+			enhanced_status(&response("5.1.1 No such user here")),
+			Some("5.1.1".into())
+		);
+		assert_eq!(
+			enhanced_status(&response("5.1.10 Recipient address has null MX")),
+			Some("5.1.10".into())
+		);
+		assert_eq!(
+			enhanced_status(&response("4.2.1: Mailbox disabled")),
+			Some("4.2.1".into())
+		);
+		assert_eq!(enhanced_status(&response("No such user here")), None);
+		assert_eq!(enhanced_status(&response("3.1.1 Not a class")), None);
+		assert_eq!(enhanced_status(&response("5.1 Too short")), None);
+		assert_eq!(enhanced_status(&response("1.2.3.4 An IP")), None);
+
+		assert_eq!(
+			classify_enhanced_status("5.1.1"),
+			Some(MailboxStatus::NotFound)
+		);
+		assert_eq!(
+			classify_enhanced_status("5.2.1"),
+			Some(MailboxStatus::Disabled)
+		);
+		assert_eq!(classify_enhanced_status("4.2.1"), None);
+		assert_eq!(classify_enhanced_status("4.2.2"), None);
+		assert_eq!(classify_enhanced_status("4.1.1"), None);
+	}
+
+	#[test]
+	fn should_fingerprint_similar_replies_the_same() {
+		let response = |message: &str| {
+			Response::new(
+				Code::new(
+					Severity::PermanentNegativeCompletion,
+					Category::MailSystem,
+					Detail::Zero,
+				),
+				vec![message.to_string()],
+			)
+		};
+
+		let first = fingerprint(&response(
+			"5.7.1 Service unavailable; Client host [203.0.113.7] blocked using zen.spamhaus.org",
+		));
+		let second = fingerprint(&response(
+			"5.7.1 Service unavailable; Client host [198.51.100.42] blocked using zen.spamhaus.org",
+		));
+		assert_eq!(first, second);
+		assert_eq!(
+			first,
+			"550 5.7.1 service unavailable; client host [<ip>] blocked using <host>"
+		);
+
+		assert_eq!(
+			fingerprint(&response(
+				"5.1.1 <foo@example.com>: Recipient address rejected: User unknown in mx3.example.com, id 4Xz9k2"
+			)),
+			"550 5.1.1 <<email>>: recipient address rejected: user unknown in <host>, id <n>"
+		);
+		assert_eq!(
+			fingerprint(&response("No such user here")),
+			"550 - no such user here"
+		);
+	}
 }
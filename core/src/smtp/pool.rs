@@ -0,0 +1,230 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pool of idle SMTP connections, by MX host, reused across verifications to
+//! skip the TCP and TLS handshakes of the next verifications on the same host.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_smtp::smtp::commands::{NoopCommand, RsetCommand};
+use tokio::task::JoinHandle;
+
+use super::transport::SmtpTransport;
+use crate::LOG_TARGET;
+
+/// Default duration after which an idle connection is closed. Servers must
+/// wait at least 5 minutes before closing an idle connection (RFC 5321), but
+/// many close them sooner.
+pub const DEFAULT_SMTP_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Default interval of the NOOP commands keeping the idle connections alive.
+pub const DEFAULT_SMTP_POOL_KEEP_ALIVE: Duration = Duration::from_secs(20);
+
+/// The "host:port" part of a pool key, see `connect::pool_key`.
+fn host(key: &str) -> &str {
+	key.split(' ').next().unwrap_or_default()
+}
+
+/// A connection which is not in use.
+struct IdleConnection {
+	transport: SmtpTransport,
+	/// Number of `RCPT TO` commands sent on the connection so far, see the
+	/// input's `max_rcpt_per_connection`.
+	rcpt_count: usize,
+	/// When we got the reply to the last `RCPT TO` on the connection, see
+	/// the input's `rcpt_pacing`.
+	last_rcpt: Option<Instant>,
+	/// When the connection was returned to the pool.
+	since: Instant,
+	/// When we last sent a command on the connection.
+	last_command: Instant,
+}
+
+/// Pool of idle SMTP connections, by MX host and connection settings. At most
+/// `max_idle_per_host` idle connections are kept for each of them, for at
+/// most `idle_timeout`, and a NOOP is sent on them every `keep_alive` so that
+/// the servers don't close them. It's meant to be shared between
+/// verifications, see `ReacherConfig`. Defaults to a disabled pool.
+///
+/// The connections keep the settings they were opened with, e.g. the
+/// timeout. Connections through a Unix domain socket or with the PROXY
+/// protocol are never pooled.
+pub struct SmtpPool {
+	/// Maximum number of idle connections per host, 0 disables the pool.
+	max_idle_per_host: usize,
+	idle_timeout: Duration,
+	keep_alive: Duration,
+	idle: Mutex<HashMap<String, Vec<IdleConnection>>>,
+}
+
+impl Default for SmtpPool {
+	fn default() -> Self {
+		Self::new(
+			0,
+			DEFAULT_SMTP_POOL_IDLE_TIMEOUT,
+			DEFAULT_SMTP_POOL_KEEP_ALIVE,
+		)
+	}
+}
+
+impl std::fmt::Debug for SmtpPool {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SmtpPool")
+			.field("max_idle_per_host", &self.max_idle_per_host)
+			.field("idle_timeout", &self.idle_timeout)
+			.field("keep_alive", &self.keep_alive)
+			.finish()
+	}
+}
+
+impl SmtpPool {
+	pub fn new(max_idle_per_host: usize, idle_timeout: Duration, keep_alive: Duration) -> Self {
+		Self {
+			max_idle_per_host,
+			idle_timeout,
+			keep_alive,
+			idle: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Whether connections are pooled at all.
+	pub(crate) fn is_enabled(&self) -> bool {
+		self.max_idle_per_host > 0
+	}
+
+	/// Send a NOOP on the idle connections every `keep_alive`, in the
+	/// background, until the pool is dropped. Without it, the NOOP is only
+	/// sent when a connection is taken from the pool.
+	pub fn spawn_keep_alive(self: &Arc<Self>) -> JoinHandle<()> {
+		let pool = Arc::downgrade(self);
+		let interval = self.keep_alive;
+		tokio::spawn(async move {
+			loop {
+				tokio::time::sleep(interval).await;
+				match pool.upgrade() {
+					Some(pool) => pool.keep_alive().await,
+					None => return,
+				}
+			}
+		})
+	}
+
+	/// Send a NOOP on the idle connections, and close the ones idle for
+	/// longer than `idle_timeout` or closed by the server. The connections
+	/// are taken out of the pool one at a time, so that the others can still
+	/// be taken by the verifications meanwhile.
+	pub async fn keep_alive(&self) {
+		let started = Instant::now();
+		while let Some((key, mut connection)) = self.take_quiet_since(started) {
+			// The connection is closed if it's expired, closed by the
+			// server, or if the pool got full for this key meanwhile.
+			let mut connection = if connection.since.elapsed() < self.idle_timeout
+				&& connection.transport.command(NoopCommand).await.is_ok()
+			{
+				connection.last_command = Instant::now();
+				match self.push_idle(&key, connection) {
+					Some(connection) => connection,
+					None => continue,
+				}
+			} else {
+				connection
+			};
+			log::debug!(target: LOG_TARGET, "Closing an idle SMTP connection to {}", host(&key));
+			let _ = connection.transport.close().await;
+		}
+	}
+
+	/// Take an idle connection which got no command since `instant`, if any.
+	fn take_quiet_since(&self, instant: Instant) -> Option<(String, IdleConnection)> {
+		let mut idle = self.idle.lock().expect("Lock is not poisoned. qed.");
+		idle.iter_mut().find_map(|(key, connections)| {
+			let index = connections
+				.iter()
+				.position(|connection| connection.last_command < instant)?;
+			Some((key.clone(), connections.remove(index)))
+		})
+	}
+
+	/// Add a connection to the pool, or give it back if the pool is full for
+	/// this key.
+	fn push_idle(&self, key: &str, connection: IdleConnection) -> Option<IdleConnection> {
+		let mut idle = self.idle.lock().expect("Lock is not poisoned. qed.");
+		let connections = idle.entry(key.to_string()).or_default();
+		if connections.len() < self.max_idle_per_host {
+			connections.push(connection);
+			None
+		} else {
+			Some(connection)
+		}
+	}
+
+	/// Take the most recently returned idle connection for this key, with
+	/// its number of `RCPT TO` so far and when the last one was answered.
+	/// A NOOP checks it's still open if it's been quiet for longer than
+	/// `keep_alive`. The connections idle for longer than `idle_timeout` are
+	/// closed.
+	pub(crate) async fn take_idle(
+		&self,
+		key: &str,
+	) -> Option<(SmtpTransport, usize, Option<Instant>)> {
+		loop {
+			let mut connection = {
+				let mut idle = self.idle.lock().expect("Lock is not poisoned. qed.");
+				idle.get_mut(key)?.pop()?
+			};
+			let is_open = connection.since.elapsed() < self.idle_timeout
+				&& (connection.last_command.elapsed() < self.keep_alive
+					|| connection.transport.command(NoopCommand).await.is_ok());
+			if is_open {
+				return Some((
+					connection.transport,
+					connection.rcpt_count,
+					connection.last_rcpt,
+				));
+			}
+			log::debug!(target: LOG_TARGET, "Closing an idle SMTP connection to {}", host(key));
+			let _ = connection.transport.close().await;
+		}
+	}
+
+	/// Return a connection to the pool, after a RSET so that the next
+	/// verification starts a fresh transaction. It's closed instead if the
+	/// RSET fails or the pool is full for this key.
+	pub(crate) async fn put_idle(
+		&self,
+		key: &str,
+		mut transport: SmtpTransport,
+		rcpt_count: usize,
+		last_rcpt: Option<Instant>,
+	) {
+		if transport.command(RsetCommand).await.is_ok() {
+			let connection = IdleConnection {
+				transport,
+				rcpt_count,
+				last_rcpt,
+				since: Instant::now(),
+				last_command: Instant::now(),
+			};
+			match self.push_idle(key, connection) {
+				Some(connection) => transport = connection.transport,
+				None => return,
+			}
+		}
+
+		let _ = transport.close().await;
+	}
+}
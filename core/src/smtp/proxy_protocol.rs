@@ -0,0 +1,153 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Support for the PROXY protocol (v1 and v2), for when the SMTP connections
+//! go through a TCP load balancer which requires it. See
+//! https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt.
+//!
+//! async-smtp opens the TCP connection itself, so we can't write the header
+//! on its stream directly. Instead, we connect to the SMTP server ourselves,
+//! write the header, and relay a local listener to this connection.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::AsyncWriteExt;
+
+use super::relay::{connect_tcp, relay, Relay};
+
+/// Version of the PROXY protocol header.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+	/// Human-readable header, e.g. "PROXY TCP4 1.2.3.4 5.6.7.8 1234 25".
+	V1,
+	/// Binary header.
+	V2,
+}
+
+/// Signature starting all the v2 headers.
+const V2_SIGNATURE: [u8; 12] = [
+	0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// Build the PROXY protocol header of a connection from `source` to
+/// `destination`.
+pub(crate) fn header(
+	version: ProxyProtocol,
+	source: SocketAddr,
+	destination: SocketAddr,
+) -> Vec<u8> {
+	match version {
+		ProxyProtocol::V1 => match (source.ip(), destination.ip()) {
+			(IpAddr::V4(src), IpAddr::V4(dst)) => format!(
+				"PROXY TCP4 {src} {dst} {} {}\r\n",
+				source.port(),
+				destination.port()
+			),
+			(IpAddr::V6(src), IpAddr::V6(dst)) => format!(
+				"PROXY TCP6 {src} {dst} {} {}\r\n",
+				source.port(),
+				destination.port()
+			),
+			// Mixed address families can't be expressed.
+			_ => "PROXY UNKNOWN\r\n".into(),
+		}
+		.into_bytes(),
+		ProxyProtocol::V2 => {
+			let mut header = V2_SIGNATURE.to_vec();
+			let addresses = match (source.ip(), destination.ip()) {
+				(IpAddr::V4(src), IpAddr::V4(dst)) => {
+					// Version 2, PROXY command, TCP over IPv4.
+					header.extend([0x21, 0x11]);
+					[src.octets().to_vec(), dst.octets().to_vec()].concat()
+				}
+				(IpAddr::V6(src), IpAddr::V6(dst)) => {
+					// Version 2, PROXY command, TCP over IPv6.
+					header.extend([0x21, 0x21]);
+					[src.octets().to_vec(), dst.octets().to_vec()].concat()
+				}
+				// Version 2, LOCAL command, unspecified protocol.
+				_ => {
+					header.extend([0x20, 0x00, 0x00, 0x00]);
+					return header;
+				}
+			};
+			let len = (addresses.len() + 4) as u16;
+			header.extend(len.to_be_bytes());
+			header.extend(addresses);
+			header.extend(source.port().to_be_bytes());
+			header.extend(destination.port().to_be_bytes());
+			header
+		}
+	}
+}
+
+/// Connect to the SMTP server, write the PROXY protocol header, and relay a
+/// local listener to this connection. Returns the relay, which accepts a
+/// single connection authenticating with its password.
+///
+/// The source address of the header is `source` if set, or else the local
+/// address of the connection to the server, which originates from
+/// `bind_addr` if set.
+pub(crate) async fn start_relay(
+	host: &str,
+	port: u16,
+	version: ProxyProtocol,
+	source: Option<SocketAddr>,
+	bind_addr: Option<IpAddr>,
+) -> io::Result<Relay> {
+	let mut upstream = connect_tcp(host, port, bind_addr).await?;
+	let source = source.unwrap_or(upstream.local_addr()?);
+	upstream
+		.write_all(&header(version, source, upstream.peer_addr()?))
+		.await?;
+
+	relay(
+		upstream,
+		format!("[host={}:{}] with PROXY protocol", host, port),
+	)
+	.await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{header, ProxyProtocol};
+	use std::net::SocketAddr;
+
+	#[test]
+	fn should_build_v1_header() {
+		let source: SocketAddr = "192.0.2.1:56324".parse().unwrap();
+		let destination: SocketAddr = "198.51.100.7:25".parse().unwrap();
+		assert_eq!(
+			header(ProxyProtocol::V1, source, destination),
+			b"PROXY TCP4 192.0.2.1 198.51.100.7 56324 25\r\n"
+		);
+	}
+
+	#[test]
+	fn should_build_v2_header() {
+		let source: SocketAddr = "192.0.2.1:56324".parse().unwrap();
+		let destination: SocketAddr = "198.51.100.7:25".parse().unwrap();
+		let header = header(ProxyProtocol::V2, source, destination);
+
+		assert_eq!(&header[..12], b"\r\n\r\n\0\r\nQUIT\n");
+		assert_eq!(&header[12..16], &[0x21, 0x11, 0x00, 12]);
+		assert_eq!(&header[16..20], &[192, 0, 2, 1]);
+		assert_eq!(&header[20..24], &[198, 51, 100, 7]);
+		assert_eq!(&header[24..], &[0xDC, 0x04, 0x00, 25]);
+	}
+}
@@ -0,0 +1,375 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Local TCP relays to SMTP servers that async-smtp can't connect to by
+//! itself. async-smtp opens the TCP connection to the server on its own, so
+//! when we need to open the connection ourselves, we relay a local listener
+//! to it, and the SMTP client connects to the local listener instead.
+//!
+//! Any local process can connect to the listener, so it is a one-shot SOCKS5
+//! server: the SMTP client authenticates with a random password, and the
+//! connections which don't are dropped.
+
+use async_smtp::smtp::Socks5Config;
+use rand::{distributions::Alphanumeric, Rng};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream};
+use tokio::sync::mpsc;
+
+use crate::LOG_TARGET;
+
+/// How long the relay waits for the SMTP client to connect to it.
+const RELAY_ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SOCKS5 username of the SMTP client, the password is random.
+const RELAY_USERNAME: &str = "reacher";
+
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_AUTH_PASSWORD: u8 = 0x02;
+const SOCKS5_AUTH_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+
+/// A local relay, which the SMTP client connects to through SOCKS5.
+#[derive(Debug)]
+pub(crate) struct Relay {
+	/// Address of the local listener.
+	pub addr: SocketAddr,
+	password: String,
+}
+
+impl Relay {
+	/// SOCKS5 configuration for the SMTP client to connect to the relay.
+	pub fn socks5_config(&self) -> Socks5Config {
+		Socks5Config::new_with_user_pass(
+			self.addr.ip().to_string(),
+			self.addr.port(),
+			RELAY_USERNAME.into(),
+			self.password.clone(),
+		)
+	}
+}
+
+/// Relay a local listener to the `upstream` connection. The listener relays
+/// the first connection authenticating with the returned relay's password,
+/// and drops the others. `target` describes the upstream connection in the
+/// logs.
+pub(crate) async fn relay<S>(mut upstream: S, target: String) -> io::Result<Relay>
+where
+	S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+	let listener = TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).await?;
+	let relay = Relay {
+		addr: listener.local_addr()?,
+		password: rand::thread_rng()
+			.sample_iter(&Alphanumeric)
+			.take(32)
+			.map(char::from)
+			.collect(),
+	};
+
+	let password = relay.password.clone();
+	tokio::spawn(async move {
+		// Authenticate the connections concurrently, so that a connection
+		// which never completes the handshake doesn't block the SMTP client.
+		let (tx, mut rx) = mpsc::channel(1);
+		let acceptor = tokio::spawn(async move {
+			while let Ok((mut stream, peer)) = listener.accept().await {
+				let (tx, password) = (tx.clone(), password.clone());
+				tokio::spawn(async move {
+					let authenticated = tokio::time::timeout(
+						RELAY_ACCEPT_TIMEOUT,
+						authenticate(&mut stream, &password),
+					)
+					.await
+					.unwrap_or_else(|elapsed| Err(elapsed.into()));
+					match authenticated {
+						Ok(true) => {
+							let _ = tx.send(stream).await;
+						}
+						Ok(false) => log::debug!(
+							target: LOG_TARGET,
+							"Relay rejected a connection from {}",
+							peer
+						),
+						Err(err) => log::debug!(
+							target: LOG_TARGET,
+							"Relay dropped a connection from {}: {}",
+							peer,
+							err
+						),
+					}
+				});
+			}
+		});
+
+		let downstream = tokio::time::timeout(RELAY_ACCEPT_TIMEOUT, rx.recv()).await;
+		acceptor.abort();
+		let mut downstream = match downstream {
+			Ok(Some(downstream)) => downstream,
+			_ => return,
+		};
+		if let Err(err) = tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await {
+			log::debug!(
+				target: LOG_TARGET,
+				"Relay to {} closed: {}",
+				target,
+				err
+			);
+		}
+	});
+
+	Ok(relay)
+}
+
+/// Read exactly `len` bytes from the stream.
+async fn read_bytes(stream: &mut TcpStream, len: usize) -> io::Result<Vec<u8>> {
+	let mut buf = vec![0; len];
+	stream.read_exact(&mut buf).await?;
+	Ok(buf)
+}
+
+/// Compare two byte strings in a time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Run the server side of the SOCKS5 handshake (RFC 1928), with the
+/// username/password authentication (RFC 1929). Returns whether the client
+/// authenticated with `password`. The requested destination is ignored, as
+/// the relay only forwards to its upstream connection.
+async fn authenticate(stream: &mut TcpStream, password: &str) -> io::Result<bool> {
+	let greeting = read_bytes(stream, 2).await?;
+	let methods = read_bytes(stream, greeting[1] as usize).await?;
+	if greeting[0] != SOCKS5_VERSION || !methods.contains(&SOCKS5_AUTH_PASSWORD) {
+		stream
+			.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_NO_ACCEPTABLE_METHOD])
+			.await?;
+		return Ok(false);
+	}
+	stream
+		.write_all(&[SOCKS5_VERSION, SOCKS5_AUTH_PASSWORD])
+		.await?;
+
+	let header = read_bytes(stream, 2).await?;
+	let username = read_bytes(stream, header[1] as usize).await?;
+	let password_len = read_bytes(stream, 1).await?;
+	let given_password = read_bytes(stream, password_len[0] as usize).await?;
+	let authenticated = header[0] == 0x01
+		&& username == RELAY_USERNAME.as_bytes()
+		&& constant_time_eq(&given_password, password.as_bytes());
+	stream
+		.write_all(&[0x01, if authenticated { 0x00 } else { 0x01 }])
+		.await?;
+	if !authenticated {
+		return Ok(false);
+	}
+
+	let request = read_bytes(stream, 4).await?;
+	let addr_len = match request[3] {
+		0x01 => 4,
+		0x03 => read_bytes(stream, 1).await?[0] as usize,
+		0x04 => 16,
+		atyp => {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("unknown SOCKS5 address type {}", atyp),
+			))
+		}
+	};
+	// The destination address and port.
+	read_bytes(stream, addr_len + 2).await?;
+	if request[1] != SOCKS5_CMD_CONNECT {
+		stream
+			.write_all(&[
+				SOCKS5_VERSION,
+				SOCKS5_REPLY_COMMAND_NOT_SUPPORTED,
+				0x00,
+				0x01,
+				0,
+				0,
+				0,
+				0,
+				0,
+				0,
+			])
+			.await?;
+		return Ok(false);
+	}
+	stream
+		.write_all(&[SOCKS5_VERSION, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+		.await?;
+
+	Ok(true)
+}
+
+/// Connect to the SMTP server at `host:port`. If `bind_addr` is set, the
+/// connection originates from this local address, so only the server's
+/// addresses of the same IP family are tried.
+pub(crate) async fn connect_tcp(
+	host: &str,
+	port: u16,
+	bind_addr: Option<IpAddr>,
+) -> io::Result<TcpStream> {
+	let bind_addr = match bind_addr {
+		Some(bind_addr) => bind_addr,
+		None => return TcpStream::connect((host, port)).await,
+	};
+
+	let mut last_err = None;
+	for addr in lookup_host((host, port)).await? {
+		if addr.is_ipv4() != bind_addr.is_ipv4() {
+			continue;
+		}
+		let socket = if addr.is_ipv4() {
+			TcpSocket::new_v4()?
+		} else {
+			TcpSocket::new_v6()?
+		};
+		socket.bind(SocketAddr::new(bind_addr, 0))?;
+		match socket.connect(addr).await {
+			Ok(stream) => return Ok(stream),
+			Err(err) => last_err = Some(err),
+		}
+	}
+
+	Err(last_err.unwrap_or_else(|| {
+		io::Error::new(
+			io::ErrorKind::AddrNotAvailable,
+			format!("{host} has no address of the same IP family as {bind_addr}"),
+		)
+	}))
+}
+
+/// Connect to the SMTP server from the `bind_addr` local address, and relay
+/// a local listener to this connection.
+pub(crate) async fn start_bound_relay(
+	host: &str,
+	port: u16,
+	bind_addr: IpAddr,
+) -> io::Result<Relay> {
+	let upstream = connect_tcp(host, port, Some(bind_addr)).await?;
+	relay(
+		upstream,
+		format!("[host={}:{}] from {}", host, port, bind_addr),
+	)
+	.await
+}
+
+/// Connect to the SMTP server listening on the Unix domain socket at `path`,
+/// and relay a local listener to this connection.
+#[cfg(unix)]
+pub(crate) async fn start_unix_relay(path: &Path) -> io::Result<Relay> {
+	let upstream = tokio::net::UnixStream::connect(path).await?;
+	relay(upstream, format!("[socket={}]", path.display())).await
+}
+
+#[cfg(not(unix))]
+pub(crate) async fn start_unix_relay(_path: &Path) -> io::Result<Relay> {
+	Err(io::Error::new(
+		io::ErrorKind::Unsupported,
+		"Unix domain sockets are not supported on this platform",
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{relay, RELAY_USERNAME};
+	use fast_socks5::client::{Config, Socks5Stream};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::{TcpListener, TcpStream};
+
+	/// Start a server sending a greeting to the first connection, and relay
+	/// a local listener to it.
+	async fn start_relay() -> super::Relay {
+		let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let upstream = TcpStream::connect(server.local_addr().unwrap())
+			.await
+			.unwrap();
+		tokio::spawn(async move {
+			let (mut stream, _) = server.accept().await.unwrap();
+			stream.write_all(b"220 hi\r\n").await.unwrap();
+			let mut buf = [0; 1];
+			let _ = stream.read(&mut buf).await;
+		});
+
+		relay(upstream, "test".into()).await.unwrap()
+	}
+
+	#[tokio::test]
+	async fn should_only_relay_the_authenticated_client() {
+		let relay = start_relay().await;
+
+		// A SOCKS5 client without authentication.
+		let mut intruder = TcpStream::connect(relay.addr).await.unwrap();
+		intruder.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+		let mut buf = Vec::new();
+		intruder.read_to_end(&mut buf).await.unwrap();
+		assert_eq!(buf, [0x05, 0xff]);
+
+		// A SOCKS5 client with the wrong password.
+		let wrong = Socks5Stream::connect_with_password(
+			relay.addr,
+			"example.com".into(),
+			25,
+			RELAY_USERNAME.into(),
+			"wrong".into(),
+			Config::default(),
+		)
+		.await;
+		assert!(wrong.is_err());
+
+		let mut client = Socks5Stream::connect_with_password(
+			relay.addr,
+			"example.com".into(),
+			25,
+			RELAY_USERNAME.into(),
+			relay.password.clone(),
+			Config::default(),
+		)
+		.await
+		.unwrap();
+		let mut greeting = [0; 8];
+		client.read_exact(&mut greeting).await.unwrap();
+		assert_eq!(&greeting, b"220 hi\r\n");
+	}
+
+	#[tokio::test]
+	async fn should_not_wait_for_a_stalled_handshake() {
+		let relay = start_relay().await;
+
+		// Connects, but never completes the SOCKS5 handshake.
+		let _stalled = TcpStream::connect(relay.addr).await.unwrap();
+
+		let mut client = Socks5Stream::connect_with_password(
+			relay.addr,
+			"example.com".into(),
+			25,
+			RELAY_USERNAME.into(),
+			relay.password.clone(),
+			Config::default(),
+		)
+		.await
+		.unwrap();
+		let mut greeting = [0; 8];
+		client.read_exact(&mut greeting).await.unwrap();
+		assert_eq!(&greeting, b"220 hi\r\n");
+	}
+}
@@ -0,0 +1,432 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal SPF (RFC 7208) evaluator, used to check if the domain of our
+//! `from_email` allows the IP we connect from to send emails.
+
+use async_recursion::async_recursion;
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+
+use crate::config::ReacherConfig;
+use crate::util::input_output::CheckEmailInput;
+use crate::LOG_TARGET;
+
+/// Maximum number of DNS-querying terms (include, a, mx, exists, redirect)
+/// in one evaluation, as defined in RFC 7208.
+const MAX_DNS_LOOKUPS: usize = 10;
+
+/// Result of an SPF evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfResult {
+	Pass,
+	Fail,
+	SoftFail,
+	Neutral,
+	/// The domain has no SPF record.
+	None,
+	/// The SPF record is invalid, or needs too many DNS lookups.
+	PermError,
+	/// A DNS lookup failed.
+	TempError,
+}
+
+/// Check if the domain of the input's `from_email` would pass SPF for the
+/// IP we connect to this host from. This IP is the input's `bind_addr` if
+/// set, or else the local address the OS routes to the host with, so behind
+/// a NAT it's not the public one.
+///
+/// Returns None if we can't tell: null sender, SOCKS5 proxy, or a DNS error.
+pub(crate) async fn from_spf_aligned(
+	host: &str,
+	port: u16,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Option<bool> {
+	if input.use_null_sender || input.proxy.is_some() {
+		return None;
+	}
+	let (_, from_domain) = input.from_email.rsplit_once('@')?;
+
+	let host = host.trim_end_matches('.');
+	let host_ip = match host.parse::<IpAddr>() {
+		Ok(ip) => ip,
+		Err(_) => config.resolver.lookup_ip(host).await.ok()?.iter().next()?,
+	};
+	let local_ip = match input.bind_addr {
+		Some(ip) => Ok(ip),
+		None => local_ip_towards(SocketAddr::new(host_ip, port)).await,
+	};
+	let local_ip = match local_ip {
+		Ok(ip) => ip,
+		Err(err) => {
+			log::debug!(
+				target: LOG_TARGET,
+				"[email={}] Cannot get the local IP towards {}: {}",
+				input.to_email,
+				host_ip,
+				err
+			);
+			return None;
+		}
+	};
+
+	let mut lookups = 0;
+	let result = check_host(&config.resolver, local_ip, from_domain, &mut lookups).await;
+	log::debug!(
+		target: LOG_TARGET,
+		"[email={}] SPF of {} for [ip={}]: {:?}",
+		input.to_email,
+		from_domain,
+		local_ip,
+		result
+	);
+
+	match result {
+		SpfResult::Pass => Some(true),
+		SpfResult::TempError => None,
+		_ => Some(false),
+	}
+}
+
+/// Get the local IP the OS uses to reach this address. Connecting a UDP
+/// socket doesn't send any packet.
+async fn local_ip_towards(addr: SocketAddr) -> std::io::Result<IpAddr> {
+	let bind_addr: SocketAddr = if addr.is_ipv4() {
+		"0.0.0.0:0".parse().expect("This is a valid address. qed.")
+	} else {
+		"[::]:0".parse().expect("This is a valid address. qed.")
+	};
+	let socket = UdpSocket::bind(bind_addr).await?;
+	socket.connect(addr).await?;
+	Ok(socket.local_addr()?.ip())
+}
+
+/// The `check_host()` function of RFC 7208, without macros support: terms
+/// with macros never match.
+#[async_recursion]
+pub async fn check_host(
+	resolver: &TokioAsyncResolver,
+	ip: IpAddr,
+	domain: &str,
+	lookups: &mut usize,
+) -> SpfResult {
+	let record = match find_spf_record(resolver, domain).await {
+		Ok(Some(record)) => record,
+		Ok(None) => return SpfResult::None,
+		Err(_) => return SpfResult::TempError,
+	};
+
+	let mut redirect = None;
+	for term in record.split_whitespace().skip(1) {
+		if let Some(target) = term.strip_prefix("redirect=") {
+			redirect = Some(target.to_string());
+			continue;
+		}
+		// Other modifiers, like exp=, don't change the result.
+		if term.contains('=') {
+			continue;
+		}
+
+		let (qualifier, mechanism) = match term.chars().next() {
+			Some('+') => (SpfResult::Pass, &term[1..]),
+			Some('-') => (SpfResult::Fail, &term[1..]),
+			Some('~') => (SpfResult::SoftFail, &term[1..]),
+			Some('?') => (SpfResult::Neutral, &term[1..]),
+			_ => (SpfResult::Pass, term),
+		};
+		if mechanism.contains('%') {
+			continue;
+		}
+
+		match matches_mechanism(resolver, ip, domain, mechanism, lookups).await {
+			Ok(true) => return qualifier,
+			Ok(false) => {}
+			Err(result) => return result,
+		}
+	}
+
+	match redirect {
+		Some(target) => {
+			*lookups += 1;
+			if *lookups > MAX_DNS_LOOKUPS {
+				return SpfResult::PermError;
+			}
+			match check_host(resolver, ip, &target, lookups).await {
+				SpfResult::None => SpfResult::PermError,
+				result => result,
+			}
+		}
+		None => SpfResult::Neutral,
+	}
+}
+
+/// Check if the IP matches the mechanism. Errors are returned as the result
+/// of the whole evaluation.
+async fn matches_mechanism(
+	resolver: &TokioAsyncResolver,
+	ip: IpAddr,
+	domain: &str,
+	mechanism: &str,
+	lookups: &mut usize,
+) -> Result<bool, SpfResult> {
+	let (name, arg) = match mechanism.split_once([':', '/']) {
+		Some((name, _)) => (name, &mechanism[name.len()..]),
+		None => (mechanism, ""),
+	};
+	let name = name.to_lowercase();
+
+	if ["include", "a", "mx", "exists", "ptr"].contains(&name.as_str()) {
+		*lookups += 1;
+		if *lookups > MAX_DNS_LOOKUPS {
+			return Err(SpfResult::PermError);
+		}
+	}
+
+	match name.as_str() {
+		"all" => Ok(true),
+		"ip4" | "ip6" => {
+			let (addr, prefix) = split_cidr(arg.trim_start_matches(':'));
+			let network = addr.parse::<IpAddr>().map_err(|_| SpfResult::PermError)?;
+			Ok(in_network(ip, network, prefix))
+		}
+		"include" => {
+			let target = arg.strip_prefix(':').ok_or(SpfResult::PermError)?;
+			match check_host(resolver, ip, target, lookups).await {
+				SpfResult::Pass => Ok(true),
+				SpfResult::Fail | SpfResult::SoftFail | SpfResult::Neutral => Ok(false),
+				SpfResult::TempError => Err(SpfResult::TempError),
+				SpfResult::None | SpfResult::PermError => Err(SpfResult::PermError),
+			}
+		}
+		"a" | "mx" => {
+			let (target, prefix4, prefix6) = parse_domain_spec(arg, domain);
+			let prefix = if ip.is_ipv4() { prefix4 } else { prefix6 };
+			let hosts = if name == "a" {
+				vec![target]
+			} else {
+				match resolver.mx_lookup(format!("{target}.")).await {
+					Ok(lookup) => lookup.iter().map(|mx| mx.exchange().to_string()).collect(),
+					Err(err) => return none_if_no_records(err).map(|_| false),
+				}
+			};
+			for host in hosts {
+				let addrs = match resolver.lookup_ip(host.as_str()).await {
+					Ok(lookup) => lookup.iter().collect::<Vec<_>>(),
+					Err(err) => {
+						none_if_no_records(err)?;
+						continue;
+					}
+				};
+				let prefix = prefix.unwrap_or(if ip.is_ipv4() { 32 } else { 128 });
+				if addrs.iter().any(|addr| in_network(ip, *addr, Some(prefix))) {
+					return Ok(true);
+				}
+			}
+			Ok(false)
+		}
+		"exists" => {
+			let target = arg.strip_prefix(':').ok_or(SpfResult::PermError)?;
+			match resolver.ipv4_lookup(format!("{target}.")).await {
+				Ok(lookup) => Ok(lookup.iter().next().is_some()),
+				Err(err) => none_if_no_records(err).map(|_| false),
+			}
+		}
+		// ptr is deprecated, and too costly.
+		"ptr" => Ok(false),
+		_ => Err(SpfResult::PermError),
+	}
+}
+
+/// Find the "v=spf1" TXT record of the domain, if any.
+async fn find_spf_record(
+	resolver: &TokioAsyncResolver,
+	domain: &str,
+) -> Result<Option<String>, ResolveError> {
+	// Add a trailing dot, so that the domain is not resolved relative to the
+	// system's search domains.
+	let lookup = match resolver.txt_lookup(format!("{domain}.")).await {
+		Ok(lookup) => lookup,
+		Err(err) => {
+			return match err.kind() {
+				ResolveErrorKind::NoRecordsFound { .. } => Ok(None),
+				_ => Err(err),
+			}
+		}
+	};
+
+	Ok(lookup
+		.iter()
+		.map(|txt| {
+			txt.iter()
+				.map(|part| String::from_utf8_lossy(part))
+				.collect::<String>()
+		})
+		.find(|record| {
+			let record = record.to_lowercase();
+			record == "v=spf1" || record.starts_with("v=spf1 ")
+		}))
+}
+
+/// A lookup without records doesn't match, but other DNS errors make the
+/// evaluation fail.
+fn none_if_no_records(err: ResolveError) -> Result<(), SpfResult> {
+	match err.kind() {
+		ResolveErrorKind::NoRecordsFound { .. } => Ok(()),
+		_ => Err(SpfResult::TempError),
+	}
+}
+
+/// Parse the `[:domain][/prefix4][//prefix6]` argument of the a and mx
+/// mechanisms.
+fn parse_domain_spec(arg: &str, domain: &str) -> (String, Option<u8>, Option<u8>) {
+	let (arg, prefix6) = match arg.split_once("//") {
+		Some((arg, prefix6)) => (arg, prefix6.parse().ok()),
+		None => (arg, None),
+	};
+	let (arg, prefix4) = match arg.split_once('/') {
+		Some((arg, prefix4)) => (arg, prefix4.parse().ok()),
+		None => (arg, None),
+	};
+	let target = match arg.strip_prefix(':') {
+		Some(target) if !target.is_empty() => target.to_string(),
+		_ => domain.to_string(),
+	};
+
+	(target, prefix4, prefix6)
+}
+
+/// Split "addr/prefix" into its parts.
+fn split_cidr(cidr: &str) -> (&str, Option<u8>) {
+	match cidr.split_once('/') {
+		Some((addr, prefix)) => (addr, prefix.parse().ok()),
+		None => (cidr, None),
+	}
+}
+
+/// Check if the IP is in the network. Without prefix, the IP must be equal
+/// to the network address.
+fn in_network(ip: IpAddr, network: IpAddr, prefix: Option<u8>) -> bool {
+	match (ip, network) {
+		(IpAddr::V4(ip), IpAddr::V4(network)) => {
+			let prefix = u32::from(prefix.unwrap_or(32).min(32));
+			let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+			u32::from(ip) & mask == u32::from(network) & mask
+		}
+		(IpAddr::V6(ip), IpAddr::V6(network)) => {
+			let prefix = u32::from(prefix.unwrap_or(128).min(128));
+			let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+			u128::from(ip) & mask == u128::from(network) & mask
+		}
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::mock_dns::{response, txt_response, MockDnsServer};
+	use crate::CheckEmailInputBuilder;
+	use hickory_proto::op::ResponseCode;
+	use std::sync::Arc;
+
+	async fn spf_server() -> MockDnsServer {
+		MockDnsServer::start(|query| {
+			let spf = match query.queries()[0].name().to_string().as_str() {
+				"aligned.example." => "v=spf1 include:_spf.aligned.example -all",
+				"_spf.aligned.example." => "v=spf1 ip4:127.0.0.0/8 ~all",
+				"misaligned.example." => "v=spf1 ip4:192.0.2.1 ip6:2001:db8::/32 -all",
+				_ => return response(query, ResponseCode::NXDomain),
+			};
+			txt_response(query, 300, &[spf])
+		})
+		.await
+	}
+
+	#[test]
+	fn should_match_networks() {
+		let ip = "192.0.2.10".parse().unwrap();
+		assert!(in_network(ip, "192.0.2.0".parse().unwrap(), Some(24)));
+		assert!(!in_network(ip, "192.0.2.0".parse().unwrap(), None));
+		assert!(in_network(ip, "10.0.0.0".parse().unwrap(), Some(0)));
+		assert!(!in_network(ip, "2001:db8::".parse().unwrap(), Some(0)));
+		assert!(in_network(
+			"2001:db8::1".parse().unwrap(),
+			"2001:db8::".parse().unwrap(),
+			Some(32)
+		));
+	}
+
+	#[tokio::test]
+	async fn should_check_from_spf_alignment() {
+		let server = spf_server().await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let input = CheckEmailInputBuilder::default()
+			.from_email("reacher@aligned.example".into())
+			.build()
+			.unwrap();
+		assert_eq!(
+			from_spf_aligned("127.0.0.1", 25, &input, &config).await,
+			Some(true)
+		);
+
+		let input = CheckEmailInput {
+			from_email: "reacher@misaligned.example".into(),
+			..input
+		};
+		assert_eq!(
+			from_spf_aligned("127.0.0.1", 25, &input, &config).await,
+			Some(false)
+		);
+
+		let input = CheckEmailInput {
+			use_null_sender: true,
+			..input
+		};
+		assert_eq!(
+			from_spf_aligned("127.0.0.1", 25, &input, &config).await,
+			None
+		);
+	}
+
+	#[tokio::test]
+	async fn should_evaluate_spf_record() {
+		let server = spf_server().await;
+		let resolver = server.resolver();
+		let ip = "192.0.2.1".parse().unwrap();
+
+		let mut lookups = 0;
+		assert_eq!(
+			check_host(&resolver, ip, "misaligned.example", &mut lookups).await,
+			SpfResult::Pass
+		);
+		let mut lookups = 0;
+		assert_eq!(
+			check_host(&resolver, ip, "aligned.example", &mut lookups).await,
+			SpfResult::Fail
+		);
+		let mut lookups = 0;
+		assert_eq!(
+			check_host(&resolver, ip, "nospf.example", &mut lookups).await,
+			SpfResult::None
+		);
+	}
+}
@@ -0,0 +1,89 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Record the SMTP dialog of a verification, for the input's
+//! `smtp_transcript`. The lines are recorded in a task-local buffer, so that
+//! the SMTP functions don't need to pass it around.
+
+use async_smtp::smtp::{error::Error as AsyncSmtpError, response::Response};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+tokio::task_local! {
+	static TRANSCRIPT: Arc<Mutex<Vec<String>>>;
+}
+
+/// Run `fut`, and return its output with the lines recorded while it ran.
+pub(crate) async fn record<F: Future>(fut: F) -> (F::Output, Vec<String>) {
+	let lines = Arc::new(Mutex::new(vec![]));
+	let output = TRANSCRIPT.scope(Arc::clone(&lines), fut).await;
+	let lines = std::mem::take(&mut *lines.lock().expect("Transcript lock poisoned"));
+
+	(output, lines)
+}
+
+/// Add a line to the transcript. Does nothing outside of `record`.
+pub(crate) fn push(line: impl Into<String>) {
+	let _ = TRANSCRIPT.try_with(|lines| {
+		lines
+			.lock()
+			.expect("Transcript lock poisoned")
+			.push(line.into())
+	});
+}
+
+/// Add the command we sent, prefixed with "C: ".
+pub(crate) fn push_command(command: &impl ToString) {
+	push(format!("C: {}", command.to_string().trim_end()));
+}
+
+/// Add the server's reply to a command, each line prefixed with "S: ", or
+/// the error prefixed with "!: " if there was no reply.
+pub(crate) fn push_reply(reply: &Result<Response, AsyncSmtpError>) {
+	let response = match reply {
+		Ok(response)
+		| Err(AsyncSmtpError::Transient(response))
+		| Err(AsyncSmtpError::Permanent(response)) => response,
+		Err(err) => return push(format!("!: {err}")),
+	};
+	if response.message.is_empty() {
+		return push(format!("S: {}", response.code));
+	}
+	let last = response.message.len() - 1;
+	for (i, line) in response.message.iter().enumerate() {
+		let separator = if i == last { ' ' } else { '-' };
+		push(format!("S: {}{}{}", response.code, separator, line));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{push, record};
+
+	#[tokio::test]
+	async fn should_record_lines_in_scope_only() {
+		push("ignored");
+		let ((), lines) = record(async {
+			push("first");
+			tokio::task::yield_now().await;
+			push("second");
+		})
+		.await;
+		push("ignored");
+
+		assert_eq!(lines, vec!["first", "second"]);
+	}
+}
@@ -0,0 +1,389 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The SMTP connection to a server. It's async-smtp's low-level client, on a
+//! stream which bounds the size of the server's replies while they're read:
+//! async-smtp reads each reply line by line until its last line, without any
+//! limit, so a hostile server could make us buffer an endless reply.
+
+use async_smtp::smtp::{
+	client::{
+		net::{Connector, NetworkStream},
+		InnerClient,
+	},
+	commands::{EhloCommand, StarttlsCommand},
+	error::{Error as AsyncSmtpError, SmtpResult},
+	extension::{ClientId, Extension, ServerInfo},
+	ClientSecurity, ServerAddress, Socks5Config,
+};
+use async_smtp::ClientTlsParameters;
+use async_trait::async_trait;
+use std::fmt::{self, Display};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::CheckEmailInput;
+
+/// Default of the input's `smtp_max_response_bytes`.
+const DEFAULT_MAX_RESPONSE_BYTES: usize = 64 * 1024;
+/// Default of the input's `smtp_max_response_lines`.
+const DEFAULT_MAX_RESPONSE_LINES: usize = 100;
+
+/// The error of async-smtp when the server doesn't offer STARTTLS, but TLS
+/// is required.
+pub(crate) const STARTTLS_UNSUPPORTED: &str = "Could not encrypt connection, aborting";
+
+/// Limits on the size of a single SMTP reply, i.e. of what the server sends
+/// between two of our commands.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ResponseLimits {
+	max_bytes: usize,
+	max_lines: usize,
+}
+
+impl Default for ResponseLimits {
+	fn default() -> Self {
+		ResponseLimits {
+			max_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+			max_lines: DEFAULT_MAX_RESPONSE_LINES,
+		}
+	}
+}
+
+impl ResponseLimits {
+	/// The input's `smtp_max_response_bytes` and `smtp_max_response_lines`,
+	/// or their defaults.
+	pub fn from_input(input: &CheckEmailInput) -> Self {
+		ResponseLimits {
+			max_bytes: input
+				.smtp_max_response_bytes
+				.unwrap_or(DEFAULT_MAX_RESPONSE_BYTES),
+			max_lines: input
+				.smtp_max_response_lines
+				.unwrap_or(DEFAULT_MAX_RESPONSE_LINES),
+		}
+	}
+}
+
+/// The IO error with which reading a reply fails once it's above the
+/// limits. It surfaces as an `AsyncSmtpError::Io`, see `response_too_large`.
+#[derive(Debug)]
+struct ResponseTooLarge(String);
+
+impl Display for ResponseTooLarge {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+/// Why the reply was rejected, if the error is a read aborted because the
+/// reply was above the limits.
+pub(crate) fn response_too_large(err: &AsyncSmtpError) -> Option<String> {
+	match err {
+		AsyncSmtpError::Io(e) => e
+			.get_ref()
+			.and_then(|e| e.downcast_ref::<ResponseTooLarge>())
+			.map(|e| e.0.clone()),
+		_ => None,
+	}
+}
+
+/// A stream counting the bytes and the lines read since the last write, and
+/// failing the reads above the limits.
+///
+/// It wraps the `NetworkStream`, above TLS, so it counts the plaintext
+/// replies. async-smtp writes a command before reading each reply (except
+/// the greeting, the first thing read), so the counters are those of the
+/// current reply.
+pub(crate) struct LimitedStream {
+	inner: NetworkStream,
+	limits: ResponseLimits,
+	bytes: usize,
+	lines: usize,
+}
+
+impl LimitedStream {
+	fn new(inner: NetworkStream, limits: ResponseLimits) -> Self {
+		LimitedStream {
+			inner,
+			limits,
+			bytes: 0,
+			lines: 0,
+		}
+	}
+
+	fn check_limits(&self) -> io::Result<()> {
+		let ResponseLimits {
+			max_bytes,
+			max_lines,
+		} = self.limits;
+		let reason = if self.lines > max_lines {
+			format!("more than {max_lines} lines")
+		} else if self.bytes > max_bytes {
+			format!("more than {max_bytes} bytes")
+		} else {
+			return Ok(());
+		};
+
+		Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			ResponseTooLarge(reason),
+		))
+	}
+}
+
+impl AsyncRead for LimitedStream {
+	fn poll_read(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		// Don't read any further once above the limits.
+		this.check_limits()?;
+
+		let filled = buf.filled().len();
+		ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+		let read = &buf.filled()[filled..];
+		this.bytes += read.len();
+		this.lines += read.iter().filter(|&&b| b == b'\n').count();
+
+		Poll::Ready(this.check_limits())
+	}
+}
+
+impl AsyncWrite for LimitedStream {
+	fn poll_write(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		// The next reply is the one to this command.
+		this.bytes = 0;
+		this.lines = 0;
+		Pin::new(&mut this.inner).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+	}
+}
+
+#[async_trait]
+impl Connector for LimitedStream {
+	async fn connect(
+		addr: &SocketAddr,
+		timeout: Option<Duration>,
+		tls_parameters: Option<&ClientTlsParameters>,
+	) -> io::Result<Self> {
+		let stream = NetworkStream::connect(addr, timeout, tls_parameters).await?;
+		Ok(LimitedStream::new(stream, ResponseLimits::default()))
+	}
+
+	async fn connect_socks5(
+		socks5: &Socks5Config,
+		addr: &ServerAddress,
+		timeout: Option<Duration>,
+		tls_parameters: Option<&ClientTlsParameters>,
+	) -> io::Result<Self> {
+		let stream = NetworkStream::connect_socks5(socks5, addr, timeout, tls_parameters).await?;
+		Ok(LimitedStream::new(stream, ResponseLimits::default()))
+	}
+
+	async fn upgrade_tls(self, tls_parameters: &ClientTlsParameters) -> io::Result<Self> {
+		let stream = self.inner.upgrade_tls(tls_parameters).await?;
+		Ok(LimitedStream::new(stream, self.limits))
+	}
+
+	fn is_encrypted(&self) -> bool {
+		self.inner.is_encrypted()
+	}
+}
+
+/// An SMTP connection, opened like async-smtp's `SmtpTransport` does: read
+/// the greeting, send EHLO, then STARTTLS and EHLO again if the security
+/// asks for it.
+pub(crate) struct SmtpTransport {
+	client: InnerClient<LimitedStream>,
+	server_address: ServerAddress,
+	security: ClientSecurity,
+	hello_name: ClientId,
+	timeout: Option<Duration>,
+	greeting_timeout: Option<Duration>,
+	socks5: Option<Socks5Config>,
+	limits: ResponseLimits,
+}
+
+impl SmtpTransport {
+	/// A transport to the server, not connected yet.
+	pub fn new(
+		server_address: ServerAddress,
+		security: ClientSecurity,
+		hello_name: ClientId,
+	) -> Self {
+		SmtpTransport {
+			client: InnerClient::new(),
+			server_address,
+			security,
+			hello_name,
+			timeout: None,
+			greeting_timeout: None,
+			socks5: None,
+			limits: ResponseLimits::default(),
+		}
+	}
+
+	/// Timeout of connecting and of each command.
+	pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Timeout of reading the greeting once connected, instead of `timeout`.
+	pub fn greeting_timeout(mut self, greeting_timeout: Option<Duration>) -> Self {
+		self.greeting_timeout = greeting_timeout;
+		self
+	}
+
+	/// Connect through this SOCKS5 proxy.
+	pub fn use_socks5(mut self, socks5: Socks5Config) -> Self {
+		self.socks5 = Some(socks5);
+		self
+	}
+
+	/// Limits on the size of the server's replies.
+	pub fn response_limits(mut self, limits: ResponseLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Connect to the server, up to the EHLO.
+	pub async fn connect(&mut self) -> Result<(), AsyncSmtpError> {
+		let tls_parameters = match &self.security {
+			ClientSecurity::Wrapper(tls_parameters) => Some(tls_parameters),
+			_ => None,
+		};
+		let stream = match &self.socks5 {
+			Some(socks5) => {
+				NetworkStream::connect_socks5(
+					socks5,
+					&self.server_address,
+					self.timeout,
+					tls_parameters,
+				)
+				.await?
+			}
+			None => self.connect_direct(tls_parameters).await?,
+		};
+		self.client
+			.connect_with_stream(LimitedStream::new(stream, self.limits))
+			.await?;
+		self.client.set_timeout(self.timeout);
+
+		// The 220 greeting, which servers may delay on purpose.
+		match self.greeting_timeout {
+			Some(t) => {
+				tokio::time::timeout(
+					t,
+					Pin::new(&mut self.client).read_response_with_timeout(None),
+				)
+				.await
+				.map_err(io::Error::from)??;
+			}
+			None => {
+				Pin::new(&mut self.client).read_response().await?;
+			}
+		}
+
+		let server_info = ehlo(&mut self.client, &self.hello_name).await?;
+		let tls_parameters = match (
+			&self.security,
+			server_info.supports_feature(Extension::StartTls),
+		) {
+			(ClientSecurity::Required(_), false) => return Err(STARTTLS_UNSUPPORTED.into()),
+			(ClientSecurity::Opportunistic(p), true) | (ClientSecurity::Required(p), true) => p,
+			_ => return Ok(()),
+		};
+		Pin::new(&mut self.client).command(StarttlsCommand).await?;
+		let client = std::mem::take(&mut self.client);
+		self.client = client.upgrade_tls_stream(tls_parameters).await?;
+
+		// Servers may advertise different extensions over TLS.
+		ehlo(&mut self.client, &self.hello_name).await?;
+
+		Ok(())
+	}
+
+	/// Open the TCP connection to the first address of the server which
+	/// accepts it.
+	async fn connect_direct(
+		&self,
+		tls_parameters: Option<&ClientTlsParameters>,
+	) -> Result<NetworkStream, AsyncSmtpError> {
+		let addresses =
+			tokio::net::lookup_host((self.server_address.host.as_str(), self.server_address.port))
+				.await?;
+
+		let mut last_err = None;
+		for addr in addresses {
+			match NetworkStream::connect(&addr, self.timeout, tls_parameters).await {
+				Ok(stream) => return Ok(stream),
+				Err(err) => last_err = Some(err),
+			}
+		}
+
+		Err(last_err.map_or(AsyncSmtpError::Resolution, Into::into))
+	}
+
+	/// Whether the connection is open.
+	#[cfg(test)]
+	pub fn is_connected(&self) -> bool {
+		self.client.is_connected()
+	}
+
+	/// Send a command and read the server's reply.
+	pub async fn command<C: Display>(&mut self, command: C) -> SmtpResult {
+		Pin::new(&mut self.client).command(command).await
+	}
+
+	/// Send QUIT and close the connection.
+	pub async fn close(&mut self) -> Result<(), AsyncSmtpError> {
+		Pin::new(&mut self.client).close().await
+	}
+}
+
+/// Send EHLO and parse the extensions of the reply.
+async fn ehlo(
+	client: &mut InnerClient<LimitedStream>,
+	hello_name: &ClientId,
+) -> Result<ServerInfo, AsyncSmtpError> {
+	let response = Pin::new(client)
+		.command(EhloCommand::new(hello_name.clone()))
+		.await?;
+
+	ServerInfo::from_response(&response)
+}
@@ -0,0 +1,279 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Pool of WebDriver sessions, reused across headless verifications, as
+//! creating a session starts a new browser.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use fantoccini::Client;
+use tokio::sync::Semaphore;
+
+use super::headless::{create_headless_client, HeadlessError};
+use crate::LOG_TARGET;
+
+/// Default maximum number of WebDriver sessions in use at once.
+pub const DEFAULT_WEBDRIVER_MAX_SESSIONS: usize = 4;
+/// Default duration after which an idle session is closed.
+pub const DEFAULT_WEBDRIVER_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A session which is not in use, with the time it was returned.
+struct IdleSession {
+	client: Client,
+	since: Instant,
+}
+
+/// Pool of WebDriver sessions, by WebDriver address. At most `max_sessions`
+/// sessions are in use at once, the other verifications wait for one to be
+/// returned, and sessions idle for longer than `idle_timeout` are closed.
+/// It's meant to be shared between verifications, see `ReacherConfig`.
+pub struct WebDriverPool {
+	permits: Semaphore,
+	idle_timeout: Duration,
+	idle: Mutex<HashMap<String, Vec<IdleSession>>>,
+}
+
+impl Default for WebDriverPool {
+	fn default() -> Self {
+		Self::new(
+			DEFAULT_WEBDRIVER_MAX_SESSIONS,
+			DEFAULT_WEBDRIVER_IDLE_TIMEOUT,
+		)
+	}
+}
+
+impl std::fmt::Debug for WebDriverPool {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("WebDriverPool")
+			.field("available_permits", &self.permits.available_permits())
+			.field("idle_timeout", &self.idle_timeout)
+			.finish()
+	}
+}
+
+impl WebDriverPool {
+	pub fn new(max_sessions: usize, idle_timeout: Duration) -> Self {
+		Self {
+			permits: Semaphore::new(max_sessions.max(1)),
+			idle_timeout,
+			idle: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Run `f` with a session to the WebDriver server at `webdriver`: an
+	/// idle session of the pool, or a new one. The session is returned to
+	/// the pool if `f` succeeds, and closed if it fails, as the browser may
+	/// be in a bad state.
+	pub async fn run<T, F, Fut>(&self, webdriver: &str, f: F) -> Result<T, HeadlessError>
+	where
+		F: FnOnce(Client) -> Fut,
+		Fut: Future<Output = Result<T, HeadlessError>>,
+	{
+		let _permit = self
+			.permits
+			.acquire()
+			.await
+			.expect("The semaphore is never closed. qed.");
+
+		let client = match self.take_idle(webdriver).await {
+			Some(client) => client,
+			None => {
+				log::debug!(target: LOG_TARGET, "Creating a WebDriver session on {}", webdriver);
+				create_headless_client(webdriver).await?
+			}
+		};
+
+		let result = f(client.clone()).await;
+		match result {
+			Ok(_) => self.put_idle(webdriver, client).await,
+			Err(_) => {
+				let _ = client.close().await;
+			}
+		}
+
+		result
+	}
+
+	/// Take the most recently returned idle session, and close the ones
+	/// idle for longer than `idle_timeout`.
+	async fn take_idle(&self, webdriver: &str) -> Option<Client> {
+		let (client, expired) = {
+			let mut idle = self.idle.lock().expect("Lock is not poisoned. qed.");
+			let sessions = idle.entry(webdriver.to_string()).or_default();
+			let (expired, fresh): (Vec<_>, Vec<_>) = sessions
+				.drain(..)
+				.partition(|session| session.since.elapsed() >= self.idle_timeout);
+			*sessions = fresh;
+			(sessions.pop().map(|session| session.client), expired)
+		};
+
+		for session in expired {
+			log::debug!(target: LOG_TARGET, "Closing an idle WebDriver session on {}", webdriver);
+			let _ = session.client.close().await;
+		}
+
+		client
+	}
+
+	/// Return a session to the pool, after clearing the cookies so that the
+	/// next verification starts from a clean state.
+	async fn put_idle(&self, webdriver: &str, client: Client) {
+		if client.delete_all_cookies().await.is_err() {
+			let _ = client.close().await;
+			return;
+		}
+
+		self.idle
+			.lock()
+			.expect("Lock is not poisoned. qed.")
+			.entry(webdriver.to_string())
+			.or_default()
+			.push(IdleSession {
+				client,
+				since: Instant::now(),
+			});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::WebDriverPool;
+	use crate::smtp::headless::HeadlessError;
+	use fantoccini::error::CmdError;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+	use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+	use tokio::net::TcpListener;
+
+	/// Start a mock WebDriver server, which creates sessions and records
+	/// the requests it receives, as "METHOD /path".
+	async fn mock_webdriver() -> (String, Arc<Mutex<Vec<String>>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = format!("http://{}", listener.local_addr().unwrap());
+		let requests = Arc::new(Mutex::new(vec![]));
+		let received = Arc::clone(&requests);
+		tokio::spawn(async move {
+			let sessions = Arc::new(AtomicUsize::new(0));
+			while let Ok((socket, _)) = listener.accept().await {
+				let received = Arc::clone(&received);
+				let sessions = Arc::clone(&sessions);
+				tokio::spawn(async move {
+					let mut socket = BufReader::new(socket);
+					loop {
+						let mut request_line = String::new();
+						if socket.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+							return;
+						}
+						let mut content_length = 0;
+						loop {
+							let mut header = String::new();
+							socket.read_line(&mut header).await.unwrap();
+							if header.trim().is_empty() {
+								break;
+							}
+							if let Some((name, value)) = header.split_once(':') {
+								if name.eq_ignore_ascii_case("content-length") {
+									content_length = value.trim().parse().unwrap();
+								}
+							}
+						}
+						let mut body = vec![0; content_length];
+						socket.read_exact(&mut body).await.unwrap();
+
+						let request = request_line
+							.split_whitespace()
+							.take(2)
+							.collect::<Vec<_>>()
+							.join(" ");
+						let value = if request == "POST /session" {
+							let session = sessions.fetch_add(1, Ordering::SeqCst);
+							format!(r#"{{"sessionId":"s{}","capabilities":{{}}}}"#, session)
+						} else {
+							"null".into()
+						};
+						received.lock().unwrap().push(request);
+						let body = format!(r#"{{"value":{}}}"#, value);
+						let response = format!(
+							"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+							body.len(),
+							body
+						);
+						socket.write_all(response.as_bytes()).await.unwrap();
+					}
+				});
+			}
+		});
+
+		(addr, requests)
+	}
+
+	fn count(requests: &Mutex<Vec<String>>, request: &str) -> usize {
+		requests
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|r| r.as_str() == request)
+			.count()
+	}
+
+	#[tokio::test]
+	async fn should_reuse_a_bounded_number_of_sessions() {
+		let (webdriver, requests) = mock_webdriver().await;
+		let pool = WebDriverPool::new(2, Duration::from_secs(60));
+
+		let verifications = (0..6).map(|_| {
+			pool.run(&webdriver, |_client| async {
+				tokio::time::sleep(Duration::from_millis(50)).await;
+				Ok(())
+			})
+		});
+		let results = futures::future::join_all(verifications).await;
+
+		assert!(results.iter().all(Result::is_ok));
+		assert_eq!(count(&requests, "POST /session"), 2);
+	}
+
+	#[tokio::test]
+	async fn should_discard_failed_and_idle_sessions() {
+		let (webdriver, requests) = mock_webdriver().await;
+		let pool = WebDriverPool::new(2, Duration::from_secs(60));
+
+		// A session which errored isn't reused.
+		let result: Result<(), _> = pool
+			.run(&webdriver, |_client| async {
+				Err(HeadlessError::Cmd(CmdError::WaitTimeout))
+			})
+			.await;
+		assert!(result.is_err());
+		pool.run(&webdriver, |_client| async { Ok(()) })
+			.await
+			.unwrap();
+		assert_eq!(count(&requests, "POST /session"), 2);
+
+		// An idle session is closed after the idle timeout.
+		let pool = WebDriverPool::new(2, Duration::ZERO);
+		for _ in 0..2 {
+			pool.run(&webdriver, |_client| async { Ok(()) })
+				.await
+				.unwrap();
+		}
+		assert_eq!(count(&requests, "POST /session"), 4);
+	}
+}
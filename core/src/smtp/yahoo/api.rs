@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::YahooError;
+use crate::config::ReacherConfig;
 use crate::LOG_TARGET;
 use crate::{
 	smtp::{http_api::create_client, SmtpDetails},
@@ -76,8 +77,12 @@ struct FormResponse {
 
 /// Use well-crafted HTTP requests to verify if a Yahoo email address exists.
 /// Inspired by https://github.com/hbattat/verifyEmail.
-pub async fn check_api(to_email: &str, input: &CheckEmailInput) -> Result<SmtpDetails, YahooError> {
-	let res = create_client(input, "yahoo")?
+pub async fn check_api(
+	to_email: &str,
+	input: &CheckEmailInput,
+	config: &ReacherConfig,
+) -> Result<SmtpDetails, YahooError> {
+	let res = create_client(input, config, "yahoo")?
 		.get(SIGNUP_PAGE)
 		.header("User-Agent", USER_AGENT)
 		.send()
@@ -130,7 +135,7 @@ pub async fn check_api(to_email: &str, input: &CheckEmailInput) -> Result<SmtpDe
 	};
 
 	// Mimic a real HTTP request.
-	let res = create_client(input, "yahoo")?
+	let res = create_client(input, config, "yahoo")?
 		.post(SIGNUP_API)
 		.header("Origin", "https://login.yahoo.com")
 		.header("X-Requested-With", "XMLHttpRequest")
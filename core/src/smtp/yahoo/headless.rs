@@ -23,91 +23,106 @@ use fantoccini::Locator;
 use futures::future::select_ok;
 use futures::{Future, TryFutureExt};
 
-use crate::smtp::headless::{create_headless_client, HeadlessError};
+use crate::smtp::headless::{find_element, HeadlessConfig, HeadlessError};
+use crate::smtp::WebDriverPool;
 use crate::{smtp::SmtpDetails, LOG_TARGET};
 
 /// Check if a Hotmail/Outlook email exists by connecting to the password
 /// recovery page https://account.live.com/password/reset using a headless
 /// browser. Make sure you have a WebDriver server running locally before
-/// running this, or this will error.
-pub async fn check_headless(to_email: &str, webdriver: &str) -> Result<SmtpDetails, HeadlessError> {
+/// running this, or this will error. The WebDriver session is taken from the
+/// pool, and the page elements are found with the `headless` selectors.
+///
+/// Returns the details, with the selectors which matched the page elements.
+pub async fn check_headless(
+	to_email: &str,
+	webdriver: &str,
+	pool: &WebDriverPool,
+	headless: &HeadlessConfig,
+) -> Result<(SmtpDetails, Vec<String>), HeadlessError> {
 	log::debug!(
 		target: LOG_TARGET,
 		"[email={}] Using Yahoo password recovery in headless navigator",
 		to_email,
 	);
 
-	let c = create_headless_client(webdriver).await?;
-
-	// Navigate to Microsoft password recovery page.
-	c.goto("https://login.yahoo.com/forgot").await?;
-
-	// Wait for network/javascript/dom to make the input-box available
-	// and click it.
-	let input = c.wait().for_element(Locator::Id("username")).await?;
-	// Sometimes I get "input ... is not reachable by keyboard". Addind this
-	// small sleep helps.
-	sleep(Duration::from_millis(200));
-	input.send_keys(to_email).await?;
-
-	// Click on "Continue"
-	c.find(Locator::Css("button[name=\"verifyYid\"]"))
-		.await?
-		.click()
-		.await?;
-
-	// Depending on what answers we have on the account recovery page, return
-	// the relevant (is_deliverable, is_disabled) tuple.
-
-	// "Sorry, we don't recognise that email address or phone number".
-	let f1 = c
-		.wait()
-		.for_element(Locator::Css(".error-msg"))
-		.and_then(|_| async { Ok((false, false)) });
-	// "This account has been deactivated due to inactivity, but we would love to welcome you back!"
-	let f2 = c
-		.wait()
-		.for_element(Locator::Css(".ctx-account_is_locked"))
-		.and_then(|_| async { Ok((false, true)) });
-	// Recaptcha
-	let f3 = c
-		.wait()
-		.for_element(Locator::Css(".recaptcha-challenge"))
-		.and_then(|_| async { Ok((true, false)) });
-	// "Enter verification code sent to your email address"
-	let f4 = c
-		.wait()
-		.for_element(Locator::Id("email-verify-challenge"))
-		.and_then(|_| async { Ok((true, false)) });
-	// "Select an option to sign in to your account"
-	let f5 = c
-		.wait()
-		.for_element(Locator::Id("challenge-selector-challenge"))
-		.and_then(|_| async { Ok((true, false)) });
-
-	let vec = vec![
-		Box::pin(f1) as Pin<Box<dyn Future<Output = Result<(bool, bool), CmdError>> + Send>>,
-		Box::pin(f2),
-		Box::pin(f3),
-		Box::pin(f4),
-		Box::pin(f5),
-	];
-	let ((is_deliverable, is_disabled), _) = select_ok(vec).await?;
-
-	c.close().await?;
-
-	Ok(SmtpDetails {
-		can_connect_smtp: true,
-		has_full_inbox: false,
-		is_catch_all: false,
-		is_deliverable,
-		is_disabled,
+	pool.run(webdriver, |c| async move {
+		// Navigate to Microsoft password recovery page.
+		c.goto("https://login.yahoo.com/forgot").await?;
+
+		// Wait for network/javascript/dom to make the input-box available
+		// and click it.
+		let (input, input_selector) = find_element(&c, &headless.yahoo_email_input).await?;
+		// Sometimes I get "input ... is not reachable by keyboard". Addind this
+		// small sleep helps.
+		sleep(Duration::from_millis(200));
+		input.send_keys(to_email).await?;
+
+		// Click on "Continue"
+		let (submit, submit_selector) = find_element(&c, &headless.yahoo_submit).await?;
+		submit.click().await?;
+
+		// Depending on what answers we have on the account recovery page, return
+		// the relevant (is_deliverable, is_disabled) tuple.
+
+		// "Sorry, we don't recognise that email address or phone number".
+		let f1 = c
+			.wait()
+			.for_element(Locator::Css(".error-msg"))
+			.and_then(|_| async { Ok((false, false)) });
+		// "This account has been deactivated due to inactivity, but we would love to welcome you back!"
+		let f2 = c
+			.wait()
+			.for_element(Locator::Css(".ctx-account_is_locked"))
+			.and_then(|_| async { Ok((false, true)) });
+		// Recaptcha
+		let f3 = c
+			.wait()
+			.for_element(Locator::Css(".recaptcha-challenge"))
+			.and_then(|_| async { Ok((true, false)) });
+		// "Enter verification code sent to your email address"
+		let f4 = c
+			.wait()
+			.for_element(Locator::Id("email-verify-challenge"))
+			.and_then(|_| async { Ok((true, false)) });
+		// "Select an option to sign in to your account"
+		let f5 = c
+			.wait()
+			.for_element(Locator::Id("challenge-selector-challenge"))
+			.and_then(|_| async { Ok((true, false)) });
+
+		let vec = vec![
+			Box::pin(f1) as Pin<Box<dyn Future<Output = Result<(bool, bool), CmdError>> + Send>>,
+			Box::pin(f2),
+			Box::pin(f3),
+			Box::pin(f4),
+			Box::pin(f5),
+		];
+		let ((is_deliverable, is_disabled), _) = select_ok(vec).await?;
+
+		Ok((
+			SmtpDetails {
+				can_connect_smtp: true,
+				has_full_inbox: false,
+				is_catch_all: false,
+				catch_all_confidence: None,
+				enhanced_status: None,
+				fingerprint: None,
+				description: None,
+				accepts_at_rcpt_only: false,
+				is_deliverable,
+				is_disabled,
+			},
+			vec![input_selector, submit_selector],
+		))
 	})
+	.await
 }
 
 #[cfg(test)]
 mod tests {
 	use super::check_headless;
+	use crate::smtp::{HeadlessConfig, WebDriverPool};
 
 	// Ignoring this test as it requires a local process of WebDriver running on
 	// "http://localhost:9515". To debug the headless password recovery page,
@@ -116,25 +131,38 @@ mod tests {
 	#[tokio::test]
 	#[ignore = "Run a webdriver server locally to test this"]
 	async fn test_yahoo_address() {
+		let pool = WebDriverPool::default();
+		let headless = HeadlessConfig::default();
 		// Run 5 headless sessions with the below dummy emails.
 		for _ in 0..5 {
 			// Email does not exist.
-			let res = check_headless("test42134@yahoo.com", "http://localhost:9515")
-				.await
-				.unwrap();
+			let (res, _) = check_headless(
+				"test42134@yahoo.com",
+				"http://localhost:9515",
+				&pool,
+				&headless,
+			)
+			.await
+			.unwrap();
 			assert!(!res.is_deliverable);
 
 			// Disabled email.
-			let res = check_headless("amaury@yahoo.com", "http://localhost:9515")
-				.await
-				.unwrap();
+			let (res, _) = check_headless(
+				"amaury@yahoo.com",
+				"http://localhost:9515",
+				&pool,
+				&headless,
+			)
+			.await
+			.unwrap();
 			assert!(!res.is_deliverable);
 			assert!(res.is_disabled);
 
 			// OK email.
-			let res = check_headless("test2@yahoo.com", "http://localhost:9515")
-				.await
-				.unwrap();
+			let (res, _) =
+				check_headless("test2@yahoo.com", "http://localhost:9515", &pool, &headless)
+					.await
+					.unwrap();
 			assert!(res.is_deliverable);
 			assert!(!res.is_disabled);
 		}
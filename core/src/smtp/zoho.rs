@@ -0,0 +1,92 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Zoho Mail answers `RCPT TO` in its own way: it rejects unknown recipients
+//! of the domains it hosts with "553 Relaying disallowed", and it throttles
+//! verifiers with replies that look like the mailbox doesn't exist. These
+//! replies are only read the Zoho way on Zoho's MX hosts.
+
+use crate::mx::find_mx_provider;
+
+/// What a negative reply of Zoho to `RCPT TO` says about the mailbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZohoReply {
+	/// The mailbox doesn't exist.
+	NotFound,
+	/// Zoho is throttling or blocking us, the reply says nothing about the
+	/// mailbox.
+	Unknown,
+}
+
+/// Check if the MX host is from Zoho Mail.
+pub fn is_zoho(host: &str) -> bool {
+	find_mx_provider(host).as_deref() == Some("Zoho")
+}
+
+/// Interpret a negative reply of Zoho to `RCPT TO`, or return None to fall
+/// back to the generic parsing. `e` must be lowercase.
+pub fn interpret_rcpt_error(e: &str) -> Option<ZohoReply> {
+	// 550 5.4.6 Unusual sending activity detected. Please try after sometime.
+	if e.contains("unusual sending activity")
+	// 554 5.7.1 : Recipient address rejected: Access denied
+	|| e.contains("access denied")
+	// 451 4.2.1 Too many recipients, try again later
+	|| e.contains("try again later")
+	{
+		return Some(ZohoReply::Unknown);
+	}
+
+	// 553 Relaying disallowed. Invalid Domain - foo@example.com
+	// 553 Relaying disallowed as foo@example.com
+	if e.contains("relaying disallowed") {
+		return Some(ZohoReply::NotFound);
+	}
+
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_detect_zoho_mx_hosts() {
+		assert!(is_zoho("mx.zoho.com."));
+		assert!(is_zoho("mx2.zoho.eu."));
+		assert!(is_zoho("MX3.ZOHO.IN."));
+		assert!(!is_zoho("aspmx.l.google.com."));
+	}
+
+	#[test]
+	fn should_interpret_zoho_replies() {
+		assert_eq!(
+			interpret_rcpt_error("553 relaying disallowed as foo@example.com"),
+			Some(ZohoReply::NotFound)
+		);
+		assert_eq!(
+			interpret_rcpt_error("554 5.7.1 : recipient address rejected: access denied"),
+			Some(ZohoReply::Unknown)
+		);
+		assert_eq!(
+			interpret_rcpt_error("550 5.4.6 unusual sending activity detected"),
+			Some(ZohoReply::Unknown)
+		);
+		assert_eq!(
+			interpret_rcpt_error("550 5.1.1 user does not exist - foo@example.com"),
+			None
+		);
+	}
+}
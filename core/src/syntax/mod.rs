@@ -16,17 +16,57 @@
 
 mod normalize;
 
-use async_smtp::EmailAddress;
+use async_smtp::error::Error as AsyncSmtpError;
 use levenshtein::levenshtein;
 use normalize::normalize_email;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::str::FromStr;
 
+/// An email address, as sent in the `RCPT TO` command. Like async-smtp's
+/// `EmailAddress`, it can't contain characters which would break the SMTP
+/// command, but its local part may be non-ASCII (RFC 6531).
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct EmailAddress(String);
+
+impl EmailAddress {
+	pub fn new(address: String) -> Result<EmailAddress, AsyncSmtpError> {
+		if address
+			.chars()
+			.any(|c| c.is_control() || c.is_whitespace() || c == '<' || c == '>')
+		{
+			return Err(AsyncSmtpError::InvalidEmailAddress);
+		}
+
+		Ok(EmailAddress(address))
+	}
+}
+
+impl FromStr for EmailAddress {
+	type Err = AsyncSmtpError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		EmailAddress::new(s.to_string())
+	}
+}
+
+impl fmt::Display for EmailAddress {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl AsRef<str> for EmailAddress {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
 /// Syntax information after parsing an email address
 #[derive(Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct SyntaxDetails {
-	/// The email address as a async_smtp `EmailAddress`. It will be `None` if
-	/// the email address is ill-formed.
+	/// The email address as an `EmailAddress`. It will be `None` if the email
+	/// address is ill-formed.
 	pub address: Option<EmailAddress>,
 	/// The domain name, after "@". It will be the empty string if the email
 	/// address if ill-formed.
@@ -58,22 +98,9 @@ impl Default for SyntaxDetails {
 /// From an `email_address` string, compute syntax information about it, such as
 /// username and domain.
 pub fn check_syntax(email_address: &str) -> SyntaxDetails {
-	let email_address = match EmailAddress::from_str(email_address) {
-		Ok(m) => {
-			if mailchecker::is_valid(email_address) {
-				m
-			} else {
-				return SyntaxDetails {
-					address: None,
-					domain: "".into(),
-					is_valid_syntax: false,
-					username: "".into(),
-					normalized_email: None,
-					suggestion: None,
-				};
-			}
-		}
-		_ => {
+	let email_address = match parse_address(email_address) {
+		Some(m) => m,
+		None => {
 			return SyntaxDetails {
 				address: None,
 				domain: "".into(),
@@ -107,6 +134,39 @@ pub fn check_syntax(email_address: &str) -> SyntaxDetails {
 	}
 }
 
+/// Parse the email address into an `EmailAddress`. Internationalized
+/// addresses (RFC 6531) are accepted if only their local part is non-ASCII:
+/// the domain must be in its ASCII (punycode) form.
+fn parse_address(email_address: &str) -> Option<EmailAddress> {
+	let (local_part, domain) = email_address.rsplit_once('@')?;
+	// fast_chemail only accepts ASCII, so we check an
+	// internationalized address with the non-ASCII letters and digits of its
+	// local part replaced by an ASCII letter.
+	let checked = if local_part.is_ascii() {
+		email_address.to_string()
+	} else {
+		let local_part = local_part
+			.chars()
+			.map(|c| {
+				if !c.is_ascii() && c.is_alphanumeric() {
+					'a'
+				} else {
+					c
+				}
+			})
+			.collect::<String>();
+		format!("{local_part}@{domain}")
+	};
+
+	// This is mailchecker's format check, without its list of disposable
+	// domains: they are flagged in the misc checks, after the
+	// `disposable_allowlist`.
+	if !fast_chemail::is_valid_email(&checked) {
+		return None;
+	}
+	EmailAddress::from_str(email_address).ok()
+}
+
 const MAIL_PROVIDERS: &[&str] = &[
 	"gmail.com",
 	"yahoo.com",
@@ -183,6 +243,44 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn should_accept_internationalized_local_part() {
+		let syntax = check_syntax("jöe.müller@bar.com");
+		assert!(syntax.is_valid_syntax);
+		assert_eq!(syntax.username, "jöe.müller");
+		assert_eq!(syntax.domain, "bar.com");
+		assert_eq!(
+			syntax.address.map(|a| a.to_string()).as_deref(),
+			Some("jöe.müller@bar.com")
+		);
+
+		// The domain must be in its punycode form.
+		assert!(!check_syntax("joe@bär.com").is_valid_syntax);
+		assert!(!check_syntax("jöe\u{a0}x@bar.com").is_valid_syntax);
+	}
+
+	#[test]
+	fn should_accept_disposable_domains() {
+		// Unlike `mailchecker::is_valid`, the syntax check doesn't reject
+		// disposable domains, see `MiscDetails::is_disposable`.
+		assert!(!mailchecker::is_valid("foo@yopmail.com"));
+		assert!(check_syntax("foo@yopmail.com").is_valid_syntax);
+		assert!(check_syntax("foo@sub.mailinator.com").is_valid_syntax);
+	}
+
+	#[test]
+	fn should_reject_characters_breaking_the_smtp_command() {
+		assert!(EmailAddress::new("jöe@bar.com".into()).is_ok());
+		for address in [
+			"foo>@bar.com",
+			"<foo@bar.com",
+			"foo\r\n@bar.com",
+			"jöe\u{2003}@bar.com",
+		] {
+			assert!(EmailAddress::new(address.into()).is_err(), "{}", address);
+		}
+	}
+
 	#[test]
 	fn should_suggest_a_correct_mail_if_similar() {
 		let mut syntax = SyntaxDetails {
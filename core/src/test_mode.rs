@@ -0,0 +1,134 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Deterministic test mode: canned outputs returned by `check_email` for
+//! given addresses or domains, without any network call, so that downstream
+//! crates can test how they handle each `Reachable`. Only honoured with the
+//! `test-mode` feature, so that they can't be returned in production.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{CheckEmailInput, CheckEmailOutput};
+
+/// The function building a canned output for an input.
+pub type CannedOutputFn = dyn Fn(&CheckEmailInput) -> CheckEmailOutput + Send + Sync;
+
+/// Canned outputs by address or domain, see `ReacherConfig`'s
+/// `canned_results`. Addresses take precedence over domains, and both are
+/// matched case-insensitively.
+#[derive(Clone, Default)]
+pub struct CannedResults {
+	by_address: HashMap<String, Arc<CannedOutputFn>>,
+	by_domain: HashMap<String, Arc<CannedOutputFn>>,
+}
+
+impl CannedResults {
+	/// Return the output of `canned` for this address.
+	pub fn address<F>(mut self, address: &str, canned: F) -> Self
+	where
+		F: Fn(&CheckEmailInput) -> CheckEmailOutput + Send + Sync + 'static,
+	{
+		self.by_address
+			.insert(address.to_lowercase(), Arc::new(canned));
+		self
+	}
+
+	/// Return the output of `canned` for all the addresses of this domain.
+	pub fn domain<F>(mut self, domain: &str, canned: F) -> Self
+	where
+		F: Fn(&CheckEmailInput) -> CheckEmailOutput + Send + Sync + 'static,
+	{
+		self.by_domain
+			.insert(domain.to_lowercase(), Arc::new(canned));
+		self
+	}
+
+	/// The canned output for the input's email, if it's mapped.
+	#[cfg(feature = "test-mode")]
+	pub(crate) fn find(&self, input: &CheckEmailInput) -> Option<CheckEmailOutput> {
+		let to_email = input.to_email.trim().to_lowercase();
+		let domain = to_email.rsplit_once('@').map(|(_, domain)| domain);
+		let canned = self
+			.by_address
+			.get(&to_email)
+			.or_else(|| self.by_domain.get(domain?))?;
+
+		Some(canned(input))
+	}
+}
+
+impl fmt::Debug for CannedResults {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("CannedResults")
+			.field("addresses", &self.by_address.keys())
+			.field("domains", &self.by_domain.keys())
+			.finish()
+	}
+}
+
+#[cfg(all(test, feature = "test-mode"))]
+mod tests {
+	use super::CannedResults;
+	use crate::config::ReacherConfig;
+	use crate::util::mock_dns::{response, MockDnsServer};
+	use crate::{check_email, CheckEmailInput, CheckEmailOutput, Reachable};
+	use hickory_proto::op::ResponseCode;
+	use std::sync::Arc;
+
+	fn canned(is_reachable: Reachable) -> impl Fn(&CheckEmailInput) -> CheckEmailOutput {
+		move |input| CheckEmailOutput {
+			input: input.to_email.clone(),
+			is_reachable: is_reachable.clone(),
+			..Default::default()
+		}
+	}
+
+	#[tokio::test]
+	async fn should_return_canned_outputs_without_network() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			canned_results: Some(
+				CannedResults::default()
+					.address("safe@example.com", canned(Reachable::Safe))
+					.domain("example.com", canned(Reachable::Risky)),
+			),
+			..Default::default()
+		};
+		let check = |email: &str| {
+			let input = CheckEmailInput {
+				to_email: email.into(),
+				..Default::default()
+			};
+			let config = &config;
+			async move { check_email(&input, config).await }
+		};
+
+		let output = check("Safe@Example.com").await;
+		assert_eq!(output.input, "Safe@Example.com");
+		assert_eq!(output.is_reachable, Reachable::Safe);
+		let output = check("other@example.com").await;
+		assert_eq!(output.is_reachable, Reachable::Risky);
+		assert_eq!(server.queries(), 0);
+
+		// Unmapped emails are verified as usual.
+		let output = check("foo@example.org").await;
+		assert_eq!(output.is_reachable, Reachable::Invalid);
+		assert_eq!(server.queries(), 1);
+	}
+}
@@ -0,0 +1,125 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Export verification outputs in the Elasticsearch `_bulk` format, to index
+//! them without reshaping them first.
+
+use serde_json::{json, Map, Value};
+
+use crate::CheckEmailOutput;
+
+impl CheckEmailOutput {
+	/// Get the Elasticsearch `_bulk` lines indexing this output into `index`,
+	/// i.e. an `{"index":{"_index":...}}` action line followed by the
+	/// document line, each ending with a newline.
+	///
+	/// The document is the serialized output with its nested fields
+	/// flattened into `_`-separated keys, e.g. `smtp_is_deliverable` or
+	/// `debug_smtp_verif_method_type`, so that Elasticsearch doesn't map them
+	/// as objects. Arrays are kept as they are. Timestamps are in the ISO 8601
+	/// format, and `@timestamp` is the end time of the verification.
+	pub fn to_es_bulk(&self, index: &str) -> String {
+		let mut document = Map::new();
+		document.insert(
+			"@timestamp".into(),
+			Value::String(self.debug.end_time.to_rfc3339()),
+		);
+		flatten(
+			"",
+			serde_json::to_value(self).unwrap_or_default(),
+			&mut document,
+		);
+
+		format!(
+			"{}\n{}\n",
+			json!({ "index": { "_index": index } }),
+			Value::Object(document)
+		)
+	}
+}
+
+/// Get the Elasticsearch `_bulk` lines indexing all these outputs into
+/// `index`, see [CheckEmailOutput::to_es_bulk]. The result can be sent as
+/// is as the body of a `_bulk` request.
+pub fn to_es_bulk(outputs: &[CheckEmailOutput], index: &str) -> String {
+	outputs
+		.iter()
+		.map(|output| output.to_es_bulk(index))
+		.collect()
+}
+
+/// Insert the leaves of `value` into `document`, their keys being their
+/// path from `prefix` joined by `_`.
+fn flatten(prefix: &str, value: Value, document: &mut Map<String, Value>) {
+	match value {
+		Value::Object(map) if !map.is_empty() => {
+			for (key, value) in map {
+				let key = if prefix.is_empty() {
+					key
+				} else {
+					format!("{}_{}", prefix, key)
+				};
+				flatten(&key, value, document);
+			}
+		}
+		value => {
+			document.insert(prefix.to_string(), value);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::to_es_bulk;
+	use crate::{CheckEmailOutput, Reachable, SmtpDetails};
+	use serde_json::Value;
+
+	#[test]
+	fn should_output_valid_ndjson() {
+		let output = CheckEmailOutput {
+			input: "foo@example.com".into(),
+			is_reachable: Reachable::Safe,
+			smtp: Ok(SmtpDetails {
+				can_connect_smtp: true,
+				is_deliverable: true,
+				..Default::default()
+			}),
+			..Default::default()
+		};
+
+		let bulk = to_es_bulk(&[output], "emails");
+
+		assert!(bulk.ends_with('\n'));
+		let lines = bulk
+			.lines()
+			.map(|line| serde_json::from_str::<Value>(line).unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(lines.len(), 2);
+		assert_eq!(
+			lines[0],
+			serde_json::json!({ "index": { "_index": "emails" } })
+		);
+		let document = lines[1].as_object().unwrap();
+		assert_eq!(document["input"], "foo@example.com");
+		assert_eq!(document["is_reachable"], "safe");
+		assert_eq!(document["smtp_is_deliverable"], true);
+		assert!(document.values().all(|value| !value.is_object()));
+		assert!(chrono::DateTime::parse_from_rfc3339(
+			document["debug_start_time"].as_str().unwrap()
+		)
+		.is_ok());
+	}
+}
@@ -14,17 +14,25 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 
+use async_native_tls::Protocol;
 use async_smtp::{ClientSecurity, ClientTlsParameters};
 use chrono::{DateTime, Utc};
 use derive_builder::Builder;
 use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 
 use crate::misc::{MiscDetails, MiscError};
+use crate::mta_sts::{MtaStsDetails, MtaStsError};
 use crate::mx::{MxDetails, MxError};
-use crate::smtp::{SmtpDebug, SmtpDetails, SmtpError, SmtpErrorDesc};
+use crate::smtp::{
+	is_gmail, is_hotmail_b2b, is_hotmail_b2c, is_yahoo, ProxyProtocol, SmtpDebug, SmtpDetails,
+	SmtpError, SmtpErrorDesc,
+};
 use crate::syntax::SyntaxDetails;
 
 /// Perform the email verification via a specified proxy. The usage of a proxy
@@ -72,10 +80,57 @@ impl SmtpSecurity {
 	}
 }
 
+/// A TLS protocol version.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum TlsVersion {
+	#[serde(rename = "1.0")]
+	Tls10,
+	#[serde(rename = "1.1")]
+	Tls11,
+	#[default]
+	#[serde(rename = "1.2")]
+	Tls12,
+}
+
+impl From<TlsVersion> for Protocol {
+	fn from(version: TlsVersion) -> Self {
+		match version {
+			TlsVersion::Tls10 => Protocol::Tlsv10,
+			TlsVersion::Tls11 => Protocol::Tlsv11,
+			TlsVersion::Tls12 => Protocol::Tlsv12,
+		}
+	}
+}
+
+/// Define how to interpret a 252 reply to `RCPT TO`, i.e. "Cannot VRFY user,
+/// but will accept message", which some servers (e.g. Exchange) send whether
+/// the mailbox exists or not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum Rcpt252Policy {
+	/// Fail the SMTP verification with a `CannotVerify` error, so the email
+	/// is Unknown.
+	#[default]
+	Unknown,
+	/// Consider the email deliverable.
+	Deliverable,
+}
+
+/// The methods to verify the emails of a provider, e.g. [YahooVerifMethod].
+pub trait ProviderVerifMethod: FromStr + Copy + 'static {
+	/// All the methods.
+	const ALL: &'static [Self];
+
+	/// The name of the method, as parsed by `FromStr` and (de)serialized.
+	/// The capitalized names, e.g. "Api", are still deserialized.
+	fn as_str(&self) -> &'static str;
+}
+
 /// Select how to verify Yahoo emails.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum YahooVerifMethod {
 	/// Use Yahoo's API to check if an email exists.
+	#[serde(alias = "Api")]
 	Api,
 	/// Use Yahoo's password recovery page to check if an email exists.
 	///
@@ -84,67 +139,110 @@ pub enum YahooVerifMethod {
 	/// variable RCH_WEBDRIVER_ADDR. We recommend running chromedriver (and not
 	/// geckodriver) as it allows parallel requests.
 	#[default]
+	#[serde(alias = "Headless")]
 	Headless,
 	/// Use Yahoo's SMTP servers to check if an email exists.
+	#[serde(alias = "Smtp")]
 	Smtp,
 }
 
+impl ProviderVerifMethod for YahooVerifMethod {
+	const ALL: &'static [Self] = &[Self::Api, Self::Headless, Self::Smtp];
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Api => "api",
+			Self::Headless => "headless",
+			Self::Smtp => "smtp",
+		}
+	}
+}
+
 impl FromStr for YahooVerifMethod {
 	type Err = String;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match s {
-			"api" => Ok(Self::Api),
-			"headless" => Ok(Self::Headless),
-			"smtp" => Ok(Self::Smtp),
-			_ => Err(format!("Unknown yahoo verify method: {}", s)),
-		}
+		Self::ALL
+			.iter()
+			.find(|method| method.as_str() == s)
+			.copied()
+			.ok_or_else(|| format!("Unknown yahoo verify method: {}", s))
 	}
 }
 
 /// Select how to verify Gmail emails.
 #[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum GmailVerifMethod {
-	/// Use Gmail's API to check if an email exists.
+	/// Use Gmail's API to check if an email exists. Only applies to
+	/// @gmail.com and @googlemail.com addresses, and falls back to SMTP if
+	/// Gmail rate-limits us.
+	#[serde(alias = "Api")]
 	Api,
 	/// Use Gmail's SMTP servers to check if an email exists.
 	#[default]
+	#[serde(alias = "Smtp")]
 	Smtp,
 }
 
+impl ProviderVerifMethod for GmailVerifMethod {
+	const ALL: &'static [Self] = &[Self::Api, Self::Smtp];
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Api => "api",
+			Self::Smtp => "smtp",
+		}
+	}
+}
+
 impl FromStr for GmailVerifMethod {
 	type Err = String;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match s {
-			"api" => Ok(Self::Api),
-			"smtp" => Ok(Self::Smtp),
-			_ => Err(format!("Unknown gmail verify method: {}", s)),
-		}
+		Self::ALL
+			.iter()
+			.find(|method| method.as_str() == s)
+			.copied()
+			.ok_or_else(|| format!("Unknown gmail verify method: {}", s))
 	}
 }
 
 /// Select how to verify Hotmail B2B emails.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum HotmailB2BVerifMethod {
 	/// Use Hotmail's SMTP servers to check if an email exists.
 	#[default]
+	#[serde(alias = "Smtp")]
 	Smtp,
 }
 
+impl ProviderVerifMethod for HotmailB2BVerifMethod {
+	const ALL: &'static [Self] = &[Self::Smtp];
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Smtp => "smtp",
+		}
+	}
+}
+
 impl FromStr for HotmailB2BVerifMethod {
 	type Err = String;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match s {
-			"smtp" => Ok(Self::Smtp),
-			_ => Err(format!("Unknown hotmailb2b verify method: {}", s)),
-		}
+		Self::ALL
+			.iter()
+			.find(|method| method.as_str() == s)
+			.copied()
+			.ok_or_else(|| format!("Unknown hotmailb2b verify method: {}", s))
 	}
 }
 
 /// Select how to verify Hotmail B2C emails.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum HotmailB2CVerifMethod {
 	/// Use Hotmail's password recovery page to check if an email exists.
 	///
@@ -153,27 +251,75 @@ pub enum HotmailB2CVerifMethod {
 	/// variable RCH_WEBDRIVER_ADDR. We recommend running chromedriver (and not
 	/// geckodriver) as it allows parallel requests.
 	#[default]
+	#[serde(alias = "Headless")]
 	Headless,
 	/// Use Hotmail's SMTP servers to check if an email exists.
+	#[serde(alias = "Smtp")]
 	Smtp,
 }
 
+impl ProviderVerifMethod for HotmailB2CVerifMethod {
+	const ALL: &'static [Self] = &[Self::Headless, Self::Smtp];
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Headless => "headless",
+			Self::Smtp => "smtp",
+		}
+	}
+}
+
 impl FromStr for HotmailB2CVerifMethod {
 	type Err = String;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match s {
-			"headless" => Ok(Self::Headless),
-			"smtp" => Ok(Self::Smtp),
-			_ => Err(format!("Unknown hotmailb2c verify method: {}", s)),
+		Self::ALL
+			.iter()
+			.find(|method| method.as_str() == s)
+			.copied()
+			.ok_or_else(|| format!("Unknown hotmailb2c verify method: {}", s))
+	}
+}
+
+/// The email providers which verifications are routed to, as detected from
+/// the MX host, see `provider_proxies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+	/// Gmail and Google Workspace.
+	Gmail,
+	/// Microsoft 365, i.e. Hotmail B2B.
+	HotmailB2b,
+	/// Consumer Microsoft domains, e.g. @hotmail.com or @outlook.com.
+	HotmailB2c,
+	/// Yahoo.
+	Yahoo,
+	/// Any other provider.
+	EverythingElse,
+}
+
+impl Provider {
+	/// Detect the provider from an MX host.
+	pub fn from_mx_host(host: &str) -> Self {
+		if is_hotmail_b2c(host) {
+			Provider::HotmailB2c
+		} else if is_hotmail_b2b(host) {
+			Provider::HotmailB2b
+		} else if is_gmail(host) {
+			Provider::Gmail
+		} else if is_yahoo(host) {
+			Provider::Yahoo
+		} else {
+			Provider::EverythingElse
 		}
 	}
 }
 
 /// Builder pattern for the input argument into the main `email_exists`
-/// function.
+/// function. When deserializing, missing fields take their default value.
 #[derive(Builder, Debug, Clone, Deserialize, Serialize)]
 #[builder(default)]
+#[serde(default)]
 pub struct CheckEmailInput {
 	/// The email to validate.
 	pub to_email: String,
@@ -189,6 +335,13 @@ pub struct CheckEmailInput {
 	/// Perform the email verification via the specified SOCK5 proxy. The usage of a
 	/// proxy is optional.
 	pub proxy: Option<CheckEmailInputProxy>,
+	/// Use these SOCKS5 proxies instead of `proxy` for the verifications
+	/// routed to their provider, e.g. a residential proxy for Hotmail only.
+	/// Providers without an entry use `proxy`.
+	///
+	/// Defaults to no entry.
+	#[serde(default)]
+	pub provider_proxies: HashMap<Provider, CheckEmailInputProxy>,
 	/// SMTP port to use for email validation. Generally, ports 25, 465, 587
 	/// and 2525 are used.
 	///
@@ -199,6 +352,54 @@ pub struct CheckEmailInput {
 	///
 	/// Defaults to 30s.
 	pub smtp_timeout: Option<Duration>,
+	/// SMTP timeouts overriding `smtp_timeout` for some providers, keyed by
+	/// the email's domain or by the MX host's domain, e.g. "gmail.com" or
+	/// "outlook.com". Keys also match their subdomains. The timeout for the
+	/// email's domain takes precedence over the one for the MX host.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub smtp_timeouts: Option<HashMap<String, Duration>>,
+	/// Maximum time to get the SMTP server's 220 greeting, once connected,
+	/// instead of `smtp_timeout`, which still bounds connecting and the
+	/// commands which follow. Some servers delay their 220 banner on purpose
+	/// to catch clients which talk too early; we always wait for the full
+	/// banner before sending EHLO.
+	///
+	/// Defaults to None, which uses `smtp_timeout`.
+	#[serde(default)]
+	pub smtp_greeting_timeout: Option<Duration>,
+	/// For debugging: verify the email on the MX host at this index, among
+	/// the domain's MX hosts sorted by preference (0 being the most
+	/// preferred), instead of choosing one. If there's no MX host at this
+	/// index, the SMTP verification fails with an `InvalidMxHostIndex` error.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub mx_host_index: Option<usize>,
+	/// If the chosen MX host fails with a transient error (a 4xx reply, a
+	/// connection error or a timeout), try the domain's other MX hosts, by
+	/// preference, until one gives an answer. Ignored if `mx_host_index` is
+	/// set. Hosts refusing to relay the email are always skipped this way.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub smtp_mx_fallback: bool,
+	/// Shuffle the MX hosts which share the same preference, so that the
+	/// verifications spread over them like regular MTAs do, instead of
+	/// always choosing the same one. Hosts are still tried by preference.
+	/// Ignored if `mx_host_index` is set.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub shuffle_equal_mx: bool,
+	/// For debugging: record the result on each MX host tried in
+	/// `debug.smtp.per_host`, e.g. to see backup MX hosts disagreeing with
+	/// the primary ones when `smtp_mx_fallback` is set.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub smtp_per_host_debug: bool,
 	/// Select how to verify Yahoo emails.
 	///
 	/// Defaults to Headless.
@@ -215,14 +416,46 @@ pub struct CheckEmailInput {
 	///
 	/// Defaults to Headless.
 	pub hotmailb2c_verif_method: HotmailB2CVerifMethod,
+	/// When a provider's email is verified with another method than SMTP,
+	/// e.g. Yahoo's Headless, also verify it via SMTP, and only keep the
+	/// result if both methods agree on the deliverability. Otherwise, the
+	/// SMTP verification fails with a `MethodsDisagree` error, so the email
+	/// is Unknown. Both results are recorded in the SMTP debug details'
+	/// `cross_check`. This trades latency for accuracy.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub cross_check: bool,
 	/// Whether to check if a gravatar image is existing for the given email.
 	/// Adds a bit of latency to the verification process.
 	///
 	/// Defaults to false.
 	pub check_gravatar: bool,
+	/// Whether to check the MTA-STS policy of the email's domain, and if the
+	/// domain's MX hosts match it. Adds a DNS lookup, and an HTTPS request if
+	/// the domain publishes a policy.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub check_mta_sts: bool,
+	/// Whether to check if the domain of `from_email` passes SPF for the IP
+	/// we connect to the SMTP server from, in `debug.smtp.from_spf_aligned`.
+	/// Strict servers reject senders failing SPF. Adds a few DNS lookups.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub check_from_spf: bool,
 	/// Check if a the email address is present in HaveIBeenPwned API.
 	/// If the api_key is filled, HaveIBeenPwned API is checked
 	pub haveibeenpwned_api_key: Option<String>,
+	/// Whether to also fetch the pastes the email address appears in from
+	/// the HaveIBeenPwned API, in `misc.pastes`. Only used if
+	/// `haveibeenpwned_api_key` is set. Adds an API call, which counts
+	/// against the key's rate limit.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub check_haveibeenpwned_pastes: bool,
 	/// Number of retries of SMTP connections to do. Setting to 2 might bypass
 	/// greylisting on some servers, but takes more time.
 	///
@@ -232,6 +465,248 @@ pub struct CheckEmailInput {
 	///
 	/// Defaults to Opportunistic.
 	pub smtp_security: SmtpSecurity,
+	/// Verify the certificate of the SMTP server when using TLS. If the
+	/// certificate is invalid (expired, self-signed, wrong hostname), the
+	/// SMTP check fails with a `TlsCertInvalid` error. Many mail servers have
+	/// invalid certificates, so this is off by default.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub smtp_verify_certificates: bool,
+	/// The minimum TLS version to negotiate with the SMTP server, when TLS is
+	/// required, i.e. with the `Required` or `Wrapper` `smtp_security`, or
+	/// with `require_starttls_capability`. If the server only offers older
+	/// versions, the SMTP check fails with a `TlsVersionTooLow` error.
+	/// Opportunistic TLS accepts any version.
+	///
+	/// Defaults to 1.2.
+	#[serde(default)]
+	pub min_tls_version: TlsVersion,
+	/// Only verify the email if the SMTP server offers STARTTLS in its EHLO
+	/// reply, and fail with a `NoTlsSupport` error otherwise, instead of
+	/// going on over plaintext. The connection is upgraded to TLS, unless
+	/// `smtp_security` is None. Doesn't apply with a `unix_socket`.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub require_starttls_capability: bool,
+	/// Timeout for the HTTP requests made during the misc checks (Gravatar,
+	/// HaveIBeenPwned, MTA-STS), independent of the SMTP timeout. Set to None if you don't want to use
+	/// a timeout.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub http_timeout: Option<Duration>,
+	/// User-Agent header to send with the HTTP requests made during the misc
+	/// checks (Gravatar, HaveIBeenPwned). HaveIBeenPwned requires a
+	/// descriptive User-Agent, so "reacher" is used there if this is None.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub user_agent: Option<String>,
+	/// Domains which are never flagged as disposable, even if they are in
+	/// the built-in list of disposable email providers, or in the list set
+	/// in `ReacherConfig`. Subdomains of the listed domains are allowlisted
+	/// too.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub disposable_allowlist: Option<Vec<String>>,
+	/// Domains which must never be contacted. Emails on these domains, or on
+	/// their subdomains, are returned as Unknown without any network call,
+	/// with a `Blocklisted` SMTP error. Entries can be a TLD, e.g. "gov".
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub domain_blocklist: Option<Vec<String>>,
+	/// Trusted domains, e.g. our own. Emails on these domains are returned
+	/// as Safe without any network call, with an `Allowlisted` SMTP error.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub domain_allowlist: Option<Vec<String>>,
+	/// Whether the entries of `domain_allowlist` also match their
+	/// subdomains.
+	///
+	/// Defaults to true.
+	pub domain_allowlist_subdomains: bool,
+	/// Domains of the mail servers of domain parking services. If all the MX
+	/// records of an email's domain are on these domains (or their
+	/// subdomains), the email is Invalid, with a `Parked` SMTP error.
+	///
+	/// Defaults to None, which uses a bundled list of parking services.
+	#[serde(default)]
+	pub parking_mx_hosts: Option<Vec<String>>,
+	/// Maximum size, in bytes, of a single SMTP response, the greeting and
+	/// the EHLO response included. Reading stops as soon as a response goes
+	/// above it, and the SMTP verification fails with a `ResponseTooLarge`
+	/// error.
+	///
+	/// Defaults to None, which means 64KiB.
+	#[serde(default)]
+	pub smtp_max_response_bytes: Option<usize>,
+	/// Maximum number of lines (i.e. the first line plus its continuation
+	/// lines) of a single SMTP response. Above it, the SMTP verification
+	/// fails with a `ResponseTooLarge` error. Like `smtp_max_response_bytes`,
+	/// it's enforced while the response is read.
+	///
+	/// Defaults to None, which means 100 lines.
+	#[serde(default)]
+	pub smtp_max_response_lines: Option<usize>,
+	/// Use the null sender, i.e. `MAIL FROM:<>` (as used for bounces), instead
+	/// of `from_email`. Some servers are more lenient with the null sender,
+	/// others are stricter, so this can change the results.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub use_null_sender: bool,
+	/// Adapt the SMTP timeout to each MX host: the timeout becomes 3 times
+	/// the host's average response latency, learned from the previous
+	/// verifications sharing the same `ReacherConfig`. It's bounded by 1s
+	/// and by `smtp_timeout`, and is not used if `smtp_timeout` is None.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub adaptive_timeout: bool,
+	/// Send a PROXY protocol header of this version at the start of the SMTP
+	/// connection, for SMTP servers behind a load balancer which requires
+	/// it. Not supported with a SOCKS5 `proxy`.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub proxy_protocol: Option<ProxyProtocol>,
+	/// Source address announced in the PROXY protocol header. If None, the
+	/// local address of the connection to the SMTP server is used.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub proxy_protocol_source: Option<SocketAddr>,
+	/// Local IP address the SMTP connections originate from, e.g. to match
+	/// the PTR and SPF records on a host with several IPs. Not supported
+	/// with a SOCKS5 `proxy`.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub bind_addr: Option<IpAddr>,
+	/// How to interpret a 252 reply to `RCPT TO`.
+	///
+	/// Defaults to Unknown.
+	#[serde(default)]
+	pub rcpt_252_policy: Rcpt252Policy,
+	/// Only return Safe for emails on domains confirmed not to be catch-all.
+	/// Catch-all, disposable and role accounts are always Risky, but by
+	/// default a failed catch-all check (e.g. the server answers with a
+	/// temporary error) counts as not catch-all. In strict mode, it counts
+	/// as catch-all, so the email is Risky.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub strict_safe: bool,
+	/// Number of random addresses to probe on the same connection to detect
+	/// a catch-all. A single accepted probe isn't always conclusive: with
+	/// several probes, the domain is a catch-all if at least half of them
+	/// are accepted, and the share accepted is reported in the SMTP details'
+	/// `catch_all_confidence`. Providers which don't support a true
+	/// catch-all, like Google Workspace, are always probed at least twice,
+	/// and need every probe accepted.
+	///
+	/// Defaults to 1.
+	pub catch_all_probes: usize,
+	/// Probe `postmaster@domain` on the same connection, before the email.
+	/// Every domain must accept it, so a server rejecting it validates
+	/// recipients against its mailboxes: it's recorded as an honest
+	/// responder in the SMTP debug details' `honest_responder`, and its
+	/// permanent rejections of the email's mailbox (with a 5.1.x enhanced
+	/// status code) are trusted, even if we can't parse them. On a catch-all domain, an address at a `.invalid` domain is
+	/// probed too, see `SmtpDetails::accepts_at_rcpt_only`.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub probe_postmaster: bool,
+	/// After the email is found deliverable, on a domain which isn't a
+	/// catch-all, send a `RCPT TO` for `user+tag@domain`, with a random tag,
+	/// on the same connection. Whether it's accepted is recorded in the misc
+	/// details' `supports_subaddressing`.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub probe_subaddressing: bool,
+	/// On providers known to support plus-addressing, e.g. Gmail, send the
+	/// `RCPT TO` for the email without its `+tag`, since the base mailbox
+	/// determines the existence. If unset, the literal email is verified. The
+	/// verified email is recorded in the SMTP debug details' `rcpt_to`.
+	///
+	/// Defaults to true.
+	pub strip_plus_tag_before_smtp: bool,
+	/// Maximum number of `RCPT TO` commands sent on one SMTP connection, for
+	/// the catch-all probes, the postmaster probe and the email itself. Past
+	/// it, we `QUIT` and open a fresh connection: too many recipients on one
+	/// connection can trip the server's abuse detection.
+	///
+	/// Defaults to 5.
+	pub max_rcpt_per_connection: usize,
+	/// Minimum delay between two consecutive `RCPT TO` commands on the same
+	/// SMTP connection, including one reused from the config's `smtp_pool`,
+	/// so that our probes don't look like a dictionary attack to rate-based
+	/// detectors.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub rcpt_pacing: Option<Duration>,
+	/// How to classify the email when the SMTP server still replies with a
+	/// transient (4xx) error after all the `retries`. Some servers greylist
+	/// forever, or use 4xx replies for mailboxes that don't exist, so setting
+	/// this to Invalid can be useful on those.
+	///
+	/// Defaults to Unknown.
+	pub persistent_transient_as: Reachable,
+	/// Record the SMTP commands we send and the server's replies in the SMTP
+	/// debug details' `transcript`, to inspect the dialog later.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub smtp_transcript: bool,
+	/// Behave like a regular MTA in the SMTP dialog: send a NOOP after the
+	/// EHLO, and end the dialog with RSET and QUIT, even after an error,
+	/// instead of dropping the connection. Some reputation-sensitive servers
+	/// penalize clients which don't.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub polite_dialog: bool,
+	/// Connect to the SMTP server listening on this Unix domain socket,
+	/// instead of the domain's MX hosts. The MX lookup is skipped, and
+	/// `smtp_security`, `proxy` and `proxy_protocol` don't apply. Mostly
+	/// useful to test against a local mail server.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub unix_socket: Option<PathBuf>,
+	/// Connect to this SMTP host and port, instead of the domain's MX hosts,
+	/// e.g. to verify an address through an internal relay. The MX lookup
+	/// is skipped, and the output's `mx` is marked as `overridden`. The
+	/// full address is still used in `RCPT TO`. Ignored if `unix_socket` is
+	/// set.
+	///
+	/// Defaults to None.
+	#[serde(default)]
+	pub smtp_host_override: Option<(String, u16)>,
+	/// Skip the Gravatar and HaveIBeenPwned API calls when the SMTP
+	/// verification conclusively marks the email as Invalid. The offline
+	/// misc checks, e.g. disposable or role account, still run.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub skip_misc_on_invalid: bool,
+	/// Only make the Gravatar and HaveIBeenPwned API calls if the
+	/// reachability so far, after the MX and SMTP checks, isn't Invalid.
+	/// Unlike `skip_misc_on_invalid`, this follows the final `is_reachable`,
+	/// e.g. a persistent transient error with `persistent_transient_as` set
+	/// to Invalid also skips them. The offline misc checks still run.
+	///
+	/// Defaults to false.
+	#[serde(default)]
+	pub misc_only_if_reachable: bool,
 }
 
 impl Default for CheckEmailInput {
@@ -241,16 +716,59 @@ impl Default for CheckEmailInput {
 			from_email: "reacher.email@gmail.com".into(), // Unused, owned by Reacher
 			hello_name: "gmail.com".into(),
 			proxy: None,
+			provider_proxies: HashMap::new(),
 			smtp_port: 25,
 			smtp_security: SmtpSecurity::default(),
+			smtp_verify_certificates: false,
+			min_tls_version: TlsVersion::default(),
+			require_starttls_capability: false,
 			smtp_timeout: Some(Duration::from_secs(30)),
+			smtp_timeouts: None,
+			smtp_greeting_timeout: None,
+			mx_host_index: None,
+			smtp_mx_fallback: false,
+			shuffle_equal_mx: false,
+			smtp_per_host_debug: false,
 			yahoo_verif_method: YahooVerifMethod::default(),
 			gmail_verif_method: GmailVerifMethod::default(),
 			hotmailb2b_verif_method: HotmailB2BVerifMethod::default(),
 			hotmailb2c_verif_method: HotmailB2CVerifMethod::default(),
+			cross_check: false,
 			check_gravatar: false,
+			check_mta_sts: false,
+			check_from_spf: false,
 			haveibeenpwned_api_key: None,
+			check_haveibeenpwned_pastes: false,
 			retries: 1,
+			http_timeout: None,
+			user_agent: None,
+			disposable_allowlist: None,
+			domain_blocklist: None,
+			domain_allowlist: None,
+			domain_allowlist_subdomains: true,
+			parking_mx_hosts: None,
+			smtp_max_response_bytes: None,
+			smtp_max_response_lines: None,
+			use_null_sender: false,
+			adaptive_timeout: false,
+			proxy_protocol: None,
+			proxy_protocol_source: None,
+			bind_addr: None,
+			rcpt_252_policy: Rcpt252Policy::default(),
+			strict_safe: false,
+			catch_all_probes: 1,
+			probe_postmaster: false,
+			probe_subaddressing: false,
+			strip_plus_tag_before_smtp: true,
+			max_rcpt_per_connection: 5,
+			rcpt_pacing: None,
+			persistent_transient_as: Reachable::Unknown,
+			smtp_transcript: false,
+			polite_dialog: false,
+			unix_socket: None,
+			smtp_host_override: None,
+			skip_misc_on_invalid: false,
+			misc_only_if_reachable: false,
 		}
 	}
 }
@@ -288,8 +806,23 @@ pub struct DebugDetails {
 	pub end_time: DateTime<Utc>,
 	/// The duration of the email verification.
 	pub duration: Duration,
+	/// The duration of the MX lookup, if it was performed.
+	#[serde(default)]
+	pub mx_lookup_duration: Option<Duration>,
 	/// Details about the email verification used for debugging.
 	pub smtp: SmtpDebug,
+	/// The reason given by the config's `post_classify` hook, if it
+	/// overrode `is_reachable`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub reachable_override: Option<String>,
+	/// Whether the output was served from a cache of previous results,
+	/// instead of a live verification.
+	#[serde(default)]
+	pub from_cache: bool,
+	/// When the cached output was originally verified, i.e. its `end_time`.
+	/// Only set if `from_cache` is.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cached_at: Option<DateTime<Utc>>,
 }
 
 impl Default for DebugDetails {
@@ -298,7 +831,11 @@ impl Default for DebugDetails {
 			start_time: SystemTime::now().into(),
 			end_time: SystemTime::now().into(),
 			duration: Duration::default(),
+			mx_lookup_duration: None,
 			smtp: SmtpDebug::default(),
+			reachable_override: None,
+			from_cache: false,
+			cached_at: None,
 			backend_name: "backend-dev".into(),
 		}
 	}
@@ -314,6 +851,9 @@ pub struct CheckEmailOutput {
 	pub misc: Result<MiscDetails, MiscError>,
 	/// Details about the MX host.
 	pub mx: Result<MxDetails, MxError>,
+	/// Details about the domain's MTA-STS policy, if `check_mta_sts` is set
+	/// in the input.
+	pub mta_sts: Option<Result<MtaStsDetails, MtaStsError>>,
 	/// Details about the SMTP responses of the email.
 	pub smtp: Result<SmtpDetails, SmtpError>,
 	/// Details about the email address.
@@ -329,6 +869,7 @@ impl Default for CheckEmailOutput {
 			is_reachable: Reachable::Unknown,
 			misc: Ok(MiscDetails::default()),
 			mx: Ok(MxDetails::default()),
+			mta_sts: None,
 			smtp: Ok(SmtpDetails::default()),
 			syntax: SyntaxDetails::default(),
 			debug: DebugDetails::default(),
@@ -336,6 +877,123 @@ impl Default for CheckEmailOutput {
 	}
 }
 
+/// The message of a serialized error enum, i.e. its "message" field, or its
+/// "type" if it has no message.
+fn error_message<E: Serialize>(error: &E) -> String {
+	let value = serde_json::to_value(error).unwrap_or_default();
+	match &value["message"] {
+		serde_json::Value::String(message) => message.clone(),
+		serde_json::Value::Null => value["type"].as_str().unwrap_or_default().to_string(),
+		message => message.to_string(),
+	}
+}
+
+impl CheckEmailOutput {
+	/// Flatten the key fields of the output into a single level, e.g. for a
+	/// CSV row or a spreadsheet, keyed by the column name:
+	/// - `input`, `is_reachable`,
+	/// - `is_valid_syntax`,
+	/// - `mx_accepts_mail`, `mx_error`,
+	/// - `is_disposable`, `is_role_account`,
+	/// - `can_connect_smtp`, `is_deliverable`, `is_catch_all`,
+	///   `has_full_inbox`, `is_disabled`, `smtp_error`.
+	///
+	/// Booleans are "true" or "false". If a step failed, its error message is
+	/// in its `*_error` column, and its other columns are empty.
+	pub fn to_flat_record(&self) -> BTreeMap<String, String> {
+		let mut record = BTreeMap::new();
+		let mut insert = |column: &str, value: String| {
+			record.insert(column.to_string(), value);
+		};
+		let empty = String::new;
+
+		insert("input", self.input.clone());
+		insert(
+			"is_reachable",
+			serde_json::to_value(&self.is_reachable)
+				.ok()
+				.and_then(|v| v.as_str().map(str::to_string))
+				.unwrap_or_default(),
+		);
+		insert("is_valid_syntax", self.syntax.is_valid_syntax.to_string());
+
+		match &self.mx {
+			Ok(mx) => {
+				let accepts_mail = mx
+					.lookup
+					.as_ref()
+					.map(|lookup| lookup.iter().next().is_some())
+					.unwrap_or(false);
+				insert("mx_accepts_mail", accepts_mail.to_string());
+				insert("mx_error", empty());
+			}
+			Err(err) => {
+				insert("mx_accepts_mail", empty());
+				insert("mx_error", error_message(err));
+			}
+		}
+
+		match &self.misc {
+			Ok(misc) => {
+				insert("is_disposable", misc.is_disposable.to_string());
+				insert("is_role_account", misc.is_role_account.to_string());
+			}
+			Err(_) => {
+				insert("is_disposable", empty());
+				insert("is_role_account", empty());
+			}
+		}
+
+		let smtp_columns = [
+			"can_connect_smtp",
+			"is_deliverable",
+			"is_catch_all",
+			"has_full_inbox",
+			"is_disabled",
+		];
+		match &self.smtp {
+			Ok(smtp) => {
+				let values = [
+					smtp.can_connect_smtp,
+					smtp.is_deliverable,
+					smtp.is_catch_all,
+					smtp.has_full_inbox,
+					smtp.is_disabled,
+				];
+				for (column, value) in smtp_columns.iter().zip(values) {
+					insert(column, value.to_string());
+				}
+				insert("smtp_error", empty());
+			}
+			Err(err) => {
+				for column in smtp_columns {
+					insert(column, empty());
+				}
+				insert("smtp_error", error_message(err));
+			}
+		}
+
+		record
+	}
+}
+
+/// The result of the [validate_offline](validate_offline) function. All of
+/// these fields are computed without any network call.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct OfflineOutput {
+	/// Input by the user.
+	pub input: String,
+	/// Details about the email address, including its normalized form and a
+	/// suggestion if the domain looks like a typo of a popular provider.
+	pub syntax: SyntaxDetails,
+	/// Is this a DEA (disposable email account)?
+	pub is_disposable: bool,
+	/// Is this email a role-based account?
+	pub is_role_account: bool,
+	/// Is this email hosted by a free email provider, e.g. Gmail?
+	pub is_free_provider: bool,
+}
+
 // Implement a custom serialize.
 impl Serialize for CheckEmailOutput {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -350,6 +1008,10 @@ impl Serialize for CheckEmailOutput {
 			// the `get_description` on SmtpError.
 			#[serde(skip_serializing_if = "Option::is_none")]
 			description: Option<SmtpErrorDesc>,
+			// The optional "fingerprint" field, given by the `fingerprint` on
+			// SmtpError.
+			#[serde(skip_serializing_if = "Option::is_none")]
+			fingerprint: Option<String>,
 		}
 
 		let mut map = serializer.serialize_map(Some(1))?;
@@ -362,6 +1024,7 @@ impl Serialize for CheckEmailOutput {
 				&MyError {
 					error,
 					description: None,
+					fingerprint: None,
 				},
 			)?,
 		}
@@ -372,9 +1035,23 @@ impl Serialize for CheckEmailOutput {
 				&MyError {
 					error,
 					description: None,
+					fingerprint: None,
 				},
 			)?,
 		}
+		// Only serialized if checked, to keep the output unchanged otherwise.
+		match &self.mta_sts {
+			Some(Ok(t)) => map.serialize_entry("mta_sts", &t)?,
+			Some(Err(error)) => map.serialize_entry(
+				"mta_sts",
+				&MyError {
+					error,
+					description: None,
+					fingerprint: None,
+				},
+			)?,
+			None => {}
+		}
 		match &self.smtp {
 			Ok(t) => map.serialize_entry("smtp", &t)?,
 			Err(error) => map.serialize_entry(
@@ -382,19 +1059,45 @@ impl Serialize for CheckEmailOutput {
 				&MyError {
 					error,
 					description: error.get_description(),
+					fingerprint: error.fingerprint(),
 				},
 			)?,
 		}
 		map.serialize_entry("syntax", &self.syntax)?;
 		map.serialize_entry("debug", &self.debug)?;
+		map.serialize_entry("verdict", &self.verdict())?;
 		map.end()
 	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{CheckEmailOutput, DebugDetails};
+	use super::{
+		CheckEmailOutput, DebugDetails, GmailVerifMethod, HotmailB2BVerifMethod,
+		HotmailB2CVerifMethod, ProviderVerifMethod, YahooVerifMethod,
+	};
 	use async_smtp::smtp::response::{Category, Code, Detail, Response, Severity};
+	use std::str::FromStr;
+
+	#[test]
+	fn should_parse_verif_methods_by_name() {
+		fn names<M: ProviderVerifMethod>() -> Vec<&'static str> {
+			M::ALL
+				.iter()
+				.map(|method| {
+					let name = method.as_str();
+					assert!(M::from_str(name).is_ok(), "{}", name);
+					name
+				})
+				.collect()
+		}
+
+		assert_eq!(names::<YahooVerifMethod>(), vec!["api", "headless", "smtp"]);
+		assert_eq!(names::<GmailVerifMethod>(), vec!["api", "smtp"]);
+		assert_eq!(names::<HotmailB2BVerifMethod>(), vec!["smtp"]);
+		assert_eq!(names::<HotmailB2CVerifMethod>(), vec!["headless", "smtp"]);
+		assert!(YahooVerifMethod::from_str("foo").is_err());
+	}
 
 	#[test]
 	fn should_serialize_correctly() {
@@ -415,6 +1118,7 @@ mod tests {
 				is_reachable: super::Reachable::Unknown,
 				misc: Ok(super::MiscDetails::default()),
 				mx: Ok(super::MxDetails::default()),
+				mta_sts: None,
 				syntax: super::SyntaxDetails::default(),
 				smtp: Err(super::SmtpError::SmtpError(r.into())),
 				debug: DebugDetails::default(),
@@ -424,20 +1128,80 @@ mod tests {
 		let res = dummy_response_with_message("blacklist");
 		let actual = serde_json::to_string(&res).unwrap();
 		// Make sure the `description` is present with IpBlacklisted.
-		let expected = r#""smtp":{"error":{"type":"SmtpError","message":"transient: blacklist"},"description":"IpBlacklisted"}"#;
+		let expected = r#""smtp":{"error":{"type":"SmtpError","message":"transient: blacklist"},"description":"IpBlacklisted","fingerprint":"450 - blacklist <n> size <n>"}"#;
 		assert!(actual.contains(expected));
 
 		let res =
 			dummy_response_with_message("Client host rejected: cannot find your reverse hostname");
 		let actual = serde_json::to_string(&res).unwrap();
 		// Make sure the `description` is present with NeedsRDNs.
-		let expected = r#"smtp":{"error":{"type":"SmtpError","message":"transient: Client host rejected: cannot find your reverse hostname"},"description":"NeedsRDNS"}"#;
+		let expected = r#"smtp":{"error":{"type":"SmtpError","message":"transient: Client host rejected: cannot find your reverse hostname"},"description":"NeedsRDNS","fingerprint":"450 - client host rejected: cannot find your reverse hostname <n> size <n>"}"#;
 		assert!(actual.contains(expected));
 
 		let res = dummy_response_with_message("foobar");
 		let actual = serde_json::to_string(&res).unwrap();
-		// Make sure the `description` is NOT present.
-		let expected = r#""smtp":{"error":{"type":"SmtpError","message":"transient: foobar"}}"#;
+		// Make sure the `description` is NOT present, but the `fingerprint` is.
+		let expected = r#""smtp":{"error":{"type":"SmtpError","message":"transient: foobar"},"fingerprint":"450 - foobar <n> size <n>"}"#;
 		assert!(actual.contains(expected));
 	}
+
+	#[test]
+	fn should_flatten_to_record() {
+		let output = CheckEmailOutput {
+			input: "foo@example.com".into(),
+			is_reachable: super::Reachable::Safe,
+			smtp: Ok(super::SmtpDetails {
+				can_connect_smtp: true,
+				is_deliverable: true,
+				..Default::default()
+			}),
+			..Default::default()
+		};
+
+		let record = output.to_flat_record();
+
+		let expected = [
+			("can_connect_smtp", "true"),
+			("has_full_inbox", "false"),
+			("input", "foo@example.com"),
+			("is_catch_all", "false"),
+			("is_deliverable", "true"),
+			("is_disabled", "false"),
+			("is_disposable", "false"),
+			("is_reachable", "safe"),
+			("is_role_account", "false"),
+			("is_valid_syntax", "false"),
+			("mx_accepts_mail", "false"),
+			("mx_error", ""),
+			("smtp_error", ""),
+		];
+		assert_eq!(
+			record,
+			expected
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect()
+		);
+	}
+
+	#[test]
+	fn should_flatten_errors_into_their_column() {
+		let output = CheckEmailOutput {
+			input: "foo@example.com".into(),
+			smtp: Err(super::SmtpError::Blocklisted("example.com".into())),
+			..Default::default()
+		};
+
+		let record = output.to_flat_record();
+
+		assert_eq!(record["smtp_error"], "example.com");
+		assert_eq!(record["is_deliverable"], "");
+		assert_eq!(record["is_reachable"], "unknown");
+
+		let output = CheckEmailOutput {
+			smtp: Err(super::SmtpError::SmtpUtf8Unsupported),
+			..Default::default()
+		};
+		assert_eq!(output.to_flat_record()["smtp_error"], "SmtpUtf8Unsupported");
+	}
 }
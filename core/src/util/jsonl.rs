@@ -0,0 +1,142 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Verify a stream of emails in the JSON Lines format, to build pipelines
+//! without the HTTP layer of the backend.
+
+use futures::stream::{self, StreamExt};
+use std::io;
+use std::pin::pin;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::config::ReacherConfig;
+use crate::{check_email, CheckEmailInput};
+
+/// Read one `CheckEmailInput` per line from `reader`, verify them with at
+/// most `concurrency` verifications at a time, and write one
+/// `CheckEmailOutput` per line to `writer`.
+///
+/// The outputs are written as soon as they're ready, so not necessarily in
+/// the order of the inputs: use their `input` field to correlate them. Empty
+/// lines are skipped, and a line which isn't a valid `CheckEmailInput` gives
+/// an output line like `{"line":3,"error":"..."}` (line numbers start at 1).
+///
+/// Returns an error if reading or writing fails.
+pub async fn run_jsonl_stream<R, W>(
+	reader: R,
+	mut writer: W,
+	config: &ReacherConfig,
+	concurrency: usize,
+) -> io::Result<()>
+where
+	R: AsyncRead + Unpin,
+	W: AsyncWrite + Unpin,
+{
+	let lines = stream::unfold(
+		(BufReader::new(reader).lines(), 0, false),
+		|(mut lines, n, failed)| async move {
+			if failed {
+				return None;
+			}
+			match lines.next_line().await {
+				Ok(Some(line)) => Some((Ok((n + 1, line)), (lines, n + 1, false))),
+				Ok(None) => None,
+				// Yield the error, then stop.
+				Err(err) => Some((Err(err), (lines, n, true))),
+			}
+		},
+	);
+
+	let mut outputs = pin!(lines
+		.filter(|line| {
+			let is_empty = matches!(line, Ok((_, line)) if line.trim().is_empty());
+			async move { !is_empty }
+		})
+		.map(|line| async move {
+			let (n, line) = line?;
+			let mut output = match serde_json::from_str::<CheckEmailInput>(&line) {
+				Ok(input) => serde_json::to_vec(&check_email(&input, config).await)?,
+				Err(err) => serde_json::to_vec(&serde_json::json!({
+					"line": n,
+					"error": err.to_string(),
+				}))?,
+			};
+			output.push(b'\n');
+			Ok::<_, io::Error>(output)
+		})
+		.buffer_unordered(concurrency.max(1)));
+
+	while let Some(output) = outputs.next().await {
+		writer.write_all(&output?).await?;
+	}
+	writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::run_jsonl_stream;
+	use crate::config::ReacherConfig;
+	use crate::util::mock_dns::{response, MockDnsServer};
+	use hickory_proto::op::ResponseCode;
+	use std::sync::Arc;
+
+	#[tokio::test]
+	async fn should_verify_each_line() {
+		let server = MockDnsServer::start(|query| response(query, ResponseCode::NXDomain)).await;
+		let config = ReacherConfig {
+			resolver: Arc::new(server.resolver()),
+			..Default::default()
+		};
+		let reader: &[u8] =
+			b"{\"to_email\": \"foo@example.com\"}\n\n{\"to_email\": \"not-an-email\"}\n";
+		let mut writer = vec![];
+
+		run_jsonl_stream(reader, &mut writer, &config, 2)
+			.await
+			.unwrap();
+
+		let mut outputs = String::from_utf8(writer)
+			.unwrap()
+			.lines()
+			.map(|line| {
+				let output = serde_json::from_str::<serde_json::Value>(line).unwrap();
+				(output["input"].clone(), output["is_reachable"].clone())
+			})
+			.collect::<Vec<_>>();
+		outputs.sort_by_key(|(input, _)| input.to_string());
+		assert_eq!(
+			outputs,
+			vec![
+				("foo@example.com".into(), "invalid".into()),
+				("not-an-email".into(), "invalid".into()),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn should_report_invalid_lines() {
+		let reader: &[u8] = b"not json\n";
+		let mut writer = vec![];
+
+		run_jsonl_stream(reader, &mut writer, &ReacherConfig::default(), 1)
+			.await
+			.unwrap();
+
+		let output = serde_json::from_slice::<serde_json::Value>(&writer).unwrap();
+		assert_eq!(output["line"], 1);
+		assert!(output["error"].is_string());
+	}
+}
@@ -0,0 +1,121 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal DNS server listening on localhost, used in tests to control the
+//! answers given to the resolver, and to count the queries it receives.
+
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{MX, TXT};
+use hickory_proto::rr::{Name, RData, Record};
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// A mock DNS server, answering each query with the given handler.
+pub struct MockDnsServer {
+	addr: SocketAddr,
+	queries: Arc<AtomicUsize>,
+}
+
+impl MockDnsServer {
+	pub async fn start<F>(handler: F) -> Self
+	where
+		F: Fn(&Message) -> Message + Send + Sync + 'static,
+	{
+		let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let addr = socket.local_addr().unwrap();
+		let queries = Arc::new(AtomicUsize::new(0));
+		let counter = Arc::clone(&queries);
+
+		tokio::spawn(async move {
+			let mut buf = vec![0; 4096];
+			while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+				let Ok(query) = Message::from_vec(&buf[..n]) else {
+					continue;
+				};
+				counter.fetch_add(1, Ordering::SeqCst);
+				let response = handler(&query).to_vec().unwrap();
+				let _ = socket.send_to(&response, peer).await;
+			}
+		});
+
+		Self { addr, queries }
+	}
+
+	/// Number of queries received so far.
+	pub fn queries(&self) -> usize {
+		self.queries.load(Ordering::SeqCst)
+	}
+
+	/// Create a resolver which only talks to this server.
+	pub fn resolver(&self) -> TokioAsyncResolver {
+		let config = ResolverConfig::from_parts(
+			None,
+			vec![],
+			NameServerConfigGroup::from_ips_clear(&[self.addr.ip()], self.addr.port(), true),
+		);
+		let mut opts = ResolverOpts::default();
+		opts.attempts = 1;
+		TokioAsyncResolver::tokio(config, opts)
+	}
+}
+
+/// Build an empty response to the query, with the given response code.
+pub fn response(query: &Message, code: ResponseCode) -> Message {
+	let mut response = Message::new();
+	response
+		.set_id(query.id())
+		.set_message_type(MessageType::Response)
+		.set_op_code(query.op_code())
+		.set_recursion_desired(query.recursion_desired())
+		.set_recursion_available(true)
+		.set_response_code(code)
+		.add_queries(query.queries().to_vec());
+	response
+}
+
+/// Build a response to the query with the given MX records, as
+/// `(preference, exchange)` pairs.
+pub fn mx_response(query: &Message, ttl: u32, records: &[(u16, &str)]) -> Message {
+	let mut response = response(query, ResponseCode::NoError);
+	let name = query.queries()[0].name().clone();
+	for (preference, exchange) in records {
+		response.add_answer(Record::from_rdata(
+			name.clone(),
+			ttl,
+			RData::MX(MX::new(*preference, Name::from_str(exchange).unwrap())),
+		));
+	}
+	response
+}
+
+/// Build a response to the query with the given TXT records.
+pub fn txt_response(query: &Message, ttl: u32, records: &[&str]) -> Message {
+	let mut response = response(query, ResponseCode::NoError);
+	let name = query.queries()[0].name().clone();
+	for txt in records {
+		response.add_answer(Record::from_rdata(
+			name.clone(),
+			ttl,
+			RData::TXT(TXT::new(vec![txt.to_string()])),
+		));
+	}
+	response
+}
@@ -0,0 +1,181 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal SMTP server listening on localhost, used in tests to control the
+//! replies received by the SMTP client.
+
+use async_native_tls::TlsAcceptor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+type Handler = dyn Fn(&str) -> String + Send + Sync;
+
+/// A mock SMTP server, replying to each command with the given handler.
+pub struct MockSmtpServer {
+	addr: SocketAddr,
+	commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockSmtpServer {
+	/// Start the server. It greets each connection with a 220 banner, then
+	/// writes the handler's reply to each command line (without CRLF). The
+	/// reply must include its own CRLFs.
+	pub async fn start<F>(handler: F) -> Self
+	where
+		F: Fn(&str) -> String + Send + Sync + 'static,
+	{
+		Self::start_inner("127.0.0.1:0".parse().unwrap(), Arc::new(handler), None).await
+	}
+
+	/// Start the server like `start`, but listening on this address, e.g. to
+	/// have several servers on the same port of different loopback IPs.
+	pub async fn start_on<F>(addr: SocketAddr, handler: F) -> Self
+	where
+		F: Fn(&str) -> String + Send + Sync + 'static,
+	{
+		Self::start_inner(addr, Arc::new(handler), None).await
+	}
+
+	/// Start the server like `start`, but reply 220 to `STARTTLS` and then
+	/// upgrade the connection to TLS, with a self-signed certificate for
+	/// localhost. The handler should advertise STARTTLS in its EHLO reply.
+	pub async fn start_starttls<F>(handler: F) -> Self
+	where
+		F: Fn(&str) -> String + Send + Sync + 'static,
+	{
+		// Generated with `openssl req -x509 -subj /CN=localhost`, and
+		// exported with the password "mock".
+		let identity = &include_bytes!("mock_smtp.p12")[..];
+		let tls = TlsAcceptor::new(identity, "mock").await.unwrap();
+		Self::start_inner("127.0.0.1:0".parse().unwrap(), Arc::new(handler), Some(tls)).await
+	}
+
+	async fn start_inner(
+		addr: SocketAddr,
+		handler: Arc<Handler>,
+		tls: Option<TlsAcceptor>,
+	) -> Self {
+		let listener = TcpListener::bind(addr).await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let commands = Arc::new(Mutex::new(vec![]));
+		let received = Arc::clone(&commands);
+		let tls = tls.map(Arc::new);
+
+		tokio::spawn(async move {
+			while let Ok((mut socket, _)) = listener.accept().await {
+				let handler = Arc::clone(&handler);
+				let received = Arc::clone(&received);
+				let tls = tls.clone();
+				tokio::spawn(async move {
+					if socket.write_all(b"220 mock ESMTP\r\n").await.is_err() {
+						return;
+					}
+					let upgrade = serve(&mut socket, &*handler, &received, tls.is_some()).await;
+					if let (true, Some(tls)) = (upgrade, tls) {
+						if let Ok(mut stream) = tls.accept(socket).await {
+							serve(&mut stream, &*handler, &received, false).await;
+						}
+					}
+				});
+			}
+		});
+
+		Self { addr, commands }
+	}
+
+	/// Start the server like `start`, but listening on a Unix domain socket
+	/// at this path. The server's `port` is then 0.
+	#[cfg(unix)]
+	pub async fn start_unix<F>(path: &std::path::Path, handler: F) -> Self
+	where
+		F: Fn(&str) -> String + Send + Sync + 'static,
+	{
+		let listener = tokio::net::UnixListener::bind(path).unwrap();
+		let handler: Arc<Handler> = Arc::new(handler);
+		let commands = Arc::new(Mutex::new(vec![]));
+		let received = Arc::clone(&commands);
+
+		tokio::spawn(async move {
+			while let Ok((mut socket, _)) = listener.accept().await {
+				let handler = Arc::clone(&handler);
+				let received = Arc::clone(&received);
+				tokio::spawn(async move {
+					if socket.write_all(b"220 mock ESMTP\r\n").await.is_err() {
+						return;
+					}
+					serve(&mut socket, &*handler, &received, false).await;
+				});
+			}
+		});
+
+		Self {
+			addr: "0.0.0.0:0".parse().unwrap(),
+			commands,
+		}
+	}
+
+	pub fn port(&self) -> u16 {
+		self.addr.port()
+	}
+
+	/// The command lines received so far, across all connections.
+	pub fn commands(&self) -> Vec<String> {
+		self.commands.lock().unwrap().clone()
+	}
+}
+
+/// Reply to the commands read on the stream until the connection closes.
+/// If `starttls` is set, stop after replying to `STARTTLS`, and return
+/// true.
+async fn serve<S>(
+	stream: &mut S,
+	handler: &Handler,
+	received: &Mutex<Vec<String>>,
+	starttls: bool,
+) -> bool
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let mut stream = BufReader::new(stream);
+	let mut line = String::new();
+	loop {
+		line.clear();
+		match stream.read_line(&mut line).await {
+			Ok(0) | Err(_) => return false,
+			Ok(_) => {}
+		}
+		let command = line.trim_end_matches(['\r', '\n']);
+		received.lock().unwrap().push(command.to_string());
+		if starttls && command.eq_ignore_ascii_case("STARTTLS") {
+			let _ = stream.write_all(b"220 Ready to start TLS\r\n").await;
+			return true;
+		}
+		let reply = handler(command);
+		if stream.write_all(reply.as_bytes()).await.is_err() {
+			return false;
+		}
+	}
+}
+/// A handler which accepts all the commands, with a one-line reply.
+pub fn accept_all(command: &str) -> String {
+	if command.starts_with("QUIT") {
+		"221 Bye\r\n".into()
+	} else {
+		"250 OK\r\n".into()
+	}
+}
@@ -14,7 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod es_bulk;
 pub mod input_output;
+pub mod jsonl;
+#[cfg(test)]
+pub mod mock_dns;
+#[cfg(test)]
+pub mod mock_smtp;
 #[cfg(feature = "sentry")]
 pub mod sentry;
 pub mod ser_with_display;
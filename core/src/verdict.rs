@@ -0,0 +1,210 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A single opinionated verdict on a verification output, for consumers who
+//! don't want to interpret the raw fields themselves. It's derived from the
+//! other fields of the output only, so the same output always gives the same
+//! verdict.
+
+use crate::smtp::SmtpError;
+use crate::{is_transient_error, reachable_reasons, CheckEmailOutput, Reachable};
+use serde::{Deserialize, Serialize};
+
+/// How confident we are in the verdict.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+	/// Safe and Invalid emails.
+	High,
+	/// Risky emails.
+	Medium,
+	/// Unknown emails.
+	Low,
+}
+
+/// What we recommend doing with the email.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+	/// The email is safe to send to.
+	Send,
+	/// The email can be sent to, but may bounce or get low engagement.
+	SendWithCaution,
+	/// The email shouldn't be sent to.
+	DoNotSend,
+	/// We couldn't conclude, but verifying the email again later may.
+	RetryLater,
+}
+
+/// The deliverability verdict of an email, combining all the signals of its
+/// verification.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Verdict {
+	/// Same as the output's `is_reachable`.
+	pub is_reachable: Reachable,
+	/// How confident we are in the verdict.
+	pub confidence: Confidence,
+	/// The reasons behind `is_reachable`, e.g. "catch_all" or
+	/// "disposable". Empty for a Safe email.
+	pub reasons: Vec<String>,
+	/// The recommended action.
+	pub action: Action,
+}
+
+impl CheckEmailOutput {
+	/// Get the deliverability verdict of this output.
+	pub fn verdict(&self) -> Verdict {
+		let mut reasons: Vec<String> = reachable_reasons(self)
+			.into_iter()
+			.map(String::from)
+			.collect();
+		let (confidence, action) = match self.is_reachable {
+			Reachable::Safe => (Confidence::High, Action::Send),
+			Reachable::Risky => (Confidence::Medium, Action::SendWithCaution),
+			Reachable::Invalid => (Confidence::High, Action::DoNotSend),
+			Reachable::Unknown => {
+				if is_transient_error(&self.smtp) {
+					reasons.push("transient_error".into());
+				}
+				(Confidence::Low, unknown_action(self))
+			}
+		};
+
+		Verdict {
+			is_reachable: self.is_reachable.clone(),
+			confidence,
+			reasons,
+			action,
+		}
+	}
+}
+
+/// The action for an Unknown email. Only a transient error, e.g. greylisting,
+/// may go away by verifying the email again later. Otherwise we don't know
+/// more than that the domain accepts email, unless we didn't contact the
+/// server on purpose.
+fn unknown_action(output: &CheckEmailOutput) -> Action {
+	match &output.smtp {
+		_ if is_transient_error(&output.smtp) => Action::RetryLater,
+		Err(SmtpError::Blocklisted(_)) => Action::DoNotSend,
+		_ => Action::SendWithCaution,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Action, Confidence};
+	use crate::smtp::{SmtpConnection, SmtpDebug, SmtpDetails, SmtpError, VerifMethod};
+	use crate::syntax::SyntaxDetails;
+	use crate::{CheckEmailOutput, DebugDetails, Reachable};
+	use async_smtp::smtp::error::Error as AsyncSmtpError;
+	use async_smtp::smtp::response::{Category, Code, Detail, Response, Severity};
+
+	/// An output verified via SMTP, with a valid syntax.
+	fn smtp_output(
+		is_reachable: Reachable,
+		smtp: Result<SmtpDetails, SmtpError>,
+	) -> CheckEmailOutput {
+		CheckEmailOutput {
+			is_reachable,
+			smtp,
+			syntax: SyntaxDetails {
+				is_valid_syntax: true,
+				..Default::default()
+			},
+			debug: DebugDetails {
+				smtp: SmtpDebug {
+					verif_method: VerifMethod::Smtp(SmtpConnection::default()),
+					..Default::default()
+				},
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn should_send_to_safe_email() {
+		let output = smtp_output(
+			Reachable::Safe,
+			Ok(SmtpDetails {
+				can_connect_smtp: true,
+				is_deliverable: true,
+				..Default::default()
+			}),
+		);
+
+		let verdict = output.verdict();
+		assert_eq!(verdict.is_reachable, Reachable::Safe);
+		assert_eq!(verdict.confidence, Confidence::High);
+		assert!(verdict.reasons.is_empty());
+		assert_eq!(verdict.action, Action::Send);
+	}
+
+	#[test]
+	fn should_retry_greylisted_email_later() {
+		let greylisted = Response::new(
+			Code::new(
+				Severity::TransientNegativeCompletion,
+				Category::MailSystem,
+				Detail::Zero,
+			),
+			vec!["4.7.1 Greylisted, try again later".into()],
+		);
+		let output = smtp_output(
+			Reachable::Unknown,
+			Err(SmtpError::SmtpError(AsyncSmtpError::Transient(greylisted))),
+		);
+
+		let verdict = output.verdict();
+		assert_eq!(verdict.is_reachable, Reachable::Unknown);
+		assert_eq!(verdict.confidence, Confidence::Low);
+		assert_eq!(verdict.reasons, vec!["smtp_error", "transient_error"]);
+		assert_eq!(verdict.action, Action::RetryLater);
+		// The verdict is deterministic.
+		assert_eq!(output.verdict(), verdict);
+	}
+
+	#[test]
+	fn should_only_retry_transient_errors_later() {
+		let output = smtp_output(
+			Reachable::Unknown,
+			Err(SmtpError::SmtpError(AsyncSmtpError::Permanent(
+				Response::new(
+					Code::new(
+						Severity::PermanentNegativeCompletion,
+						Category::MailSystem,
+						Detail::Zero,
+					),
+					vec!["Unparsable reply".into()],
+				),
+			))),
+		);
+		assert_eq!(output.verdict().action, Action::SendWithCaution);
+
+		let output = smtp_output(
+			Reachable::Unknown,
+			Err(SmtpError::CannotVerify("252 Cannot VRFY user".into())),
+		);
+		assert_eq!(output.verdict().action, Action::SendWithCaution);
+
+		let output = smtp_output(
+			Reachable::Unknown,
+			Err(SmtpError::Blocklisted("foo.com".into())),
+		);
+		assert_eq!(output.verdict().action, Action::DoNotSend);
+	}
+}
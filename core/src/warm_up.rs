@@ -0,0 +1,199 @@
+// check-if-email-exists
+// Copyright (C) 2018-2023 Reacher
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Warm up the resources used by the verifications, so that the first
+//! verification on a fresh worker doesn't pay for them.
+
+use crate::config::ReacherConfig;
+use crate::{CheckEmailInputProxy, LOG_TARGET};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Timeout of each warm-up check.
+const WARM_UP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Domains of the most common providers, whose MX records are resolved
+/// during the warm-up to fill the resolver's cache.
+const COMMON_DOMAINS: &[&str] = &[
+	"gmail.com",
+	"yahoo.com",
+	"hotmail.com",
+	"outlook.com",
+	"icloud.com",
+];
+
+/// The result of [warm_up](warm_up). Each check is Ok if it succeeded, or
+/// contains the reason of its failure.
+#[derive(Debug)]
+pub struct WarmUpReport {
+	/// Whether the proxy accepts TCP connections, or None if there's no
+	/// proxy.
+	pub proxy: Option<Result<(), String>>,
+	/// Whether the WebDriver server's `/status` endpoint reports it's ready.
+	pub webdriver: Result<(), String>,
+	/// The MX lookups of the common providers' domains, by domain.
+	pub mx: BTreeMap<String, Result<(), String>>,
+}
+
+impl WarmUpReport {
+	/// Whether all the checks succeeded.
+	pub fn is_ok(&self) -> bool {
+		!matches!(self.proxy, Some(Err(_)))
+			&& self.webdriver.is_ok()
+			&& self.mx.values().all(Result::is_ok)
+	}
+}
+
+/// Warm up the resources used by the verifications: check the proxy
+/// accepts connections, check the WebDriver server is ready, and resolve
+/// the MX records of common providers into the config's resolver cache.
+/// Meant to be called once at startup. Failures are only reported, and
+/// logged as warnings.
+pub async fn warm_up(config: &ReacherConfig, proxy: Option<&CheckEmailInputProxy>) -> WarmUpReport {
+	let (proxy, webdriver, mx) = tokio::join!(
+		async {
+			match proxy {
+				Some(proxy) => Some(check_proxy(proxy).await),
+				None => None,
+			}
+		},
+		check_webdriver(&config.webdriver_addr),
+		resolve_common_mx(config),
+	);
+	let report = WarmUpReport {
+		proxy,
+		webdriver,
+		mx,
+	};
+
+	if !report.is_ok() {
+		log::warn!(target: LOG_TARGET, "Warm-up failed: {:?}", report);
+	}
+
+	report
+}
+
+async fn check_proxy(proxy: &CheckEmailInputProxy) -> Result<(), String> {
+	match timeout(
+		WARM_UP_TIMEOUT,
+		TcpStream::connect((proxy.host.as_str(), proxy.port)),
+	)
+	.await
+	{
+		Ok(Ok(_)) => Ok(()),
+		Ok(Err(err)) => Err(err.to_string()),
+		Err(_) => Err("Timeout".into()),
+	}
+}
+
+async fn check_webdriver(webdriver_addr: &str) -> Result<(), String> {
+	let url = format!("{}/status", webdriver_addr.trim_end_matches('/'));
+	let client = reqwest::Client::builder()
+		.timeout(WARM_UP_TIMEOUT)
+		.build()
+		.map_err(|err| err.to_string())?;
+	let status: serde_json::Value = client
+		.get(url)
+		.send()
+		.await
+		.and_then(|response| response.error_for_status())
+		.map_err(|err| err.to_string())?
+		.json()
+		.await
+		.map_err(|err| err.to_string())?;
+
+	// WebDriver servers answer {"value": {"ready": bool, "message": string}}.
+	match status["value"]["ready"].as_bool() {
+		Some(true) => Ok(()),
+		_ => Err(format!(
+			"WebDriver is not ready: {}",
+			status["value"]["message"]
+		)),
+	}
+}
+
+async fn resolve_common_mx(config: &ReacherConfig) -> BTreeMap<String, Result<(), String>> {
+	let lookups = COMMON_DOMAINS.iter().map(|domain| async move {
+		let result = match timeout(WARM_UP_TIMEOUT, config.resolver.mx_lookup(*domain)).await {
+			Ok(Ok(_)) => Ok(()),
+			Ok(Err(err)) => Err(err.to_string()),
+			Err(_) => Err("Timeout".into()),
+		};
+		(domain.to_string(), result)
+	});
+
+	futures::future::join_all(lookups)
+		.await
+		.into_iter()
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::mock_dns::{response, MockDnsServer};
+	use hickory_proto::op::ResponseCode;
+	use std::sync::Arc;
+	use tokio::net::TcpListener;
+
+	/// Return a local address nothing listens on.
+	async fn closed_addr() -> std::net::SocketAddr {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		listener.local_addr().unwrap()
+	}
+
+	#[tokio::test]
+	async fn should_report_unreachable_resources() {
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::ServFail)).await;
+		let proxy_addr = closed_addr().await;
+		let webdriver_addr = closed_addr().await;
+		let config = ReacherConfig {
+			webdriver_addr: format!("http://{}", webdriver_addr),
+			resolver: Arc::new(dns.resolver()),
+			..Default::default()
+		};
+		let proxy = CheckEmailInputProxy {
+			host: proxy_addr.ip().to_string(),
+			port: proxy_addr.port(),
+			username: None,
+			password: None,
+		};
+
+		let report = warm_up(&config, Some(&proxy)).await;
+
+		assert!(!report.is_ok());
+		assert!(matches!(report.proxy, Some(Err(_))));
+		assert!(report.webdriver.is_err());
+		assert_eq!(report.mx.len(), COMMON_DOMAINS.len());
+		assert!(report.mx.values().all(Result::is_err));
+	}
+
+	#[tokio::test]
+	async fn should_skip_proxy_if_none() {
+		let dns = MockDnsServer::start(|query| response(query, ResponseCode::ServFail)).await;
+		let config = ReacherConfig {
+			webdriver_addr: format!("http://{}", closed_addr().await),
+			resolver: Arc::new(dns.resolver()),
+			..Default::default()
+		};
+
+		let report = warm_up(&config, None).await;
+
+		assert!(report.proxy.is_none());
+	}
+}